@@ -0,0 +1,213 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use tower_lsp::lsp_types::{DocumentSymbol, Location, Position, Range, SymbolInformation, SymbolKind, Url};
+
+use crate::ast::ast_structs::{AstDB, AstDefinition};
+use crate::ast::ast_db::{definitions, doc_usages, usages};
+use crate::ast::treesitter::structs::SymbolType;
+
+const REFERENCES_LIMIT: usize = 1000;
+
+
+/// Identifier under the cursor, `character` is allowed to point right after the last letter
+pub fn word_at_position(text: &str, line: usize, character: usize) -> Option<String> {
+    let line_chars: Vec<char> = text.lines().nth(line)?.chars().collect();
+    let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+    let mut start = character.min(line_chars.len());
+    if (start == line_chars.len() || !is_word(&line_chars[start])) && start > 0 && is_word(&line_chars[start - 1]) {
+        start -= 1;
+    }
+    if start >= line_chars.len() || !is_word(&line_chars[start]) {
+        return None;
+    }
+    let mut end = start;
+    while start > 0 && is_word(&line_chars[start - 1]) {
+        start -= 1;
+    }
+    while end < line_chars.len() && is_word(&line_chars[end]) {
+        end += 1;
+    }
+    Some(line_chars[start..end].iter().collect())
+}
+
+/// Prefers usages on that line resolved by the indexer, falls back to looking up the bare name
+pub async fn definitions_at_position(ast_index: Arc<AstDB>, cpath: &String, text: &str, line: usize, character: usize) -> Vec<Arc<AstDefinition>> {
+    let word = match word_at_position(text, line, character) {
+        Some(word) => word,
+        None => return vec![],
+    };
+    let suffix = format!("::{}", word);
+    let mut resolved: Vec<String> = doc_usages(ast_index.clone(), cpath).await.into_iter()
+        .filter(|(uline, resolved_as)| *uline == line && (resolved_as.ends_with(&suffix) || *resolved_as == word))
+        .map(|(_, resolved_as)| resolved_as)
+        .collect();
+    resolved.dedup();
+
+    let mut defs = vec![];
+    for resolved_as in resolved.iter() {
+        defs.extend(definitions(ast_index.clone(), resolved_as).unwrap_or_default());
+    }
+    if defs.is_empty() {
+        defs = definitions(ast_index.clone(), &word).unwrap_or_default();
+    }
+    let mut seen = HashSet::new();
+    defs.retain(|d| seen.insert(d.path()));
+    defs
+}
+
+pub fn references_of(ast_index: Arc<AstDB>, defs: &Vec<Arc<AstDefinition>>, include_declaration: bool) -> Vec<Location> {
+    let mut locations = vec![];
+    for def in defs.iter() {
+        if include_declaration {
+            locations.extend(definition_location(def));
+        }
+        for (used_at_def, uline) in usages(ast_index.clone(), def.path(), REFERENCES_LIMIT).unwrap_or_default() {
+            if let Ok(uri) = Url::from_file_path(&used_at_def.cpath) {
+                locations.push(Location {
+                    uri,
+                    range: Range { start: Position::new(uline as u32, 0), end: Position::new(uline as u32 + 1, 0) },
+                });
+            }
+        }
+    }
+    locations
+}
+
+/// From `decl_line1` to the end of the body, lines in AstDefinition start from 1
+pub fn definition_range(def: &AstDefinition) -> Range {
+    Range {
+        start: Position::new(def.full_line1().saturating_sub(1) as u32, 0),
+        end: Position::new(def.full_line2() as u32, 0),
+    }
+}
+
+fn definition_selection_range(def: &AstDefinition) -> Range {
+    Range {
+        start: Position::new(def.decl_line1.saturating_sub(1) as u32, 0),
+        end: Position::new(def.decl_line2 as u32, 0),
+    }
+}
+
+pub fn definition_location(def: &AstDefinition) -> Option<Location> {
+    Some(Location { uri: Url::from_file_path(&def.cpath).ok()?, range: definition_range(def) })
+}
+
+fn symbol_kind(def: &AstDefinition, parent: Option<&AstDefinition>) -> Option<SymbolKind> {
+    let inside_class = parent.map_or(false, |p| p.symbol_type == SymbolType::StructDeclaration);
+    match def.symbol_type {
+        SymbolType::Module => Some(SymbolKind::MODULE),
+        SymbolType::StructDeclaration => Some(SymbolKind::CLASS),
+        SymbolType::TypeAlias => Some(SymbolKind::TYPE_PARAMETER),
+        SymbolType::ClassFieldDeclaration => Some(SymbolKind::FIELD),
+        SymbolType::VariableDefinition => Some(SymbolKind::VARIABLE),
+        SymbolType::FunctionDeclaration if inside_class => Some(SymbolKind::METHOD),
+        SymbolType::FunctionDeclaration => Some(SymbolKind::FUNCTION),
+        // not interesting in outlines and symbol search
+        SymbolType::ImportDeclaration | SymbolType::CommentDefinition |
+        SymbolType::FunctionCall | SymbolType::VariableUsage | SymbolType::Unknown => None,
+    }
+}
+
+fn container_name(def: &AstDefinition) -> Option<String> {
+    let path = def.path_drop0();
+    path.rsplit_once("::").map(|(container, _)| container.to_string())
+}
+
+#[allow(deprecated)]
+pub fn workspace_symbol(def: &AstDefinition) -> Option<SymbolInformation> {
+    Some(SymbolInformation {
+        name: def.name(),
+        kind: symbol_kind(def, None)?,
+        tags: None,
+        deprecated: None,
+        location: definition_location(def)?,
+        container_name: container_name(def),
+    })
+}
+
+/// Nests definitions by `official_path`, a method ends up inside its class
+pub fn document_symbols(defs: &Vec<Arc<AstDefinition>>) -> Vec<DocumentSymbol> {
+    let mut defs: Vec<&Arc<AstDefinition>> = defs.iter().collect();
+    defs.sort_by_key(|d| (d.official_path.len(), d.full_line1()));
+
+    // parent is the closest definition whose path is a prefix of ours
+    let parents: Vec<Option<usize>> = defs.iter().map(|d| {
+        (0..defs.len()).rev()
+            .filter(|&i| defs[i].official_path.len() < d.official_path.len())
+            .find(|&i| d.official_path.starts_with(&defs[i].official_path))
+    }).collect();
+
+    #[allow(deprecated)]
+    fn build(i: usize, defs: &Vec<&Arc<AstDefinition>>, parents: &Vec<Option<usize>>) -> Option<DocumentSymbol> {
+        let def = defs[i];
+        let kind = symbol_kind(def, parents[i].map(|p| defs[p].as_ref()))?;
+        let mut children: Vec<DocumentSymbol> = (0..defs.len())
+            .filter(|&c| parents[c] == Some(i))
+            .filter_map(|c| build(c, defs, parents))
+            .collect();
+        children.sort_by_key(|c| c.range.start.line);
+        Some(DocumentSymbol {
+            name: def.name(),
+            detail: None,
+            kind,
+            tags: None,
+            deprecated: None,
+            range: definition_range(def),
+            selection_range: definition_selection_range(def),
+            children: if children.is_empty() { None } else { Some(children) },
+        })
+    }
+
+    let mut result: Vec<DocumentSymbol> = (0..defs.len())
+        .filter(|&i| parents[i].is_none())
+        .filter_map(|i| build(i, &defs, &parents))
+        .collect();
+    result.sort_by_key(|s| s.range.start.line);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::ast_db::{ast_index_init, connect_usages, connect_usages_look_if_full_reset_needed, doc_add, doc_defs};
+    use crate::ast::ast_structs::AstErrorStats;
+
+    #[test]
+    fn test_word_at_position() {
+        let text = "x = goat.jump_around()\n";
+        assert_eq!(word_at_position(text, 0, 5), Some("goat".to_string()));
+        assert_eq!(word_at_position(text, 0, 8), Some("goat".to_string()));
+        assert_eq!(word_at_position(text, 0, 9), Some("jump_around".to_string()));
+        assert_eq!(word_at_position(text, 0, 2), None);
+        assert_eq!(word_at_position(text, 5, 0), None);
+    }
+
+    #[tokio::test]
+    async fn test_ast_lsp_py() {
+        let ast_index = ast_index_init("".to_string(), 10).await;
+        let library_path = std::fs::canonicalize("src/ast/alt_testsuite/py_goat_library.py").unwrap().to_string_lossy().to_string();
+        let main_path = std::fs::canonicalize("src/ast/alt_testsuite/py_goat_main.py").unwrap().to_string_lossy().to_string();
+        let main_text = std::fs::read_to_string(&main_path).unwrap();
+        let mut errstats = AstErrorStats::default();
+        doc_add(ast_index.clone(), &library_path, &std::fs::read_to_string(&library_path).unwrap(), &mut errstats).await.unwrap();
+        doc_add(ast_index.clone(), &main_path, &main_text, &mut errstats).await.unwrap();
+        let mut ucx = connect_usages_look_if_full_reset_needed(ast_index.clone()).unwrap();
+        while connect_usages(ast_index.clone(), &mut ucx).unwrap() {}
+
+        // `CosmicGoat(10, 20, 30.5)` inside goat_generator1
+        let defs = definitions_at_position(ast_index.clone(), &main_path, &main_text, 21, 12).await;
+        assert_eq!(defs.len(), 1);
+        assert_eq!(defs[0].name(), "CosmicGoat");
+        assert_eq!(definition_range(&defs[0]).start.line, 10);
+
+        let refs = references_of(ast_index.clone(), &defs, false);
+        assert!(refs.iter().any(|loc| loc.range.start.line == 21));
+
+        let outline = document_symbols(&doc_defs(ast_index.clone(), &main_path));
+        let cosmic_goat = outline.iter().find(|s| s.name == "CosmicGoat").unwrap();
+        assert_eq!(cosmic_goat.kind, SymbolKind::CLASS);
+        let methods = cosmic_goat.children.as_ref().unwrap().iter().map(|c| (c.name.as_str(), c.kind)).collect::<Vec<_>>();
+        assert!(methods.contains(&("say_hi", SymbolKind::METHOD)));
+        assert!(outline.iter().any(|s| s.name == "goat_generator1" && s.kind == SymbolKind::FUNCTION));
+    }
+}
@@ -22,12 +22,20 @@ fn split_line_if_needed(line: &str, tokenizer: Option<Arc<Tokenizer>>, tokens_li
         tokenizer.encode(line, false).map_or_else(
             |_| split_without_tokenizer(line, tokens_limit),
             |tokens| {
-                let ids = tokens.get_ids();
-                if ids.len() <= tokens_limit {
+                let offsets = tokens.get_offsets();
+                if offsets.len() <= tokens_limit {
                     vec![line.to_string()]
                 } else {
-                    ids.chunks(tokens_limit)
-                        .filter_map(|chunk| tokenizer.decode(chunk, true).ok())
+                    // cut the text where each chunk of tokens starts, approximate tokenizers can't decode
+                    let mut cuts = offsets.iter().step_by(tokens_limit).skip(1)
+                        .map(|(start, _)| *start)
+                        .filter(|start| line.is_char_boundary(*start))
+                        .collect::<Vec<_>>();
+                    cuts.insert(0, 0);
+                    cuts.push(line.len());
+                    cuts.windows(2)
+                        .filter(|w| w[0] < w[1])
+                        .map(|w| line[w[0]..w[1]].to_string())
                         .collect()
                 }
             }
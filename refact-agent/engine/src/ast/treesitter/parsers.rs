@@ -2,6 +2,7 @@ use std::fmt::Display;
 use std::path::PathBuf;
 
 use tracing::error;
+use tree_sitter::{Language, Tree};
 
 use crate::ast::treesitter::ast_instance_structs::AstSymbolInstanceArc;
 use crate::ast::treesitter::language_id::LanguageId;
@@ -99,3 +100,39 @@ pub fn get_language_id_by_filename(filename: &PathBuf) -> Option<LanguageId> {
     }
 }
 
+pub fn get_tree_sitter_language(language_id: LanguageId) -> Option<Language> {
+    match language_id {
+        LanguageId::Cpp => Some(tree_sitter_cpp::LANGUAGE.into()),
+        LanguageId::Python => Some(tree_sitter_python::LANGUAGE.into()),
+        LanguageId::Java => Some(tree_sitter_java::LANGUAGE.into()),
+        LanguageId::JavaScript => Some(tree_sitter_javascript::LANGUAGE.into()),
+        LanguageId::Rust => Some(tree_sitter_rust::LANGUAGE.into()),
+        LanguageId::TypeScript => Some(tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into()),
+        LanguageId::TypeScriptReact => Some(tree_sitter_typescript::LANGUAGE_TSX.into()),
+        _ => None,
+    }
+}
+
+/// ERROR and MISSING nodes in the tree
+pub fn count_syntax_errors_in_tree(tree: &Tree) -> usize {
+    if !tree.root_node().has_error() {
+        return 0;
+    }
+    let mut errors = 0;
+    let mut cursor = tree.walk();
+    'walk: loop {
+        let node = cursor.node();
+        if node.is_error() || node.is_missing() {
+            errors += 1;
+        } else if node.has_error() && cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
+    errors
+}
+
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use tokio::sync::Mutex as AMutex;
+
+use crate::at_commands::at_commands::{AtCommand, AtCommandsContext, AtParam};
+use crate::at_commands::execute_at::AtCommandMember;
+use crate::call_validation::{ChatMessage, ContextEnum};
+use crate::integrations::integr_jira::{format_jira_issue, jira_client_from_config};
+
+// issues change while the chat is being typed, a preview older than that is fetched again
+const ISSUE_PREVIEW_CACHE_TTL: Duration = Duration::from_secs(60);
+
+pub struct AtIssue {
+    pub params: Vec<Box<dyn AtParam>>,
+}
+
+impl AtIssue {
+    pub fn new() -> Self {
+        AtIssue {
+            params: vec![],
+        }
+    }
+}
+
+#[async_trait]
+impl AtCommand for AtIssue {
+    fn params(&self) -> &Vec<Box<dyn AtParam>> {
+        &self.params
+    }
+
+    async fn at_execute(
+        &self,
+        ccx: Arc<AMutex<AtCommandsContext>>,
+        cmd: &mut AtCommandMember,
+        args: &mut Vec<AtCommandMember>,
+    ) -> Result<(Vec<ContextEnum>, String), String> {
+        let key = match args.get(0) {
+            Some(x) => x.clone(),
+            None => {
+                cmd.ok = false; cmd.reason = Some("missing issue key".to_string());
+                args.clear();
+                return Err("missing issue key".to_string());
+            }
+        };
+        args.truncate(1);
+
+        let gcx = ccx.lock().await.global_context.clone();
+        let preview_cache = gcx.read().await.at_commands_preview_cache.clone();
+        let text_from_cache = preview_cache.lock().await.get_fresh(&format!("@issue:{}", key.text), ISSUE_PREVIEW_CACHE_TTL);
+
+        let text = match text_from_cache {
+            Some(text) => text,
+            None => {
+                let client = jira_client_from_config(gcx.clone()).await?;
+                let issue = client.get_issue(&key.text).await
+                    .map_err(|e| format!("Failed to execute @issue {}.\nError: {e}", key.text))?;
+                let text = format_jira_issue(&issue, &client.browse_url(&key.text));
+                preview_cache.lock().await.insert(format!("@issue:{}", key.text), text.clone());
+                text
+            }
+        };
+
+        tracing::info!("executed @issue {}", key.text);
+        Ok((vec![ContextEnum::ChatMessage(ChatMessage::new("plain_text".to_string(), text))], format!("[see issue {} above]", key.text)))
+    }
+
+    fn depends_on(&self) -> Vec<String> {
+        vec![]
+    }
+}
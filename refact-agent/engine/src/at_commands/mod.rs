@@ -4,6 +4,7 @@ pub mod at_ast_reference;
 pub mod at_commands;
 pub mod at_file;
 pub mod at_web;
+pub mod at_issue;
 pub mod at_tree;
 pub mod at_search;
 pub mod at_knowledge;
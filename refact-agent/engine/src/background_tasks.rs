@@ -49,6 +49,7 @@ pub async fn start_background_tasks(gcx: Arc<ARwLock<GlobalContext>>, config_dir
         tokio::spawn(crate::memories::memories_migration(gcx.clone(), config_dir.clone())),
         tokio::spawn(crate::git::cleanup::git_shadow_cleanup_background_task(gcx.clone())),
         tokio::spawn(crate::cloud::threads_sub::watch_threads_subscription(gcx.clone())),
+        tokio::spawn(crate::caps::model_discovery::local_models_background_discovery(gcx.clone())),
     ]);
     let ast = gcx.clone().read().await.ast_service.clone();
     if let Some(ast_service) = ast {
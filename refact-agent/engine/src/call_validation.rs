@@ -70,13 +70,17 @@ pub struct CodeCompletionPost {
     pub no_cache: bool,
     #[serde(default)]
     pub use_ast: bool,
-    #[allow(dead_code)]
     #[serde(default)]
     pub use_vecdb: bool,
     #[serde(default)]
     pub rag_tokens_n: usize,
+    // instead of completing at the cursor, predict the next edit elsewhere in the file, from the recent edits
+    #[serde(default)]
+    pub next_edit: bool,
 }
 
+const CODE_COMPLETION_MAX_CANDIDATES: usize = 8;
+
 pub fn code_completion_post_validate(
     code_completion_post: &CodeCompletionPost,
 ) -> axum::response::Result<(), ScratchError> {
@@ -107,6 +111,12 @@ pub fn code_completion_post_validate(
             "Invalid post: char number exceeds chars in line".to_string(),
         ));
     }
+    if code_completion_post.parameters.n.unwrap_or(1) > CODE_COMPLETION_MAX_CANDIDATES {
+        return Err(ScratchError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid post: n exceeds {} candidates", CODE_COMPLETION_MAX_CANDIDATES),
+        ));
+    }
     Ok(())
 }
 
@@ -164,6 +174,17 @@ pub struct ChatUsage {
     pub prompt_tokens: usize,
     pub completion_tokens: usize,
     pub total_tokens: usize, // TODO: remove (can produce self-contradictory data when prompt+completion != total)
+    // prompt caching, prompt_tokens include these
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<usize>,
+    // completion_tokens include these
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_tokens: Option<usize>,
+    // dollars, if the model has pricing, see crate::usage_ledger
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
 }
 
 #[derive(Debug, Serialize, Clone, Default)]
@@ -389,6 +410,7 @@ mod tests {
             use_ast: true,
             use_vecdb: true,
             rag_tokens_n: 0,
+            next_edit: false,
         };
         assert!(code_completion_post_validate(&post).is_ok());
     }
@@ -419,6 +441,7 @@ mod tests {
             use_ast: true,
             use_vecdb: true,
             rag_tokens_n: 0,
+            next_edit: false,
         };
         assert!(code_completion_post_validate(&post).is_ok());
     }
@@ -449,6 +472,7 @@ mod tests {
             use_ast: true,
             use_vecdb: true,
             rag_tokens_n: 0,
+            next_edit: false,
         };
         assert!(code_completion_post_validate(&post).is_err());
     }
@@ -479,6 +503,7 @@ mod tests {
             use_ast: true,
             use_vecdb: true,
             rag_tokens_n: 0,
+            next_edit: false,
         };
         assert!(code_completion_post_validate(&post).is_err());
     }
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use indexmap::IndexMap;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::Deserialize;
 use serde::Serialize;
 use tokio::sync::RwLock as ARwLock;
@@ -11,8 +12,10 @@ use crate::custom_error::MapErrToString;
 use crate::global_context::CommandLine;
 use crate::global_context::GlobalContext;
 use crate::caps::providers::{add_models_to_caps, read_providers_d, resolve_provider_api_key,
-    post_process_provider, CapsProvider};
+    resolve_provider_headers_and_params, post_process_provider, CapsProvider};
 use crate::caps::self_hosted::SelfHostedCaps;
+use crate::caps::model_discovery::discover_and_merge_models_cached;
+use crate::mock_provider::resolve_mock_endpoints;
 
 pub const CAPS_FILENAME: &str = "refact-caps";
 pub const CAPS_FILENAME_FALLBACK: &str = "coding_assistant_caps.json";
@@ -37,6 +40,12 @@ pub struct BaseModelRecord {
     pub api_key: String,
     #[serde(default, skip_serializing)]
     pub tokenizer_api_key: String,
+    #[serde(default, skip_serializing)]
+    pub auth_header_name: String,
+    #[serde(default, skip_serializing)]
+    pub extra_headers: IndexMap<String, String>,
+    #[serde(default, skip_serializing)]
+    pub query_params: IndexMap<String, String>,
 
     #[serde(default, skip_serializing)]
     pub support_metadata: bool,
@@ -44,6 +53,8 @@ pub struct BaseModelRecord {
     pub similar_models: Vec<String>,
     #[serde(default)]
     pub tokenizer: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<ModelPricing>,
 
     #[serde(default = "default_true")]
     pub enabled: bool,
@@ -56,6 +67,47 @@ pub struct BaseModelRecord {
     pub user_configured: bool,
 }
 
+impl BaseModelRecord {
+    /// The api key goes to auth_header_name or `default_auth_header`, as Bearer if it's Authorization,
+    /// extra_headers are added on top
+    pub fn auth_headers(&self, default_auth_header: &str) -> Result<HeaderMap, String> {
+        let mut headers = HeaderMap::new();
+        if !self.api_key.is_empty() {
+            let name = if self.auth_header_name.is_empty() { default_auth_header } else { &self.auth_header_name };
+            let value = if name.eq_ignore_ascii_case("authorization") { format!("Bearer {}", self.api_key) } else { self.api_key.clone() };
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes()).map_err(|e| format!("invalid auth header name {:?}: {}", name, e))?,
+                HeaderValue::from_str(&value).map_err(|e| format!("invalid api key for {}: {}", self.id, e))?,
+            );
+        }
+        for (name, value) in &self.extra_headers {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes()).map_err(|e| format!("invalid header name {:?}: {}", name, e))?,
+                HeaderValue::from_str(value).map_err(|e| format!("invalid value of header {:?}: {}", name, e))?,
+            );
+        }
+        Ok(headers)
+    }
+}
+
+/// Dollars per million tokens
+#[derive(Debug, Serialize, Clone, Deserialize, Default, PartialEq)]
+pub struct ModelPricing {
+    #[serde(default)]
+    pub input: f64,
+    /// Prompt tokens read from the cache, `input` if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_input: Option<f64>,
+    /// Prompt tokens written to the cache, `input` if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_write_input: Option<f64>,
+    #[serde(default)]
+    pub output: f64,
+    /// Reasoning tokens, `output` if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<f64>,
+}
+
 fn default_true() -> bool { true }
 
 pub trait HasBaseModelRecord {
@@ -136,9 +188,12 @@ impl CompletionModelFamily {
 
 pub fn default_completion_scratchpad() -> String { "REPLACE_PASSTHROUGH".to_string() }
 
+/// rag_ratio: part of the prompt for the context from other files (AST and VecDB),
+/// vecdb_ratio: part of that context for VecDB results when AST has something to say as well
 pub fn default_completion_scratchpad_patch() -> serde_json::Value { serde_json::json!({
     "context_format": "chat",
-    "rag_ratio": 0.5
+    "rag_ratio": 0.5,
+    "vecdb_ratio": 0.5
 }) }
 
 impl HasBaseModelRecord for CompletionModelRecord {
@@ -237,6 +292,14 @@ pub struct DefaultModels {
     pub chat_thinking_model: String,
     #[serde(default)]
     pub chat_light_model: String,
+
+    // Full "provider/model" ids to try in order when the model of the mode keeps failing
+    #[serde(default)]
+    pub chat_default_fallback_models: Vec<String>,
+    #[serde(default)]
+    pub chat_thinking_fallback_models: Vec<String>,
+    #[serde(default)]
+    pub chat_light_fallback_models: Vec<String>,
 }
 
 impl DefaultModels {
@@ -265,6 +328,26 @@ impl DefaultModels {
                 None => other.chat_light_model.clone(),
             };
         }
+        if !other.chat_default_fallback_models.is_empty() {
+            self.chat_default_fallback_models = other.chat_default_fallback_models.clone();
+        }
+        if !other.chat_thinking_fallback_models.is_empty() {
+            self.chat_thinking_fallback_models = other.chat_thinking_fallback_models.clone();
+        }
+        if !other.chat_light_fallback_models.is_empty() {
+            self.chat_light_fallback_models = other.chat_light_fallback_models.clone();
+        }
+    }
+
+    /// Fallbacks of the mode that uses `model_id`, models picked by hand fall back like the default one
+    pub fn chat_fallback_models(&self, model_id: &str) -> &Vec<String> {
+        if !self.chat_thinking_model.is_empty() && model_id == self.chat_thinking_model {
+            &self.chat_thinking_fallback_models
+        } else if !self.chat_light_model.is_empty() && model_id == self.chat_light_model {
+            &self.chat_light_fallback_models
+        } else {
+            &self.chat_default_fallback_models
+        }
     }
 }
 
@@ -327,9 +410,9 @@ pub async fn load_caps(
     cmdline: crate::global_context::CommandLine,
     gcx: Arc<ARwLock<GlobalContext>>,
 ) -> Result<Arc<CodeAssistantCaps>, String> {
-    let (config_dir, cmdline_api_key, experimental) = {
+    let (config_dir, cmdline_api_key, experimental, http_client) = {
         let gcx_locked = gcx.read().await;
-        (gcx_locked.config_dir.clone(), gcx_locked.cmdline.api_key.clone(), gcx_locked.cmdline.experimental)
+        (gcx_locked.config_dir.clone(), gcx_locked.cmdline.api_key.clone(), gcx_locked.cmdline.experimental, gcx_locked.http_client.clone())
     };
 
     let (caps_value, caps_url) = load_caps_value_from_url(cmdline, gcx).await?;
@@ -354,9 +437,14 @@ pub async fn load_caps(
     for e in error_log {
         tracing::error!("{e}");
     }
+    discover_and_merge_models_cached(&http_client, &mut providers, experimental).await;
     for provider in &mut providers {
         post_process_provider(provider, false, experimental);
-        provider.api_key = resolve_provider_api_key(&provider, &cmdline_api_key);
+        provider.api_key = resolve_provider_api_key(&provider, &cmdline_api_key).await;
+        resolve_provider_headers_and_params(provider).await;
+        if let Err(e) = resolve_mock_endpoints(provider, &config_dir) {
+            tracing::error!("{}: {}", provider.name, e);
+        }
     }
     add_models_to_caps(&mut caps, providers);
 
@@ -1,4 +1,5 @@
 pub mod caps;
+pub mod model_discovery;
 pub mod providers;
 pub mod self_hosted;
 
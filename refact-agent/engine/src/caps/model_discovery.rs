@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use futures::future::join_all;
+use indexmap::IndexMap;
+use serde_json::{json, Value};
+use tokio::sync::RwLock as ARwLock;
+use tracing::{info, warn};
+use url::Url;
+
+use crate::caps::{BaseModelRecord, ChatModelRecord, HasBaseModelRecord};
+use crate::caps::providers::{
+    find_model_match, get_known_models, get_provider_model_default_settings_ui, read_providers_d,
+    resolve_provider_api_key, resolve_provider_headers_and_params, CapsProvider,
+};
+use crate::global_context::GlobalContext;
+
+// Local servers (Ollama, LM Studio, vLLM, ...) are asked which models they run when caps load,
+// `models_discovery` in the provider yaml says how: "ollama" or "openai" (GET /v1/models).
+// What they say is cached, local_models_background_discovery() drops it when the list of models changes.
+// A server that didn't answer is asked again after DISCOVERY_RETRY_AFTER, so caps don't wait for it on every load.
+
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
+const CAPS_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+const DISCOVERY_RETRY_AFTER: Duration = Duration::from_secs(30);
+const LOCAL_MODELS_BACKGROUND_RELOAD: u64 = 60;  // seconds
+
+struct CachedDiscovery {
+    models: Vec<DiscoveredModel>,
+    failed_at: Option<Instant>,
+}
+
+impl CachedDiscovery {
+    fn is_fresh(&self) -> bool {
+        self.failed_at.map_or(true, |failed_at| failed_at.elapsed() < DISCOVERY_RETRY_AFTER)
+    }
+
+    fn sorted_names(&self) -> Vec<String> {
+        let mut names = self.models.iter().map(|x| x.name.clone()).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
+
+static DISCOVERY_CACHE: OnceLock<StdMutex<HashMap<String, CachedDiscovery>>> = OnceLock::new();
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiscoveredModel {
+    pub name: String,
+    pub n_ctx: Option<usize>,
+    pub supports_tools: Option<bool>,
+    pub supports_multimodality: Option<bool>,
+    pub is_embedding: bool,
+}
+
+async fn discovery_headers(provider: &CapsProvider) -> Result<reqwest::header::HeaderMap, String> {
+    let mut provider = provider.clone();
+    provider.api_key = resolve_provider_api_key(&provider, "").await;
+    resolve_provider_headers_and_params(&mut provider).await;
+    BaseModelRecord {
+        id: provider.name.clone(),
+        api_key: provider.api_key,
+        auth_header_name: provider.auth_header_name,
+        extra_headers: provider.extra_headers,
+        ..Default::default()
+    }.auth_headers("Authorization")
+}
+
+fn provider_endpoint(provider: &CapsProvider) -> Result<&str, String> {
+    [&provider.chat_endpoint, &provider.completion_endpoint].into_iter()
+        .find(|x| !x.is_empty())
+        .map(|x| x.as_str())
+        .ok_or(format!("provider {} has no endpoints to discover models from", provider.name))
+}
+
+/// Ollama serves its own api next to the openai-compatible one, on the same host and port
+fn ollama_api_url(provider: &CapsProvider, path: &str) -> Result<String, String> {
+    let endpoint = provider_endpoint(provider)?;
+    let url = Url::parse(endpoint).map_err(|e| format!("cannot parse {}: {}", endpoint, e))?;
+    url.join(path).map(|x| x.to_string()).map_err(|e| format!("cannot join {} to {}: {}", path, endpoint, e))
+}
+
+fn openai_models_url(provider: &CapsProvider) -> Result<String, String> {
+    let endpoint = provider_endpoint(provider)?.trim_end_matches('/');
+    for suffix in ["/chat/completions", "/completions"] {
+        if let Some(base) = endpoint.strip_suffix(suffix) {
+            return Ok(format!("{}/models", base));
+        }
+    }
+    Err(format!("cannot guess the models url from {}", endpoint))
+}
+
+async fn get_json(http_client: &reqwest::Client, provider: &CapsProvider, url: &str, body: Option<Value>) -> Result<Value, String> {
+    let request = match body {
+        Some(body) => http_client.post(url).json(&body),
+        None => http_client.get(url),
+    };
+    let response = request
+        .headers(discovery_headers(provider).await?)
+        .query(&provider.query_params)
+        .timeout(DISCOVERY_TIMEOUT)
+        .send().await
+        .map_err(|e| format!("{}: {}", url, e))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("{} status={} text {}", url, status.as_u16(), response.text().await.unwrap_or_default()));
+    }
+    response.json::<Value>().await.map_err(|e| format!("{}: {}", url, e))
+}
+
+/// Only names, cheap enough to poll in background
+pub async fn fetch_model_names(http_client: &reqwest::Client, provider: &CapsProvider) -> Result<Vec<String>, String> {
+    let (url, list_key, name_key) = match provider.models_discovery.as_str() {
+        "ollama" => (ollama_api_url(provider, "/api/tags")?, "models", "name"),
+        "openai" => (openai_models_url(provider)?, "data", "id"),
+        other => return Err(format!("unknown models_discovery {:?} for provider {}", other, provider.name)),
+    };
+    let response = get_json(http_client, provider, &url, None).await?;
+    let models = response.get(list_key).and_then(|x| x.as_array())
+        .ok_or(format!("{}: no {:?} in response", url, list_key))?;
+    Ok(models.iter().filter_map(|x| x.get(name_key).and_then(|x| x.as_str()).map(|x| x.to_string())).collect())
+}
+
+/// n_ctx set for the model in its Modelfile wins over the one it was trained with
+fn ollama_show_to_discovered(name: &str, show: &Value) -> DiscoveredModel {
+    let num_ctx = show.get("parameters").and_then(|x| x.as_str()).unwrap_or_default().lines()
+        .filter_map(|line| line.trim().strip_prefix("num_ctx"))
+        .find_map(|x| x.trim().parse::<usize>().ok());
+    let context_length = show.get("model_info").and_then(|x| x.as_object())
+        .and_then(|info| info.iter().find(|(k, _)| k.ends_with(".context_length")))
+        .and_then(|(_, v)| v.as_u64())
+        .map(|x| x as usize);
+    let capabilities = show.get("capabilities").and_then(|x| x.as_array()).map(|caps| {
+        caps.iter().filter_map(|x| x.as_str()).collect::<Vec<_>>()
+    });
+    DiscoveredModel {
+        name: name.to_string(),
+        n_ctx: num_ctx.or(context_length),
+        supports_tools: capabilities.as_ref().map(|caps| caps.contains(&"tools")),
+        supports_multimodality: capabilities.as_ref().map(|caps| caps.contains(&"vision")),
+        is_embedding: capabilities.as_ref().map_or(false, |caps| caps.contains(&"embedding") && !caps.contains(&"completion")),
+    }
+}
+
+/// `/v1/models` doesn't say what a model is for, embedding models are known by name
+fn is_embedding_model_name(name: &str) -> bool {
+    let known_models = get_known_models();
+    name.to_lowercase().contains("embed")
+        || find_model_match(&name.to_string(), &IndexMap::new(), &known_models.embedding_models, true).is_some()
+}
+
+pub async fn discover_models(http_client: &reqwest::Client, provider: &CapsProvider) -> Result<Vec<DiscoveredModel>, String> {
+    let names = fetch_model_names(http_client, provider).await?;
+    if provider.models_discovery != "ollama" {
+        return Ok(names.into_iter().map(|name| DiscoveredModel {
+            is_embedding: is_embedding_model_name(&name),
+            name,
+            ..Default::default()
+        }).collect());
+    }
+    let show_url = ollama_api_url(provider, "/api/show")?;
+    let models = join_all(names.into_iter().map(|name| {
+        let show_url = &show_url;
+        async move {
+            match get_json(http_client, provider, show_url, Some(json!({"model": name}))).await {
+                Ok(show) => ollama_show_to_discovered(&name, &show),
+                Err(e) => {
+                    warn!("cannot get details of {} from {}: {}", name, provider.name, e);
+                    DiscoveredModel { name, ..Default::default() }
+                }
+            }
+        }
+    })).await;
+    Ok(models)
+}
+
+/// Models configured in the provider yaml stay as they are. Chat models are taken from known_models
+/// if there, otherwise from model_default_settings_ui, completion models only if known (they need
+/// a FIM scratchpad).
+pub fn merge_discovered_models(provider: &mut CapsProvider, discovered: &[DiscoveredModel], experimental: bool) {
+    let known_models = get_known_models();
+    let default_chat_model = get_provider_model_default_settings_ui().get(&provider.name)
+        .or_else(|| get_provider_model_default_settings_ui().get("custom"))
+        .map(|x| x.chat.clone())
+        .unwrap_or_default();
+
+    for model in discovered.iter().filter(|x| !x.is_embedding) {
+        if provider.chat_models.contains_key(&model.name) || provider.completion_models.contains_key(&model.name) {
+            continue;
+        }
+        if provider.supports_completion {
+            if let Some(mut rec) = find_model_match(&model.name, &IndexMap::new(), &known_models.completion_models, experimental) {
+                apply_discovered_n_ctx(rec.base_mut(), model, &provider.name);
+                provider.completion_models.insert(model.name.clone(), rec);
+            }
+        }
+
+        let mut rec = find_model_match(&model.name, &IndexMap::new(), &known_models.chat_models, experimental)
+            .unwrap_or_else(|| {
+                // the default n_ctx is a guess, what the server says is better
+                let mut rec = default_chat_model.clone();
+                rec.base.n_ctx = if model.n_ctx.is_some() { 0 } else { rec.base.n_ctx };
+                rec
+            });
+        apply_discovered_n_ctx(&mut rec.base, model, &provider.name);
+        apply_discovered_capabilities(&mut rec, model);
+        provider.chat_models.insert(model.name.clone(), rec);
+
+        if !provider.running_models.contains(&model.name) {
+            provider.running_models.push(model.name.clone());
+        }
+    }
+}
+
+fn apply_discovered_n_ctx(base: &mut BaseModelRecord, model: &DiscoveredModel, provider_name: &str) {
+    base.name = model.name.clone();
+    base.id = format!("{}/{}", provider_name, model.name);
+    base.n_ctx = match (base.n_ctx, model.n_ctx) {
+        (0, Some(n_ctx)) => n_ctx,
+        (known, Some(n_ctx)) => known.min(n_ctx),
+        (known, None) => known,
+    };
+}
+
+fn apply_discovered_capabilities(rec: &mut ChatModelRecord, model: &DiscoveredModel) {
+    if let Some(supports_tools) = model.supports_tools {
+        rec.supports_tools = supports_tools;
+    }
+    if let Some(supports_multimodality) = model.supports_multimodality {
+        rec.supports_multimodality = supports_multimodality;
+    }
+}
+
+/// A server that is not running is not an error, it just has no models
+pub async fn discover_and_merge_models(http_client: &reqwest::Client, provider: &mut CapsProvider, experimental: bool) {
+    if provider.models_discovery.is_empty() {
+        return;
+    }
+    match discover_models(http_client, provider).await {
+        Ok(discovered) => {
+            info!("discovered {} models at {}", discovered.len(), provider.name);
+            merge_discovered_models(provider, &discovered, experimental);
+        },
+        Err(e) => warn!("models discovery for {} failed: {}", provider.name, e),
+    }
+}
+
+fn discovery_cache_key(provider: &CapsProvider) -> String {
+    format!("{} {}", provider.name, provider_endpoint(provider).unwrap_or_default())
+}
+
+/// All the providers at once, each under a short timeout. A server that is down counts as having no models
+/// for DISCOVERY_RETRY_AFTER, or until local_models_background_discovery() sees it up.
+pub async fn discover_and_merge_models_cached(http_client: &reqwest::Client, providers: &mut [CapsProvider], experimental: bool) {
+    let cache = DISCOVERY_CACHE.get_or_init(|| StdMutex::new(HashMap::new()));
+    let not_cached = providers.iter()
+        .filter(|p| !p.models_discovery.is_empty())
+        .filter(|p| !cache.lock().unwrap().get(&discovery_cache_key(p)).map_or(false, |x| x.is_fresh()))
+        .collect::<Vec<_>>();
+    let discovered = join_all(not_cached.into_iter().map(|provider| async move {
+        let cached = match tokio::time::timeout(CAPS_DISCOVERY_TIMEOUT, discover_models(http_client, provider)).await {
+            Ok(Ok(models)) => {
+                info!("discovered {} models at {}", models.len(), provider.name);
+                CachedDiscovery { models, failed_at: None }
+            },
+            Ok(Err(e)) => {
+                warn!("models discovery for {} failed: {}", provider.name, e);
+                CachedDiscovery { models: vec![], failed_at: Some(Instant::now()) }
+            },
+            Err(_) => {
+                warn!("models discovery for {} timed out", provider.name);
+                CachedDiscovery { models: vec![], failed_at: Some(Instant::now()) }
+            },
+        };
+        (discovery_cache_key(provider), cached)
+    })).await;
+    cache.lock().unwrap().extend(discovered);
+
+    for provider in providers.iter_mut().filter(|p| !p.models_discovery.is_empty()) {
+        let discovered = cache.lock().unwrap().get(&discovery_cache_key(provider)).map(|x| x.models.clone()).unwrap_or_default();
+        merge_discovered_models(provider, &discovered, experimental);
+    }
+}
+
+/// Asks the servers for their models, drops what's cached for those that now say something else,
+/// including the ones that were down when caps loaded. True if anything was dropped.
+async fn drop_outdated_discoveries(http_client: &reqwest::Client, providers: &[CapsProvider]) -> bool {
+    let Some(cache) = DISCOVERY_CACHE.get() else { return false };
+    let mut dropped = false;
+    for provider in providers.iter().filter(|p| p.enabled && !p.models_discovery.is_empty()) {
+        let key = discovery_cache_key(provider);
+        let Some(cached_names) = cache.lock().unwrap().get(&key).map(|x| x.sorted_names()) else { continue };
+        let mut names = fetch_model_names(http_client, provider).await.unwrap_or_default();
+        names.sort();
+        if names != cached_names {
+            info!("models at {} changed", provider.name);
+            cache.lock().unwrap().remove(&key);
+            dropped = true;
+        }
+    }
+    dropped
+}
+
+/// Models get pulled and removed while we run, caps are dropped when the list changes and the next
+/// request loads them again, the same way CAPS_BACKGROUND_RELOAD does it
+pub async fn local_models_background_discovery(gcx: Arc<ARwLock<GlobalContext>>) {
+    loop {
+        let (config_dir, experimental, http_client) = {
+            let gcx_locked = gcx.read().await;
+            (gcx_locked.config_dir.clone(), gcx_locked.cmdline.experimental, gcx_locked.http_client.clone())
+        };
+        let (providers, _) = read_providers_d(Vec::new(), &config_dir, experimental).await;
+        if drop_outdated_discoveries(&http_client, &providers).await {
+            info!("local models changed, caps will reload");
+            let mut gcx_locked = gcx.write().await;
+            gcx_locked.caps = None;
+            gcx_locked.caps_last_attempted_ts = 0;
+        }
+        tokio::time::sleep(Duration::from_secs(LOCAL_MODELS_BACKGROUND_RELOAD)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_provider::start_test_server;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use axum::{Extension, Json, Router};
+    use axum::http::StatusCode;
+    use axum::routing::{get, post};
+    use crate::caps::providers::get_provider_templates;
+
+    async fn start_mock_server() -> String {
+        let app = Router::new()
+            .route("/api/tags", get(|| async { Json(json!({"models": [
+                {"name": "qwen-qwq-32b"}, {"name": "my-llama:8b"}, {"name": "nomic-embed-text"},
+            ]})) }))
+            .route("/api/show", post(|Json(body): Json<Value>| async move {
+                Json(match body["model"].as_str().unwrap() {
+                    "qwen-qwq-32b" => json!({"parameters": "num_ctx 16384\nstop \"<|im_end|>\"", "capabilities": ["completion", "tools"]}),
+                    "my-llama:8b" => json!({"model_info": {"llama.context_length": 131072}, "capabilities": ["completion", "vision"]}),
+                    _ => json!({"model_info": {"bert.context_length": 2048}, "capabilities": ["embedding"]}),
+                })
+            }))
+            .route("/v1/models", get(|| async { Json(json!({"object": "list", "data": [{"id": "qwen-qwq-32b"}, {"id": "text-embedding-nomic-embed-text-v1.5"}]})) }));
+        start_test_server(app)
+    }
+
+    #[tokio::test]
+    async fn test_ollama_discovery() {
+        let base = start_mock_server().await;
+        let mut provider = get_provider_templates()["ollama"].clone();
+        provider.chat_endpoint = format!("{}/v1/chat/completions", base);
+        provider.completion_endpoint = format!("{}/v1/completions", base);
+        provider.chat_models.insert("my-llama:8b".to_string(), ChatModelRecord { supports_tools: true, ..Default::default() });
+
+        let http_client = reqwest::Client::new();
+        let discovered = discover_models(&http_client, &provider).await.unwrap();
+        assert_eq!(discovered.len(), 3);
+        assert_eq!(discovered[1].n_ctx, Some(131072));
+        assert!(discovered[2].is_embedding);
+
+        provider.chat_models.clear();
+        discover_and_merge_models(&http_client, &mut provider, false).await;
+        let qwq = &provider.chat_models["qwen-qwq-32b"];
+        assert_eq!((qwq.base.n_ctx, qwq.supports_tools), (16384, true));
+        assert_eq!(qwq.base.id, "ollama/qwen-qwq-32b");
+        let llama = &provider.chat_models["my-llama:8b"];
+        assert_eq!((llama.base.n_ctx, llama.supports_tools, llama.supports_multimodality), (131072, false, true));
+        assert_eq!(llama.scratchpad, "PASSTHROUGH");
+        assert!(!provider.chat_models.contains_key("nomic-embed-text"));
+    }
+
+    #[tokio::test]
+    async fn test_openai_discovery() {
+        let base = start_mock_server().await;
+        let mut provider = get_provider_templates()["lmstudio"].clone();
+        provider.chat_endpoint = format!("{}/v1/chat/completions", base);
+        provider.chat_models.insert("qwen-qwq-32b".to_string(), ChatModelRecord { base: BaseModelRecord { n_ctx: 4096, ..Default::default() }, ..Default::default() });
+
+        let discovered = discover_models(&reqwest::Client::new(), &provider).await.unwrap();
+        assert_eq!(discovered.iter().map(|x| x.is_embedding).collect::<Vec<_>>(), vec![false, true]);
+        discover_and_merge_models(&reqwest::Client::new(), &mut provider, false).await;
+        assert_eq!(provider.chat_models.len(), 1);
+        assert_eq!(provider.chat_models["qwen-qwq-32b"].base.n_ctx, 4096);  // configured by user, untouched
+
+        provider.chat_endpoint = "http://127.0.0.1:1/v1/chat/completions".to_string();
+        assert!(fetch_model_names(&reqwest::Client::new(), &provider).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_discovery_cached() {
+        let base = start_mock_server().await;
+        let mut up = get_provider_templates()["ollama"].clone();
+        up.chat_endpoint = format!("{}/v1/chat/completions", base);
+        up.completion_endpoint = String::new();
+        let mut down = get_provider_templates()["lmstudio"].clone();
+        down.chat_endpoint = "http://127.0.0.1:1/v1/chat/completions".to_string();
+
+        let http_client = reqwest::Client::new();
+        let mut providers = vec![up.clone(), down.clone()];
+        discover_and_merge_models_cached(&http_client, &mut providers, false).await;
+        assert!(providers[0].chat_models.contains_key("my-llama:8b"));
+        assert!(DISCOVERY_CACHE.get().unwrap().lock().unwrap().get(&discovery_cache_key(&down)).unwrap().models.is_empty());
+
+        // the same models from the cache, without asking the server
+        let mut moved = up.clone();
+        moved.chat_endpoint = "http://127.0.0.1:1/v1/chat/completions".to_string();
+        let models = DISCOVERY_CACHE.get().unwrap().lock().unwrap().get(&discovery_cache_key(&up)).unwrap().models.clone();
+        DISCOVERY_CACHE.get().unwrap().lock().unwrap().insert(discovery_cache_key(&moved), CachedDiscovery { models, failed_at: None });
+        let mut providers = vec![moved];
+        discover_and_merge_models_cached(&http_client, &mut providers, false).await;
+        assert!(providers[0].chat_models.contains_key("my-llama:8b"));
+    }
+
+    #[tokio::test]
+    async fn test_discovery_down_at_caps_load_up_later() {
+        let is_up = Arc::new(AtomicBool::new(false));
+        let app = Router::new()
+            .route("/api/tags", get(|Extension(is_up): Extension<Arc<AtomicBool>>| async move {
+                match is_up.load(Ordering::SeqCst) {
+                    true => (StatusCode::OK, Json(json!({"models": [{"name": "my-llama:8b"}]}))),
+                    false => (StatusCode::SERVICE_UNAVAILABLE, Json(json!({"error": "starting"}))),
+                }
+            }))
+            .route("/api/show", post(|| async { Json(json!({"capabilities": ["completion"]})) }))
+            .layer(Extension(is_up.clone()));
+        let mut provider = get_provider_templates()["ollama"].clone();
+        provider.name = "ollama_starting".to_string();
+        provider.chat_endpoint = format!("{}/v1/chat/completions", start_test_server(app));
+        provider.completion_endpoint = String::new();
+        let http_client = reqwest::Client::new();
+
+        let mut providers = vec![provider.clone()];
+        discover_and_merge_models_cached(&http_client, &mut providers, false).await;
+        assert!(!providers[0].chat_models.contains_key("my-llama:8b"));
+        assert!(!drop_outdated_discoveries(&http_client, &[provider.clone()]).await);  // still down, nothing changed
+
+        is_up.store(true, Ordering::SeqCst);
+        assert!(drop_outdated_discoveries(&http_client, &[provider.clone()]).await);
+        let mut providers = vec![provider.clone()];
+        discover_and_merge_models_cached(&http_client, &mut providers, false).await;
+        assert!(providers[0].chat_models.contains_key("my-llama:8b"));
+        assert!(!drop_outdated_discoveries(&http_client, &[provider]).await);
+    }
+}
@@ -2,18 +2,20 @@ use std::path::{Path, PathBuf};
 use std::sync::{Arc, OnceLock};
 
 use indexmap::IndexMap;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock as ARwLock;
 use structopt::StructOpt;
 
 use crate::caps::{
     BaseModelRecord, ChatModelRecord, CodeAssistantCaps, CompletionModelRecord, DefaultModels,
-    EmbeddingModelRecord, HasBaseModelRecord, default_embedding_batch, default_rejection_threshold,
+    EmbeddingModelRecord, HasBaseModelRecord, ModelPricing, default_embedding_batch, default_rejection_threshold,
     load_caps_value_from_url, resolve_relative_urls, strip_model_from_finetune, normalize_string
 };
 use crate::custom_error::{MapErrToString, YamlError};
 use crate::global_context::{CommandLine, GlobalContext};
 use crate::caps::self_hosted::SelfHostedCaps;
+use crate::secrets::{is_secret_reference, resolve_secret, untag_secrets_in_yaml};
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct CapsProvider {
@@ -41,9 +43,21 @@ pub struct CapsProvider {
     #[serde(default)]
     pub tokenizer_api_key: String,
 
+    /// Header that carries api_key, "Authorization" (with Bearer) if empty, e.g. "api-key" for Azure
+    #[serde(default)]
+    pub auth_header_name: String,
+    #[serde(default)]
+    pub extra_headers: IndexMap<String, String>,
+    #[serde(default)]
+    pub query_params: IndexMap<String, String>,
+
     #[serde(default)]
     pub code_completion_n_ctx: usize,
 
+    /// Ask the server which models it runs: "ollama" (/api/tags) or "openai" (/v1/models)
+    #[serde(default)]
+    pub models_discovery: String,
+
     #[serde(default)]
     pub support_metadata: bool,
 
@@ -71,8 +85,15 @@ impl CapsProvider {
         set_field_if_exists::<String>(&mut self.completion_endpoint, "completion_endpoint", &value)?;
         set_field_if_exists::<String>(&mut self.chat_endpoint, "chat_endpoint", &value)?;
         set_field_if_exists::<String>(&mut self.embedding_endpoint, "embedding_endpoint", &value)?;
+        if value.get("chat_endpoint").is_some() && value.get("endpoint_style").is_none() {
+            self.endpoint_style = endpoint_style_for_chat_endpoint(&self.chat_endpoint, &self.endpoint_style);
+        }
         set_field_if_exists::<String>(&mut self.api_key, "api_key", &value)?;
         set_field_if_exists::<String>(&mut self.tokenizer_api_key, "tokenizer_api_key", &value)?;
+        set_field_if_exists::<String>(&mut self.auth_header_name, "auth_header_name", &value)?;
+        set_field_if_exists::<IndexMap<String, String>>(&mut self.extra_headers, "extra_headers", &value)?;
+        set_field_if_exists::<IndexMap<String, String>>(&mut self.query_params, "query_params", &value)?;
+        set_field_if_exists::<String>(&mut self.models_discovery, "models_discovery", &value)?;
         set_field_if_exists::<EmbeddingModelRecord>(&mut self.embedding_model, "embedding_model", &value)?;
         if value.get("embedding_model").is_some() {
             self.embedding_model.base.removable = true;
@@ -138,6 +159,18 @@ fn extend_model_collection<T: for<'de> serde::Deserialize<'de> + HasBaseModelRec
 
 fn default_endpoint_style() -> String { "openai".to_string() }
 
+/// Configs written before the anthropic style set only `chat_endpoint`, an OpenAI-compatible URL or a proxy,
+/// Messages API bodies would fail there, so only the native `/v1/messages` keeps the anthropic style
+fn endpoint_style_for_chat_endpoint(chat_endpoint: &str, template_style: &str) -> String {
+    if chat_endpoint.trim_end_matches('/').ends_with("/v1/messages") {
+        "anthropic".to_string()
+    } else if template_style == "anthropic" {
+        default_endpoint_style()
+    } else {
+        template_style.to_string()
+    }
+}
+
 fn default_true() -> bool { true }
 
 impl<'de> serde::Deserialize<'de> for EmbeddingModelRecord {
@@ -196,11 +229,13 @@ pub struct ModelDefaultSettingsUI {
 
 const PROVIDER_TEMPLATES: &[(&str, &str)] = &[
     ("anthropic", include_str!("../yaml_configs/default_providers/anthropic.yaml")),
+    ("azure_openai", include_str!("../yaml_configs/default_providers/azure_openai.yaml")),
     ("custom", include_str!("../yaml_configs/default_providers/custom.yaml")),
     ("deepseek", include_str!("../yaml_configs/default_providers/deepseek.yaml")),
     ("google_gemini", include_str!("../yaml_configs/default_providers/google_gemini.yaml")),
     ("groq", include_str!("../yaml_configs/default_providers/groq.yaml")),
     ("lmstudio", include_str!("../yaml_configs/default_providers/lmstudio.yaml")),
+    ("mock", include_str!("../yaml_configs/default_providers/mock.yaml")),
     ("ollama", include_str!("../yaml_configs/default_providers/ollama.yaml")),
     ("openai", include_str!("../yaml_configs/default_providers/openai.yaml")),
     ("openrouter", include_str!("../yaml_configs/default_providers/openrouter.yaml")),
@@ -343,7 +378,10 @@ pub async fn read_providers_d(
                 }
             };
 
-            let mut provider: CapsProvider = match serde_yaml::from_str(&content) {
+            let mut provider: CapsProvider = match serde_yaml::from_str(&content).map(|mut value| {
+                untag_secrets_in_yaml(&mut value);
+                value
+            }).and_then(serde_yaml::from_value) {
                 Ok(provider) => provider,
                 Err(e) => {
                     error_log.push(YamlError {
@@ -413,12 +451,18 @@ pub fn add_models_to_caps(caps: &mut CodeAssistantCaps, providers: Vec<CapsProvi
         base_model_rec.endpoint = endpoint.replace("$MODEL", model_name);
         base_model_rec.support_metadata = provider.support_metadata;
         base_model_rec.endpoint_style = provider.endpoint_style.clone();
+        base_model_rec.auth_header_name = provider.auth_header_name.clone();
+        base_model_rec.extra_headers = provider.extra_headers.clone();
+        base_model_rec.query_params = provider.query_params.clone();
     }
 
     for mut provider in providers {
 
         let completion_models = std::mem::take(&mut provider.completion_models);
         for (model_name, mut model_rec) in completion_models {
+            if model_rec.base.pricing.is_none() {
+                model_rec.base.pricing = known_model_pricing(&model_name);
+            }
             if model_rec.base.endpoint.is_empty() {
                 add_provider_details_to_model(
                     &mut model_rec.base, &provider, &model_name, &provider.completion_endpoint
@@ -435,6 +479,9 @@ pub fn add_models_to_caps(caps: &mut CodeAssistantCaps, providers: Vec<CapsProvi
 
         let chat_models = std::mem::take(&mut provider.chat_models);
         for (model_name, mut model_rec) in chat_models {
+            if model_rec.base.pricing.is_none() {
+                model_rec.base.pricing = known_model_pricing(&model_name);
+            }
             if model_rec.base.endpoint.is_empty() {
                 add_provider_details_to_model(
                     &mut model_rec.base, &provider, &model_name, &provider.chat_endpoint
@@ -446,6 +493,9 @@ pub fn add_models_to_caps(caps: &mut CodeAssistantCaps, providers: Vec<CapsProvi
 
         if provider.embedding_model.is_configured() && provider.embedding_model.base.enabled {
             let mut embedding_model = std::mem::take(&mut provider.embedding_model);
+            if embedding_model.base.pricing.is_none() {
+                embedding_model.base.pricing = known_model_pricing(&embedding_model.base.name);
+            }
 
             if embedding_model.base.endpoint.is_empty() {
                 let model_name = embedding_model.base.name.clone();
@@ -504,6 +554,8 @@ pub struct KnownModels {
     pub completion_models: IndexMap<String, CompletionModelRecord>,
     pub chat_models: IndexMap<String, ChatModelRecord>,
     pub embedding_models: IndexMap<String, EmbeddingModelRecord>,
+    #[serde(default)]
+    pub pricing: IndexMap<String, ModelPricing>,
 }
 const UNPARSED_KNOWN_MODELS: &'static str = include_str!("../known_models.json");
 static KNOWN_MODELS: OnceLock<KnownModels> = OnceLock::new();
@@ -517,6 +569,19 @@ pub fn get_known_models() -> &'static KnownModels {
     })
 }
 
+/// Pricing from known_models.json, "anthropic/claude-3-7-sonnet-20250219" finds "claude-3-7-sonnet"
+pub fn known_model_pricing(model_name: &str) -> Option<ModelPricing> {
+    static DATE_SUFFIX_RE: OnceLock<Regex> = OnceLock::new();
+    let date_suffix_re = DATE_SUFFIX_RE.get_or_init(|| Regex::new(r"-(\d{4}-\d{2}-\d{2}|\d{8}|latest)$").unwrap());
+    let pricing = &get_known_models().pricing;
+    let name = model_name.rsplit('/').next().unwrap_or(model_name);
+    let undated = date_suffix_re.replace(name, "");
+    pricing.get(model_name)
+        .or_else(|| pricing.get(name))
+        .or_else(|| pricing.get(undated.as_ref()))
+        .cloned()
+}
+
 fn populate_model_records(provider: &mut CapsProvider, experimental: bool) {
     let known_models = get_known_models();
 
@@ -553,7 +618,7 @@ fn populate_model_records(provider: &mut CapsProvider, experimental: bool) {
     }
 }
 
-fn find_model_match<T: Clone + HasBaseModelRecord>(
+pub fn find_model_match<T: Clone + HasBaseModelRecord>(
     model_name: &String,
     provider_models: &IndexMap<String, T>,
     known_models: &IndexMap<String, T>,
@@ -596,15 +661,16 @@ fn find_model_match<T: Clone + HasBaseModelRecord>(
     None
 }
 
-pub fn resolve_api_key(provider: &CapsProvider, key: &str, fallback: &str, key_name: &str) -> String {
+/// The key is a literal, `$ENV_VAR`, `!cmd ...`, `!file ...` or `!keyring ...`, see crate::secrets
+pub async fn resolve_api_key(provider: &CapsProvider, key: &str, fallback: &str, key_name: &str) -> String {
     match key {
         k if k.is_empty() => fallback.to_string(),
-        k if k.starts_with("$") => {
-            match std::env::var(&k[1..]) {
-                Ok(env_val) => env_val,
+        k if is_secret_reference(k) => {
+            match resolve_secret(k).await {
+                Ok(secret) => secret,
                 Err(e) => {
                     tracing::error!(
-                        "tried to read {} from env var {} for provider {}, but failed: {}",
+                        "tried to read {} from {} for provider {}, but failed: {}",
                         key_name, k, provider.name, e
                     );
                     fallback.to_string()
@@ -615,12 +681,26 @@ pub fn resolve_api_key(provider: &CapsProvider, key: &str, fallback: &str, key_n
     }
 }
 
-pub fn resolve_provider_api_key(provider: &CapsProvider, cmdline_api_key: &str) -> String {
-    resolve_api_key(provider, &provider.api_key, &cmdline_api_key, "API key")
+pub async fn resolve_provider_api_key(provider: &CapsProvider, cmdline_api_key: &str) -> String {
+    resolve_api_key(provider, &provider.api_key, &cmdline_api_key, "API key").await
+}
+
+pub async fn resolve_tokenizer_api_key(provider: &CapsProvider) -> String {
+    resolve_api_key(provider, &provider.tokenizer_api_key, "", "tokenizer API key").await
 }
 
-pub fn resolve_tokenizer_api_key(provider: &CapsProvider) -> String {
-    resolve_api_key(provider, &provider.tokenizer_api_key, "", "tokenizer API key")
+/// Values of extra_headers and query_params can also come from env vars, like api_key
+pub async fn resolve_provider_headers_and_params(provider: &mut CapsProvider) {
+    let mut extra_headers = IndexMap::new();
+    for (k, v) in provider.extra_headers.iter() {
+        extra_headers.insert(k.clone(), resolve_api_key(provider, v, "", &format!("header {k}")).await);
+    }
+    let mut query_params = IndexMap::new();
+    for (k, v) in provider.query_params.iter() {
+        query_params.insert(k.clone(), resolve_api_key(provider, v, "", &format!("query param {k}")).await);
+    }
+    provider.extra_headers = extra_headers;
+    provider.query_params = query_params;
 }
 
 pub async fn get_provider_from_template_and_config_file(
@@ -632,8 +712,10 @@ pub async fn get_provider_from_template_and_config_file(
     let provider_path = config_dir.join("providers.d").join(format!("{name}.yaml"));
     let config_file_value = match tokio::fs::read_to_string(&provider_path).await {
         Ok(content) => {
-            serde_yaml::from_str::<serde_yaml::Value>(&content)
-                .map_err_with_prefix(format!("Error parsing file {}:", provider_path.display()))?
+            let mut value = serde_yaml::from_str::<serde_yaml::Value>(&content)
+                .map_err_with_prefix(format!("Error parsing file {}:", provider_path.display()))?;
+            untag_secrets_in_yaml(&mut value);
+            value
         },
         Err(e) if e.kind() == std::io::ErrorKind::NotFound && !config_file_must_exist => {
             serde_yaml::Value::Mapping(serde_yaml::Mapping::new())
@@ -661,16 +743,18 @@ pub async fn get_provider_from_server(gcx: Arc<ARwLock<GlobalContext>>) -> Resul
     if let Ok(self_hosted_caps) = serde_json::from_value::<SelfHostedCaps>(caps_value.clone()) {
         let mut provider = self_hosted_caps.into_provider(&caps_url, &cmdline_api_key)?;
         post_process_provider(&mut provider, true, cmdline_experimental);
-        provider.api_key = resolve_provider_api_key(&provider, &cmdline_api_key);
-        provider.tokenizer_api_key = resolve_tokenizer_api_key(&provider);
+        provider.api_key = resolve_provider_api_key(&provider, &cmdline_api_key).await;
+        provider.tokenizer_api_key = resolve_tokenizer_api_key(&provider).await;
+        resolve_provider_headers_and_params(&mut provider).await;
         Ok(provider)
     } else {
         let mut provider = serde_json::from_value::<CapsProvider>(caps_value).map_err_to_string()?;
 
         resolve_relative_urls(&mut provider, &caps_url)?;
         post_process_provider(&mut provider, true, cmdline_experimental);
-        provider.api_key = resolve_provider_api_key(&provider, &cmdline_api_key);
-        provider.tokenizer_api_key = resolve_tokenizer_api_key(&provider);
+        provider.api_key = resolve_provider_api_key(&provider, &cmdline_api_key).await;
+        provider.tokenizer_api_key = resolve_tokenizer_api_key(&provider).await;
+        resolve_provider_headers_and_params(&mut provider).await;
         Ok(provider)
     }
 }
@@ -687,5 +771,50 @@ mod tests {
     #[test]
     fn test_parse_known_models() {
         let _ = get_known_models(); // This will panic if any model fails to parse
+        assert_eq!(known_model_pricing("anthropic/claude-3-7-sonnet-20250219").map(|x| x.output), Some(15.0));
+        assert_eq!(known_model_pricing("gpt-4o-mini-2024-07-18").map(|x| x.input), Some(0.15));
+        assert!(known_model_pricing("qwen2.5/coder/1.5b/instruct").is_none());
+    }
+
+    #[test]
+    fn test_anthropic_chat_endpoint_override_keeps_openai_style() {
+        let template = get_provider_templates()["anthropic"].clone();
+        assert_eq!(template.endpoint_style, "anthropic");
+
+        let mut provider = template.clone();
+        provider.apply_override(serde_yaml::from_str("chat_endpoint: https://api.anthropic.com/v1/chat/completions").unwrap()).unwrap();
+        assert_eq!(provider.endpoint_style, "openai");
+
+        let mut provider = template.clone();
+        provider.apply_override(serde_yaml::from_str("chat_endpoint: https://proxy.example.com/anthropic/v1/messages").unwrap()).unwrap();
+        assert_eq!(provider.endpoint_style, "anthropic");
+
+        let mut provider = template.clone();
+        provider.apply_override(serde_yaml::from_str("chat_endpoint: https://proxy.example.com/chat\nendpoint_style: anthropic").unwrap()).unwrap();
+        assert_eq!(provider.endpoint_style, "anthropic");
+
+        let mut provider = template.clone();
+        provider.apply_override(serde_yaml::from_str("api_key: sk-ant-1").unwrap()).unwrap();
+        assert_eq!(provider.endpoint_style, "anthropic");
+    }
+
+    #[tokio::test]
+    async fn test_azure_headers_and_params() {
+        std::env::set_var("TEST_REFACT_TENANT", "tenant-1");
+        let mut provider = get_provider_templates()["azure_openai"].clone();
+        provider.api_key = "key".to_string();
+        provider.extra_headers.insert("x-tenant".to_string(), "$TEST_REFACT_TENANT".to_string());
+        resolve_provider_headers_and_params(&mut provider).await;
+        provider.chat_models.insert("gpt-4o".to_string(), ChatModelRecord::default());
+
+        let mut caps = CodeAssistantCaps::default();
+        add_models_to_caps(&mut caps, vec![provider]);
+        let model = &caps.chat_models.values().next().unwrap().base;
+        assert!(model.endpoint.contains("/deployments/gpt-4o/"));
+        assert_eq!(model.query_params["api-version"], "2024-10-21");
+        let headers = model.auth_headers("Authorization").unwrap();
+        assert_eq!(headers["api-key"], "key");
+        assert_eq!(headers["x-tenant"], "tenant-1");
+        assert!(headers.get("authorization").is_none());
     }
 }
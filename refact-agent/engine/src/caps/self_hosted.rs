@@ -285,6 +285,7 @@ impl SelfHostedCaps {
                 } else {
                     format!("{}/{}", self.cloud_name, self.chat.default_light_model)
                 },
+                ..Default::default()
             },
             customization: self.customization.clone(),
             caps_version: self.caps_version,
@@ -340,7 +341,11 @@ impl SelfHostedCaps {
             embedding_endpoint: self.embedding.endpoint.clone(),
             api_key: cmdline_api_key.to_string(),
             tokenizer_api_key: cmdline_api_key.to_string(),
+            auth_header_name: String::new(),
+            extra_headers: IndexMap::new(),
+            query_params: IndexMap::new(),
             code_completion_n_ctx: 0,
+            models_discovery: String::new(),
             support_metadata: self.support_metadata,
             completion_models: IndexMap::new(),
             chat_models: IndexMap::new(),
@@ -359,6 +364,7 @@ impl SelfHostedCaps {
                 } else {
                     format!("{}/{}", self.cloud_name, self.chat.default_light_model)
                 },
+                ..Default::default()
             },
             running_models: Vec::new(),
         };
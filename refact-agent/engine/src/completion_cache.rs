@@ -1,17 +1,21 @@
-use crate::call_validation::CodeCompletionPost;
+use crate::call_validation::{CodeCompletionPost, CursorPosition};
 use std::sync::Arc;
 use std::sync::RwLock as StdRwLock;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use tokio::sync::{Mutex as AMutex, OwnedMutexGuard};
 
 use ropey::Rope;
 // use tracing::info;
 
 const CACHE_ENTRIES: usize = 500;
 const CACHE_KEY_CHARS: usize = 5000;  // max memory CACHE_KEY_CHARS * CACHE_ENTRIES = 2500000 = 2.5M
+const CACHE_ANCHOR_CHARS: usize = 64;
+const CACHE_TYPED_CHARS: usize = 1000;
+const IN_FLIGHT_TYPED_CHARS: usize = 64;
 
 
 // aggregate this struct in scratchpad to save cache
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct CompletionSaveToCache {
     pub cache_arc: Arc<StdRwLock<CompletionCache>>,
     pub cache_key: (String, String),
@@ -19,6 +23,8 @@ pub struct CompletionSaveToCache {
     pub completion0_finish_reason: String,
     pub completion0_snippet_telemetry_id: Option<u64>,
     pub model: String,
+    // requests typed right after this one wait on it, released after the result is in the cache
+    in_flight: Option<(Arc<AMutex<()>>, OwnedMutexGuard<()>)>,
 }
 
 impl CompletionSaveToCache {
@@ -26,60 +32,167 @@ impl CompletionSaveToCache {
         cache_arc: Arc<StdRwLock<CompletionCache>>,
         post: &CodeCompletionPost
     ) -> Self {
+        let cache_key = cache_key_from_post(post);
+        let in_flight = if !post.no_cache && !post.next_edit {
+            let mutex = Arc::new(AMutex::new(()));
+            let guard = mutex.clone().try_lock_owned().expect("new mutex is not locked");
+            cache_arc.write().unwrap().in_flight.push(InFlightRequest {
+                file: post.inputs.cursor.file.clone(),
+                line: post.inputs.cursor.line,
+                character: post.inputs.cursor.character,
+                key: cache_key.clone(),
+                mutex: mutex.clone(),
+            });
+            Some((mutex, guard))
+        } else {
+            None
+        };
         CompletionSaveToCache {
             cache_arc: cache_arc.clone(),
-            cache_key: cache_key_from_post(post),
+            cache_key,
             completion0_text: String::new(),
             completion0_finish_reason: String::new(),
             completion0_snippet_telemetry_id: None,
             model: post.model.clone(),
+            in_flight,
         }
     }
 }
 
 
+#[derive(Debug, Clone)]
+pub struct CachedCompletion {
+    pub key: (String, String),
+    pub completion: String,
+    // how many chars of the completion the user can type and still get the rest from the cache
+    pub believe_chars: usize,
+    pub finish_reason: String,
+    pub snippet_telemetry_id: Option<u64>,
+    pub model: String,
+}
+
+#[derive(Debug)]
+pub struct InFlightRequest {
+    pub file: String,
+    pub line: i32,
+    pub character: i32,
+    pub key: (String, String),
+    pub mutex: Arc<AMutex<()>>,
+}
+
 #[derive(Debug)]
 pub struct CompletionCache {
-    pub map: HashMap<(String, String), serde_json::Value>,
-    pub in_added_order: Vec<(String, String)>,
+    // by the last CACHE_ANCHOR_CHARS of the key and the key part 2, the typed part of a completion follows the anchor
+    pub entries: HashMap<(String, String), Vec<CachedCompletion>>,
+    // anchors in the order of cache_put, to drop the oldest entries
+    pub entries_order: VecDeque<(String, String)>,
+    pub in_flight: Vec<InFlightRequest>,
 }
 
 impl CompletionCache {
     pub fn new(
     ) -> Self {
-        Self { map: HashMap::new(), in_added_order: Vec::new() }
+        Self { entries: HashMap::new(), entries_order: VecDeque::new(), in_flight: Vec::new() }
+    }
+}
+
+fn last_chars(text: &str, n: usize) -> &str {
+    if n == 0 {
+        return "";
+    }
+    text.char_indices().rev().nth(n - 1).map_or(text, |(i, _)| &text[i..])
+}
+
+// Both keys are cut to the last CACHE_KEY_CHARS, so after typing the beginning of `origin` might be gone from `key`
+fn key_extends(origin: &str, key: &str, typed: &str) -> bool {
+    let Some(before_typed) = key.strip_suffix(typed) else {
+        return false;
+    };
+    if before_typed.len() >= origin.len() {
+        return before_typed == origin;
     }
+    origin.ends_with(before_typed) && key.chars().count() >= CACHE_KEY_CHARS
 }
 
+/// Exact match, or the user typed the beginning of a cached completion (up to CACHE_TYPED_CHARS): the rest of it
+/// is returned. Each split of the key into the text before and the typed text is one lookup by the anchor.
 pub fn cache_get(
     cache: Arc<StdRwLock<CompletionCache>>,
     key: (String, String),
 ) -> Option<serde_json::Value> {
-    let cache_locked = cache.write().unwrap();
-    if let Some(value) = cache_locked.map.get(&key) {
-        return Some(value.clone());
+    let cache_locked = cache.read().unwrap();
+    let char_starts = key.0.char_indices().map(|(i, _)| i).chain(std::iter::once(key.0.len())).collect::<Vec<_>>();
+    let key_chars = char_starts.len() - 1;
+    for typed_chars in 0..=key_chars.min(CACHE_TYPED_CHARS) {
+        let before_typed_end = char_starts[key_chars - typed_chars];
+        let anchor_start = char_starts[(key_chars - typed_chars).saturating_sub(CACHE_ANCHOR_CHARS)];
+        let anchor = (key.0[anchor_start..before_typed_end].to_string(), key.1.clone());
+        let typed = &key.0[before_typed_end..];
+        for entry in cache_locked.entries.get(&anchor).into_iter().flatten().rev() {
+            if typed_chars < entry.believe_chars && entry.completion.starts_with(typed) && key_extends(&entry.key.0, &key.0, typed) {
+                let typed_bytes = typed.len();
+                return Some(serde_json::json!(
+                    {
+                        "choices": [{
+                            "index": 0,
+                            "code_completion": entry.completion[typed_bytes..],
+                            "finish_reason": entry.finish_reason,
+                        }],
+                        "model": entry.model,
+                        "cached": true,
+                        "snippet_telemetry_id": entry.snippet_telemetry_id,
+                    }
+                ));
+            }
+        }
     }
     None
 }
 
 pub fn cache_put(
     cache: Arc<StdRwLock<CompletionCache>>,
-    new_key: (String, String),
-    value: serde_json::Value,
+    entry: CachedCompletion,
 ) {
     let mut cache_locked = cache.write().unwrap();
-    while cache_locked.in_added_order.len() > CACHE_ENTRIES {
-        let old_key = cache_locked.in_added_order.remove(0);
-        cache_locked.map.remove(&old_key);
-    }
-    // info!("cache put: {:?} = {:?}", new_key, value);
-    let mut new_key_copy = new_key.clone();
-    let k0_chars = new_key_copy.0.chars();
-    if k0_chars.clone().count() > CACHE_KEY_CHARS {
-        new_key_copy.0 = k0_chars.clone().skip(k0_chars.count() - CACHE_KEY_CHARS).collect();
-    }
-    cache_locked.map.entry(new_key_copy.clone()).or_insert(value);
-    cache_locked.in_added_order.push(new_key_copy.clone());
+    let anchor = (last_chars(&entry.key.0, CACHE_ANCHOR_CHARS).to_string(), entry.key.1.clone());
+    if cache_locked.entries.get(&anchor).map_or(false, |bucket| bucket.iter().any(|e| e.key == entry.key)) {
+        return;
+    }
+    while cache_locked.entries_order.len() >= CACHE_ENTRIES {
+        let Some(oldest) = cache_locked.entries_order.pop_front() else { break };
+        if let Some(bucket) = cache_locked.entries.get_mut(&oldest) {
+            bucket.remove(0);
+            if bucket.is_empty() {
+                cache_locked.entries.remove(&oldest);
+            }
+        }
+    }
+    // info!("cache put: {:?} = {:?}", entry.key, entry.completion);
+    cache_locked.entries_order.push_back(anchor.clone());
+    cache_locked.entries.entry(anchor).or_default().push(entry);
+}
+
+/// A request still waiting for the model, at the same line of the same file a few chars before the cursor,
+/// and the user has typed these chars on top of it
+pub fn cache_in_flight(
+    cache: Arc<StdRwLock<CompletionCache>>,
+    cursor: &CursorPosition,
+    key: &(String, String),
+) -> Option<Arc<AMutex<()>>> {
+    let cache_locked = cache.read().unwrap();
+    for request in cache_locked.in_flight.iter().rev() {
+        if request.file != cursor.file || request.line != cursor.line || request.key.1 != key.1 {
+            continue;
+        }
+        let typed_chars = cursor.character - request.character;
+        if typed_chars < 0 || typed_chars as usize > IN_FLIGHT_TYPED_CHARS {
+            continue;
+        }
+        if key_extends(&request.key.0, &key.0, last_chars(&key.0, typed_chars as usize)) {
+            return Some(request.mutex.clone());
+        }
+    }
+    None
 }
 
 pub fn cache_key_from_post(
@@ -125,8 +238,9 @@ pub fn cache_key_from_post(
     key.push_str(&cursor_line.to_string());
     let chars = key.chars();
 
-    if chars.clone().count() > CACHE_KEY_CHARS {
-        key = chars.skip(key.len() - CACHE_KEY_CHARS).collect();
+    let chars_n = chars.clone().count();
+    if chars_n > CACHE_KEY_CHARS {
+        key = chars.skip(chars_n - CACHE_KEY_CHARS).collect();
     }
     return (key, cache_part2_from_post(post));
 }
@@ -140,36 +254,104 @@ pub fn cache_part2_from_post(post: &CodeCompletionPost) -> String {
 impl Drop for CompletionSaveToCache {
     fn drop(&mut self) {
         // flush to cache on destruction
-        if self.completion0_finish_reason.is_empty() { // error happened, no nothing happened (prompt only request)
-            return;
+        if !self.completion0_finish_reason.is_empty() { // empty if error happened, no nothing happened (prompt only request)
+            let mut believe_chars = self.completion0_text.chars().count();
+            if self.completion0_finish_reason == "length" {
+                // Model stopped because of max tokens, there is a continuation, so it's good for cache in the beginning, but don't believe it to the end.
+                // For example CODECODECODECOMPLETION| with empty completion is obviously junk as cache.
+                // And it's not junk for "stop", it actually saves one model call after accepting each completion.
+                believe_chars = believe_chars.checked_sub(10).unwrap_or(0);
+            } else {
+                believe_chars += 1;
+            }
+            cache_put(self.cache_arc.clone(), CachedCompletion {
+                key: self.cache_key.clone(),
+                completion: self.completion0_text.clone(),
+                believe_chars,
+                finish_reason: self.completion0_finish_reason.clone(),
+                snippet_telemetry_id: self.completion0_snippet_telemetry_id,
+                model: self.model.clone(),
+            });
         }
-        let mut believe_chars = self.completion0_text.len();
-        if self.completion0_finish_reason == "length" {
-            // Model stopped because of max tokens, there is a continuation, so it's good for cache in the beginning, but don't believe it to the end.
-            // For example CODECODECODECOMPLETION| with empty completion is obviously junk as cache.
-            // And it's not junk for "stop", it actually saves one model call after accepting each completion.
-            believe_chars = believe_chars.checked_sub(10).unwrap_or(0);
-        } else {
-            believe_chars += 1;
+        if let Some((mutex, _guard)) = self.in_flight.take() {
+            self.cache_arc.write().unwrap().in_flight.retain(|request| !Arc::ptr_eq(&request.mutex, &mutex));
         }
-        for char_num in 0..believe_chars {
-            let code_completion_ahead: String = self.completion0_text.chars().skip(char_num).collect();
-            let cache_key_ahead: (String, String) = (
-                self.cache_key.0.clone() + &self.completion0_text.chars().take(char_num).collect::<String>(),
-                self.cache_key.1.clone()
-            );
-            cache_put(self.cache_arc.clone(), cache_key_ahead, serde_json::json!(
-                {
-                    "choices": [{
-                        "index": 0,
-                        "code_completion": code_completion_ahead,
-                        "finish_reason": self.completion0_finish_reason,
-                    }],
-                    "model": self.model,
-                    "cached": true,
-                    "snippet_telemetry_id": self.completion0_snippet_telemetry_id,
-                }
-            ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(cache: &Arc<StdRwLock<CompletionCache>>, key: &str, completion: &str, finish_reason: &str) {
+        let believe_chars = if finish_reason == "length" { completion.chars().count().saturating_sub(10) } else { completion.chars().count() + 1 };
+        cache_put(cache.clone(), CachedCompletion {
+            key: (key.to_string(), "multiline".to_string()),
+            completion: completion.to_string(),
+            believe_chars,
+            finish_reason: finish_reason.to_string(),
+            snippet_telemetry_id: None,
+            model: "model".to_string(),
+        });
+    }
+
+    fn get(cache: &Arc<StdRwLock<CompletionCache>>, key: &str) -> Option<String> {
+        cache_get(cache.clone(), (key.to_string(), "multiline".to_string()))
+            .map(|v| v["choices"][0]["code_completion"].as_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_cache_typed_prefix() {
+        let cache = Arc::new(StdRwLock::new(CompletionCache::new()));
+        put(&cache, "def f(x):\n    ", "return x + 1\n", "stop");
+        assert_eq!(get(&cache, "def f(x):\n    "), Some("return x + 1\n".to_string()));
+        assert_eq!(get(&cache, "def f(x):\n    retu"), Some("rn x + 1\n".to_string()));
+        assert_eq!(get(&cache, "def f(x):\n    return x + 1\n"), Some("".to_string()));
+        assert_eq!(get(&cache, "def f(x):\n    retx"), None);
+        assert_eq!(get(&cache, "def g(x):\n    retu"), None);
+
+        // the end of a completion cut by max tokens is not reliable
+        put(&cache, "y = ", "compute_something_long(1, 2, 3)", "length");
+        assert_eq!(get(&cache, "y = compute"), Some("_something_long(1, 2, 3)".to_string()));
+        assert_eq!(get(&cache, "y = compute_something_long(1, 2"), None);
+    }
+
+    #[test]
+    fn test_cache_typed_prefix_long_key() {
+        let cache = Arc::new(StdRwLock::new(CompletionCache::new()));
+        let origin: String = "#".repeat(CACHE_KEY_CHARS - 4) + "x = ";
+        put(&cache, &origin, "goat()", "stop");
+        // typing drops the first chars of the key, it still has the last CACHE_KEY_CHARS
+        let typed: String = origin.chars().skip(3).collect::<String>() + "goa";
+        assert_eq!(get(&cache, &typed), Some("t()".to_string()));
+    }
+
+    #[test]
+    fn test_cache_evicts_oldest() {
+        let cache = Arc::new(StdRwLock::new(CompletionCache::new()));
+        for i in 0..CACHE_ENTRIES + 1 {
+            put(&cache, &format!("x{} = ", i), "1", "stop");
         }
+        assert_eq!(get(&cache, "x0 = "), None);
+        assert_eq!(get(&cache, "x1 = "), Some("1".to_string()));
+        assert_eq!(cache.read().unwrap().entries_order.len(), CACHE_ENTRIES);
+    }
+
+    #[test]
+    fn test_cache_in_flight() {
+        let cache = Arc::new(StdRwLock::new(CompletionCache::new()));
+        let key = |text: &str| (text.to_string(), "multiline".to_string());
+        let cursor = |character: i32| CursorPosition { file: "a.py".to_string(), line: 1, character };
+        cache.write().unwrap().in_flight.push(InFlightRequest {
+            file: "a.py".to_string(),
+            line: 1,
+            character: 4,
+            key: key("def f(x):\n    "),
+            mutex: Arc::new(AMutex::new(())),
+        });
+        assert!(cache_in_flight(cache.clone(), &cursor(4), &key("def f(x):\n    ")).is_some());
+        assert!(cache_in_flight(cache.clone(), &cursor(7), &key("def f(x):\n    ret")).is_some());
+        assert!(cache_in_flight(cache.clone(), &cursor(7), &key("def g(x):\n    ret")).is_none());
+        assert!(cache_in_flight(cache.clone(), &cursor(3), &key("def f(x):\n   ")).is_none());
     }
 }
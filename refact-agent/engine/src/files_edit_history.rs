@@ -0,0 +1,149 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+
+// Recent user edits per open file, for the completion models to see what the user is doing:
+// a rename or a new argument applied at one call site is likely to be applied at the next one.
+
+const EDITS_PER_FILE: usize = 16;
+const FILES_TRACKED: usize = 32;
+const MERGE_WITHIN_SECONDS: f64 = 30.0;
+
+
+/// Whole lines `before` starting at `line1` became `after`, lines count from 0
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinesEdit {
+    pub line1: usize,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+    pub ts: f64,
+}
+
+impl LinesEdit {
+    /// Common head and tail lines are cut off, whatever is in the middle is the edit
+    pub fn from_texts(old_text: &str, new_text: &str, ts: f64) -> Option<LinesEdit> {
+        if old_text == new_text {
+            return None;
+        }
+        let old_lines: Vec<&str> = old_text.split_inclusive('\n').collect();
+        let new_lines: Vec<&str> = new_text.split_inclusive('\n').collect();
+        let head = old_lines.iter().zip(new_lines.iter()).take_while(|(a, b)| a == b).count();
+        let tail = old_lines[head..].iter().rev().zip(new_lines[head..].iter().rev()).take_while(|(a, b)| a == b).count();
+        Some(LinesEdit {
+            line1: head,
+            before: old_lines[head..old_lines.len() - tail].iter().map(|x| x.to_string()).collect(),
+            after: new_lines[head..new_lines.len() - tail].iter().map(|x| x.to_string()).collect(),
+            ts,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct EditHistory {
+    files: HashMap<PathBuf, VecDeque<LinesEdit>>,
+}
+
+impl EditHistory {
+    /// Keystrokes within the lines of the previous edit extend it, instead of making a new edit per letter
+    pub fn record(&mut self, path: &PathBuf, edit: LinesEdit) {
+        if edit.before == edit.after {
+            return;
+        }
+        if !self.files.contains_key(path) && self.files.len() >= FILES_TRACKED {
+            let least_recent = self.files.iter()
+                .min_by(|a, b| _last_ts(a.1).total_cmp(&_last_ts(b.1)))
+                .map(|(p, _)| p.clone());
+            if let Some(least_recent) = least_recent {
+                self.files.remove(&least_recent);
+            }
+        }
+        let edits = self.files.entry(path.clone()).or_default();
+        if let Some(last) = edits.back_mut() {
+            let inside = edit.line1 >= last.line1 && edit.line1 + edit.before.len() <= last.line1 + last.after.len();
+            if inside && edit.ts - last.ts < MERGE_WITHIN_SECONDS {
+                let offset = edit.line1 - last.line1;
+                last.after.splice(offset..offset + edit.before.len(), edit.after);
+                last.ts = edit.ts;
+                if last.before == last.after {
+                    edits.pop_back();
+                }
+                return;
+            }
+        }
+        // older edits below this one move up or down
+        let delta = edit.after.len() as i64 - edit.before.len() as i64;
+        for older in edits.iter_mut() {
+            if older.line1 >= edit.line1 + edit.before.len() {
+                older.line1 = (older.line1 as i64 + delta).max(0) as usize;
+            }
+        }
+        edits.push_back(edit);
+        while edits.len() > EDITS_PER_FILE {
+            edits.pop_front();
+        }
+    }
+
+    pub fn file_edits(&self, path: &PathBuf) -> Vec<LinesEdit> {
+        self.files.get(path).map(|edits| edits.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    pub fn forget_file(&mut self, path: &PathBuf) {
+        self.files.remove(path);
+    }
+}
+
+fn _last_ts(edits: &VecDeque<LinesEdit>) -> f64 {
+    edits.back().map(|e| e.ts).unwrap_or(0.0)
+}
+
+/// Unified-diff-like hunk, line numbers start from 1 as in editors
+pub fn edit_to_diff(edit: &LinesEdit) -> String {
+    let mut diff = format!("@@ -{},{} +{},{} @@\n", edit.line1 + 1, edit.before.len(), edit.line1 + 1, edit.after.len());
+    for line in edit.before.iter() {
+        diff.push_str(&format!("-{}\n", line.trim_end_matches(&['\r', '\n'][..])));
+    }
+    for line in edit.after.iter() {
+        diff.push_str(&format!("+{}\n", line.trim_end_matches(&['\r', '\n'][..])));
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split_inclusive('\n').map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_lines_edit_from_texts() {
+        let edit = LinesEdit::from_texts("a\nb(1)\nc\n", "a\nb(1, 2)\nx\nc\n", 0.0).unwrap();
+        assert_eq!(edit.line1, 1);
+        assert_eq!(edit.before, lines("b(1)\n"));
+        assert_eq!(edit.after, lines("b(1, 2)\nx\n"));
+        assert!(LinesEdit::from_texts("a\n", "a\n", 0.0).is_none());
+        assert_eq!(edit_to_diff(&edit), "@@ -2,1 +2,2 @@\n-b(1)\n+b(1, 2)\n+x\n");
+    }
+
+    #[test]
+    fn test_edit_history_merges_keystrokes() {
+        let path = PathBuf::from("/tmp/goat.py");
+        let mut history = EditHistory::default();
+        history.record(&path, LinesEdit { line1: 5, before: lines("jump(1)\n"), after: lines("jump(1,)\n"), ts: 1.0 });
+        history.record(&path, LinesEdit { line1: 5, before: lines("jump(1,)\n"), after: lines("jump(1, 2)\n"), ts: 2.0 });
+        let edits = history.file_edits(&path);
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].before, lines("jump(1)\n"));
+        assert_eq!(edits[0].after, lines("jump(1, 2)\n"));
+
+        // another place above, the first edit moves down by one line
+        history.record(&path, LinesEdit { line1: 1, before: lines("jump(3)\n"), after: lines("jump(3, 2)\n# more\n"), ts: 3.0 });
+        let edits = history.file_edits(&path);
+        assert_eq!(edits.len(), 2);
+        assert_eq!(edits[0].line1, 6);
+
+        // typed and erased back is no edit at all
+        history.record(&path, LinesEdit { line1: 1, before: lines("jump(3, 2)\n# more\n"), after: lines("jump(3)\n"), ts: 4.0 });
+        assert_eq!(history.file_edits(&path).len(), 1);
+    }
+}
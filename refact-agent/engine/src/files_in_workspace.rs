@@ -11,7 +11,7 @@ use ropey::Rope;
 use tokio::sync::{RwLock as ARwLock, Mutex as AMutex};
 use walkdir::WalkDir;
 use which::which;
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::files_correction::{canonical_path, CommandSimplifiedDirExt};
 use crate::git::operations::git_ls_files;
@@ -27,6 +27,7 @@ use crate::files_blocklist::{
     reload_indexing_everywhere_if_needed,
 };
 use crate::files_correction_cache::PathTrie;
+use crate::files_edit_history::{EditHistory, LinesEdit};
 use crate::files_in_jsonl::enqueue_all_docs_from_jsonl_but_read_first;
 
 
@@ -59,6 +60,14 @@ pub struct Document {
     pub doc_text: Option<Rope>,
 }
 
+/// One entry of `contentChanges` in an incremental didChange: positions are 0-based (line, character),
+/// characters counted in UTF-16 code units as LSP does. No range means the whole text is replaced.
+#[derive(Debug, Clone)]
+pub struct DocumentEdit {
+    pub range: Option<((usize, usize), (usize, usize))>,
+    pub text: String,
+}
+
 pub async fn get_file_text_from_memory_or_disk(global_context: Arc<ARwLock<GlobalContext>>, file_path: &PathBuf) -> Result<String, String>
 {
     check_file_privacy(load_privacy_if_needed(global_context.clone()).await, &file_path, &FilePrivacyLevel::AllowToSendAnywhere)?;
@@ -102,6 +111,36 @@ impl Document {
         self.doc_text = Some(Rope::from_str(text));
     }
 
+    /// Returns true if the text has actually changed
+    pub fn apply_edit(&mut self, edit: &DocumentEdit) -> Result<bool, String> {
+        let ((line1, character1), (line2, character2)) = match edit.range {
+            Some(range) => range,
+            None => {
+                let changed = self.doc_text.as_ref().map_or(true, |r| *r != edit.text.as_str());
+                self.doc_text = Some(Rope::from_str(&edit.text));
+                return Ok(changed);
+            }
+        };
+        if (line2, character2) < (line1, character1) {
+            return Err(format!("edit range in {} ends before it starts", self.doc_path.display()));
+        }
+        let rope = self.doc_text.as_mut().ok_or(format!("no text loaded in {}", self.doc_path.display()))?;
+        let char1 = rope_char_idx(rope, line1, character1);
+        let char2 = rope_char_idx(rope, line2, character2).max(char1);
+        let changed = rope.slice(char1..char2) != edit.text.as_str();
+        rope.remove(char1..char2);
+        rope.insert(char1, &edit.text);
+        Ok(changed)
+    }
+
+    /// Lines from `line1` up to but not including `line2`, with line endings
+    pub fn text_lines(&self, line1: usize, line2: usize) -> Vec<String> {
+        match &self.doc_text {
+            Some(r) => (line1..line2.min(r.len_lines())).map(|i| r.line(i).to_string()).collect(),
+            None => vec![],
+        }
+    }
+
     pub fn text_as_string(&self) -> Result<String, String> {
         if let Some(r) = &self.doc_text {
             return Ok(r.to_string());
@@ -177,6 +216,21 @@ pub struct DocumentsState {
     pub cache_dirty: Arc<AMutex<f64>>,
     pub cache_correction: Arc<CacheCorrection>,
     pub fs_watcher: Arc<ARwLock<RecommendedWatcher>>,
+    pub edit_history: Arc<StdMutex<EditHistory>>,
+}
+
+// positions past the end of a line stick to the end of that line, past the last line to the end of text
+fn rope_char_idx(rope: &Rope, line: usize, character: usize) -> usize {
+    if line >= rope.len_lines() {
+        return rope.len_chars();
+    }
+    let line_slice = rope.line(line);
+    let mut line_len = line_slice.len_chars();
+    while line_len > 0 && matches!(line_slice.char(line_len - 1), '\n' | '\r') {
+        line_len -= 1;
+    }
+    let character = character.min(line_slice.char_to_utf16_cu(line_len));
+    rope.line_to_char(line) + line_slice.utf16_cu_to_char(character)
 }
 
 async fn mem_overwrite_or_create_document(
@@ -210,6 +264,7 @@ impl DocumentsState {
             memory_document_map: HashMap::new(),
             cache_dirty: Arc::new(AMutex::<f64>::new(0.0)),
             cache_correction: Arc::new(CacheCorrection::new()),
+            edit_history: Arc::new(StdMutex::new(EditHistory::default())),
             fs_watcher: Arc::new(ARwLock::new(watcher)),
         }
     }
@@ -708,6 +763,16 @@ pub async fn on_did_change(
     text: &String,
 ) {
     let t0 = Instant::now();
+    let (old_doc_arc, edit_history) = {
+        let gcx_locked = gcx.read().await;
+        (gcx_locked.documents_state.memory_document_map.get(path).cloned(), gcx_locked.documents_state.edit_history.clone())
+    };
+    if let Some(old_doc_arc) = old_doc_arc {
+        let old_text = old_doc_arc.read().await.text_as_string().unwrap_or_default();
+        if let Some(lines_edit) = LinesEdit::from_texts(&old_text, text, now_ts()) {
+            edit_history.lock().unwrap().record(path, lines_edit);
+        }
+    }
     let (doc_arc, dirty_arc, mark_dirty) = {
         let mut doc = Document::new(path);
         doc.update_text(text);
@@ -720,9 +785,88 @@ pub async fn on_did_change(
         *dirty_arc.lock().await = now;
     }
 
+    let cpath = doc_arc.read().await.doc_path.clone().to_string_lossy().to_string();
+    on_document_text_changed(gcx.clone(), path, &cpath, true).await;
+
+    telemetry::snippets_collection::sources_changed(
+        gcx.clone(),
+        &path.to_string_lossy().to_string(),
+        text,
+    ).await;
+
+    info!("on_did_change {}, total time {:.3}s", crate::nicer_logs::last_n_chars(&path.to_string_lossy().to_string(), 30), t0.elapsed().as_secs_f32());
+}
+
+/// Applies range edits to the rope of an open document, the whole text never travels over the wire
+pub async fn on_did_change_incremental(
+    gcx: Arc<ARwLock<GlobalContext>>,
+    path: &PathBuf,
+    edits: &Vec<DocumentEdit>,
+) -> Result<(), String> {
+    let t0 = Instant::now();
+    let doc_arc = gcx.read().await.documents_state.memory_document_map.get(path).cloned()
+        .ok_or(format!("{} is not open, cannot apply edits", path.display()))?;
+    let edit_history = gcx.read().await.documents_state.edit_history.clone();
+    let mut changed = false;
+    let applied = {
+        let mut doc = doc_arc.write().await;
+        edits.iter().try_for_each(|edit| {
+            let lines_edit = match edit.range {
+                Some(((line1, _), (line2, _))) => {
+                    let before = doc.text_lines(line1, line2 + 1);
+                    changed |= doc.apply_edit(edit)?;
+                    let after = doc.text_lines(line1, line1 + edit.text.matches('\n').count() + 1);
+                    Some(LinesEdit { line1, before, after, ts: now_ts() })
+                }
+                None => {
+                    let old_text = doc.text_as_string().unwrap_or_default();
+                    changed |= doc.apply_edit(edit)?;
+                    LinesEdit::from_texts(&old_text, &edit.text, now_ts())
+                }
+            };
+            if let Some(lines_edit) = lines_edit {
+                edit_history.lock().unwrap().record(path, lines_edit);
+            }
+            Ok::<(), String>(())
+        })
+    };
+    if let Err(e) = applied {
+        // some of the edits are applied, and the next ranges are relative to the editor's unsaved buffer, not to the disk,
+        // so nothing here can be trusted until didOpen or a full text change brings the text again
+        warn!("{}: {}, dropping the document", path.display(), e);
+        gcx.write().await.documents_state.memory_document_map.remove(path);
+        return Err(e);
+    }
+
+    let cpath = doc_arc.read().await.doc_path.clone().to_string_lossy().to_string();
+    on_document_text_changed(gcx.clone(), path, &cpath, changed).await;
+
+    // most edits don't need the full text for telemetry, only the fact that the file is being typed in
+    let uri = path.to_string_lossy().to_string();
+    if telemetry::snippets_collection::sources_changed_needs_text(gcx.clone(), &uri, edits).await {
+        let text = doc_arc.read().await.text_as_string()?;
+        telemetry::snippets_collection::sources_changed(gcx.clone(), &uri, &text).await;
+    } else {
+        telemetry::snippets_collection::sources_touched(gcx.clone(), &uri).await;
+    }
+
+    info!("on_did_change_incremental {} edits in {}, total time {:.3}s", edits.len(), crate::nicer_logs::last_n_chars(&uri, 30), t0.elapsed().as_secs_f32());
+    Ok(())
+}
+
+fn now_ts() -> f64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs_f64()
+}
+
+async fn on_document_text_changed(
+    gcx: Arc<ARwLock<GlobalContext>>,
+    path: &PathBuf,
+    cpath: &String,
+    reindex: bool,
+) {
     gcx.write().await.documents_state.active_file_path = Some(path.clone());
 
-    let mut go_ahead = true;
+    let mut go_ahead = reindex;
     {
         let is_it_good = is_valid_file(path, false, false);
         if is_it_good.is_err() {
@@ -731,18 +875,9 @@ pub async fn on_did_change(
         }
     }
 
-    let cpath = doc_arc.read().await.doc_path.clone().to_string_lossy().to_string();
     if go_ahead {
-        enqueue_some_docs(gcx.clone(), &vec![cpath], false).await;
+        enqueue_some_docs(gcx.clone(), &vec![cpath.clone()], false).await;
     }
-
-    telemetry::snippets_collection::sources_changed(
-        gcx.clone(),
-        &path.to_string_lossy().to_string(),
-        text,
-    ).await;
-
-    info!("on_did_change {}, total time {:.3}s", crate::nicer_logs::last_n_chars(&path.to_string_lossy().to_string(), 30), t0.elapsed().as_secs_f32());
 }
 
 pub async fn on_did_delete(gcx: Arc<ARwLock<GlobalContext>>, path: &PathBuf)
@@ -752,6 +887,7 @@ pub async fn on_did_delete(gcx: Arc<ARwLock<GlobalContext>>, path: &PathBuf)
     let (vec_db_module, ast_service, dirty_arc) = {
         let mut cx = gcx.write().await;
         cx.documents_state.memory_document_map.remove(path);
+        cx.documents_state.edit_history.lock().unwrap().forget_file(path);
         (cx.vec_db.clone(), cx.ast_service.clone(), cx.documents_state.cache_dirty.clone())
     };
 
@@ -898,3 +1034,57 @@ pub async fn files_in_workspace_init_task(gcx: Arc<ARwLock<GlobalContext>>) {
     enqueue_all_docs_from_jsonl_but_read_first(gcx.clone(), true, false).await;
     crate::git::checkpoints::enqueue_init_shadow_repos(gcx.clone()).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+    use crate::global_context::{create_global_context_with_cmdline, CommandLine};
+
+    fn edit(line1: usize, character1: usize, line2: usize, character2: usize, text: &str) -> DocumentEdit {
+        DocumentEdit { range: Some(((line1, character1), (line2, character2))), text: text.to_string() }
+    }
+
+    #[test]
+    fn test_document_apply_edit() {
+        let mut doc = Document::new(&PathBuf::from("/tmp/goat.py"));
+        doc.update_text(&"def jump():\n    return 1\n".to_string());
+        assert_eq!(doc.apply_edit(&edit(1, 11, 1, 12, "42")), Ok(true));
+        assert_eq!(doc.apply_edit(&edit(0, 4, 0, 8, "leap")), Ok(true));
+        assert_eq!(doc.text_as_string().unwrap(), "def leap():\n    return 42\n");
+        // past the end of line and past the end of text
+        assert_eq!(doc.apply_edit(&edit(0, 100, 0, 100, "  # hop")), Ok(true));
+        assert_eq!(doc.apply_edit(&edit(7, 0, 7, 0, "print()\n")), Ok(true));
+        assert_eq!(doc.text_as_string().unwrap(), "def leap():  # hop\n    return 42\nprint()\n");
+        assert_eq!(doc.apply_edit(&edit(1, 4, 1, 10, "return")), Ok(false));
+    }
+
+    #[tokio::test]
+    async fn test_failed_incremental_edit_drops_document() {
+        let dir = tempfile::tempdir().unwrap();
+        let cmdline = CommandLine::from_iter(["refact-lsp"]);
+        let (gcx, _ask_shutdown_receiver, _) = create_global_context_with_cmdline(dir.path().join("cache"), dir.path().join("config"), cmdline).await;
+        let path = dir.path().join("goat.py");
+        std::fs::write(&path, "def jump():\n    return 1\n").unwrap();
+        let mut doc = Document::new(&path);
+        doc.update_text(&"def jump():\n    return 1\n\nprint(jump())\n".to_string());
+        gcx.write().await.documents_state.memory_document_map.insert(path.clone(), Arc::new(ARwLock::new(doc)));
+
+        assert!(on_did_change_incremental(gcx.clone(), &path, &vec![edit(3, 0, 3, 0, "# "), edit(3, 5, 1, 0, "x")]).await.is_err());
+        assert!(gcx.read().await.documents_state.memory_document_map.get(&path).is_none());
+        // relative to the editor's buffer, on the disk text it would land past the end
+        assert!(on_did_change_incremental(gcx.clone(), &path, &vec![edit(3, 0, 3, 0, "# ")]).await.is_err());
+        assert!(gcx.read().await.documents_state.memory_document_map.get(&path).is_none());
+    }
+
+    #[test]
+    fn test_document_apply_edit_utf16() {
+        let mut doc = Document::new(&PathBuf::from("/tmp/goat.py"));
+        doc.update_text(&"s = \"🐐🐐\"\nx = 1\n".to_string());
+        // each goat is two UTF-16 code units
+        assert_eq!(doc.apply_edit(&edit(0, 7, 0, 9, "")), Ok(true));
+        assert_eq!(doc.text_as_string().unwrap(), "s = \"🐐\"\nx = 1\n");
+        assert_eq!(doc.apply_edit(&DocumentEdit { range: None, text: "y = 2\n".to_string() }), Ok(true));
+        assert_eq!(doc.text_as_string().unwrap(), "y = 2\n");
+    }
+}
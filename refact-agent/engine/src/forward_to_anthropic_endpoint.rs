@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::header::RETRY_AFTER;
+use reqwest::header::USER_AGENT;
+use reqwest::header::HeaderMap;
+use reqwest::header::HeaderValue;
+use reqwest_eventsource::EventSource;
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::call_validation::{ChatUsage, SamplingParameters};
+use crate::caps::BaseModelRecord;
+use crate::llm_traffic::llm_endpoint;
+use crate::model_failover::endpoint_status_error;
+use crate::custom_error::MapErrToString;
+
+// Messages API, https://docs.anthropic.com/en/api/messages
+// Requests are made from the PASSTHROUGH prompt (see convert_messages_to_anthropic_format), responses
+// and stream events are converted to the OpenAI chat format that the rest of the code expects.
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+
+fn anthropic_headers(model_rec: &BaseModelRecord) -> Result<HeaderMap, String> {
+    let mut headers = model_rec.auth_headers("x-api-key")?;
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.entry("anthropic-version").or_insert(HeaderValue::from_static(ANTHROPIC_VERSION));
+    if model_rec.support_metadata {
+        headers.insert(USER_AGENT, HeaderValue::from_str(&format!("refact-lsp {}", crate::version::build::PKG_VERSION)).unwrap());
+    }
+    Ok(headers)
+}
+
+fn anthropic_tool(openai_tool: &Value) -> Value {
+    let function = openai_tool.get("function").unwrap_or(openai_tool);
+    json!({
+        "name": function["name"],
+        "description": function.get("description").cloned().unwrap_or(json!("")),
+        "input_schema": function.get("parameters").cloned().unwrap_or(json!({"type": "object", "properties": {}})),
+    })
+}
+
+fn anthropic_tool_choice(tool_choice: &Value) -> Option<Value> {
+    match tool_choice.as_str() {
+        Some("required") => Some(json!({"type": "any"})),
+        Some("none") => Some(json!({"type": "none"})),
+        Some(_) => None,
+        None => tool_choice.get("function").and_then(|f| f.get("name")).map(|name| json!({"type": "tool", "name": name})),
+    }
+}
+
+// The system prompt and the tools are the same from turn to turn, a breakpoint after each of them caches the prefix
+fn set_cache_control_on_last(blocks: &mut Vec<Value>) {
+    if let Some(last) = blocks.last_mut() {
+        last["cache_control"] = json!({"type": "ephemeral"});
+    }
+}
+
+fn anthropic_request_body(
+    model_rec: &BaseModelRecord,
+    prompt: &str,
+    sampling_parameters: &SamplingParameters,
+    stream: bool,
+) -> Result<Value, String> {
+    if !prompt.starts_with("PASSTHROUGH ") {
+        return Err(format!("endpoint_style anthropic supports chat only, model {}", model_rec.id));
+    }
+    let big_json: Value = serde_json::from_str(&prompt[12..]).map_err_to_string()?;
+    let mut data = json!({
+        "model": model_rec.name.clone(),
+        "max_tokens": sampling_parameters.max_new_tokens,
+        "messages": big_json["messages"],
+        "stream": stream,
+    });
+    let mut system = big_json.get("system").and_then(|x| x.as_array()).cloned().unwrap_or_default();
+    if !system.is_empty() {
+        set_cache_control_on_last(&mut system);
+        data["system"] = json!(system);
+    }
+    let mut tools = big_json.get("tools").and_then(|x| x.as_array()).map(|x| x.iter().map(anthropic_tool).collect::<Vec<_>>()).unwrap_or_default();
+    if !tools.is_empty() {
+        set_cache_control_on_last(&mut tools);
+        data["tools"] = json!(tools);
+        if let Some(tool_choice) = big_json.get("tool_choice").and_then(anthropic_tool_choice) {
+            data["tool_choice"] = tool_choice;
+        }
+    }
+    let stop: Vec<String> = sampling_parameters.stop.iter().filter(|x| !x.trim().is_empty()).cloned().collect();
+    if !stop.is_empty() {
+        data["stop_sequences"] = json!(stop);
+    }
+    // temperature can't be set together with thinking
+    if let Some(thinking) = sampling_parameters.thinking.clone() {
+        data["thinking"] = thinking;
+    } else if let Some(temperature) = sampling_parameters.temperature {
+        data["temperature"] = json!(temperature);
+    }
+    if sampling_parameters.n.unwrap_or(1) > 1 {
+        info!("endpoint_style anthropic doesn't support n={}, generating one choice", sampling_parameters.n.unwrap());
+    }
+    info!("Request: model={}, thinking={}, T={}, stream={}",
+        model_rec.name,
+        sampling_parameters.thinking.is_some(),
+        sampling_parameters.temperature.map(|x| x.to_string()).unwrap_or("none".to_string()),
+        stream,
+    );
+    Ok(data)
+}
+
+fn openai_finish_reason(stop_reason: &Value) -> Value {
+    match stop_reason.as_str() {
+        Some("max_tokens") => json!("length"),
+        Some("tool_use") => json!("tool_calls"),
+        Some(_) => json!("stop"),
+        None => Value::Null,
+    }
+}
+
+fn usage_from_anthropic(usage: &Value, prev: &ChatUsage) -> ChatUsage {
+    let get = |field: &str, prev_value: usize| usage.get(field).and_then(|x| x.as_u64()).map(|x| x as usize).unwrap_or(prev_value);
+    let cache_creation = get("cache_creation_input_tokens", prev.cache_creation_input_tokens.unwrap_or(0));
+    let cache_read = get("cache_read_input_tokens", prev.cache_read_input_tokens.unwrap_or(0));
+    // input_tokens doesn't include what was read from or written to the cache
+    let prompt_tokens = get("input_tokens", prev.prompt_tokens.saturating_sub(prev.cache_creation_input_tokens.unwrap_or(0) + prev.cache_read_input_tokens.unwrap_or(0)))
+        + cache_creation + cache_read;
+    let completion_tokens = get("output_tokens", prev.completion_tokens);
+    ChatUsage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+        cache_creation_input_tokens: Some(cache_creation),
+        cache_read_input_tokens: Some(cache_read),
+        ..Default::default()
+    }
+}
+
+/// Non-streaming Messages API response as an OpenAI chat completion with one choice
+pub fn anthropic_response_to_openai(resp: &Value) -> Value {
+    if resp.get("type").and_then(|x| x.as_str()) == Some("error") {
+        return json!({"error": resp["error"]});
+    }
+    let mut content = String::new();
+    let mut thinking_blocks = vec![];
+    let mut tool_calls = vec![];
+    for block in resp.get("content").and_then(|x| x.as_array()).cloned().unwrap_or_default() {
+        match block.get("type").and_then(|x| x.as_str()).unwrap_or_default() {
+            "text" => content.push_str(block["text"].as_str().unwrap_or_default()),
+            "thinking" | "redacted_thinking" => thinking_blocks.push(block),
+            "tool_use" => tool_calls.push(json!({
+                "id": block["id"],
+                "type": "function",
+                "function": {"name": block["name"], "arguments": block["input"].to_string()},
+                "index": tool_calls.len(),
+            })),
+            other => info!("anthropic response: skipping content block of type {:?}", other),
+        }
+    }
+    let mut message = json!({"role": "assistant", "content": content});
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = json!(tool_calls);
+    }
+    if !thinking_blocks.is_empty() {
+        message["reasoning_content"] = json!(thinking_blocks.iter().filter_map(|x| x.get("thinking").and_then(|x| x.as_str())).collect::<String>());
+        message["thinking_blocks"] = json!(thinking_blocks);
+    }
+    json!({
+        "id": resp["id"],
+        "object": "chat.completion",
+        "model": resp["model"],
+        "choices": [{"index": 0, "message": message, "finish_reason": openai_finish_reason(&resp["stop_reason"])}],
+        "usage": usage_from_anthropic(&resp["usage"], &ChatUsage::default()),
+    })
+}
+
+/// Turns Messages API stream events into OpenAI chat completion chunks, `message_stop` ends the stream
+#[derive(Default)]
+pub struct AnthropicStreamState {
+    model: String,
+    usage: ChatUsage,
+    tool_call_by_block: HashMap<u64, usize>,
+}
+
+impl AnthropicStreamState {
+    fn chunk(&self, delta: Value, finish_reason: Value) -> Value {
+        json!({
+            "object": "chat.completion.chunk",
+            "model": self.model,
+            "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+        })
+    }
+
+    /// The index of the content block lets the client merge the deltas of each thinking block separately
+    fn thinking_chunk(&self, block_index: u64, thinking: &str, signature: Value) -> Value {
+        self.chunk(json!({
+            "reasoning_content": thinking,
+            "thinking_blocks": [{"type": "thinking", "thinking": thinking, "signature": signature, "index": block_index}],
+        }), Value::Null)
+    }
+
+    pub fn event_to_openai_chunk(&mut self, event: &Value) -> Option<Value> {
+        let block_index = event.get("index").and_then(|x| x.as_u64()).unwrap_or(0);
+        match event.get("type").and_then(|x| x.as_str()).unwrap_or_default() {
+            "message_start" => {
+                let message = event.get("message").cloned().unwrap_or_default();
+                self.model = message.get("model").and_then(|x| x.as_str()).unwrap_or_default().to_string();
+                self.usage = usage_from_anthropic(&message["usage"], &self.usage);
+                Some(self.chunk(json!({"role": "assistant", "content": ""}), Value::Null))
+            },
+            "content_block_start" => {
+                let block = event.get("content_block").cloned().unwrap_or_default();
+                match block.get("type").and_then(|x| x.as_str()).unwrap_or_default() {
+                    "tool_use" => {
+                        let tool_call_index = self.tool_call_by_block.len();
+                        self.tool_call_by_block.insert(block_index, tool_call_index);
+                        Some(self.chunk(json!({"tool_calls": [{
+                            "index": tool_call_index,
+                            "id": block["id"],
+                            "type": "function",
+                            "function": {"name": block["name"], "arguments": ""},
+                        }]}), Value::Null))
+                    },
+                    "redacted_thinking" => Some(self.chunk(json!({
+                        "reasoning_content": "",
+                        "thinking_blocks": [{"type": "redacted_thinking", "data": block["data"], "index": block_index}],
+                    }), Value::Null)),
+                    "text" => block.get("text").and_then(|x| x.as_str()).filter(|x| !x.is_empty())
+                        .map(|text| self.chunk(json!({"content": text}), Value::Null)),
+                    _ => None,
+                }
+            },
+            "content_block_delta" => {
+                let delta = event.get("delta").cloned().unwrap_or_default();
+                let field = |name: &str| delta.get(name).and_then(|x| x.as_str()).unwrap_or_default().to_string();
+                match delta.get("type").and_then(|x| x.as_str()).unwrap_or_default() {
+                    "text_delta" => Some(self.chunk(json!({"content": field("text")}), Value::Null)),
+                    "thinking_delta" => Some(self.thinking_chunk(block_index, &field("thinking"), Value::Null)),
+                    "signature_delta" => Some(self.thinking_chunk(block_index, "", json!(field("signature")))),
+                    "input_json_delta" => self.tool_call_by_block.get(&block_index).map(|tool_call_index| self.chunk(json!({"tool_calls": [{
+                        "index": tool_call_index,
+                        "function": {"arguments": field("partial_json")},
+                    }]}), Value::Null)),
+                    _ => None,
+                }
+            },
+            "message_delta" => {
+                self.usage = usage_from_anthropic(&event["usage"], &self.usage);
+                let finish_reason = openai_finish_reason(&event["delta"]["stop_reason"]);
+                let mut chunk = self.chunk(json!({}), finish_reason);
+                chunk["usage"] = json!(self.usage);
+                Some(chunk)
+            },
+            "error" => Some(json!({"error": event["error"]})),
+            _ => None,  // ping, content_block_stop, message_stop
+        }
+    }
+}
+
+pub async fn forward_to_anthropic_endpoint(
+    model_rec: &BaseModelRecord,
+    prompt: &str,
+    client: &reqwest::Client,
+    sampling_parameters: &SamplingParameters,
+) -> Result<Value, String> {
+    let data = anthropic_request_body(model_rec, prompt, sampling_parameters, false)?;
+    let resp = client.post(llm_endpoint(&model_rec.endpoint))
+        .query(&model_rec.query_params)
+        .headers(anthropic_headers(model_rec)?)
+        .body(data.to_string())
+        .send()
+        .await
+        .map_err_to_string()?;
+    let status_code = resp.status().as_u16();
+    let retry_after = resp.headers().get(RETRY_AFTER).cloned();
+    let response_txt = resp.text().await.map_err(|e|
+        format!("reading from socket {}: {}", model_rec.endpoint, e)
+    )?;
+    // errors come as json with details, just like for openai style
+    if status_code != 200 && status_code != 400 {
+        return Err(endpoint_status_error(&model_rec.endpoint, status_code, retry_after.as_ref(), &response_txt));
+    }
+    let parsed_json: Value = serde_json::from_str(&response_txt)
+        .map_err(|e| format!("Failed to parse JSON response: {}\n{}", e, response_txt))?;
+    Ok(anthropic_response_to_openai(&parsed_json))
+}
+
+pub async fn forward_to_anthropic_endpoint_streaming(
+    model_rec: &BaseModelRecord,
+    prompt: &str,
+    client: &reqwest::Client,
+    sampling_parameters: &SamplingParameters,
+) -> Result<EventSource, String> {
+    if model_rec.endpoint.is_empty() {
+        return Err(format!("No endpoint configured for {}", model_rec.id));
+    }
+    let data = anthropic_request_body(model_rec, prompt, sampling_parameters, true)?;
+    let builder = client.post(llm_endpoint(&model_rec.endpoint))
+        .query(&model_rec.query_params)
+        .headers(anthropic_headers(model_rec)?)
+        .body(data.to_string());
+    EventSource::new(builder).map_err(|e|
+        format!("can't stream from {}: {}", model_rec.endpoint, e)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anthropic_response_to_openai() {
+        let resp = json!({
+            "id": "msg_1", "model": "claude", "stop_reason": "tool_use",
+            "content": [
+                {"type": "thinking", "thinking": "hmm", "signature": "sig"},
+                {"type": "text", "text": "Let me look"},
+                {"type": "tool_use", "id": "toolu_1", "name": "cat", "input": {"paths": "a.py"}},
+            ],
+            "usage": {"input_tokens": 10, "output_tokens": 5, "cache_creation_input_tokens": 100, "cache_read_input_tokens": 1000},
+        });
+        let openai = anthropic_response_to_openai(&resp);
+        let message = &openai["choices"][0]["message"];
+        assert_eq!(message["content"], "Let me look");
+        assert_eq!(message["tool_calls"][0]["function"]["arguments"], "{\"paths\":\"a.py\"}");
+        assert_eq!(message["thinking_blocks"][0]["signature"], "sig");
+        assert_eq!(openai["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(openai["usage"]["prompt_tokens"], 1110);
+        assert_eq!(openai["usage"]["cache_read_input_tokens"], 1000);
+    }
+
+    #[test]
+    fn test_anthropic_stream_events() {
+        let events = vec![
+            json!({"type": "message_start", "message": {"model": "claude", "usage": {"input_tokens": 10, "cache_read_input_tokens": 90, "output_tokens": 1}}}),
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "thinking", "thinking": ""}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "thinking_delta", "thinking": "hmm"}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "signature_delta", "signature": "sig"}}),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "content_block_start", "index": 1, "content_block": {"type": "tool_use", "id": "toolu_1", "name": "cat", "input": {}}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "{\"paths\":"}}),
+            json!({"type": "ping"}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "tool_use"}, "usage": {"output_tokens": 20}}),
+        ];
+        let mut state = AnthropicStreamState::default();
+        let chunks: Vec<Value> = events.iter().filter_map(|x| state.event_to_openai_chunk(x)).collect();
+        assert_eq!(chunks.len(), 6);
+        assert_eq!(chunks[1]["choices"][0]["delta"]["reasoning_content"], "hmm");
+        assert_eq!(chunks[2]["choices"][0]["delta"]["thinking_blocks"][0]["signature"], "sig");
+        assert_eq!(chunks[2]["choices"][0]["delta"]["thinking_blocks"][0]["index"], 0);
+        assert_eq!(chunks[3]["choices"][0]["delta"]["tool_calls"][0]["id"], "toolu_1");
+        assert_eq!(chunks[4]["choices"][0]["delta"]["tool_calls"][0]["index"], 0);
+        assert_eq!(chunks[5]["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!((chunks[5]["usage"]["prompt_tokens"].as_u64(), chunks[5]["usage"]["completion_tokens"].as_u64()), (Some(100), Some(20)));
+    }
+
+    #[test]
+    fn test_anthropic_request_body() {
+        let prompt = format!("PASSTHROUGH {}", json!({
+            "system": [{"type": "text", "text": "You are a bot"}],
+            "messages": [{"role": "user", "content": [{"type": "text", "text": "hi"}]}],
+            "tools": [{"type": "function", "function": {"name": "cat", "description": "read", "parameters": {"type": "object"}}}],
+            "tool_choice": "auto",
+        }));
+        let model_rec = BaseModelRecord { name: "claude".to_string(), ..Default::default() };
+        let params = SamplingParameters { max_new_tokens: 100, stop: vec!["\n".to_string()], temperature: Some(0.2), ..Default::default() };
+        let data = anthropic_request_body(&model_rec, &prompt, &params, true).unwrap();
+        assert_eq!(data["system"][0]["cache_control"]["type"], "ephemeral");
+        assert_eq!(data["tools"][0]["input_schema"]["type"], "object");
+        assert_eq!(data["tools"][0]["cache_control"]["type"], "ephemeral");
+        assert!(data.get("tool_choice").is_none() && data.get("stop_sequences").is_none());
+        assert!(anthropic_request_body(&model_rec, "def f():", &params, false).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use reqwest::header::AUTHORIZATION;
 use reqwest::header::CONTENT_TYPE;
-use reqwest::header::HeaderMap;
+use reqwest::header::RETRY_AFTER;
 use reqwest::header::HeaderValue;
 use reqwest_eventsource::EventSource;
 use serde_json::json;
@@ -8,6 +8,8 @@ use tokio::sync::Mutex as AMutex;
 
 use crate::call_validation::{ChatMeta, SamplingParameters};
 use crate::caps::BaseModelRecord;
+use crate::llm_traffic::llm_endpoint;
+use crate::model_failover::endpoint_status_error;
 use crate::caps::EmbeddingModelRecord;
 
 // Idea: use USER_AGENT
@@ -21,11 +23,8 @@ pub async fn forward_to_hf_style_endpoint(
     sampling_parameters: &SamplingParameters,
     meta: Option<ChatMeta>
 ) -> Result<serde_json::Value, String> {
-    let mut headers = HeaderMap::new();
+    let mut headers = model_rec.auth_headers(AUTHORIZATION.as_str())?;
     headers.insert(CONTENT_TYPE, HeaderValue::from_str("application/json").unwrap());
-    if !model_rec.api_key.is_empty() {
-        headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", model_rec.api_key)).unwrap());
-    }
     let params_string = serde_json::to_string(sampling_parameters).unwrap();
     let mut params_json = serde_json::from_str::<serde_json::Value>(&params_string).unwrap();
     params_json["return_full_text"] = serde_json::Value::Bool(false);
@@ -38,18 +37,20 @@ pub async fn forward_to_hf_style_endpoint(
         data["meta"] = serde_json::to_value(meta).unwrap();
     }
     
-    let req = client.post(&model_rec.endpoint)
+    let req = client.post(llm_endpoint(&model_rec.endpoint))
+        .query(&model_rec.query_params)
         .headers(headers)
         .body(data.to_string())
         .send()
         .await;
     let resp = req.map_err(|e| format!("{}", e))?;
     let status_code = resp.status().as_u16();
+    let retry_after = resp.headers().get(RETRY_AFTER).cloned();
     let response_txt = resp.text().await.map_err(|e|
         format!("reading from socket {}: {}", model_rec.endpoint, e)
     )?;
     if status_code != 200 {
-        return Err(format!("{} status={} text {}", model_rec.endpoint, status_code, response_txt));
+        return Err(endpoint_status_error(&model_rec.endpoint, status_code, retry_after.as_ref(), &response_txt));
     }
     Ok(match serde_json::from_str(&response_txt) {
         Ok(json) => json,
@@ -65,11 +66,8 @@ pub async fn forward_to_hf_style_endpoint_streaming(
     sampling_parameters: &SamplingParameters,
     meta: Option<ChatMeta>
 ) -> Result<EventSource, String> {
-    let mut headers = HeaderMap::new();
+    let mut headers = model_rec.auth_headers(AUTHORIZATION.as_str())?;
     headers.insert(CONTENT_TYPE, HeaderValue::from_str("application/json").unwrap());
-    if !model_rec.api_key.is_empty() {
-        headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", model_rec.api_key)).unwrap());
-    }
     let params_string = serde_json::to_string(sampling_parameters).unwrap();
     let mut params_json = serde_json::from_str::<serde_json::Value>(&params_string).unwrap();
     params_json["return_full_text"] = serde_json::Value::Bool(false);
@@ -83,7 +81,8 @@ pub async fn forward_to_hf_style_endpoint_streaming(
         data["meta"] = serde_json::to_value(meta).unwrap();
     }
 
-    let builder = client.post(&model_rec.endpoint)
+    let builder = client.post(llm_endpoint(&model_rec.endpoint))
+        .query(&model_rec.query_params)
         .headers(headers)
         .body(data.to_string());
     let event_source: EventSource = EventSource::new(builder).map_err(|e|
@@ -117,8 +116,9 @@ pub async fn get_embedding_hf_style(
     let payload = EmbeddingsPayloadHF { inputs: text, options: EmbeddingsPayloadHFOptions::new() };
 
     let maybe_response = client.lock().await
-        .post(&model.base.endpoint)
-        .bearer_auth(model.base.api_key.clone())
+        .post(llm_endpoint(&model.base.endpoint))
+        .query(&model.base.query_params)
+        .headers(model.base.auth_headers(AUTHORIZATION.as_str())?)
         .json(&payload)
         .send()
         .await;
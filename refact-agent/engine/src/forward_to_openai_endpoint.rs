@@ -1,7 +1,7 @@
 use reqwest::header::AUTHORIZATION;
 use reqwest::header::CONTENT_TYPE;
+use reqwest::header::RETRY_AFTER;
 use reqwest::header::USER_AGENT;
-use reqwest::header::HeaderMap;
 use reqwest::header::HeaderValue;
 use reqwest_eventsource::EventSource;
 use serde_json::json;
@@ -10,6 +10,8 @@ use tracing::info;
 
 use crate::call_validation::{ChatMeta, SamplingParameters};
 use crate::caps::BaseModelRecord;
+use crate::llm_traffic::llm_endpoint;
+use crate::model_failover::endpoint_status_error;
 use crate::custom_error::MapErrToString;
 use crate::scratchpads::chat_utils_limit_history::CompressionStrength;
 use crate::caps::EmbeddingModelRecord;
@@ -22,11 +24,8 @@ pub async fn forward_to_openai_style_endpoint(
     meta: Option<ChatMeta>
 ) -> Result<serde_json::Value, String> {
     let is_passthrough = prompt.starts_with("PASSTHROUGH ");
-    let mut headers = HeaderMap::new();
+    let mut headers = model_rec.auth_headers(AUTHORIZATION.as_str())?;
     headers.insert(CONTENT_TYPE, HeaderValue::from_str("application/json").unwrap());
-    if !model_rec.api_key.is_empty() {
-        headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", model_rec.api_key)).unwrap());
-    }
     if model_rec.support_metadata {
         headers.insert(USER_AGENT, HeaderValue::from_str(&format!("refact-lsp {}", crate::version::build::PKG_VERSION)).unwrap());
     }
@@ -68,20 +67,22 @@ pub async fn forward_to_openai_style_endpoint(
     }
 
     // When cancelling requests, coroutine ususally gets aborted here on the following line.
-    let req = client.post(&model_rec.endpoint)
+    let req = client.post(llm_endpoint(&model_rec.endpoint))
+        .query(&model_rec.query_params)
         .headers(headers)
         .body(data.to_string())
         .send()
         .await;
     let resp = req.map_err_to_string()?;
     let status_code = resp.status().as_u16();
+    let retry_after = resp.headers().get(RETRY_AFTER).cloned();
     let response_txt = resp.text().await.map_err(|e|
         format!("reading from socket {}: {}", model_rec.endpoint, e)
     )?;
     // 400 "client error" is likely a json that we rather accept here, pick up error details as we analyse json fields at the level
     // higher, the most often 400 is no such model.
     if status_code != 200 && status_code != 400 {
-        return Err(format!("{} status={} text {}", model_rec.endpoint, status_code, response_txt));
+        return Err(endpoint_status_error(&model_rec.endpoint, status_code, retry_after.as_ref(), &response_txt));
     }
     if status_code != 200 {
         tracing::info!("forward_to_openai_style_endpoint: {} {}\n{}", model_rec.endpoint, status_code, response_txt);
@@ -101,11 +102,8 @@ pub async fn forward_to_openai_style_endpoint_streaming(
     meta: Option<ChatMeta>
 ) -> Result<EventSource, String> {
     let is_passthrough = prompt.starts_with("PASSTHROUGH ");
-    let mut headers = HeaderMap::new();
+    let mut headers = model_rec.auth_headers(AUTHORIZATION.as_str())?;
     headers.insert(CONTENT_TYPE, HeaderValue::from_str("application/json").unwrap());
-    if !model_rec.api_key.is_empty() {
-        headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", model_rec.api_key)).unwrap());
-    }
     if model_rec.support_metadata {
         headers.insert(USER_AGENT, HeaderValue::from_str(format!("refact-lsp {}", crate::version::build::PKG_VERSION).as_str()).unwrap());
     }
@@ -155,7 +153,8 @@ pub async fn forward_to_openai_style_endpoint_streaming(
     if model_rec.endpoint.is_empty() {
         return Err(format!("No endpoint configured for {}", model_rec.id));
     }
-    let builder = client.post(&model_rec.endpoint)
+    let builder = client.post(llm_endpoint(&model_rec.endpoint))
+        .query(&model_rec.query_params)
         .headers(headers)
         .body(data.to_string());
     let event_source: EventSource = EventSource::new(builder).map_err(|e|
@@ -232,8 +231,9 @@ pub async fn get_embedding_openai_style(
         model: model_rec.base.name.to_string(),
     };
     let response = client.lock().await
-        .post(&model_rec.base.endpoint)
-        .bearer_auth(&model_rec.base.api_key)
+        .post(llm_endpoint(&model_rec.base.endpoint))
+        .query(&model_rec.base.query_params)
+        .headers(model_rec.base.auth_headers(AUTHORIZATION.as_str())?)
         .json(&payload)
         .send()
         .await
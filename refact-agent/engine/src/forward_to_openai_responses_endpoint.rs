@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use reqwest::header::AUTHORIZATION;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::header::RETRY_AFTER;
+use reqwest::header::USER_AGENT;
+use reqwest::header::HeaderMap;
+use reqwest::header::HeaderValue;
+use reqwest_eventsource::EventSource;
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::call_validation::{ChatUsage, SamplingParameters};
+use crate::caps::BaseModelRecord;
+use crate::llm_traffic::llm_endpoint;
+use crate::model_failover::endpoint_status_error;
+use crate::custom_error::MapErrToString;
+
+// Responses API, https://platform.openai.com/docs/api-reference/responses
+// Requests are made from the PASSTHROUGH prompt (see convert_messages_to_openai_responses_format), responses
+// and stream events are converted to the OpenAI chat format that the rest of the code expects. Nothing is
+// stored on the server, reasoning items come back encrypted and travel in the assistant's thinking_blocks.
+
+
+fn responses_headers(model_rec: &BaseModelRecord) -> Result<HeaderMap, String> {
+    let mut headers = model_rec.auth_headers(AUTHORIZATION.as_str())?;
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    if model_rec.support_metadata {
+        headers.insert(USER_AGENT, HeaderValue::from_str(&format!("refact-lsp {}", crate::version::build::PKG_VERSION)).unwrap());
+    }
+    Ok(headers)
+}
+
+fn responses_tool(openai_tool: &Value) -> Value {
+    let function = openai_tool.get("function").unwrap_or(openai_tool);
+    json!({
+        "type": "function",
+        "name": function["name"],
+        "description": function.get("description").cloned().unwrap_or(json!("")),
+        "parameters": function.get("parameters").cloned().unwrap_or(json!({"type": "object", "properties": {}})),
+    })
+}
+
+fn responses_tool_choice(tool_choice: &Value) -> Option<Value> {
+    match tool_choice.as_str() {
+        Some(choice) => Some(json!(choice)),
+        None => tool_choice.get("function").and_then(|f| f.get("name")).map(|name| json!({"type": "function", "name": name})),
+    }
+}
+
+fn responses_request_body(
+    model_rec: &BaseModelRecord,
+    prompt: &str,
+    sampling_parameters: &SamplingParameters,
+    stream: bool,
+) -> Result<Value, String> {
+    if !prompt.starts_with("PASSTHROUGH ") {
+        return Err(format!("endpoint_style openai_responses supports chat only, model {}", model_rec.id));
+    }
+    let big_json: Value = serde_json::from_str(&prompt[12..]).map_err_to_string()?;
+    let mut data = json!({
+        "model": model_rec.name.clone(),
+        "input": big_json["messages"],
+        "max_output_tokens": sampling_parameters.max_new_tokens,
+        "store": false,
+        "stream": stream,
+    });
+    let tools = big_json.get("tools").and_then(|x| x.as_array()).map(|x| x.iter().map(responses_tool).collect::<Vec<_>>()).unwrap_or_default();
+    if !tools.is_empty() {
+        data["tools"] = json!(tools);
+        if let Some(tool_choice) = big_json.get("tool_choice").and_then(responses_tool_choice) {
+            data["tool_choice"] = tool_choice;
+        }
+    }
+    // reasoning_effort is set for reasoning models only, others reject the reasoning parameters
+    if let Some(reasoning_effort) = sampling_parameters.reasoning_effort.clone() {
+        data["reasoning"] = json!({"effort": reasoning_effort.to_string(), "summary": "auto"});
+        data["include"] = json!(["reasoning.encrypted_content"]);
+    }
+    if let Some(temperature) = sampling_parameters.temperature {
+        data["temperature"] = json!(temperature);
+    }
+    if sampling_parameters.n.unwrap_or(1) > 1 {
+        info!("endpoint_style openai_responses doesn't support n={}, generating one choice", sampling_parameters.n.unwrap());
+    }
+    info!("Request: model={}, reasoning_effort={}, T={}, stream={}",
+        model_rec.name,
+        sampling_parameters.reasoning_effort.clone().map(|x| x.to_string()).unwrap_or("none".to_string()),
+        sampling_parameters.temperature.map(|x| x.to_string()).unwrap_or("none".to_string()),
+        stream,
+    );
+    Ok(data)
+}
+
+fn openai_finish_reason(response: &Value, has_tool_calls: bool) -> Value {
+    let incomplete_reason = response.get("incomplete_details").and_then(|x| x.get("reason")).and_then(|x| x.as_str());
+    if incomplete_reason == Some("max_output_tokens") {
+        json!("length")
+    } else if has_tool_calls {
+        json!("tool_calls")
+    } else {
+        json!("stop")
+    }
+}
+
+fn usage_from_responses(usage: &Value) -> ChatUsage {
+    let get = |value: &Value| value.as_u64().unwrap_or(0) as usize;
+    let prompt_tokens = get(&usage["input_tokens"]);
+    let completion_tokens = get(&usage["output_tokens"]);
+    ChatUsage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+        cache_creation_input_tokens: None,
+        cache_read_input_tokens: Some(get(&usage["input_tokens_details"]["cached_tokens"])),
+        reasoning_tokens: Some(get(&usage["output_tokens_details"]["reasoning_tokens"])),
+        cost: None,
+    }
+}
+
+fn reasoning_summary_text(item: &Value) -> String {
+    item.get("summary").and_then(|x| x.as_array()).map(|parts| {
+        parts.iter().filter_map(|x| x.get("text").and_then(|x| x.as_str())).collect::<Vec<_>>().join("\n\n")
+    }).unwrap_or_default()
+}
+
+fn responses_error(response: &Value) -> Option<Value> {
+    response.get("error").filter(|x| !x.is_null()).map(|error| json!({"error": error}))
+}
+
+/// Non-streaming Responses API response as an OpenAI chat completion with one choice
+pub fn responses_response_to_openai(resp: &Value) -> Value {
+    if let Some(error) = responses_error(resp) {
+        return error;
+    }
+    let mut content = String::new();
+    let mut thinking_blocks = vec![];
+    let mut tool_calls = vec![];
+    for item in resp.get("output").and_then(|x| x.as_array()).cloned().unwrap_or_default() {
+        match item.get("type").and_then(|x| x.as_str()).unwrap_or_default() {
+            "message" => for part in item.get("content").and_then(|x| x.as_array()).cloned().unwrap_or_default() {
+                content.push_str(part.get("text").or(part.get("refusal")).and_then(|x| x.as_str()).unwrap_or_default());
+            },
+            "reasoning" => thinking_blocks.push(item),
+            "function_call" => tool_calls.push(json!({
+                "id": item["call_id"],
+                "type": "function",
+                "function": {"name": item["name"], "arguments": item["arguments"]},
+                "index": tool_calls.len(),
+            })),
+            other => info!("responses api: skipping output item of type {:?}", other),
+        }
+    }
+    let mut message = json!({"role": "assistant", "content": content});
+    let finish_reason = openai_finish_reason(resp, !tool_calls.is_empty());
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = json!(tool_calls);
+    }
+    if !thinking_blocks.is_empty() {
+        message["reasoning_content"] = json!(thinking_blocks.iter().map(reasoning_summary_text).collect::<Vec<_>>().join("\n\n"));
+        message["thinking_blocks"] = json!(thinking_blocks);
+    }
+    json!({
+        "id": resp["id"],
+        "object": "chat.completion",
+        "model": resp["model"],
+        "choices": [{"index": 0, "message": message, "finish_reason": finish_reason}],
+        "usage": usage_from_responses(&resp["usage"]),
+    })
+}
+
+/// Turns Responses API stream events into OpenAI chat completion chunks, `finished` is set by the last event
+#[derive(Default)]
+pub struct ResponsesStreamState {
+    model: String,
+    tool_call_by_output: HashMap<u64, usize>,
+    pub finished: bool,
+}
+
+impl ResponsesStreamState {
+    fn chunk(&self, delta: Value, finish_reason: Value) -> Value {
+        json!({
+            "object": "chat.completion.chunk",
+            "model": self.model,
+            "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+        })
+    }
+
+    pub fn event_to_openai_chunk(&mut self, event: &Value) -> Option<Value> {
+        let output_index = event.get("output_index").and_then(|x| x.as_u64()).unwrap_or(0);
+        let delta = event.get("delta").and_then(|x| x.as_str()).unwrap_or_default();
+        match event.get("type").and_then(|x| x.as_str()).unwrap_or_default() {
+            "response.created" => {
+                self.model = event["response"].get("model").and_then(|x| x.as_str()).unwrap_or_default().to_string();
+                Some(self.chunk(json!({"role": "assistant", "content": ""}), Value::Null))
+            },
+            "response.output_text.delta" | "response.refusal.delta" => Some(self.chunk(json!({"content": delta}), Value::Null)),
+            "response.reasoning_summary_text.delta" => Some(self.chunk(json!({"reasoning_content": delta}), Value::Null)),
+            "response.reasoning_summary_part.added" if event["summary_index"].as_u64().unwrap_or(0) > 0 => {
+                Some(self.chunk(json!({"reasoning_content": "\n\n"}), Value::Null))
+            },
+            "response.output_item.added" => {
+                let item = event.get("item").cloned().unwrap_or_default();
+                if item.get("type").and_then(|x| x.as_str()) != Some("function_call") {
+                    return None;
+                }
+                let tool_call_index = self.tool_call_by_output.len();
+                self.tool_call_by_output.insert(output_index, tool_call_index);
+                Some(self.chunk(json!({"tool_calls": [{
+                    "index": tool_call_index,
+                    "id": item["call_id"],
+                    "type": "function",
+                    "function": {"name": item["name"], "arguments": item.get("arguments").cloned().unwrap_or(json!(""))},
+                }]}), Value::Null))
+            },
+            "response.function_call_arguments.delta" => self.tool_call_by_output.get(&output_index).map(|tool_call_index| self.chunk(json!({"tool_calls": [{
+                "index": tool_call_index,
+                "function": {"arguments": delta},
+            }]}), Value::Null)),
+            // the encrypted content arrives with the complete item only, the index keeps items apart when the client merges deltas
+            "response.output_item.done" if event["item"].get("type").and_then(|x| x.as_str()) == Some("reasoning") => {
+                let mut item = event["item"].clone();
+                item["index"] = json!(output_index);
+                Some(self.chunk(json!({"reasoning_content": "", "thinking_blocks": [item]}), Value::Null))
+            },
+            "response.completed" | "response.incomplete" => {
+                self.finished = true;
+                let response = event.get("response").cloned().unwrap_or_default();
+                let mut chunk = self.chunk(json!({}), openai_finish_reason(&response, !self.tool_call_by_output.is_empty()));
+                chunk["usage"] = json!(usage_from_responses(&response["usage"]));
+                Some(chunk)
+            },
+            "response.failed" => {
+                self.finished = true;
+                responses_error(&event["response"])
+            },
+            "error" => Some(json!({"error": {"code": event["code"], "message": event["message"]}})),
+            _ => None,
+        }
+    }
+}
+
+pub async fn forward_to_openai_responses_endpoint(
+    model_rec: &BaseModelRecord,
+    prompt: &str,
+    client: &reqwest::Client,
+    sampling_parameters: &SamplingParameters,
+) -> Result<Value, String> {
+    let data = responses_request_body(model_rec, prompt, sampling_parameters, false)?;
+    let resp = client.post(llm_endpoint(&model_rec.endpoint))
+        .query(&model_rec.query_params)
+        .headers(responses_headers(model_rec)?)
+        .body(data.to_string())
+        .send()
+        .await
+        .map_err_to_string()?;
+    let status_code = resp.status().as_u16();
+    let retry_after = resp.headers().get(RETRY_AFTER).cloned();
+    let response_txt = resp.text().await.map_err(|e|
+        format!("reading from socket {}: {}", model_rec.endpoint, e)
+    )?;
+    if status_code != 200 && status_code != 400 {
+        return Err(endpoint_status_error(&model_rec.endpoint, status_code, retry_after.as_ref(), &response_txt));
+    }
+    let parsed_json: Value = serde_json::from_str(&response_txt)
+        .map_err(|e| format!("Failed to parse JSON response: {}\n{}", e, response_txt))?;
+    Ok(responses_response_to_openai(&parsed_json))
+}
+
+pub async fn forward_to_openai_responses_endpoint_streaming(
+    model_rec: &BaseModelRecord,
+    prompt: &str,
+    client: &reqwest::Client,
+    sampling_parameters: &SamplingParameters,
+) -> Result<EventSource, String> {
+    if model_rec.endpoint.is_empty() {
+        return Err(format!("No endpoint configured for {}", model_rec.id));
+    }
+    let data = responses_request_body(model_rec, prompt, sampling_parameters, true)?;
+    let builder = client.post(llm_endpoint(&model_rec.endpoint))
+        .query(&model_rec.query_params)
+        .headers(responses_headers(model_rec)?)
+        .body(data.to_string());
+    EventSource::new(builder).map_err(|e|
+        format!("can't stream from {}: {}", model_rec.endpoint, e)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::call_validation::ReasoningEffort;
+
+    #[test]
+    fn test_responses_response_to_openai() {
+        let resp = json!({
+            "id": "resp_1", "model": "o4-mini", "status": "completed",
+            "output": [
+                {"type": "reasoning", "id": "rs_1", "encrypted_content": "xxx", "summary": [{"type": "summary_text", "text": "hmm"}]},
+                {"type": "message", "role": "assistant", "content": [{"type": "output_text", "text": "Let me look"}]},
+                {"type": "function_call", "id": "fc_1", "call_id": "call_1", "name": "cat", "arguments": "{\"paths\":\"a.py\"}"},
+            ],
+            "usage": {"input_tokens": 1100, "input_tokens_details": {"cached_tokens": 1000}, "output_tokens": 5},
+        });
+        let openai = responses_response_to_openai(&resp);
+        let message = &openai["choices"][0]["message"];
+        assert_eq!(message["content"], "Let me look");
+        assert_eq!(message["tool_calls"][0]["id"], "call_1");
+        assert_eq!(message["reasoning_content"], "hmm");
+        assert_eq!(message["thinking_blocks"][0]["encrypted_content"], "xxx");
+        assert_eq!(openai["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(openai["usage"]["prompt_tokens"], 1100);
+        assert_eq!(openai["usage"]["cache_read_input_tokens"], 1000);
+    }
+
+    #[test]
+    fn test_responses_stream_events() {
+        let events = vec![
+            json!({"type": "response.created", "response": {"model": "o4-mini"}}),
+            json!({"type": "response.output_item.added", "output_index": 0, "item": {"type": "reasoning", "id": "rs_1"}}),
+            json!({"type": "response.reasoning_summary_text.delta", "output_index": 0, "delta": "hmm"}),
+            json!({"type": "response.output_item.done", "output_index": 0, "item": {"type": "reasoning", "id": "rs_1", "encrypted_content": "xxx"}}),
+            json!({"type": "response.output_item.added", "output_index": 1, "item": {"type": "function_call", "call_id": "call_1", "name": "cat", "arguments": ""}}),
+            json!({"type": "response.function_call_arguments.delta", "output_index": 1, "delta": "{\"paths\":"}),
+            json!({"type": "response.completed", "response": {"status": "completed", "usage": {"input_tokens": 100, "output_tokens": 20}}}),
+        ];
+        let mut state = ResponsesStreamState::default();
+        let chunks: Vec<Value> = events.iter().filter_map(|x| state.event_to_openai_chunk(x)).collect();
+        assert_eq!(chunks.len(), 6);
+        assert_eq!(chunks[1]["choices"][0]["delta"]["reasoning_content"], "hmm");
+        assert_eq!(chunks[2]["choices"][0]["delta"]["thinking_blocks"][0]["encrypted_content"], "xxx");
+        assert_eq!(chunks[2]["choices"][0]["delta"]["thinking_blocks"][0]["index"], 0);
+        assert_eq!(chunks[3]["choices"][0]["delta"]["tool_calls"][0]["id"], "call_1");
+        assert_eq!(chunks[4]["choices"][0]["delta"]["tool_calls"][0]["index"], 0);
+        assert_eq!(chunks[5]["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(chunks[5]["usage"]["completion_tokens"], 20);
+        assert!(state.finished);
+    }
+
+    #[test]
+    fn test_responses_request_body() {
+        let prompt = format!("PASSTHROUGH {}", json!({
+            "messages": [{"type": "message", "role": "user", "content": [{"type": "input_text", "text": "hi"}]}],
+            "tools": [{"type": "function", "function": {"name": "cat", "description": "read", "parameters": {"type": "object"}}}],
+            "tool_choice": {"type": "function", "function": {"name": "cat"}},
+        }));
+        let model_rec = BaseModelRecord { name: "o4-mini".to_string(), ..Default::default() };
+        let params = SamplingParameters { max_new_tokens: 100, reasoning_effort: Some(ReasoningEffort::High), ..Default::default() };
+        let data = responses_request_body(&model_rec, &prompt, &params, true).unwrap();
+        assert_eq!(data["tools"][0]["name"], "cat");
+        assert_eq!(data["tool_choice"]["name"], "cat");
+        assert_eq!(data["reasoning"]["effort"], "high");
+        assert_eq!(data["include"][0], "reasoning.encrypted_content");
+        assert!(data.get("temperature").is_none());
+        assert!(responses_request_body(&model_rec, "def f():", &params, false).is_err());
+    }
+}
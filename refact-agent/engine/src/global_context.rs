@@ -7,6 +7,7 @@ use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex as StdMutex;
 use std::sync::RwLock as StdRwLock;
+use std::time::{Duration, Instant};
 use hyper::StatusCode;
 use structopt::StructOpt;
 use tokenizers::Tokenizer;
@@ -54,6 +55,10 @@ pub struct CommandLine {
     pub enduser_client_version: String,
     #[structopt(long, short="b", help="Send basic telemetry (counters and errors).")]
     pub basic_telemetry: bool,
+    #[structopt(long, default_value="90", help="Keep completion statistics in a local database for this many days, see /v1/get-local-completion-stats. Zero turns it off.")]
+    pub local_stats_keep_days: u32,
+    #[structopt(long, default_value="90", help="Keep the usage ledger (tokens and costs of model calls, see /v1/usage-costs) for this many days. Zero turns it off.")]
+    pub usage_ledger_keep_days: u32,
     #[structopt(long, short="v", help="Makes DEBUG log level visible, instead of the default INFO.")]
     pub verbose: bool,
 
@@ -109,6 +114,16 @@ pub struct CommandLine {
     pub active_group_id: Option<String>,
     #[structopt(long, help="Enable cloud threads support")]
     pub cloud_threads: bool,
+
+    #[structopt(long, default_value="", help="Save requests to models and their responses into this directory, to replay them later with --llm-replay.")]
+    pub llm_record: String,
+    #[structopt(long, default_value="", help="Answer requests to models from the recordings made with --llm-record, without network. A request that wasn't recorded fails.")]
+    pub llm_replay: String,
+
+    #[structopt(long, default_value="", help="Download tokenizers of all the configured models into this directory and exit, for machines without internet access. Without a tokenizer (or with `approx://<family>` in the model config) tokens are counted with an approximate splitter, which is not a real tokenizer: counts are not exact.")]
+    pub tokenizers_export: String,
+    #[structopt(long, default_value="", help="Take tokenizers from a directory made with --tokenizers-export, instead of downloading them, and exit.")]
+    pub tokenizers_import: String,
 }
 
 impl CommandLine {
@@ -125,13 +140,13 @@ impl CommandLine {
 }
 
 pub struct AtCommandsPreviewCache {
-    pub cache: HashMap<String, String>,
+    pub cache: HashMap<String, (Instant, String)>,
 }
 
 impl AtCommandsPreviewCache {
     pub fn new() -> Self { Self { cache: HashMap::new() } }
     pub fn get(&self, key: &str) -> Option<String> {
-        let val = self.cache.get(key).cloned();
+        let val = self.cache.get(key).map(|(_, text)| text.clone());
         // if val.is_some() {
         //     info!("AtCommandsPreviewCache: SOME: key={:?}", key);
         // } else {
@@ -139,8 +154,12 @@ impl AtCommandsPreviewCache {
         // }
         val
     }
+    /// Like get(), but entries older than `ttl` are not returned, for things that change on their own
+    pub fn get_fresh(&self, key: &str, ttl: Duration) -> Option<String> {
+        self.cache.get(key).filter(|(ts, _)| ts.elapsed() < ttl).map(|(_, text)| text.clone())
+    }
     pub fn insert(&mut self, key: String, value: String) {
-        self.cache.insert(key.clone(), value);
+        self.cache.insert(key.clone(), (Instant::now(), value));
         // info!("AtCommandsPreviewCache: insert: key={:?}. new_len: {:?}", key, self.cache.len());
     }
     pub fn clear(&mut self) {
@@ -161,9 +180,11 @@ pub struct GlobalContext {
     pub caps_last_error: String,
     pub caps_last_attempted_ts: u64,
     pub tokenizer_map: HashMap<String, Option<Arc<Tokenizer>>>,
+    pub tokenizer_fallbacks: HashMap<String, (Arc<Tokenizer>, std::time::Instant)>,  // approximate ones, until it's time to try the real one again
     pub tokenizer_download_lock: Arc<AMutex<bool>>,
     pub completions_cache: Arc<StdRwLock<CompletionCache>>,
     pub telemetry: Arc<StdRwLock<telemetry_structs::Storage>>,
+    pub local_stats: Arc<AMutex<Option<crate::telemetry::local_stats::LocalStatsDb>>>,
     pub vec_db: Arc<AMutex<Option<crate::vecdb::vdb_highlev::VecDb>>>,
     pub vec_db_error: String,
     pub ast_service: Option<Arc<AMutex<AstIndexService>>>,
@@ -174,6 +195,7 @@ pub struct GlobalContext {
     pub indexing_everywhere: Arc<crate::files_blocklist::IndexingEverywhere>,
     pub integration_sessions: HashMap<String, Arc<AMutex<Box<dyn IntegrationSession>>>>,
     pub codelens_cache: Arc<AMutex<crate::http::routers::v1::code_lens::CodeLensCache>>,
+    pub completion_vecdb_cache: Arc<AMutex<crate::scratchpads::completon_rag::CompletionVecdbCache>>,
     pub docker_ssh_tunnel: Arc<AMutex<Option<SshTunnel>>>,
     pub active_group_id: Option<String>,
     pub init_shadow_repos_background_task_holder: BackgroundTasksHolder,
@@ -385,7 +407,14 @@ pub async fn create_global_context(
     cache_dir: PathBuf,
     config_dir: PathBuf,
 ) -> (Arc<ARwLock<GlobalContext>>, std::sync::mpsc::Receiver<String>, CommandLine) {
-    let cmdline = CommandLine::from_args();
+    create_global_context_with_cmdline(cache_dir, config_dir, CommandLine::from_args()).await
+}
+
+pub async fn create_global_context_with_cmdline(
+    cache_dir: PathBuf,
+    config_dir: PathBuf,
+    cmdline: CommandLine,
+) -> (Arc<ARwLock<GlobalContext>>, std::sync::mpsc::Receiver<String>, CommandLine) {
     let (ask_shutdown_sender, ask_shutdown_receiver) = std::sync::mpsc::channel::<String>();
     let mut http_client_builder = reqwest::Client::builder();
     if cmdline.insecure {
@@ -410,9 +439,11 @@ pub async fn create_global_context(
         caps_last_error: String::new(),
         caps_last_attempted_ts: 0,
         tokenizer_map: HashMap::new(),
+        tokenizer_fallbacks: HashMap::new(),
         tokenizer_download_lock: Arc::new(AMutex::<bool>::new(false)),
         completions_cache: Arc::new(StdRwLock::new(CompletionCache::new())),
         telemetry: Arc::new(StdRwLock::new(telemetry_structs::Storage::new())),
+        local_stats: Arc::new(AMutex::new(None)),
         vec_db: Arc::new(AMutex::new(None)),
         vec_db_error: String::new(),
         ast_service: None,
@@ -423,6 +454,7 @@ pub async fn create_global_context(
         indexing_everywhere: Arc::new(crate::files_blocklist::IndexingEverywhere::default()),
         integration_sessions: HashMap::new(),
         codelens_cache: Arc::new(AMutex::new(crate::http::routers::v1::code_lens::CodeLensCache::default())),
+        completion_vecdb_cache: Arc::new(AMutex::new(crate::scratchpads::completon_rag::CompletionVecdbCache::default())),
         docker_ssh_tunnel: Arc::new(AMutex::new(None)),
         active_group_id: cmdline.active_group_id.clone(),
         init_shadow_repos_background_task_holder: BackgroundTasksHolder::new(vec![]),
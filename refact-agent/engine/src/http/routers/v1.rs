@@ -14,7 +14,7 @@ use crate::http::routers::v1::caps::handle_v1_ping;
 use crate::http::routers::v1::chat::{handle_v1_chat, handle_v1_chat_completions};
 use crate::http::routers::v1::chat_based_handlers::{handle_v1_commit_message_from_diff, handle_v1_trajectory_compress};
 use crate::http::routers::v1::chat_based_handlers::handle_v1_trajectory_save;
-use crate::http::routers::v1::dashboard::get_dashboard_plots;
+use crate::http::routers::v1::dashboard::{get_dashboard_plots, get_local_completion_stats, get_usage_costs};
 use crate::http::routers::v1::docker::{handle_v1_docker_container_action, handle_v1_docker_container_list};
 use crate::http::routers::v1::git::{handle_v1_git_commit, handle_v1_checkpoints_preview, handle_v1_checkpoints_restore};
 use crate::http::routers::v1::graceful_shutdown::handle_v1_graceful_shutdown;
@@ -35,7 +35,7 @@ use crate::http::routers::v1::providers::{handle_v1_providers, handle_v1_provide
     handle_v1_delete_model, handle_v1_delete_provider, handle_v1_model_default, handle_v1_completion_model_families};
 
 use crate::http::routers::v1::vecdb::{handle_v1_vecdb_search, handle_v1_vecdb_status};
-use crate::http::routers::v1::v1_integrations::{handle_v1_integration_get, handle_v1_integration_icon, handle_v1_integration_save, handle_v1_integration_delete, handle_v1_integrations, handle_v1_integrations_filtered, handle_v1_integrations_mcp_logs};
+use crate::http::routers::v1::v1_integrations::{handle_v1_integration_get, handle_v1_integration_icon, handle_v1_integration_save, handle_v1_integration_delete, handle_v1_integrations, handle_v1_integrations_filtered, handle_v1_integrations_mcp_logs, handle_v1_services_status};
 use crate::http::routers::v1::file_edit_tools::handle_v1_file_edit_tool_dry_run;
 use crate::http::routers::v1::workspace::{handle_v1_get_app_searchable_id, handle_v1_set_active_group_id};
 
@@ -82,6 +82,8 @@ pub fn make_v1_router() -> Router {
         .route("/telemetry-network", post(handle_v1_telemetry_network))
         .route("/telemetry-chat", post(handle_v1_telemetry_chat))
         .route("/snippet-accepted", post(handle_v1_snippet_accepted))
+        .route("/get-local-completion-stats", get(get_local_completion_stats))
+        .route("/usage-costs", get(get_usage_costs))
 
         .route("/caps", get(handle_v1_caps))
 
@@ -125,6 +127,7 @@ pub fn make_v1_router() -> Router {
         .route("/integration-delete", delete(handle_v1_integration_delete))
         .route("/integration-icon/:icon_name", get(handle_v1_integration_icon))
         .route("/integrations-mcp-logs", post(handle_v1_integrations_mcp_logs))
+        .route("/services-status", get(handle_v1_services_status))
 
         .route("/docker-container-list", post(handle_v1_docker_container_list))
         .route("/docker-container-action", post(handle_v1_docker_container_action))
@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock as ARwLock;
 use tokio::sync::Mutex as AMutex;
 
@@ -18,6 +19,7 @@ use crate::at_commands::at_commands::AtCommandsContext;
 
 
 const CODE_COMPLETION_TOP_N: usize = 5;
+const IN_FLIGHT_WAIT_MS: u64 = 300;
 
 pub async fn handle_v1_code_completion(
     gcx: Arc<ARwLock<GlobalContext>>,
@@ -40,13 +42,26 @@ pub async fn handle_v1_code_completion(
     }
     info!("chosen completion model: {}, scratchpad: {}", code_completion_post.model, model_rec.scratchpad);
     code_completion_post.parameters.temperature = Some(code_completion_post.parameters.temperature.unwrap_or(0.2));
+    // streaming shows only one candidate, several of them are ranked and returned without streaming
+    if code_completion_post.stream || code_completion_post.parameters.n == Some(1) {
+        code_completion_post.parameters.n = None;
+    }
     let (cache_arc, tele_storage) = {
         let gcx_locked = gcx.write().await;
         (gcx_locked.completions_cache.clone(), gcx_locked.telemetry.clone())
     };
-    if !code_completion_post.no_cache {
+    // next edit depends on the edit history, not only on the text before the cursor,
+    // the cache keeps one completion, a request for several candidates goes to the model
+    if !code_completion_post.no_cache && !code_completion_post.next_edit && code_completion_post.parameters.n.is_none() {
         let cache_key = completion_cache::cache_key_from_post(&code_completion_post);
-        let cached_maybe = completion_cache::cache_get(cache_arc.clone(), cache_key.clone());
+        let mut cached_maybe = completion_cache::cache_get(cache_arc.clone(), cache_key.clone());
+        if cached_maybe.is_none() {
+            // rapid keystrokes: wait for the request made a few chars ago, its completion might still fit
+            if let Some(in_flight) = completion_cache::cache_in_flight(cache_arc.clone(), &code_completion_post.inputs.cursor, &cache_key) {
+                let _ = tokio::time::timeout(Duration::from_millis(IN_FLIGHT_WAIT_MS), in_flight.lock()).await;
+                cached_maybe = completion_cache::cache_get(cache_arc.clone(), cache_key.clone());
+            }
+        }
         if let Some(cached_json_value) = cached_maybe {
             // info!("cache hit for key {:?}", cache_key.clone());
             if !code_completion_post.stream {
@@ -1,4 +1,5 @@
 use axum::Extension;
+use axum::extract::Query;
 use axum::http::{Response, StatusCode};
 use hyper::Body;
 use crate::custom_error::ScratchError;
@@ -11,6 +12,7 @@ use tokio::io;
 use tokio::io::AsyncBufReadExt;
 use crate::dashboard::dashboard::records2plots;
 use crate::dashboard::structs::RHData;
+use crate::telemetry::local_stats::local_stats_get;
 
 
 #[derive(Debug, Deserialize)]
@@ -24,6 +26,11 @@ struct DashboardPlotsResponse {
     data: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct LocalStatsQueryParams {
+    days: Option<u32>,
+}
+
 async fn fetch_data(
     http_client: &reqwest::Client,
     url: &String,
@@ -101,3 +108,33 @@ pub async fn get_dashboard_plots(
         .body(Body::from(body))
         .unwrap())
 }
+
+pub async fn get_local_completion_stats(
+    Extension(global_context): Extension<SharedGlobalContext>,
+    Query(params): Query<LocalStatsQueryParams>,
+) -> axum::response::Result<Response<Body>, ScratchError> {
+    let stats = local_stats_get(global_context.clone(), params.days).await
+        .map_err(|e| ScratchError::new(StatusCode::SERVICE_UNAVAILABLE, e))?;
+    let body = serde_json::to_string_pretty(&stats)
+        .map_err(|e| ScratchError::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Error serializing stats: {}", e)))?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .unwrap())
+}
+
+/// Spend on models from the local usage ledger, by day, model and chat, for the last `days` (30 by default)
+pub async fn get_usage_costs(
+    Extension(global_context): Extension<SharedGlobalContext>,
+    Query(params): Query<LocalStatsQueryParams>,
+) -> axum::response::Result<Response<Body>, ScratchError> {
+    let since_ts = chrono::Local::now().timestamp() - params.days.unwrap_or(30) as i64 * 86400;
+    let summary = crate::usage_ledger::summarize_usage(global_context.clone(), since_ts).await
+        .map_err(|e| ScratchError::new(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string_pretty(&summary).unwrap()))
+        .unwrap())
+}
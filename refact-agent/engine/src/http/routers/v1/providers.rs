@@ -12,6 +12,8 @@ use crate::call_validation::ModelType;
 use crate::caps::{ChatModelRecord, CompletionModelFamily, CompletionModelRecord, EmbeddingModelRecord, HasBaseModelRecord};
 use crate::custom_error::{MapErrToString, ScratchError};
 use crate::global_context::{try_load_caps_quickly_if_not_present, GlobalContext};
+use crate::caps::model_discovery::discover_and_merge_models;
+use crate::secrets::redact_secrets;
 use crate::caps::providers::{get_known_models, get_provider_from_server, get_provider_from_template_and_config_file, get_provider_model_default_settings_ui, get_provider_templates, read_providers_d, CapsProvider};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -46,8 +48,8 @@ impl ProviderDTO {
             chat_endpoint: provider.chat_endpoint,
             completion_endpoint: if provider.supports_completion { provider.completion_endpoint } else { String::new() },
             embedding_endpoint: provider.embedding_endpoint,
-            api_key: provider.api_key,
-            tokenizer_api_key: provider.tokenizer_api_key,
+            api_key: redact_secrets(&provider.api_key),
+            tokenizer_api_key: redact_secrets(&provider.tokenizer_api_key),
             chat_default_model: provider.defaults.chat_default_model,
             chat_light_model: provider.defaults.chat_light_model,
             chat_thinking_model: provider.defaults.chat_thinking_model,
@@ -292,14 +294,22 @@ pub async fn handle_v1_post_provider(
     let mut file_value = read_yaml_file_as_value_if_exists(&provider_path).await
         .map_err(|e| ScratchError::new(StatusCode::INTERNAL_SERVER_ERROR, e))?;
 
-    update_yaml_field_if_needed(&mut file_value, "endpoint_style",
-        provider_dto.endpoint_style, provider_template.endpoint_style);
+    let endpoint_style = provider_dto.endpoint_style;
+    let api_key = unredact_yaml_field(&file_value, "api_key", provider_dto.api_key);
     update_yaml_field_if_needed(&mut file_value, "api_key",
-        provider_dto.api_key, provider_template.api_key);
+        api_key, provider_template.api_key);
+    let tokenizer_api_key = unredact_yaml_field(&file_value, "tokenizer_api_key", provider_dto.tokenizer_api_key);
     update_yaml_field_if_needed(&mut file_value, "tokenizer_api_key",
-        provider_dto.tokenizer_api_key, provider_template.tokenizer_api_key);
+        tokenizer_api_key, provider_template.tokenizer_api_key);
     update_yaml_field_if_needed(&mut file_value, "chat_endpoint",
         provider_dto.chat_endpoint, provider_template.chat_endpoint);
+    // a custom chat_endpoint without endpoint_style gets a style guessed from the URL, the one chosen in the form is kept instead
+    if file_value.get("chat_endpoint").is_some() {
+        file_value["endpoint_style"] = serde_yaml::Value::String(endpoint_style);
+    } else {
+        update_yaml_field_if_needed(&mut file_value, "endpoint_style",
+            endpoint_style, provider_template.endpoint_style);
+    }
     update_yaml_field_if_needed(&mut file_value, "completion_endpoint",
         provider_dto.completion_endpoint, provider_template.completion_endpoint);
     update_yaml_field_if_needed(&mut file_value, "embedding_endpoint",
@@ -335,6 +345,14 @@ fn update_yaml_field_if_needed(
     }
 }
 
+/// GUI sends back what it got, with secrets redacted, that means the value didn't change
+fn unredact_yaml_field(file_value: &serde_yaml::Value, field_name: &str, dto_value: String) -> String {
+    match file_value.get(field_name).and_then(|v| v.as_str()) {
+        Some(current) if current != dto_value && redact_secrets(current) == dto_value => current.to_string(),
+        _ => dto_value,
+    }
+}
+
 async fn read_yaml_file_as_value_if_exists(path: &Path) -> Result<serde_yaml::Value, String> {
     match tokio::fs::read_to_string(path).await {
         Ok(content) => {
@@ -410,9 +428,14 @@ pub async fn handle_v1_models(
         get_provider_from_server(gcx.clone()).await
             .map_err(|e| ScratchError::new(StatusCode::INTERNAL_SERVER_ERROR, e))?
     } else {
-        let config_dir = gcx.read().await.config_dir.clone();
-        get_provider_from_template_and_config_file(&config_dir, &params.provider_name, false, true, experimental).await
-            .map_err(|e| ScratchError::new(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        let (config_dir, http_client) = {
+            let gcx_locked = gcx.read().await;
+            (gcx_locked.config_dir.clone(), gcx_locked.http_client.clone())
+        };
+        let mut provider = get_provider_from_template_and_config_file(&config_dir, &params.provider_name, false, true, experimental).await
+            .map_err(|e| ScratchError::new(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        discover_and_merge_models(&http_client, &mut provider, experimental).await;
+        provider
     };
 
     let result = serde_json::json!({
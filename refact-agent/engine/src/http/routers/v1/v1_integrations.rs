@@ -235,3 +235,17 @@ pub async fn handle_v1_integrations_mcp_logs(
         }).to_string()))
         .unwrap())
 }
+
+pub async fn handle_v1_services_status(
+    Extension(gcx): Extension<Arc<ARwLock<GlobalContext>>>,
+    _: hyper::body::Bytes,
+) -> axum::response::Result<Response<Body>, ScratchError> {
+    let services = crate::integrations::integr_cmdline_service::services_status(gcx.clone()).await;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::json!({
+            "services": services,
+        }).to_string()))
+        .unwrap())
+}
@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{Mutex as AMutex, RwLock as ARwLock};
+use tracing::info;
+
+use crate::at_commands::at_commands::AtCommandsContext;
+use crate::call_validation::{ChatContent, ChatMessage, ContextEnum};
+use crate::files_correction::get_project_dirs;
+use crate::global_context::GlobalContext;
+use crate::integrations::docker::docker_container_manager::docker_container_get_compose_project;
+use crate::integrations::docker::integr_docker::ToolDocker;
+use crate::integrations::docker::integr_isolation::{SettingsIsolation, IntegrationIsolation};
+use crate::integrations::integr_abstract::{IntegrationCommon, IntegrationConfirmation};
+use crate::integrations::process_io_utils::last_n_lines;
+use crate::integrations::running_integrations::load_integrations;
+use crate::tools::tools_description::{Tool, ToolDesc, ToolParam, ToolSource, ToolSourceType};
+
+const COMPOSE_LOGS_DEFAULT_TAIL: usize = 100;
+const COMPOSE_LOGS_MAX_LINES: usize = 1000;
+
+
+#[derive(Clone, Debug)]
+pub struct ComposeProject {
+    pub project_name: String,
+    pub compose_file: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct ComposeServiceStatus {
+    #[serde(rename = "Service", default)]
+    pub service: String,
+    #[serde(rename = "Name", default)]
+    pub name: String,
+    #[serde(rename = "State", default)]
+    pub state: String,
+    #[serde(rename = "Health", default)]
+    pub health: String,
+    #[serde(rename = "Status", default)]
+    pub status: String,
+    #[serde(rename = "ExitCode", default)]
+    pub exit_code: i64,
+}
+
+impl ComposeServiceStatus {
+    pub fn is_ready(&self) -> bool {
+        self.state == "running" && (self.health.is_empty() || self.health == "healthy")
+    }
+}
+
+impl ComposeProject {
+    pub async fn from_isolation(
+        gcx: Arc<ARwLock<GlobalContext>>,
+        isolation: &SettingsIsolation,
+        chat_id: &str,
+    ) -> Option<ComposeProject> {
+        if isolation.docker_compose_file.is_empty() {
+            return None;
+        }
+        let mut compose_file = PathBuf::from(&isolation.docker_compose_file);
+        if compose_file.is_relative() {
+            if let Some(workspace_folder) = get_project_dirs(gcx.clone()).await.into_iter().next() {
+                compose_file = workspace_folder.join(compose_file);
+            }
+        }
+        Some(ComposeProject {
+            project_name: get_compose_project_name(chat_id),
+            compose_file: compose_file.to_string_lossy().to_string(),
+        })
+    }
+
+    /// Compose creates `<project>_default` network for services that don't declare their own networks
+    pub fn network_name(&self) -> String {
+        format!("{}_default", self.project_name)
+    }
+
+    fn command(&self, subcommand: &str) -> String {
+        format!(
+            "compose --file={} --project-name={} --ansi=never {subcommand}",
+            shell_words::quote(&self.compose_file),
+            shell_words::quote(&self.project_name),
+        )
+    }
+}
+
+/// Compose project names must contain only lowercase letters, digits, dashes and underscores
+pub fn get_compose_project_name(chat_id: &str) -> String {
+    let sanitized = chat_id.to_lowercase().chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .collect::<String>();
+    format!("refact-{}", sanitized)
+}
+
+pub async fn compose_up(
+    gcx: Arc<ARwLock<GlobalContext>>,
+    docker: &ToolDocker,
+    project: &ComposeProject,
+    services: &Vec<String>,
+) -> Result<(), String> {
+    let services_as_args = services.iter().map(|s| shell_words::quote(s).to_string()).collect::<Vec<_>>().join(" ");
+    let up_command = project.command(&format!("up --detach --wait {services_as_args}"));
+    info!("Executing docker command: {}", &up_command);
+    // compose writes its progress into stderr, so the status of services is the source of truth
+    let (_, up_stderr) = docker.command_execute(&up_command, gcx.clone(), false, true).await?;
+
+    let statuses = compose_services_status(gcx.clone(), docker, project).await?;
+    let not_ready = statuses.iter()
+        .filter(|s| services.is_empty() || services.contains(&s.service))
+        .filter(|s| !s.is_ready())
+        .map(|s| format!("{} ({}{})", s.service, s.state, if s.health.is_empty() { String::new() } else { format!(", {}", s.health) }))
+        .collect::<Vec<_>>();
+    if statuses.is_empty() || !not_ready.is_empty() {
+        return Err(format!(
+            "Docker compose project {} did not become ready, services not ready: [{}]\n{}",
+            project.project_name, not_ready.join(", "), last_n_lines(&up_stderr, 20),
+        ));
+    }
+    info!("Docker compose project {} is up, {} services running.", project.project_name, statuses.len());
+    Ok(())
+}
+
+pub async fn compose_down(
+    gcx: Arc<ARwLock<GlobalContext>>,
+    docker: &ToolDocker,
+    project: &ComposeProject,
+) -> Result<(), String> {
+    let down_command = project.command("down --volumes --remove-orphans");
+    docker.command_execute(&down_command, gcx.clone(), false, true).await?;
+    info!("Removed docker compose project {}.", project.project_name);
+    Ok(())
+}
+
+pub async fn compose_services_status(
+    gcx: Arc<ARwLock<GlobalContext>>,
+    docker: &ToolDocker,
+    project: &ComposeProject,
+) -> Result<Vec<ComposeServiceStatus>, String> {
+    let ps_command = project.command("ps --all --format=json");
+    let (ps_output, ps_stderr) = docker.command_execute(&ps_command, gcx.clone(), false, true).await?;
+    if ps_output.trim().is_empty() && !ps_stderr.trim().is_empty() {
+        return Err(format!("Command `{}` failed: {}", ps_command, ps_stderr));
+    }
+    parse_compose_ps_output(&ps_output)
+}
+
+/// Older compose versions print one json array, newer ones print one json object per line
+fn parse_compose_ps_output(output: &str) -> Result<Vec<ComposeServiceStatus>, String> {
+    let output = output.trim();
+    if output.starts_with('[') {
+        return serde_json::from_str(output).map_err(|e| format!("Error parsing docker compose ps output: {}", e));
+    }
+    output.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| format!("Error parsing docker compose ps output: {}", e)))
+        .collect()
+}
+
+pub async fn compose_logs(
+    gcx: Arc<ARwLock<GlobalContext>>,
+    docker: &ToolDocker,
+    project: &ComposeProject,
+    service: &str,
+    tail: usize,
+    since: &str,
+) -> Result<String, String> {
+    let mut logs_subcommand = format!("logs --no-color --timestamps --tail={tail}");
+    if !since.is_empty() {
+        logs_subcommand.push_str(&format!(" --since={}", shell_words::quote(since)));
+    }
+    if !service.is_empty() {
+        logs_subcommand.push_str(&format!(" {}", shell_words::quote(service)));
+    }
+    let (stdout, stderr) = docker.command_execute(&project.command(&logs_subcommand), gcx.clone(), false, true).await?;
+    if stdout.trim().is_empty() && !stderr.trim().is_empty() {
+        return Err(stderr);
+    }
+    Ok(stdout)
+}
+
+fn format_services_status(statuses: &Vec<ComposeServiceStatus>) -> String {
+    if statuses.is_empty() {
+        return "No services found in the compose project.".to_string();
+    }
+    let mut out = String::new();
+    for s in statuses {
+        out.push_str(&format!("{}: state={}", s.service, s.state));
+        if !s.health.is_empty() {
+            out.push_str(&format!(" health={}", s.health));
+        }
+        if s.state == "exited" {
+            out.push_str(&format!(" exit_code={}", s.exit_code));
+        }
+        out.push_str(&format!(" ({})\n", s.status));
+    }
+    out
+}
+
+async fn compose_tool_prepare(ccx: Arc<AMutex<AtCommandsContext>>) -> Result<(Arc<ARwLock<GlobalContext>>, ToolDocker, ComposeProject), String> {
+    let (gcx, chat_id) = {
+        let ccx_locked = ccx.lock().await;
+        (ccx_locked.global_context.clone(), ccx_locked.chat_id.clone())
+    };
+    let project = docker_container_get_compose_project(gcx.clone(), &chat_id).await?
+        .ok_or_else(|| "This chat has no docker compose project, compose file is not set in isolation settings".to_string())?;
+    let (docker, _) = crate::integrations::docker::docker_and_isolation_load(gcx.clone()).await?;
+    Ok((gcx, docker, project))
+}
+
+fn tool_answer(content: String, tool_call_id: &String) -> Vec<ContextEnum> {
+    vec![ContextEnum::ChatMessage(ChatMessage {
+        role: "tool".to_string(),
+        content: ChatContent::SimpleText(content),
+        tool_calls: None,
+        tool_call_id: tool_call_id.clone(),
+        ..Default::default()
+    })]
+}
+
+/// Compose tools run on the host next to the docker daemon, while the rest of the tools of an isolated chat run in the container
+pub async fn compose_tools_for_chat(gcx: Arc<ARwLock<GlobalContext>>, chat_id: &str) -> Vec<Box<dyn Tool + Send>> {
+    match docker_container_get_compose_project(gcx.clone(), chat_id).await {
+        Ok(Some(_)) => {},
+        _ => return vec![],
+    }
+    let include_paths_matching = ["**/isolation.yaml".to_string()];
+    let (integrations, _yaml_errors) = load_integrations(gcx.clone(), &include_paths_matching).await;
+    let isolation = match integrations.get("isolation").and_then(|i| i.as_any().downcast_ref::<IntegrationIsolation>()) {
+        Some(isolation) => isolation,
+        None => return vec![],
+    };
+    vec![
+        Box::new(ToolDockerComposeStatus {
+            common: isolation.common.clone(),
+            config_path: isolation.config_path.clone(),
+        }),
+        Box::new(ToolDockerComposeLogs {
+            common: isolation.common.clone(),
+            config_path: isolation.config_path.clone(),
+        }),
+    ]
+}
+
+#[derive(Clone, Default)]
+pub struct ToolDockerComposeStatus {
+    pub common: IntegrationCommon,
+    pub config_path: String,
+}
+
+#[async_trait]
+impl Tool for ToolDockerComposeStatus {
+    fn as_any(&self) -> &dyn std::any::Any { self }
+
+    fn tool_description(&self) -> ToolDesc {
+        ToolDesc {
+            name: "compose_status".to_string(),
+            display_name: "Compose Status".to_string(),
+            source: ToolSource {
+                source_type: ToolSourceType::Integration,
+                config_path: self.config_path.clone(),
+            },
+            agentic: false,
+            experimental: true,
+            description: "Show the state and health of the docker compose services (databases, caches, etc) running next to this isolated chat.".to_string(),
+            parameters: vec![],
+            parameters_required: vec![],
+        }
+    }
+
+    async fn tool_execute(
+        &mut self,
+        ccx: Arc<AMutex<AtCommandsContext>>,
+        tool_call_id: &String,
+        _args: &HashMap<String, Value>,
+    ) -> Result<(bool, Vec<ContextEnum>), String> {
+        let (gcx, docker, project) = compose_tool_prepare(ccx).await?;
+        let statuses = compose_services_status(gcx, &docker, &project).await?;
+        Ok((false, tool_answer(format_services_status(&statuses), tool_call_id)))
+    }
+
+    async fn command_to_match_against_confirm_deny(
+        &self,
+        _ccx: Arc<AMutex<AtCommandsContext>>,
+        _args: &HashMap<String, Value>,
+    ) -> Result<String, String> {
+        Ok("docker compose ps".to_string())
+    }
+
+    fn confirm_deny_rules(&self) -> Option<IntegrationConfirmation> {
+        Some(self.common.confirmation.clone())
+    }
+
+    fn has_config_path(&self) -> Option<String> {
+        Some(self.config_path.clone())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ToolDockerComposeLogs {
+    pub common: IntegrationCommon,
+    pub config_path: String,
+}
+
+#[async_trait]
+impl Tool for ToolDockerComposeLogs {
+    fn as_any(&self) -> &dyn std::any::Any { self }
+
+    fn tool_description(&self) -> ToolDesc {
+        ToolDesc {
+            name: "compose_logs".to_string(),
+            display_name: "Compose Logs".to_string(),
+            source: ToolSource {
+                source_type: ToolSourceType::Integration,
+                config_path: self.config_path.clone(),
+            },
+            agentic: false,
+            experimental: true,
+            description: "Read the logs of docker compose services running next to this isolated chat.".to_string(),
+            parameters: vec![
+                ToolParam {
+                    name: "service".to_string(),
+                    description: "Service name from the compose file, leave empty to get logs of all services.".to_string(),
+                    param_type: "string".to_string(),
+                },
+                ToolParam {
+                    name: "tail".to_string(),
+                    description: format!("Number of last lines to show for each service, default {COMPOSE_LOGS_DEFAULT_TAIL}."),
+                    param_type: "string".to_string(),
+                },
+                ToolParam {
+                    name: "since".to_string(),
+                    description: "Show logs since a timestamp (2025-01-02T13:23:37Z) or a relative time (10m).".to_string(),
+                    param_type: "string".to_string(),
+                },
+            ],
+            parameters_required: vec![],
+        }
+    }
+
+    async fn tool_execute(
+        &mut self,
+        ccx: Arc<AMutex<AtCommandsContext>>,
+        tool_call_id: &String,
+        args: &HashMap<String, Value>,
+    ) -> Result<(bool, Vec<ContextEnum>), String> {
+        let service = parse_string_arg(args, "service")?;
+        let since = parse_string_arg(args, "since")?;
+        let tail = match parse_string_arg(args, "tail")?.as_str() {
+            "" => COMPOSE_LOGS_DEFAULT_TAIL,
+            s => s.parse::<usize>().map_err(|_| format!("argument `tail` should be a number, got {:?}", s))?,
+        }.min(COMPOSE_LOGS_MAX_LINES);
+
+        let (gcx, docker, project) = compose_tool_prepare(ccx).await?;
+        let logs = compose_logs(gcx, &docker, &project, &service, tail, &since).await?;
+        let content = if logs.trim().is_empty() { "No logs.".to_string() } else { logs };
+        Ok((false, tool_answer(content, tool_call_id)))
+    }
+
+    async fn command_to_match_against_confirm_deny(
+        &self,
+        _ccx: Arc<AMutex<AtCommandsContext>>,
+        args: &HashMap<String, Value>,
+    ) -> Result<String, String> {
+        Ok(format!("docker compose logs {}", parse_string_arg(args, "service")?).trim_end().to_string())
+    }
+
+    fn confirm_deny_rules(&self) -> Option<IntegrationConfirmation> {
+        Some(self.common.confirmation.clone())
+    }
+
+    fn has_config_path(&self) -> Option<String> {
+        Some(self.config_path.clone())
+    }
+}
+
+fn parse_string_arg(args: &HashMap<String, Value>, name: &str) -> Result<String, String> {
+    match args.get(name) {
+        Some(Value::String(s)) => Ok(s.trim().to_string()),
+        Some(Value::Number(n)) => Ok(n.to_string()),
+        Some(Value::Null) | None => Ok(String::new()),
+        Some(v) => Err(format!("argument `{}` is not a string: {:?}", name, v)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compose_project_name_is_sanitized() {
+        assert_eq!(get_compose_project_name("Chat 42.A"), "refact-chat-42-a");
+        assert_eq!(get_compose_project_name("abc_DEF-1"), "refact-abc_def-1");
+    }
+
+    #[test]
+    fn test_parse_compose_ps_output() {
+        let ndjson = r#"{"Service":"db","Name":"p-db-1","State":"running","Health":"healthy","Status":"Up 5 seconds (healthy)","ExitCode":0}
+{"Service":"cache","Name":"p-cache-1","State":"exited","Health":"","Status":"Exited (1)","ExitCode":1}"#;
+        let statuses = parse_compose_ps_output(ndjson).unwrap();
+        assert_eq!(statuses.len(), 2);
+        assert!(statuses[0].is_ready());
+        assert!(!statuses[1].is_ready());
+        assert_eq!(statuses[1].exit_code, 1);
+
+        let array = r#"[{"Service":"db","State":"running","Health":"starting"}]"#;
+        let statuses = parse_compose_ps_output(array).unwrap();
+        assert_eq!(statuses.len(), 1);
+        assert!(!statuses[0].is_ready());
+
+        assert!(parse_compose_ps_output("").unwrap().is_empty());
+    }
+}
@@ -14,9 +14,10 @@ use crate::http::routers::v1::sync_files::SyncFilesExtractTarPost;
 use crate::integrations::sessions::get_session_hashmap_key;
 use crate::integrations::sessions::IntegrationSession;
 use crate::integrations::docker::docker_ssh_tunnel_utils::{ssh_tunnel_open, SshTunnel, ssh_tunnel_check_status};
-use crate::integrations::docker::integr_docker::{ToolDocker, SettingsDocker};
+use crate::integrations::docker::integr_docker::{ToolDocker, SettingsDocker, ContainerBackend};
 use crate::integrations::docker::docker_and_isolation_load;
 use crate::integrations::docker::integr_isolation::SettingsIsolation;
+use crate::integrations::docker::docker_compose::{ComposeProject, compose_up, compose_down};
 
 pub const DEFAULT_CONTAINER_LSP_PATH: &str = "/usr/local/bin/refact-lsp";
 
@@ -30,6 +31,7 @@ pub struct Port {
 pub struct DockerContainerSession {
     container_id: String,
     connection: DockerContainerConnectionEnum,
+    compose_project: Option<ComposeProject>,
     last_usage_ts: u64,
     session_timeout_after_inactivity: Duration,
     weak_gcx: Weak<ARwLock<GlobalContext>>,
@@ -56,14 +58,25 @@ impl IntegrationSession for DockerContainerSession {
 
             if let Some(gcx) = docker_session.weak_gcx.upgrade() {
                 let container_id = docker_session.container_id.clone();
-                match docker_container_kill(gcx, &container_id).await {
+                let mut message = match docker_container_kill(gcx.clone(), &container_id).await {
                     Ok(()) => format!("Cleanup docker container session: {}", container_id),
                     Err(e) => {
                         let message = format!("Failed to cleanup docker container session: {}", e);
                         error!(message);
                         message
                     }
+                };
+                if let Some(compose_project) = &docker_session.compose_project {
+                    match docker_compose_project_down(gcx, compose_project).await {
+                        Ok(()) => message.push_str(&format!(", compose project {} removed", compose_project.project_name)),
+                        Err(e) => {
+                            let compose_message = format!("Failed to remove compose project {}: {}", compose_project.project_name, e);
+                            error!(compose_message);
+                            message.push_str(&format!(", {}", compose_message));
+                        }
+                    }
                 }
+                message
             } else {
                 let message = "Detected program shutdown, quit.".to_string();
                 info!(message);
@@ -122,10 +135,30 @@ pub async fn docker_container_check_status_or_start(
             };
             ports_to_forward.insert(0, Port {published: "0".to_string(), target: LSP_PORT.to_string()});
 
-            let container_id = docker_container_create(&docker, &isolation, &chat_id, &ports_to_forward, LSP_PORT, gcx.clone()).await?;
-            docker_container_sync_config_folder(&docker, &container_id, gcx.clone()).await?;
-            docker_container_start(gcx.clone(), &docker, &container_id).await?;
-            let exposed_ports = docker_container_get_exposed_ports(&docker, &container_id, &ports_to_forward, gcx.clone()).await?;
+            let compose_project = ComposeProject::from_isolation(gcx.clone(), &isolation, &chat_id).await;
+            if let Some(compose_project) = &compose_project {
+                if let Err(e) = compose_up(gcx.clone(), &docker, compose_project, &isolation.docker_compose_depends_on).await {
+                    let _ = compose_down(gcx.clone(), &docker, compose_project).await;
+                    return Err(e);
+                }
+            }
+
+            let container_start_result = async {
+                let container_id = docker_container_create(&docker, &isolation, &chat_id, &ports_to_forward, LSP_PORT, compose_project.as_ref(), gcx.clone()).await?;
+                docker_container_sync_config_folder(&docker, &container_id, gcx.clone()).await?;
+                docker_container_start(gcx.clone(), &docker, &container_id).await?;
+                let exposed_ports = docker_container_get_exposed_ports(&docker, &container_id, &ports_to_forward, gcx.clone()).await?;
+                Ok::<_, String>((container_id, exposed_ports))
+            }.await;
+            let (container_id, exposed_ports) = match (container_start_result, &compose_project) {
+                (Ok(x), _) => x,
+                (Err(e), Some(compose_project)) => {
+                    warn!("Container for chat {} failed to start, removing compose project {}", chat_id, compose_project.project_name);
+                    let _ = compose_down(gcx.clone(), &docker, compose_project).await;
+                    return Err(e);
+                }
+                (Err(e), None) => return Err(e),
+            };
             let host_lsp_port = exposed_ports.iter().find(|p| p.target == LSP_PORT)
                 .ok_or_else(|| "No LSP port exposed".to_string())?.published.clone();
 
@@ -160,6 +193,7 @@ pub async fn docker_container_check_status_or_start(
             let session: Arc<AMutex<Box<dyn IntegrationSession>>> = Arc::new(AMutex::new(Box::new(DockerContainerSession {
                 container_id,
                 connection,
+                compose_project,
                 last_usage_ts: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs(),
                 session_timeout_after_inactivity: Duration::from_secs(60 * isolation.keep_containers_alive_for_x_minutes),
                 weak_gcx: Arc::downgrade(&gcx),
@@ -205,6 +239,27 @@ pub async fn docker_container_get_host_lsp_port_to_connect(
     }
 }
 
+pub async fn docker_container_get_compose_project(
+    gcx: Arc<ARwLock<GlobalContext>>,
+    chat_id: &str,
+) -> Result<Option<ComposeProject>, String>
+{
+    let docker_container_session_maybe = {
+        let gcx_locked = gcx.read().await;
+        gcx_locked.integration_sessions.get(&get_session_hashmap_key("docker", &chat_id)).cloned()
+    };
+
+    match docker_container_session_maybe {
+        Some(docker_container_session) => {
+            let mut docker_container_session_locked = docker_container_session.lock().await;
+            let docker_container_session = docker_container_session_locked.as_any_mut().downcast_mut::<DockerContainerSession>()
+              .ok_or_else(|| "Failed to downcast docker container session")?;
+            Ok(docker_container_session.compose_project.clone())
+        },
+        None => Ok(None),
+    }
+}
+
 pub fn get_container_name(chat_id: &str) -> String {
     format!("refact-{chat_id}")
 }
@@ -215,6 +270,7 @@ async fn docker_container_create(
     chat_id: &str,
     ports_to_forward: &Vec<Port>,
     lsp_port: &str,
+    compose_project: Option<&ComposeProject>,
     gcx: Arc<ARwLock<GlobalContext>>,
 ) -> Result<String, String> {
     let docker_image_id = isolation.docker_image_id.clone();
@@ -248,8 +304,14 @@ async fn docker_container_create(
     }
 
     let ports_to_forward_as_arg_list = ports_to_forward.iter()
-        .map(|p| format!("--publish={}:{}", p.published, p.target)).collect::<Vec<_>>().join(" ");
-    let network_if_set = if !isolation.docker_network.is_empty() {
+        .map(|p| match docker.settings_docker.container_backend {
+            // rootless podman does not accept host port 0, the target port alone means a random host port
+            ContainerBackend::Podman if p.published == "0" => format!("--publish={}", p.target),
+            _ => format!("--publish={}:{}", p.published, p.target),
+        }).collect::<Vec<_>>().join(" ");
+    let network_if_set = if let Some(compose_project) = compose_project {
+        format!("--network {}", compose_project.network_name())
+    } else if !isolation.docker_network.is_empty() {
         docker_create_network_if_not_exists(gcx.clone(), docker, &isolation.docker_network).await?;
         format!("--network {}", isolation.docker_network)
     } else {
@@ -262,8 +324,13 @@ async fn docker_container_create(
     } else {
         format!("--entrypoint={0}", isolation.docker_entrypoint)
     };
+    // podman usually runs with SELinux, without relabeling the binary cannot be executed inside the container
+    let lsp_volume_options = match docker.settings_docker.container_backend {
+        ContainerBackend::Docker => "",
+        ContainerBackend::Podman => ":z",
+    };
     let run_command = format!(
-        "container create --name={container_name} --shm-size=8g --volume={host_lsp_path}:{DEFAULT_CONTAINER_LSP_PATH} \
+        "container create --name={container_name} --shm-size=8g --volume={host_lsp_path}:{DEFAULT_CONTAINER_LSP_PATH}{lsp_volume_options} \
         {ports_to_forward_as_arg_list} {network_if_set} {extra_params} {entrypoint} {docker_image_id} -c '{lsp_command}'",
     );
 
@@ -274,8 +341,16 @@ async fn docker_container_create(
     if container_id.len() < 12 {
         return Err("Docker run error: no container ID returned.".into());
     }
+    let container_id = container_id[..12].to_string();
 
-    Ok(container_id[..12].to_string())
+    // Container can be created in one network only, the compose network has priority, the configured one is connected on top
+    if compose_project.is_some() && !isolation.docker_network.is_empty() {
+        docker_create_network_if_not_exists(gcx.clone(), docker, &isolation.docker_network).await?;
+        let network_connect_command = format!("network connect {} {container_id}", shell_words::quote(&isolation.docker_network));
+        docker.command_execute(&network_connect_command, gcx.clone(), true, true).await?;
+    }
+
+    Ok(container_id)
 }
 
 async fn get_host_cache_dir(gcx: Arc<ARwLock<GlobalContext>>, settings_docker: &SettingsDocker) -> String {
@@ -293,9 +368,9 @@ async fn get_host_cache_dir(gcx: Arc<ARwLock<GlobalContext>>, settings_docker: &
 
 async fn docker_create_network_if_not_exists(gcx: Arc<ARwLock<GlobalContext>>, docker: &ToolDocker, network_name: &str) -> Result<(), String> {
     let quoted_network_name = shell_words::quote(network_name);
-    let network_ls_command = format!("network ls --filter name={quoted_network_name}");
+    let network_ls_command = format!("network ls --filter name={quoted_network_name} --format '{{{{.Name}}}}'");
     let (network_ls_output, _) = docker.command_execute(&network_ls_command, gcx.clone(), true, true).await?;
-    if !network_ls_output.contains(network_name) {
+    if !network_ls_output.lines().any(|line| line.trim() == network_name) {
         let network_create_command = format!("network create {quoted_network_name}");
         let (_network_create_output, _) = docker.command_execute(&network_create_command, gcx.clone(), true, true).await?;
     }
@@ -514,7 +589,7 @@ async fn docker_container_get_exposed_ports(
     ports_to_forward: &Vec<Port>,
     gcx: Arc<ARwLock<GlobalContext>>,
 ) -> Result<Vec<Port>, String> {
-    let inspect_command = "inspect --format '{{json .NetworkSettings.Ports}}' ".to_string() + &container_id;
+    let inspect_command = "container inspect --format '{{json .NetworkSettings.Ports}}' ".to_string() + &container_id;
     let (inspect_output, _) = docker.command_execute(&inspect_command, gcx.clone(), true, true).await?;
     tracing::info!("{}:\n{}", inspect_command, inspect_output);
 
@@ -523,8 +598,8 @@ async fn docker_container_get_exposed_ports(
 
     let mut exposed_ports = Vec::new();
     for port in ports_to_forward {
-        let host_port = inspect_data[&format!("{}/tcp", port.target)][0]["HostPort"]
-            .as_str()
+        let host_port = inspect_data[&format!("{}/tcp", port.target)].as_array()
+            .and_then(|bindings| bindings.iter().find_map(|binding| binding["HostPort"].as_str().filter(|p| !p.is_empty())))
             .ok_or_else(|| "Error getting host port from docker inspect output.".to_string())?;
         exposed_ports.push(Port { published: host_port.to_string(), target: port.target.to_string() });
     }
@@ -539,7 +614,15 @@ async fn docker_container_kill(
 
     docker.command_execute(&format!("container stop {container_id}"), gcx.clone(), true, true).await?;
     info!("Stopped docker container {container_id}.");
-    docker.command_execute(&format!("container remove {container_id}"), gcx.clone(), true, true).await?;
+    docker.command_execute(&format!("container rm {container_id}"), gcx.clone(), true, true).await?;
     info!("Removed docker container {container_id}.");
     Ok(())
 }
+
+async fn docker_compose_project_down(
+    gcx: Arc<ARwLock<GlobalContext>>,
+    compose_project: &ComposeProject,
+) -> Result<(), String> {
+    let (docker, _) = docker_and_isolation_load(gcx.clone()).await?;
+    compose_down(gcx.clone(), &docker, compose_project).await
+}
@@ -15,8 +15,18 @@ use crate::tools::tools_description::{Tool, ToolDesc, ToolParam, ToolSource, Too
 use crate::integrations::docker::docker_ssh_tunnel_utils::{SshConfig, forward_remote_docker_if_needed};
 use crate::integrations::utils::{serialize_num_to_str, deserialize_str_to_num};
 
+#[derive(Clone, Copy, Serialize, Deserialize, Default, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerBackend {
+    #[default]
+    Docker,
+    Podman,
+}
+
 #[derive(Clone, Serialize, Deserialize, Default, Debug)]
 pub struct SettingsDocker {
+    #[serde(default)]
+    pub container_backend: ContainerBackend,
     pub label: String,
     pub docker_daemon_address: String,
     pub docker_cli_path: String,
@@ -42,6 +52,46 @@ impl SettingsDocker {
             None
         }
     }
+
+    pub fn get_cli_path(&self) -> String {
+        match self.container_backend {
+            ContainerBackend::Podman if self.docker_cli_path.is_empty() || self.docker_cli_path == "docker" => "podman".to_string(),
+            ContainerBackend::Docker if self.docker_cli_path.is_empty() => "docker".to_string(),
+            _ => self.docker_cli_path.clone(),
+        }
+    }
+
+    /// Address of the daemon (docker) or of the API socket (podman) on the remote machine, to forward it through ssh
+    fn get_remote_daemon_address(&self, ssh_config: &SshConfig) -> Result<String, String> {
+        match (self.container_backend, self.docker_daemon_address.as_str()) {
+            (ContainerBackend::Podman, "") if ssh_config.user == "root" => Ok("unix:///run/podman/podman.sock".to_string()),
+            (ContainerBackend::Podman, "") => Err(format!(
+                "Set docker_daemon_address to the podman socket of the remote user, for example unix:///run/user/1000/podman/podman.sock, \
+                run `podman info --format '{{{{.Host.RemoteSocket.Path}}}}'` on {} to find it", ssh_config.host
+            )),
+            (_, address) => Ok(address.to_string()),
+        }
+    }
+
+    /// CLI args to reach the daemon or the API socket, none for an empty address: the CLI reads DOCKER_HOST or CONTAINER_HOST then
+    fn daemon_address_args(&self, docker_host: &str) -> Vec<String> {
+        if docker_host.is_empty() {
+            return vec![];
+        }
+        match self.container_backend {
+            ContainerBackend::Docker => vec!["-H".to_string(), docker_host.to_string()],
+            ContainerBackend::Podman => vec!["--url".to_string(), podman_url(docker_host)],
+        }
+    }
+}
+
+/// Podman has no daemon, `--url` points to its API socket, ssh tunnels give plain host:port
+fn podman_url(address: &str) -> String {
+    if address.contains("://") {
+        address.to_string()
+    } else {
+        format!("tcp://{}", address)
+    }
 }
 
 #[derive(Clone, Default)]
@@ -96,11 +146,9 @@ impl ToolDocker {
         command_append_label_if_creates_resource(&mut command_args, &self.settings_docker.label);
 
         let docker_host = self.get_docker_host(gcx.clone()).await?;
-        let mut command_process = Command::new(&self.settings_docker.docker_cli_path);
-        if !docker_host.is_empty() {
-            command_process.arg("-H").arg(&docker_host);
-        }
+        let mut command_process = Command::new(&self.settings_docker.get_cli_path());
         let output = command_process
+            .args(self.settings_docker.daemon_address_args(&docker_host))
             .args(&command_args)
             .stdin(std::process::Stdio::null())
             .output()
@@ -125,7 +173,8 @@ impl ToolDocker {
     {
         match &self.settings_docker.get_ssh_config() {
             Some(ssh_config) => {
-                let local_port = forward_remote_docker_if_needed(&self.settings_docker.docker_daemon_address, ssh_config, gcx.clone()).await?;
+                let remote_daemon_address = self.settings_docker.get_remote_daemon_address(ssh_config)?;
+                let local_port = forward_remote_docker_if_needed(&remote_daemon_address, ssh_config, gcx.clone()).await?;
                 Ok(format!("127.0.0.1:{}", local_port))
             },
             None => Ok(self.settings_docker.docker_daemon_address.clone()),
@@ -147,7 +196,10 @@ impl Tool for ToolDocker {
             },
             agentic: true,
             experimental: true,
-            description: "Access to docker cli, in a non-interactive way, don't open a shell.".to_string(),
+            description: match self.settings_docker.container_backend {
+                ContainerBackend::Docker => "Access to docker cli, in a non-interactive way, don't open a shell.".to_string(),
+                ContainerBackend::Podman => "Access to docker-compatible podman cli, in a non-interactive way, don't open a shell.".to_string(),
+            },
             parameters: vec![
                 ToolParam {
                     name: "command".to_string(),
@@ -219,7 +271,7 @@ fn split_command(command: &str) -> Result<Vec<String>, String> {
     if parsed_args.is_empty() {
         return Err("Parsed command is empty".to_string());
     }
-    if parsed_args[0] == "docker" {
+    if parsed_args[0] == "docker" || parsed_args[0] == "podman" {
         parsed_args.remove(0);
     }
     Ok(parsed_args)
@@ -246,12 +298,21 @@ fn command_is_interactive_or_blocking(command_args: &Vec<String>) -> bool
     let mut command_args_iter = command_args.iter().filter(|arg| !arg.starts_with('-'));
     let subcommand_generic = command_args_iter.next().map(|arg| arg.as_str()).unwrap_or("");
 
-    let subcommand_specific = if subcommand_generic == "container" {
+    let subcommand_specific = if subcommand_generic == "container" || subcommand_generic == "compose" {
         command_args_iter.next().map(|arg| arg.as_str()).unwrap_or("")
     } else {
         subcommand_generic
     };
 
+    if subcommand_generic == "compose" {
+        if subcommand_specific == "up" && !command_contains_flag(command_args, "d", "detach") {
+            return true;
+        }
+        if subcommand_specific == "watch" {
+            return true;
+        }
+    }
+
     if COMMANDS_THAT_CAN_BE_INTERACTIVE.contains(&subcommand_specific) &&
         command_contains_flag(command_args, "i", "interactive")
     {
@@ -296,9 +357,13 @@ fn command_append_label_if_creates_resource(command_args: &mut Vec<String>, labe
 
 pub const DOCKER_INTEGRATION_SCHEMA: &str = r#"
 fields:
+  container_backend:
+    f_type: string_short
+    f_desc: "Container engine to use: docker or podman. Podman works rootless and without a daemon."
+    f_default: "docker"
   docker_cli_path:
     f_type: string_long
-    f_desc: "Path to the Docker CLI executable."
+    f_desc: "Path to the Docker CLI executable. For podman, the default is podman."
     f_default: "docker"
   label:
     f_type: string_short
@@ -306,7 +371,7 @@ fields:
     f_default: "refact"
   docker_daemon_address:
     f_type: string_long
-    f_desc: "The address to connect to the Docker daemon; specify only if not using the default."
+    f_desc: "The address to connect to the Docker daemon, or the podman API socket; specify only if not using the default."
     f_extra: true
   remote_docker:
     f_type: bool
@@ -349,3 +414,50 @@ smartlinks:
           satisfaction and relief if it works, and change nothing. If it doesn't work or the tool isn't available, go through the usual plan in the system prompt.
     sl_enable_only_with_tool: true
 "#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn podman_settings(docker_daemon_address: &str) -> SettingsDocker {
+        SettingsDocker {
+            container_backend: ContainerBackend::Podman,
+            docker_daemon_address: docker_daemon_address.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn ssh_config(user: &str) -> SshConfig {
+        SshConfig { host: "build-box".to_string(), user: user.to_string(), port: 22, identity_file: None }
+    }
+
+    #[test]
+    fn test_get_cli_path() {
+        assert_eq!(podman_settings("").get_cli_path(), "podman");
+        assert_eq!(SettingsDocker { docker_cli_path: "docker".to_string(), ..podman_settings("") }.get_cli_path(), "podman");
+        assert_eq!(SettingsDocker { docker_cli_path: "/opt/bin/podman".to_string(), ..podman_settings("") }.get_cli_path(), "/opt/bin/podman");
+        assert_eq!(SettingsDocker::default().get_cli_path(), "docker");
+    }
+
+    #[test]
+    fn test_podman_daemon_address_args() {
+        // local socket
+        assert_eq!(podman_settings("").daemon_address_args("unix:///run/user/1000/podman/podman.sock"),
+            vec!["--url", "unix:///run/user/1000/podman/podman.sock"]);
+        // no address, podman takes CONTAINER_HOST from the environment
+        assert!(podman_settings("").daemon_address_args("").is_empty());
+        // ssh tunnel to the remote socket
+        assert_eq!(podman_settings("").daemon_address_args("127.0.0.1:40123"), vec!["--url", "tcp://127.0.0.1:40123"]);
+        assert_eq!(SettingsDocker::default().daemon_address_args("127.0.0.1:40123"), vec!["-H", "127.0.0.1:40123"]);
+    }
+
+    #[test]
+    fn test_get_remote_daemon_address() {
+        assert_eq!(podman_settings("").get_remote_daemon_address(&ssh_config("root")).unwrap(), "unix:///run/podman/podman.sock");
+        let err = podman_settings("").get_remote_daemon_address(&ssh_config("dev")).unwrap_err();
+        assert!(err.contains("podman info") && err.contains("build-box"));
+        let socket = "unix:///run/user/1000/podman/podman.sock";
+        assert_eq!(podman_settings(socket).get_remote_daemon_address(&ssh_config("dev")).unwrap(), socket);
+        assert_eq!(SettingsDocker::default().get_remote_daemon_address(&ssh_config("dev")).unwrap(), "");
+    }
+}
@@ -26,6 +26,10 @@ pub struct SettingsIsolation {
     pub docker_entrypoint: String,
     #[serde(default)]
     pub docker_extra_params: Vec<String>,
+    #[serde(default)]
+    pub docker_compose_file: String,
+    #[serde(default)]
+    pub docker_compose_depends_on: Vec<String>,
 }
 
 fn default_docker_entrypoint() -> String { "sh".to_string() }
@@ -34,15 +38,17 @@ fn default_docker_entrypoint() -> String { "sh".to_string() }
 pub struct IntegrationIsolation {
     pub common:  IntegrationCommon,
     pub settings_isolation: SettingsIsolation,
+    pub config_path: String,
 }
 
 #[async_trait]
 impl IntegrationTrait for IntegrationIsolation {
     fn as_any(&self) -> &dyn std::any::Any { self }
 
-    async fn integr_settings_apply(&mut self, _gcx: Arc<ARwLock<GlobalContext>>, _config_path: String, value: &serde_json::Value) -> Result<(), serde_json::Error> {
+    async fn integr_settings_apply(&mut self, _gcx: Arc<ARwLock<GlobalContext>>, config_path: String, value: &serde_json::Value) -> Result<(), serde_json::Error> {
       self.settings_isolation = serde_json::from_value(value.clone())?;
       self.common = serde_json::from_value(value.clone())?;
+      self.config_path = config_path;
       Ok(())
   }
 
@@ -89,6 +95,15 @@ fields:
   docker_extra_params:
     f_type: string_array
     f_desc: "Extra parameters to pass to the Docker command."
+  docker_compose_file:
+    f_type: string_long
+    f_desc: "Path to a docker compose file with services the chat container needs (database, cache, etc), relative to the workspace folder. The services are started in a separate compose project for each chat, the chat container joins its network, and everything is removed when the container expires."
+    f_placeholder: "docker-compose.yml"
+    f_extra: true
+  docker_compose_depends_on:
+    f_type: string_array
+    f_desc: "Compose services to start and wait for (running, and healthy if they have a healthcheck) before the chat container starts. If empty, all services are started."
+    f_extra: true
   isolation_address_url:
     f_type: string_long
     f_desc: "The address url that refact binary will use inside the container, in case it needs to be different than the one used by the host."
@@ -11,6 +11,7 @@ pub mod integr_docker;
 pub mod integr_isolation;
 pub mod docker_ssh_tunnel_utils;
 pub mod docker_container_manager;
+pub mod docker_compose;
 
 pub async fn docker_and_isolation_load(gcx: Arc<ARwLock<GlobalContext>>) -> Result<(ToolDocker, Option<SettingsIsolation>), String>
 {
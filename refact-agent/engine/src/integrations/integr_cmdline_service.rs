@@ -1,10 +1,12 @@
 use std::any::Any;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::future::Future;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use std::process::Stdio;
-use tokio::io::BufReader;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 use tokio::sync::{Mutex as AMutex, RwLock as ARwLock};
+use tokio::task::JoinHandle;
 use async_trait::async_trait;
 use process_wrap::tokio::*;
 
@@ -13,20 +15,49 @@ use crate::tools::tools_description::{Tool, ToolParam, ToolDesc, ToolSource, Too
 use crate::call_validation::{ChatMessage, ChatContent, ContextEnum};
 use crate::global_context::GlobalContext;
 use crate::postprocessing::pp_command_output::output_mini_postprocessing;
-use crate::integrations::process_io_utils::{blocking_read_until_token_or_timeout, is_someone_listening_on_that_tcp_port};
+use crate::integrations::process_io_utils::{execute_command, is_someone_listening_on_that_tcp_port};
 use crate::integrations::sessions::IntegrationSession;
 use crate::integrations::integr_abstract::{IntegrationTrait, IntegrationCommon, IntegrationConfirmation};
 use crate::integrations::integr_cmdline::*;
+use crate::integrations::utils::{serialize_num_to_str, deserialize_str_to_num};
 use crate::custom_error::YamlError;
 
 
 const REALLY_HORRIBLE_ROUNDTRIP: u64 = 3000;   // 3000 should be a really bad ping via internet, just in rare case it's a remote port
+const HEALTH_CHECK_TIMEOUT: u64 = 10;
+const RESTART_BACKOFF_MAX: u64 = 300;
+const SERVICE_LOG_MAX_LINES: usize = 10000;
+const LOGS_TOOL_DEFAULT_TAIL: usize = 100;
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct ServiceHealthConfig {
+    #[serde(default)]
+    pub health_check_type: String,
+    #[serde(default)]
+    pub health_check_target: String,
+    #[serde(default = "_default_health_check_interval", serialize_with = "serialize_num_to_str", deserialize_with = "deserialize_str_to_num")]
+    pub health_check_interval: u64,
+    #[serde(default = "_default_health_check_failures", serialize_with = "serialize_num_to_str", deserialize_with = "deserialize_str_to_num")]
+    pub health_check_failures: u32,
+    #[serde(default)]
+    pub restart_on_failure: bool,
+    #[serde(default = "_default_restart_max_attempts", serialize_with = "serialize_num_to_str", deserialize_with = "deserialize_str_to_num")]
+    pub restart_max_attempts: u32,
+    #[serde(default = "_default_restart_backoff", serialize_with = "serialize_num_to_str", deserialize_with = "deserialize_str_to_num")]
+    pub restart_backoff: u64,
+}
+
+fn _default_health_check_interval() -> u64 { 10 }
+fn _default_health_check_failures() -> u32 { 3 }
+fn _default_restart_max_attempts() -> u32 { 5 }
+fn _default_restart_backoff() -> u64 { 1 }
 
 #[derive(Default)]
 pub struct ToolService {
     pub common:  IntegrationCommon,
     pub name: String,
     pub cfg: CmdlineToolConfig,
+    pub health: ServiceHealthConfig,
     pub config_path: String,
 }
 
@@ -36,13 +67,18 @@ impl IntegrationTrait for ToolService {
 
     async fn integr_settings_apply(&mut self, _gcx: Arc<ARwLock<GlobalContext>>, config_path: String, value: &serde_json::Value) -> Result<(), serde_json::Error> {
         self.cfg = serde_json::from_value(value.clone())?;
+        self.health = serde_json::from_value(value.clone())?;
         self.common = serde_json::from_value(value.clone())?;
         self.config_path = config_path;
         Ok(())
     }
 
     fn integr_settings_as_json(&self) -> serde_json::Value {
-        serde_json::to_value(&self.cfg).unwrap()
+        let mut value = serde_json::to_value(&self.cfg).unwrap();
+        if let (Some(obj), serde_json::Value::Object(health)) = (value.as_object_mut(), serde_json::to_value(&self.health).unwrap()) {
+            obj.extend(health);
+        }
+        value
     }
 
     fn integr_common(&self) -> IntegrationCommon {
@@ -50,12 +86,19 @@ impl IntegrationTrait for ToolService {
     }
 
     async fn integr_tools(&self, integr_name: &str) -> Vec<Box<dyn crate::tools::tools_description::Tool + Send>> {
-        vec![Box::new(ToolService {
-            common: self.common.clone(),
-            name: integr_name.to_string(),
-            cfg: self.cfg.clone(),
-            config_path: self.config_path.clone(),
-        })]
+        vec![
+            Box::new(ToolService {
+                common: self.common.clone(),
+                name: integr_name.to_string(),
+                cfg: self.cfg.clone(),
+                health: self.health.clone(),
+                config_path: self.config_path.clone(),
+            }),
+            Box::new(ToolServiceLogs {
+                service_name: integr_name.to_string(),
+                config_path: self.config_path.clone(),
+            }),
+        ]
     }
 
     fn integr_schema(&self) -> &str
@@ -64,13 +107,80 @@ impl IntegrationTrait for ToolService {
     }
 }
 
+pub struct ServiceLogLine {
+    pub seq: u64,
+    pub ts: chrono::DateTime<chrono::Local>,
+    pub is_stderr: bool,
+    pub line: String,
+}
+
+/// Everything the service printed, collected in the background so nothing is lost between tool calls
+#[derive(Default)]
+pub struct ServiceLogs {
+    pub lines: VecDeque<ServiceLogLine>,
+    pub next_seq: u64,
+}
+
+impl ServiceLogs {
+    pub fn push(&mut self, is_stderr: bool, line: String) {
+        self.lines.push_back(ServiceLogLine { seq: self.next_seq, ts: chrono::Local::now(), is_stderr, line });
+        self.next_seq += 1;
+        while self.lines.len() > SERVICE_LOG_MAX_LINES {
+            self.lines.pop_front();
+        }
+    }
+
+    pub fn stdout_stderr_since_seq(&self, seq: u64) -> (String, String) {
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+        for l in self.lines.iter().filter(|l| l.seq >= seq) {
+            let out = if l.is_stderr { &mut stderr } else { &mut stdout };
+            out.push_str(&l.line);
+            out.push('\n');
+        }
+        (stdout, stderr)
+    }
+
+    pub fn filtered(&self, since: Option<chrono::DateTime<chrono::Local>>, grep: Option<&regex::Regex>, tail: usize) -> Vec<&ServiceLogLine> {
+        let matching: Vec<&ServiceLogLine> = self.lines.iter()
+            .filter(|l| since.map_or(true, |since| l.ts >= since))
+            .filter(|l| grep.map_or(true, |re| re.is_match(&l.line)))
+            .collect();
+        let skip = matching.len().saturating_sub(tail);
+        matching.into_iter().skip(skip).collect()
+    }
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct ServiceStatus {
+    pub service_name: String,
+    pub command: String,
+    pub workdir: String,
+    pub state: String,   // running, healthy, unhealthy, restarting, crashed, gave_up
+    pub pid: Option<u32>,
+    pub started_at: String,
+    pub restarts: u32,
+    pub consecutive_failures: u32,
+    pub last_check_at: String,
+    pub last_error: String,
+}
+
 pub struct CmdlineSession {
     cmdline_string: String,
     cmdline_workdir: String,
     cmdline_process: Box<dyn TokioChildWrapper>,
-    cmdline_stdout: BufReader<tokio::process::ChildStdout>,
-    cmdline_stderr: BufReader<tokio::process::ChildStderr>,
+    env_variables: HashMap<String, String>,
     service_name: String,
+    logs: Arc<AMutex<ServiceLogs>>,
+    logs_last_seen_seq: u64,
+    status: ServiceStatus,
+    monitor: Option<JoinHandle<()>>,
+}
+
+impl CmdlineSession {
+    pub fn status(&self) -> ServiceStatus {
+        ServiceStatus { pid: self.cmdline_process.id(), ..self.status.clone() }
+    }
 }
 
 impl IntegrationSession for CmdlineSession {
@@ -91,6 +201,9 @@ impl IntegrationSession for CmdlineSession {
 
 async fn _stop_locked(sess: &mut CmdlineSession) -> String {
     tracing::info!("SERVICE STOP workdir {}:\n{:?}", sess.cmdline_workdir, sess.cmdline_string);
+    if let Some(monitor) = sess.monitor.take() {
+        monitor.abort();
+    }
     let t0 = tokio::time::Instant::now();
     match Box::into_pin(sess.cmdline_process.kill()).await {
         Ok(_) => {
@@ -103,13 +216,177 @@ async fn _stop_locked(sess: &mut CmdlineSession) -> String {
     }
 }
 
-async fn get_stdout_and_stderr(
-    timeout_ms: u64,
-    stdout: &mut BufReader<tokio::process::ChildStdout>,
-    stderr: &mut BufReader<tokio::process::ChildStderr>,
-) -> Result<(String, String), String> {
-    let (stdout_out, stderr_out, _) = blocking_read_until_token_or_timeout(stdout, stderr, timeout_ms, "").await?;
-    Ok((stdout_out, stderr_out))
+async fn read_into_service_logs<R: AsyncRead + Unpin>(reader: R, logs: Arc<AMutex<ServiceLogs>>, is_stderr: bool) {
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&buf).trim_end_matches(['\r', '\n']).to_string();
+                logs.lock().await.push(is_stderr, line);
+            }
+        }
+    }
+}
+
+async fn spawn_service_process(
+    gcx: Arc<ARwLock<GlobalContext>>,
+    command_str: &str,
+    cmdline_workdir: &String,
+    env_variables: &HashMap<String, String>,
+    logs: Arc<AMutex<ServiceLogs>>,
+) -> Result<Box<dyn TokioChildWrapper>, String> {
+    let project_dirs = crate::files_correction::get_project_dirs(gcx.clone()).await;
+    let mut command = create_command_from_string(command_str, cmdline_workdir, env_variables, project_dirs)?;
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+    let mut command_wrap = TokioCommandWrap::from(command);
+    #[cfg(unix)]
+    command_wrap.wrap(ProcessGroup::leader());
+    #[cfg(windows)]
+    command_wrap.wrap(JobObject);
+    let mut process = command_wrap.spawn().map_err(|e| format!("failed to create process: {e}"))?;
+
+    let stdout = process.stdout().take().ok_or("Failed to open stdout")?;
+    let stderr = process.stderr().take().ok_or("Failed to open stderr")?;
+    tokio::spawn(read_into_service_logs(stdout, logs.clone(), false));
+    tokio::spawn(read_into_service_logs(stderr, logs, true));
+    Ok(process)
+}
+
+async fn run_health_check(
+    gcx: Arc<ARwLock<GlobalContext>>,
+    health: &ServiceHealthConfig,
+    cmdline_workdir: &String,
+    env_variables: &HashMap<String, String>,
+) -> Result<(), String> {
+    let target = health.health_check_target.trim();
+    match health.health_check_type.as_str() {
+        "" => Ok(()),
+        "tcp" => {
+            let port = target.parse::<u16>().map_err(|_| format!("health_check_target should be a port number for tcp check, got {:?}", target))?;
+            match is_someone_listening_on_that_tcp_port(port, tokio::time::Duration::from_millis(REALLY_HORRIBLE_ROUNDTRIP)).await {
+                true => Ok(()),
+                false => Err(format!("nobody is listening on port {}", port)),
+            }
+        },
+        "http" => {
+            let http_client = gcx.read().await.http_client.clone();
+            let response = http_client.get(target)
+                .timeout(tokio::time::Duration::from_secs(HEALTH_CHECK_TIMEOUT))
+                .send().await
+                .map_err(|e| format!("GET {} failed: {}", target, e))?;
+            match response.status().is_success() {
+                true => Ok(()),
+                false => Err(format!("GET {} returned {}", target, response.status())),
+            }
+        },
+        "command" => {
+            let project_dirs = crate::files_correction::get_project_dirs(gcx.clone()).await;
+            let command = create_command_from_string(target, cmdline_workdir, env_variables, project_dirs)?;
+            let output = execute_command(command, HEALTH_CHECK_TIMEOUT, target).await?;
+            match output.status.success() {
+                true => Ok(()),
+                false => Err(format!("`{}` exited with {}: {}", target, output.status, String::from_utf8_lossy(&output.stderr).trim())),
+            }
+        },
+        other => Err(format!("unknown health_check_type {:?}, expected tcp, http or command", other)),
+    }
+}
+
+/// Runs for the lifetime of the session: notices a crash or failing health checks, and restarts the service if configured to
+async fn service_monitor(
+    gcx: Arc<ARwLock<GlobalContext>>,
+    session_weak: Weak<AMutex<Box<dyn IntegrationSession>>>,
+    health: ServiceHealthConfig,
+) {
+    let mut backoff = health.restart_backoff.max(1);
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_secs(health.health_check_interval.max(1))).await;
+        let session_arc = match session_weak.upgrade() {
+            Some(session_arc) => session_arc,
+            None => return,
+        };
+
+        let (exited, workdir, env_variables) = {
+            let mut session_locked = session_arc.lock().await;
+            let session = session_locked.as_any_mut().downcast_mut::<CmdlineSession>().unwrap();
+            let exited = match session.cmdline_process.try_wait() {
+                Ok(Some(exit_status)) => Some(format!("process exited with {}", exit_status)),
+                Ok(None) => None,
+                Err(e) => Some(format!("cannot get process status: {}", e)),
+            };
+            (exited, session.cmdline_workdir.clone(), session.env_variables.clone())
+        };
+        let check_result = match &exited {
+            Some(reason) => Err(reason.clone()),
+            None => run_health_check(gcx.clone(), &health, &workdir, &env_variables).await,
+        };
+
+        let (needs_restart, restarts) = {
+            let mut session_locked = session_arc.lock().await;
+            let session = session_locked.as_any_mut().downcast_mut::<CmdlineSession>().unwrap();
+            session.status.last_check_at = chrono::Local::now().to_rfc3339();
+            match check_result {
+                Ok(()) => {
+                    session.status.consecutive_failures = 0;
+                    session.status.state = if health.health_check_type.is_empty() { "running" } else { "healthy" }.to_string();
+                    backoff = health.restart_backoff.max(1);
+                    (false, session.status.restarts)
+                },
+                Err(e) => {
+                    tracing::warn!("service {} health check failed: {}", session.service_name, e);
+                    session.status.consecutive_failures += 1;
+                    session.status.last_error = e;
+                    let broken = exited.is_some() || session.status.consecutive_failures >= health.health_check_failures.max(1);
+                    if broken {
+                        session.status.state = if exited.is_some() { "crashed" } else { "unhealthy" }.to_string();
+                    }
+                    if broken && health.restart_on_failure && session.status.restarts >= health.restart_max_attempts {
+                        session.status.state = "gave_up".to_string();
+                    }
+                    (broken && session.status.state != "gave_up" && health.restart_on_failure, session.status.restarts)
+                },
+            }
+        };
+
+        if !needs_restart {
+            if exited.is_some() {
+                return;  // nothing left to watch
+            }
+            continue;
+        }
+
+        tracing::info!("service restart in {}s, attempt {}", backoff, restarts + 1);
+        {
+            let mut session_locked = session_arc.lock().await;
+            let session = session_locked.as_any_mut().downcast_mut::<CmdlineSession>().unwrap();
+            session.status.state = "restarting".to_string();
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(backoff)).await;
+        backoff = (backoff * 2).min(RESTART_BACKOFF_MAX);
+
+        let mut session_locked = session_arc.lock().await;
+        let session = session_locked.as_any_mut().downcast_mut::<CmdlineSession>().unwrap();
+        let _ = Box::into_pin(session.cmdline_process.kill()).await;
+        session.status.restarts += 1;
+        session.status.consecutive_failures = 0;
+        session.logs.lock().await.push(true, format!("--- restarting the service, attempt {}/{} ---", session.status.restarts, health.restart_max_attempts));
+        match spawn_service_process(gcx.clone(), &session.cmdline_string, &session.cmdline_workdir, &session.env_variables, session.logs.clone()).await {
+            Ok(process) => {
+                session.cmdline_process = process;
+                session.status.state = "running".to_string();
+                session.status.started_at = chrono::Local::now().to_rfc3339();
+            },
+            Err(e) => {
+                // old process is dead, the next check sees that and tries again
+                session.status.state = "crashed".to_string();
+                session.status.last_error = e;
+            }
+        }
+    }
 }
 
 async fn execute_background_command(
@@ -118,6 +395,7 @@ async fn execute_background_command(
     command_str: &str,
     cmdline_workdir: &String,
     cfg: &CmdlineToolConfig,
+    health: &ServiceHealthConfig,
     action: &str,
     env_variables: &HashMap<String, String>,
 ) -> Result<String, String> {
@@ -130,8 +408,21 @@ async fn execute_background_command(
         let session_arc = session_mb.clone().unwrap();
         let mut session_locked = session_arc.lock().await;
         let session = session_locked.as_any_mut().downcast_mut::<CmdlineSession>().unwrap();
-        actions_log.push_str(&format!("Currently the service is running.\nworkdir: {}\ncommand line: {}\n\n", session.cmdline_workdir, session.cmdline_string));
-        let (stdout_out, stderr_out) = get_stdout_and_stderr(100, &mut session.cmdline_stdout, &mut session.cmdline_stderr).await?;
+        actions_log.push_str(&format!("Currently the service is running.\nworkdir: {}\ncommand line: {}\n", session.cmdline_workdir, session.cmdline_string));
+        let status = session.status();
+        if !status.state.is_empty() {
+            actions_log.push_str(&format!("state: {}, restarts: {}\n", status.state, status.restarts));
+        }
+        if !status.last_error.is_empty() {
+            actions_log.push_str(&format!("last problem: {}\n", status.last_error));
+        }
+        actions_log.push_str("\n");
+        let (stdout_out, stderr_out) = {
+            let logs = session.logs.lock().await;
+            let out = logs.stdout_stderr_since_seq(session.logs_last_seen_seq);
+            session.logs_last_seen_seq = logs.next_seq;
+            out
+        };
         let filtered_stdout = output_mini_postprocessing(&cfg.output_filter, &stdout_out);
         let filtered_stderr = output_mini_postprocessing(&cfg.output_filter, &stderr_out);
         actions_log.push_str(&format!("Here are stdin/stderr since the last checking out on the service:\n{}\n\n", format_output(&filtered_stdout, &filtered_stderr)));
@@ -165,25 +456,11 @@ async fn execute_background_command(
         }
         tracing::info!("SERVICE START workdir {}:\n{:?}", cmdline_workdir, command_str);
         actions_log.push_str(&format!("Starting service with the following command line:\n{}\n", command_str));
-        let project_dirs = crate::files_correction::get_project_dirs(gcx.clone()).await;
-
-        let mut command = create_command_from_string(&command_str, cmdline_workdir, env_variables, project_dirs)?;
-        command.stdout(Stdio::piped());
-        command.stderr(Stdio::piped());
-        let mut command_wrap = TokioCommandWrap::from(command);
-        #[cfg(unix)]
-        command_wrap.wrap(ProcessGroup::leader());
-        #[cfg(windows)]
-        command_wrap.wrap(JobObject);
-        let mut process = command_wrap.spawn().map_err(|e| format!("failed to create process: {e}"))?;
 
-        let mut stdout_reader = BufReader::new(process.stdout().take().ok_or("Failed to open stdout")?);
-        let mut stderr_reader = BufReader::new(process.stderr().take().ok_or("Failed to open stderr")?);
+        let logs = Arc::new(AMutex::new(ServiceLogs::default()));
+        let mut process = spawn_service_process(gcx.clone(), &command_str, cmdline_workdir, env_variables, logs.clone()).await?;
 
         let t0 = tokio::time::Instant::now();
-
-        let mut accumulated_stdout = String::new();
-        let mut accumulated_stderr = String::new();
         let mut exit_code: i32 = -100000;
 
         loop {
@@ -192,11 +469,10 @@ async fn execute_background_command(
                 break;
             }
 
-            let (stdout_out, stderr_out) = get_stdout_and_stderr(100, &mut stdout_reader, &mut stderr_reader).await?;
-            accumulated_stdout.push_str(&stdout_out);
-            accumulated_stderr.push_str(&stderr_out);
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
 
             if !cfg.startup_wait_keyword.is_empty() {
+                let (accumulated_stdout, accumulated_stderr) = logs.lock().await.stdout_stderr_since_seq(0);
                 if accumulated_stdout.contains(&cfg.startup_wait_keyword) || accumulated_stderr.contains(&cfg.startup_wait_keyword) {
                     actions_log.push_str(&format!("Startup keyword '{}' found in output, success!\n\n", cfg.startup_wait_keyword));
                     break;
@@ -226,10 +502,17 @@ async fn execute_background_command(
                     }
                 }
             }
-
-            tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
         }
 
+        if exit_code != -100000 {
+            // let readers catch up with whatever the process printed before dying
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        }
+        let (accumulated_stdout, accumulated_stderr, logs_last_seen_seq) = {
+            let logs_locked = logs.lock().await;
+            let (stdout, stderr) = logs_locked.stdout_stderr_since_seq(0);
+            (stdout, stderr, logs_locked.next_seq)
+        };
         let filtered_stdout = output_mini_postprocessing(&cfg.output_filter, &accumulated_stdout);
         let filtered_stderr = output_mini_postprocessing(&cfg.output_filter, &accumulated_stderr);
         let out = format_output(&filtered_stdout, &filtered_stderr);
@@ -238,13 +521,26 @@ async fn execute_background_command(
         if exit_code == -100000 {
             let session: Box<dyn IntegrationSession> = Box::new(CmdlineSession {
                 cmdline_process: process,
-                cmdline_string: command_str,
+                cmdline_string: command_str.clone(),
                 cmdline_workdir: cmdline_workdir.clone(),
-                cmdline_stdout: stdout_reader,
-                cmdline_stderr: stderr_reader,
+                env_variables: env_variables.clone(),
                 service_name: service_name.to_string(),
+                logs,
+                logs_last_seen_seq,
+                status: ServiceStatus {
+                    service_name: service_name.to_string(),
+                    command: command_str,
+                    workdir: cmdline_workdir.clone(),
+                    state: "running".to_string(),
+                    started_at: chrono::Local::now().to_rfc3339(),
+                    ..Default::default()
+                },
+                monitor: None,
             });
-            gcx.write().await.integration_sessions.insert(session_key.to_string(), Arc::new(AMutex::new(session)));
+            let session_arc = Arc::new(AMutex::new(session));
+            let monitor = tokio::spawn(service_monitor(gcx.clone(), Arc::downgrade(&session_arc), health.clone()));
+            session_arc.lock().await.as_any_mut().downcast_mut::<CmdlineSession>().unwrap().monitor = Some(monitor);
+            gcx.write().await.integration_sessions.insert(session_key.to_string(), session_arc);
         }
 
         tracing::info!("SERVICE START LOG:\n{}", actions_log);
@@ -253,6 +549,22 @@ async fn execute_background_command(
     Ok(actions_log)
 }
 
+pub async fn services_status(gcx: Arc<ARwLock<GlobalContext>>) -> Vec<ServiceStatus> {
+    let sessions = gcx.read().await.integration_sessions.iter()
+        .filter(|(key, _)| key.starts_with("custom_service_"))
+        .map(|(_, session)| session.clone())
+        .collect::<Vec<_>>();
+    let mut result = vec![];
+    for session_arc in sessions {
+        let mut session_locked = session_arc.lock().await;
+        if let Some(session) = session_locked.as_any_mut().downcast_mut::<CmdlineSession>() {
+            result.push(session.status());
+        }
+    }
+    result.sort_by(|a, b| a.service_name.cmp(&b.service_name));
+    result
+}
+
 #[async_trait]
 impl Tool for ToolService {
     fn as_any(&self) -> &dyn std::any::Any { self }
@@ -290,7 +602,7 @@ impl Tool for ToolService {
                 return Err("Tool call is invalid. Param 'action' must be one of 'start', 'restart', 'stop', 'status'. Try again".to_string());
             }
             execute_background_command(
-                gcx, &self.name, &command, &workdir, &self.cfg, action.as_str(), &env_variables,
+                gcx, &self.name, &command, &workdir, &self.cfg, &self.health, action.as_str(), &env_variables,
             ).await?
         };
 
@@ -345,6 +657,134 @@ impl Tool for ToolService {
     }
 }
 
+pub struct ToolServiceLogs {
+    pub service_name: String,
+    pub config_path: String,
+}
+
+/// Accepts a timestamp like `2025-01-31T12:00:00` (local time, as printed by the logs tool) or a relative `30s`, `5m`, `2h`
+pub fn parse_since(since: &str, now: chrono::DateTime<chrono::Local>) -> Result<chrono::DateTime<chrono::Local>, String> {
+    let since = since.trim();
+    if let Ok(ts) = chrono::DateTime::parse_from_rfc3339(since) {
+        return Ok(ts.with_timezone(&chrono::Local));
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"] {
+        if let Ok(naive) = chrono::NaiveDateTime::parse_from_str(since, format) {
+            return naive.and_local_timezone(chrono::Local).earliest()
+                .ok_or(format!("{:?} doesn't exist in the local timezone", since));
+        }
+    }
+    let cannot_parse = || format!("cannot parse since={:?}, use a timestamp like 2025-01-31T12:00:00 or a relative time like 30s, 5m, 2h", since);
+    // since comes from the model, the last char might be multibyte
+    let (unit_start, unit) = since.char_indices().last().ok_or_else(cannot_parse)?;
+    let number = since[..unit_start].parse::<i64>().map_err(|_| cannot_parse())?;
+    let seconds = match unit {
+        's' => number,
+        'm' => number * 60,
+        'h' => number * 3600,
+        _ => return Err(format!("unknown time unit {:?} in since={:?}, use s, m or h", unit, since)),
+    };
+    Ok(now - chrono::Duration::seconds(seconds))
+}
+
+#[async_trait]
+impl Tool for ToolServiceLogs {
+    fn as_any(&self) -> &dyn std::any::Any { self }
+
+    async fn tool_execute(
+        &mut self,
+        ccx: Arc<AMutex<AtCommandsContext>>,
+        tool_call_id: &String,
+        args: &HashMap<String, serde_json::Value>,
+    ) -> Result<(bool, Vec<ContextEnum>), String> {
+        let gcx = ccx.lock().await.global_context.clone();
+        let arg_str = |name: &str| -> Option<String> {
+            match args.get(name) {
+                Some(serde_json::Value::String(s)) if !s.trim().is_empty() => Some(s.clone()),
+                Some(serde_json::Value::Number(n)) => Some(n.to_string()),
+                _ => None,
+            }
+        };
+        let tail = match arg_str("tail") {
+            Some(tail) => tail.parse::<usize>().map_err(|_| format!("argument `tail` should be a number, got {:?}", tail))?,
+            None => LOGS_TOOL_DEFAULT_TAIL,
+        };
+        let grep = match arg_str("grep") {
+            Some(pattern) => Some(regex::Regex::new(&pattern).map_err(|e| format!("argument `grep` is not a valid regex: {}", e))?),
+            None => None,
+        };
+        let since = match arg_str("since") {
+            Some(since) => Some(parse_since(&since, chrono::Local::now())?),
+            None => None,
+        };
+
+        let session_key = format!("custom_service_{}", self.service_name);
+        let session_arc = gcx.read().await.integration_sessions.get(&session_key).cloned()
+            .ok_or(format!("Service {} is not running, start it first", self.service_name))?;
+        let logs = {
+            let mut session_locked = session_arc.lock().await;
+            let session = session_locked.as_any_mut().downcast_mut::<CmdlineSession>().unwrap();
+            session.logs.clone()
+        };
+
+        let mut out = String::new();
+        {
+            let logs_locked = logs.lock().await;
+            let lines = logs_locked.filtered(since, grep.as_ref(), tail);
+            for l in lines.iter() {
+                out.push_str(&format!("{} {} {}\n", l.ts.format("%Y-%m-%dT%H:%M:%S%.3f"), if l.is_stderr { "stderr" } else { "stdout" }, l.line));
+            }
+            if lines.is_empty() {
+                out.push_str("No matching lines in stdout/stderr\n");
+            }
+        }
+
+        Ok((false, vec![ContextEnum::ChatMessage(ChatMessage {
+            role: "tool".to_string(),
+            content: ChatContent::SimpleText(out),
+            tool_calls: None,
+            tool_call_id: tool_call_id.clone(),
+            ..Default::default()
+        })]))
+    }
+
+    fn tool_description(&self) -> ToolDesc {
+        ToolDesc {
+            name: format!("{}_logs", self.service_name),
+            display_name: format!("{} logs", self.service_name),
+            source: ToolSource {
+                source_type: ToolSourceType::Integration,
+                config_path: self.config_path.clone(),
+            },
+            agentic: true,
+            experimental: false,
+            description: format!("Read stdout/stderr that the background service {} printed since it was started, with timestamps.", self.service_name),
+            parameters: vec![
+                ToolParam {
+                    name: "tail".to_string(),
+                    param_type: "string".to_string(),
+                    description: format!("Return only the last N matching lines, default {}.", LOGS_TOOL_DEFAULT_TAIL),
+                },
+                ToolParam {
+                    name: "grep".to_string(),
+                    param_type: "string".to_string(),
+                    description: "Regular expression, return only lines that match.".to_string(),
+                },
+                ToolParam {
+                    name: "since".to_string(),
+                    param_type: "string".to_string(),
+                    description: "Only lines printed after this time: a timestamp like 2025-01-31T12:00:00, or relative like 30s, 5m, 2h.".to_string(),
+                },
+            ],
+            parameters_required: vec![],
+        }
+    }
+
+    fn has_config_path(&self) -> Option<String> {
+        Some(self.config_path.clone())
+    }
+}
+
 pub const CMDLINE_SERVICE_INTEGRATION_SCHEMA: &str = r#"
 fields:
   command:
@@ -373,6 +813,40 @@ fields:
     f_type: string
     f_desc: "Wait until a keyword appears in stdout or stderr at startup."
     f_placeholder: "Ready"
+  health_check_type:
+    f_type: string_short
+    f_desc: "How to check the service is alive after it started: tcp, http, command. Leave empty to only watch for the process to exit."
+    f_placeholder: "http"
+    f_extra: true
+  health_check_target:
+    f_type: string_long
+    f_desc: "Port number for tcp, URL for http (2xx is healthy), or a command line for command (exit code 0 is healthy)."
+    f_placeholder: "http://localhost:8080/health"
+    f_extra: true
+  health_check_interval:
+    f_type: string_short
+    f_desc: "Seconds between health checks."
+    f_default: "10"
+    f_extra: true
+  health_check_failures:
+    f_type: string_short
+    f_desc: "The service is unhealthy after that many failed checks in a row."
+    f_default: "3"
+    f_extra: true
+  restart_on_failure:
+    f_type: bool
+    f_desc: "Restart the service if it crashes or becomes unhealthy."
+    f_extra: true
+  restart_max_attempts:
+    f_type: string_short
+    f_desc: "Give up after that many restarts."
+    f_default: "5"
+    f_extra: true
+  restart_backoff:
+    f_type: string_short
+    f_desc: "Seconds to wait before the first restart, doubles with each attempt."
+    f_default: "1"
+    f_extra: true
 description: |
   As opposed to command line argumenets
 
@@ -399,3 +873,43 @@ smartlinks:
           🔧 Please write %CURRENT_CONFIG% based on what you see in the project. Follow the plan in the system prompt. Remember that service_ tools
           are only suitable for blocking command line commands that run until you hit Ctrl+C, like web servers or `tail -f`.
 "#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_logs_filtered() {
+        let mut logs = ServiceLogs::default();
+        logs.push(false, "listening on 8080".to_string());
+        logs.push(true, "ERROR db is down".to_string());
+        logs.push(false, "GET /health 200".to_string());
+        logs.push(true, "ERROR db is still down".to_string());
+
+        let re = regex::Regex::new("ERROR").unwrap();
+        let errors = logs.filtered(None, Some(&re), 10);
+        assert_eq!(errors.iter().map(|l| l.line.as_str()).collect::<Vec<_>>(), vec!["ERROR db is down", "ERROR db is still down"]);
+        assert_eq!(logs.filtered(None, None, 1)[0].line, "ERROR db is still down");
+        let future = chrono::Local::now() + chrono::Duration::seconds(60);
+        assert!(logs.filtered(Some(future), None, 10).is_empty());
+
+        let (stdout, stderr) = logs.stdout_stderr_since_seq(2);
+        assert_eq!(stdout, "GET /health 200\n");
+        assert_eq!(stderr, "ERROR db is still down\n");
+    }
+
+    #[test]
+    fn test_parse_since() {
+        let now = chrono::Local::now();
+        assert_eq!(parse_since("5m", now).unwrap(), now - chrono::Duration::seconds(300));
+        assert_eq!(parse_since("2h", now).unwrap(), now - chrono::Duration::seconds(7200));
+        let ts = parse_since("2025-01-31T12:00:00.250", now).unwrap();
+        assert_eq!(ts.format("%Y-%m-%dT%H:%M:%S%.3f").to_string(), "2025-01-31T12:00:00.250");
+        assert!(parse_since("2025-01-31T12:00:00+00:00", now).is_ok());
+        assert!(parse_since("yesterday", now).is_err());
+        assert!(parse_since("5分", now).unwrap_err().contains("unknown time unit"));
+        assert!(parse_since("分", now).is_err());
+        assert!(parse_since("", now).is_err());
+        assert!(parse_since("5d", now).unwrap_err().contains("unknown time unit"));
+    }
+}
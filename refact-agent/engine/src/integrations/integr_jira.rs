@@ -0,0 +1,603 @@
+use std::sync::Arc;
+use std::collections::HashMap;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Mutex as AMutex;
+use tokio::sync::RwLock as ARwLock;
+
+use crate::global_context::GlobalContext;
+use crate::at_commands::at_commands::AtCommandsContext;
+use crate::call_validation::{ContextEnum, ChatMessage, ChatContent};
+use crate::integrations::go_to_configuration_message;
+use crate::integrations::integr_abstract::{IntegrationCommon, IntegrationConfirmation, IntegrationTrait};
+use crate::tools::tools_description::{Tool, ToolDesc, ToolParam, ToolSource, ToolSourceType};
+
+
+const JIRA_REQUEST_TIMEOUT: u64 = 30;
+const JIRA_SEARCH_DEFAULT_MAX: u64 = 20;
+const JIRA_ISSUE_FIELDS: &str = "summary,status,issuetype,priority,assignee,reporter,created,updated,labels,description,comment";
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct SettingsJira {
+    pub jira_url: String,
+    #[serde(default)]
+    pub jira_email: String,
+    pub jira_token: String,
+    #[serde(default)]
+    pub jira_api_version: String,
+}
+
+#[derive(Default)]
+pub struct IntegrationJira {
+    pub common: IntegrationCommon,
+    pub settings_jira: SettingsJira,
+    pub config_path: String,
+}
+
+#[async_trait]
+impl IntegrationTrait for IntegrationJira {
+    fn as_any(&self) -> &dyn std::any::Any { self }
+
+    async fn integr_settings_apply(&mut self, _gcx: Arc<ARwLock<GlobalContext>>, config_path: String, value: &serde_json::Value) -> Result<(), serde_json::Error> {
+        self.settings_jira = serde_json::from_value(value.clone())?;
+        self.common = serde_json::from_value(value.clone())?;
+        self.config_path = config_path;
+        Ok(())
+    }
+
+    fn integr_settings_as_json(&self) -> Value {
+        serde_json::to_value(&self.settings_jira).unwrap_or_default()
+    }
+
+    fn integr_common(&self) -> IntegrationCommon {
+        self.common.clone()
+    }
+
+    async fn integr_tools(&self, _integr_name: &str) -> Vec<Box<dyn Tool + Send>> {
+        [JiraOp::GetIssue, JiraOp::Search, JiraOp::AddComment, JiraOp::Transition].into_iter().map(|op| {
+            Box::new(ToolJira {
+                common: self.common.clone(),
+                settings_jira: self.settings_jira.clone(),
+                config_path: self.config_path.clone(),
+                op,
+            }) as Box<dyn Tool + Send>
+        }).collect()
+    }
+
+    fn integr_schema(&self) -> &str { JIRA_INTEGRATION_SCHEMA }
+}
+
+pub struct JiraClient {
+    http_client: reqwest::Client,
+    settings: SettingsJira,
+}
+
+impl JiraClient {
+    pub fn new(http_client: reqwest::Client, settings: SettingsJira) -> Self {
+        JiraClient { http_client, settings }
+    }
+
+    fn api_version(&self) -> &str {
+        if self.settings.jira_api_version.is_empty() { "2" } else { self.settings.jira_api_version.as_str() }
+    }
+
+    pub fn browse_url(&self, key: &str) -> String {
+        format!("{}/browse/{}", self.settings.jira_url.trim_end_matches('/'), key)
+    }
+
+    async fn request(&self, method: reqwest::Method, path: &str, query: &[(&str, String)], body: Option<Value>) -> Result<Value, String> {
+        if self.settings.jira_url.is_empty() {
+            return Err(format!("jira_url is not set. {}", go_to_configuration_message("jira")));
+        }
+        let url = format!("{}/rest/api/{}/{}", self.settings.jira_url.trim_end_matches('/'), self.api_version(), path);
+        let mut request = self.http_client.request(method.clone(), &url)
+            .timeout(std::time::Duration::from_secs(JIRA_REQUEST_TIMEOUT))
+            .header(reqwest::header::ACCEPT, "application/json")
+            .query(query);
+        // Jira Cloud wants email + API token, Server and Data Center take a personal access token
+        request = if self.settings.jira_email.is_empty() {
+            request.bearer_auth(&self.settings.jira_token)
+        } else {
+            request.basic_auth(&self.settings.jira_email, Some(&self.settings.jira_token))
+        };
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await.map_err(|e| format!("{} {} failed: {}", method, url, e))?;
+        let status = response.status();
+        let text = response.text().await.map_err(|e| format!("failed to read Jira response: {}", e))?;
+        if !status.is_success() {
+            let details = serde_json::from_str::<Value>(&text).ok()
+                .map(|v| jira_error_messages(&v))
+                .filter(|s| !s.is_empty())
+                .unwrap_or(text);
+            let hint = if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+                format!("\n{}", go_to_configuration_message("jira"))
+            } else {
+                String::new()
+            };
+            return Err(format!("Jira returned {}: {}{}", status, details, hint));
+        }
+        if text.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+        serde_json::from_str(&text).map_err(|e| format!("Jira returned invalid json: {}", e))
+    }
+
+    pub async fn get_issue(&self, key: &str) -> Result<Value, String> {
+        self.request(reqwest::Method::GET, &format!("issue/{}", encode_key(key)?), &[("fields", JIRA_ISSUE_FIELDS.to_string())], None).await
+    }
+
+    pub async fn search(&self, jql: &str, max_results: u64) -> Result<Value, String> {
+        // Jira Cloud removed /rest/api/3/search in favor of search/jql, Server and Data Center have only v2 search
+        let path = if self.api_version() == "2" { "search" } else { "search/jql" };
+        self.request(reqwest::Method::GET, path, &[
+            ("jql", jql.to_string()),
+            ("maxResults", max_results.to_string()),
+            ("fields", "summary,status,assignee,priority,updated".to_string()),
+        ], None).await
+    }
+
+    pub async fn add_comment(&self, key: &str, body: &str) -> Result<Value, String> {
+        // v3 only accepts Atlassian Document Format
+        let body = if self.api_version() == "2" {
+            json!({"body": body})
+        } else {
+            json!({"body": {"type": "doc", "version": 1, "content": body.split("\n\n").map(|paragraph| {
+                json!({"type": "paragraph", "content": [{"type": "text", "text": paragraph}]})
+            }).collect::<Vec<_>>()}})
+        };
+        self.request(reqwest::Method::POST, &format!("issue/{}/comment", encode_key(key)?), &[], Some(body)).await
+    }
+
+    pub async fn transition(&self, key: &str, transition: &str) -> Result<String, String> {
+        let path = format!("issue/{}/transitions", encode_key(key)?);
+        let available = self.request(reqwest::Method::GET, &path, &[], None).await?;
+        let transitions = available["transitions"].as_array().cloned().unwrap_or_default();
+        let wanted = transition.trim().to_lowercase();
+        let found = transitions.iter().find(|t| {
+            t["id"].as_str() == Some(transition.trim())
+                || t["name"].as_str().map(|n| n.to_lowercase()) == Some(wanted.clone())
+                || t["to"]["name"].as_str().map(|n| n.to_lowercase()) == Some(wanted.clone())
+        });
+        let found = match found {
+            Some(t) => t,
+            None => {
+                let names = transitions.iter()
+                    .map(|t| format!("{} (-> {})", t["name"].as_str().unwrap_or("?"), t["to"]["name"].as_str().unwrap_or("?")))
+                    .collect::<Vec<_>>();
+                return Err(format!("Transition {:?} is not available for {}, available transitions: {}", transition, key, names.join(", ")));
+            }
+        };
+        self.request(reqwest::Method::POST, &path, &[], Some(json!({"transition": {"id": found["id"]}}))).await?;
+        Ok(format!("{} moved to {}", key, found["to"]["name"].as_str().or(found["name"].as_str()).unwrap_or(transition)))
+    }
+}
+
+fn encode_key(key: &str) -> Result<String, String> {
+    let key = key.trim();
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("{:?} doesn't look like an issue key, expected something like PROJ-123", key));
+    }
+    Ok(key.to_string())
+}
+
+fn jira_error_messages(v: &Value) -> String {
+    let mut messages: Vec<String> = v["errorMessages"].as_array().into_iter().flatten()
+        .filter_map(|m| m.as_str().map(|s| s.to_string()))
+        .collect();
+    if let Some(errors) = v["errors"].as_object() {
+        messages.extend(errors.iter().map(|(k, m)| format!("{}: {}", k, m.as_str().unwrap_or(&m.to_string()))));
+    }
+    messages.join("; ")
+}
+
+/// Descriptions and comments are plain text in API v2 and Atlassian Document Format in v3
+pub fn jira_text(v: &Value) -> String {
+    fn walk(node: &Value, out: &mut String) {
+        if let Some(text) = node["text"].as_str() {
+            out.push_str(text);
+        }
+        match node["type"].as_str() {
+            Some("hardBreak") => out.push('\n'),
+            Some("mention") => out.push_str(node["attrs"]["text"].as_str().unwrap_or_default()),
+            _ => {}
+        }
+        for child in node["content"].as_array().into_iter().flatten() {
+            walk(child, out);
+        }
+        if matches!(node["type"].as_str(), Some("paragraph" | "heading" | "listItem" | "codeBlock" | "blockquote")) && !out.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+    match v {
+        Value::String(s) => s.clone(),
+        Value::Object(_) => {
+            let mut out = String::new();
+            walk(v, &mut out);
+            out.trim_end().to_string()
+        }
+        _ => String::new(),
+    }
+}
+
+pub fn format_jira_issue(issue: &Value, browse_url: &str) -> String {
+    let fields = &issue["fields"];
+    let name = |v: &Value| v["displayName"].as_str().or(v["name"].as_str()).unwrap_or("none").to_string();
+    let mut out = format!("# {}: {}\n", issue["key"].as_str().unwrap_or("?"), fields["summary"].as_str().unwrap_or_default());
+    out.push_str(&format!(
+        "Status: {} | Type: {} | Priority: {} | Assignee: {} | Reporter: {}\n",
+        name(&fields["status"]), name(&fields["issuetype"]), name(&fields["priority"]), name(&fields["assignee"]), name(&fields["reporter"]),
+    ));
+    out.push_str(&format!("Created: {} | Updated: {}\n", fields["created"].as_str().unwrap_or("?"), fields["updated"].as_str().unwrap_or("?")));
+    let labels = fields["labels"].as_array().into_iter().flatten().filter_map(|l| l.as_str()).collect::<Vec<_>>();
+    if !labels.is_empty() {
+        out.push_str(&format!("Labels: {}\n", labels.join(", ")));
+    }
+    out.push_str(&format!("URL: {}\n", browse_url));
+
+    let description = jira_text(&fields["description"]);
+    out.push_str(&format!("\n## Description\n{}\n", if description.is_empty() { "(no description)" } else { &description }));
+
+    let comments = fields["comment"]["comments"].as_array().cloned().unwrap_or_default();
+    if !comments.is_empty() {
+        out.push_str(&format!("\n## Comments ({})\n", comments.len()));
+        for comment in comments {
+            out.push_str(&format!("\n### {}, {}\n{}\n", name(&comment["author"]), comment["created"].as_str().unwrap_or("?"), jira_text(&comment["body"])));
+        }
+    }
+    out
+}
+
+pub fn format_jira_search(result: &Value) -> String {
+    let issues = result["issues"].as_array().cloned().unwrap_or_default();
+    if issues.is_empty() {
+        return "No issues found".to_string();
+    }
+    let mut out = String::new();
+    for issue in issues.iter() {
+        let fields = &issue["fields"];
+        out.push_str(&format!(
+            "{} [{}] {} (assignee: {}, updated: {})\n",
+            issue["key"].as_str().unwrap_or("?"),
+            fields["status"]["name"].as_str().unwrap_or("?"),
+            fields["summary"].as_str().unwrap_or_default(),
+            fields["assignee"]["displayName"].as_str().unwrap_or("none"),
+            fields["updated"].as_str().unwrap_or("?"),
+        ));
+    }
+    // search/jql has no total, only tells if there are more
+    match (result["total"].as_u64(), result["isLast"].as_bool()) {
+        (Some(total), _) if total > issues.len() as u64 => {
+            out.push_str(&format!("...showing {} of {} issues, make the JQL more specific to see the rest\n", issues.len(), total));
+        },
+        (None, Some(false)) => {
+            out.push_str(&format!("...showing the first {} issues, make the JQL more specific to see the rest\n", issues.len()));
+        },
+        _ => {},
+    }
+    out
+}
+
+/// Used by `@issue`, finds the configured jira integration
+pub async fn jira_client_from_config(gcx: Arc<ARwLock<GlobalContext>>) -> Result<JiraClient, String> {
+    let (integrations, _yaml_errors) = crate::integrations::running_integrations::load_integrations(gcx.clone(), &["**/jira.yaml".to_string()]).await;
+    let settings = integrations.get("jira")
+        .and_then(|integr| integr.as_any().downcast_ref::<IntegrationJira>())
+        .map(|jira| jira.settings_jira.clone())
+        .ok_or(format!("Jira integration is not configured. {}", go_to_configuration_message("jira")))?;
+    let http_client = gcx.read().await.http_client.clone();
+    Ok(JiraClient::new(http_client, settings))
+}
+
+#[derive(Clone, Copy)]
+pub enum JiraOp {
+    GetIssue,
+    Search,
+    AddComment,
+    Transition,
+}
+
+pub struct ToolJira {
+    pub common: IntegrationCommon,
+    pub settings_jira: SettingsJira,
+    pub config_path: String,
+    pub op: JiraOp,
+}
+
+fn arg_str(args: &HashMap<String, Value>, name: &str) -> Result<String, String> {
+    match args.get(name) {
+        Some(Value::String(s)) => Ok(s.clone()),
+        Some(v) => Err(format!("argument `{}` is not a string: {:?}", name, v)),
+        None => Err(format!("Missing argument `{}`", name)),
+    }
+}
+
+#[async_trait]
+impl Tool for ToolJira {
+    fn as_any(&self) -> &dyn std::any::Any { self }
+
+    fn tool_description(&self) -> ToolDesc {
+        let key_param = ToolParam {
+            name: "issue_key".to_string(),
+            param_type: "string".to_string(),
+            description: "Issue key, for example PROJ-123.".to_string(),
+        };
+        let (name, display_name, description, parameters) = match self.op {
+            JiraOp::GetIssue => ("jira_get_issue", "Jira Issue", "Fetch a Jira issue with its description, status and comments.", vec![key_param]),
+            JiraOp::Search => ("jira_search", "Jira Search", "Search Jira issues using JQL.", vec![
+                ToolParam {
+                    name: "jql".to_string(),
+                    param_type: "string".to_string(),
+                    description: "JQL query, for example: project = PROJ AND status = \"In Progress\" ORDER BY updated DESC".to_string(),
+                },
+                ToolParam {
+                    name: "max_results".to_string(),
+                    param_type: "string".to_string(),
+                    description: format!("How many issues to return, default {}.", JIRA_SEARCH_DEFAULT_MAX),
+                },
+            ]),
+            JiraOp::AddComment => ("jira_add_comment", "Jira Comment", "Add a comment to a Jira issue.", vec![key_param, ToolParam {
+                name: "body".to_string(),
+                param_type: "string".to_string(),
+                description: "Comment text.".to_string(),
+            }]),
+            JiraOp::Transition => ("jira_transition", "Jira Transition", "Move a Jira issue to another state. If the transition is not available, the error lists the ones that are.", vec![key_param, ToolParam {
+                name: "transition".to_string(),
+                param_type: "string".to_string(),
+                description: "Transition name or id, or the name of the target status, for example \"Done\".".to_string(),
+            }]),
+        };
+        let parameters_required = parameters.iter().map(|p| p.name.clone()).filter(|n| n != "max_results").collect();
+        ToolDesc {
+            name: name.to_string(),
+            display_name: display_name.to_string(),
+            source: ToolSource {
+                source_type: ToolSourceType::Integration,
+                config_path: self.config_path.clone(),
+            },
+            agentic: true,
+            experimental: false,
+            description: description.to_string(),
+            parameters,
+            parameters_required,
+        }
+    }
+
+    async fn tool_execute(
+        &mut self,
+        ccx: Arc<AMutex<AtCommandsContext>>,
+        tool_call_id: &String,
+        args: &HashMap<String, Value>,
+    ) -> Result<(bool, Vec<ContextEnum>), String> {
+        let gcx = ccx.lock().await.global_context.clone();
+        let http_client = gcx.read().await.http_client.clone();
+        let client = JiraClient::new(http_client, self.settings_jira.clone());
+
+        let content = match self.op {
+            JiraOp::GetIssue => {
+                let key = arg_str(args, "issue_key")?;
+                let issue = client.get_issue(&key).await?;
+                format_jira_issue(&issue, &client.browse_url(&key))
+            },
+            JiraOp::Search => {
+                let jql = arg_str(args, "jql")?;
+                let max_results = match args.get("max_results") {
+                    Some(Value::String(s)) if !s.is_empty() => s.parse::<u64>().map_err(|_| format!("argument `max_results` should be a number, got {:?}", s))?,
+                    Some(Value::Number(n)) => n.as_u64().unwrap_or(JIRA_SEARCH_DEFAULT_MAX),
+                    _ => JIRA_SEARCH_DEFAULT_MAX,
+                };
+                format_jira_search(&client.search(&jql, max_results).await?)
+            },
+            JiraOp::AddComment => {
+                let key = arg_str(args, "issue_key")?;
+                let body = arg_str(args, "body")?;
+                client.add_comment(&key, &body).await?;
+                format!("Comment added to {}", client.browse_url(&key))
+            },
+            JiraOp::Transition => {
+                let key = arg_str(args, "issue_key")?;
+                let transition = arg_str(args, "transition")?;
+                client.transition(&key, &transition).await?
+            },
+        };
+
+        Ok((false, vec![ContextEnum::ChatMessage(ChatMessage {
+            role: "tool".to_string(),
+            content: ChatContent::SimpleText(content),
+            tool_calls: None,
+            tool_call_id: tool_call_id.clone(),
+            ..Default::default()
+        })]))
+    }
+
+    async fn command_to_match_against_confirm_deny(
+        &self,
+        _ccx: Arc<AMutex<AtCommandsContext>>,
+        args: &HashMap<String, Value>,
+    ) -> Result<String, String> {
+        let subject = match self.op {
+            JiraOp::Search => arg_str(args, "jql").unwrap_or_default(),
+            _ => arg_str(args, "issue_key").unwrap_or_default(),
+        };
+        Ok(format!("{} {}", self.tool_description().name, subject))
+    }
+
+    fn confirm_deny_rules(&self) -> Option<IntegrationConfirmation> {
+        Some(self.common.confirmation.clone())
+    }
+
+    fn has_config_path(&self) -> Option<String> {
+        Some(self.config_path.clone())
+    }
+}
+
+const JIRA_INTEGRATION_SCHEMA: &str = r#"
+fields:
+  jira_url:
+    f_type: string_long
+    f_desc: "Base URL of your Jira, without /rest/api."
+    f_placeholder: "https://yourcompany.atlassian.net"
+    f_label: "URL"
+  jira_email:
+    f_type: string_short
+    f_desc: "For Jira Cloud, the email of the account that owns the API token. Leave empty for Jira Server or Data Center, then the token is sent as a personal access token."
+    f_placeholder: "me@yourcompany.com"
+    f_label: "Email"
+  jira_token:
+    f_type: string_long
+    f_desc: "Jira API token, you can create one [here](https://id.atlassian.com/manage-profile/security/api-tokens). If you don't want to send your key to the AI model that helps you to configure the agent, put it into secrets.yaml and write `$MY_SECRET_VARIABLE` in this field."
+    f_label: "Token"
+    smartlinks:
+      - sl_label: "Open secrets.yaml"
+        sl_goto: "EDITOR:secrets.yaml"
+  jira_api_version:
+    f_type: string_short
+    f_desc: "REST API version, 2 or 3."
+    f_default: "2"
+    f_extra: true
+description: |
+  The Jira integration gives the model tools to read issues with comments, search with JQL, add comments and move issues
+  between states. It also enables the @issue command that puts a ticket into the chat context, for example `@issue PROJ-123`.
+  Works with Jira Cloud, Server and Data Center, and other trackers that implement the same REST API.
+available:
+  on_your_laptop_possible: true
+  when_isolated_possible: true
+confirmation:
+  ask_user_default: ["jira_add_comment *", "jira_transition *"]
+  deny_default: []
+smartlinks:
+  - sl_label: "Test"
+    sl_chat:
+      - role: "user"
+        content: |
+          🔧 The jira tools should be visible now. To test them, search for 5 most recently updated issues assigned to the current user and briefly describe them.
+          Change nothing. If it doesn't work or the tools aren't available, go through the usual plan in the system prompt.
+    sl_enable_only_with_tool: true
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_provider::start_test_server;
+    use axum::extract::{Path, Query};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+
+    async fn mock_issue(Path(key): Path<String>) -> Json<Value> {
+        Json(json!({
+            "key": key,
+            "fields": {
+                "summary": "Login button does nothing",
+                "status": {"name": "To Do"},
+                "issuetype": {"name": "Bug"},
+                "assignee": {"displayName": "Alice"},
+                "labels": ["frontend"],
+                "description": {"type": "doc", "version": 1, "content": [
+                    {"type": "paragraph", "content": [{"type": "text", "text": "Steps to reproduce:"}]},
+                    {"type": "paragraph", "content": [{"type": "text", "text": "click it"}]},
+                ]},
+                "comment": {"comments": [{"author": {"displayName": "Bob"}, "created": "2025-01-01T10:00:00.000+0000", "body": "Confirmed on staging"}]},
+            }
+        }))
+    }
+
+    async fn mock_search(Query(q): Query<HashMap<String, String>>) -> Json<Value> {
+        assert_eq!(q.get("maxResults").map(|s| s.as_str()), Some("1"));
+        Json(json!({"total": 2, "issues": [{"key": "PROJ-1", "fields": {"summary": "Login button does nothing", "status": {"name": "To Do"}}}]}))
+    }
+
+    async fn mock_search_jql(Query(q): Query<HashMap<String, String>>) -> Json<Value> {
+        assert_eq!(q.get("jql").map(|s| s.as_str()), Some("project = PROJ"));
+        Json(json!({"isLast": false, "nextPageToken": "t", "issues": [{"key": "PROJ-1", "fields": {"summary": "Login button does nothing", "status": {"name": "To Do"}}}]}))
+    }
+
+    async fn mock_transitions() -> Json<Value> {
+        Json(json!({"transitions": [
+            {"id": "11", "name": "Start Progress", "to": {"name": "In Progress"}},
+            {"id": "31", "name": "Resolve", "to": {"name": "Done"}},
+        ]}))
+    }
+
+    async fn mock_do_transition(Json(body): Json<Value>) -> axum::http::StatusCode {
+        match body["transition"]["id"].as_str() {
+            Some("31") => axum::http::StatusCode::NO_CONTENT,
+            _ => axum::http::StatusCode::BAD_REQUEST,
+        }
+    }
+
+    async fn mock_add_comment(Path(key): Path<String>, Json(body): Json<Value>) -> (axum::http::StatusCode, Json<Value>) {
+        (axum::http::StatusCode::CREATED, Json(json!({"id": "10001", "issue": key, "body": body["body"]})))
+    }
+
+    async fn start_mock_jira() -> String {
+        let app = Router::new()
+            .route("/rest/api/2/issue/:key", get(mock_issue))
+            .route("/rest/api/2/search", get(mock_search))
+            .route("/rest/api/3/search/jql", get(mock_search_jql))
+            .route("/rest/api/2/issue/:key/transitions", get(mock_transitions).post(mock_do_transition))
+            .route("/rest/api/2/issue/:key/comment", post(mock_add_comment))
+            .route("/rest/api/3/issue/:key/comment", post(mock_add_comment));
+        start_test_server(app)
+    }
+
+    fn client(url: String) -> JiraClient {
+        JiraClient::new(reqwest::Client::new(), SettingsJira {
+            jira_url: url,
+            jira_token: "token".to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_jira_get_issue_and_search() {
+        let url = start_mock_jira().await;
+        let mut client_v3 = client(url.clone());
+        client_v3.settings.jira_api_version = "3".to_string();
+        let client = client(url);
+        let issue = client.get_issue("PROJ-1").await.unwrap();
+        let text = format_jira_issue(&issue, &client.browse_url("PROJ-1"));
+        assert!(text.starts_with("# PROJ-1: Login button does nothing\n"));
+        assert!(text.contains("Status: To Do | Type: Bug | Priority: none | Assignee: Alice"));
+        assert!(text.contains("Steps to reproduce:\nclick it"));
+        assert!(text.contains("### Bob, 2025-01-01T10:00:00.000+0000\nConfirmed on staging"));
+        assert!(text.contains("/browse/PROJ-1"));
+
+        let found = format_jira_search(&client.search("project = PROJ", 1).await.unwrap());
+        assert!(found.starts_with("PROJ-1 [To Do] Login button does nothing"));
+        assert!(found.contains("showing 1 of 2 issues"));
+
+        let found = format_jira_search(&client_v3.search("project = PROJ", 1).await.unwrap());
+        assert!(found.starts_with("PROJ-1 [To Do] Login button does nothing"));
+        assert!(found.contains("showing the first 1 issues"));
+
+        assert!(client.get_issue("PROJ-1/../admin").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_jira_transition() {
+        let client = client(start_mock_jira().await);
+        assert_eq!(client.transition("PROJ-1", "done").await.unwrap(), "PROJ-1 moved to Done");
+        let err = client.transition("PROJ-1", "Reopen").await.unwrap_err();
+        assert!(err.contains("Start Progress (-> In Progress), Resolve (-> Done)"));
+    }
+
+    #[tokio::test]
+    async fn test_jira_add_comment() {
+        let url = start_mock_jira().await;
+        let mut client_v3 = client(url.clone());
+        client_v3.settings.jira_api_version = "3".to_string();
+        let client = client(url);
+
+        let comment = client.add_comment("PROJ-1", "Fixed in main").await.unwrap();
+        assert_eq!(comment["issue"], "PROJ-1");
+        assert_eq!(comment["body"], "Fixed in main");
+
+        let comment = client_v3.add_comment("PROJ-1", "Fixed in main\n\nPlease verify").await.unwrap();
+        assert_eq!(comment["body"]["type"], "doc");
+        assert_eq!(jira_text(&comment["body"]), "Fixed in main\nPlease verify");
+
+        assert!(client.add_comment("PROJ-1/../admin", "x").await.is_err());
+    }
+}
@@ -0,0 +1,742 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex as StdMutex, OnceLock};
+use std::time::SystemTime;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex as AMutex;
+use tokio::sync::RwLock as ARwLock;
+
+use crate::at_commands::at_commands::AtCommandsContext;
+use crate::call_validation::{ChatContent, ChatMessage, ContextEnum};
+use crate::global_context::GlobalContext;
+use crate::integrations::integr_abstract::{IntegrationCommon, IntegrationConfirmation, IntegrationTrait};
+use crate::integrations::utils::{serialize_num_to_str, deserialize_str_to_num};
+use crate::tools::tools_description::{Tool, ToolDesc, ToolParam, ToolSource, ToolSourceType};
+
+const HTTP_METHODS: &[&str] = &["get", "put", "post", "delete", "options", "head", "patch", "trace"];
+const MAX_REF_DEPTH: usize = 16;
+const MAX_TOOL_NAME_LEN: usize = 64;
+const MAX_DESCRIPTION_LEN: usize = 1024;
+
+// integrations are loaded again and again, a spec is parsed again only when its file changes
+static SPEC_CACHE: OnceLock<StdMutex<HashMap<PathBuf, (SystemTime, Arc<Value>)>>> = OnceLock::new();
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct SettingsOpenAPI {
+    pub spec_path: String,
+    #[serde(default)]
+    pub base_url: String,
+    #[serde(default)]
+    pub auth_type: String,
+    #[serde(default)]
+    pub auth_token: String,
+    #[serde(default)]
+    pub auth_username: String,
+    #[serde(default)]
+    pub auth_key_name: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub operations: Vec<String>,
+    #[serde(default = "default_request_timeout", serialize_with = "serialize_num_to_str", deserialize_with = "deserialize_str_to_num")]
+    pub request_timeout: u64,
+    #[serde(default = "default_output_limit", serialize_with = "serialize_num_to_str", deserialize_with = "deserialize_str_to_num")]
+    pub output_limit: usize,
+}
+
+fn default_request_timeout() -> u64 { 30 }
+fn default_output_limit() -> usize { 20000 }
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OpenAPIParamLocation {
+    Path,
+    Query,
+    Header,
+    Body,
+}
+
+#[derive(Clone, Debug)]
+pub struct OpenAPIParam {
+    pub tool_param: ToolParam,
+    pub original_name: String,
+    pub location: OpenAPIParamLocation,
+    pub required: bool,
+    pub json_encoded: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct OpenAPIOperation {
+    pub operation_id: String,
+    pub method: String,
+    pub path: String,
+    pub description: String,
+    pub params: Vec<OpenAPIParam>,
+    /// Request body is not an object with properties, the model passes it whole as json
+    pub raw_body: bool,
+}
+
+#[derive(Default)]
+pub struct IntegrationOpenAPI {
+    pub common: IntegrationCommon,
+    pub settings_openapi: SettingsOpenAPI,
+    pub operations: Vec<OpenAPIOperation>,
+    pub spec_base_url: String,
+    pub config_path: String,
+}
+
+#[async_trait]
+impl IntegrationTrait for IntegrationOpenAPI {
+    fn as_any(&self) -> &dyn std::any::Any { self }
+
+    async fn integr_settings_apply(&mut self, gcx: Arc<ARwLock<GlobalContext>>, config_path: String, value: &serde_json::Value) -> Result<(), serde_json::Error> {
+        self.settings_openapi = serde_json::from_value(value.clone())?;
+        self.common = serde_json::from_value(value.clone())?;
+        self.config_path = config_path;
+
+        let mut spec_path = PathBuf::from(&self.settings_openapi.spec_path);
+        if spec_path.is_relative() {
+            if let Some(workspace_folder) = crate::files_correction::get_project_dirs(gcx.clone()).await.into_iter().next() {
+                spec_path = workspace_folder.join(spec_path);
+            }
+        }
+        // the settings are still applied without the spec, so they can be saved and fixed
+        let spec = match load_openapi_spec(&spec_path).await {
+            Ok(spec) => spec,
+            Err(e) => {
+                tracing::warn!("{}, no tools from {}", e, self.config_path);
+                self.spec_base_url = String::new();
+                self.operations = vec![];
+                return Ok(());
+            }
+        };
+        self.spec_base_url = spec_servers_url(&spec);
+        self.operations = openapi_operations(&spec)
+            .into_iter()
+            .filter(|op| self.settings_openapi.operations.is_empty() || self.settings_openapi.operations.contains(&op.operation_id))
+            .collect();
+        tracing::info!("OpenAPI {}: {} operations loaded", spec_path.display(), self.operations.len());
+        Ok(())
+    }
+
+    fn integr_settings_as_json(&self) -> Value {
+        serde_json::to_value(&self.settings_openapi).unwrap()
+    }
+
+    fn integr_common(&self) -> IntegrationCommon {
+        self.common.clone()
+    }
+
+    async fn integr_tools(&self, integr_name: &str) -> Vec<Box<dyn Tool + Send>> {
+        let base_url = if !self.settings_openapi.base_url.is_empty() {
+            self.settings_openapi.base_url.clone()
+        } else {
+            self.spec_base_url.clone()
+        };
+        self.operations.iter().map(|operation| {
+            Box::new(ToolOpenAPIOperation {
+                common: self.common.clone(),
+                integr_name: integr_name.to_string(),
+                settings_openapi: self.settings_openapi.clone(),
+                base_url: base_url.clone(),
+                operation: operation.clone(),
+                config_path: self.config_path.clone(),
+            }) as Box<dyn Tool + Send>
+        }).collect()
+    }
+
+    fn integr_schema(&self) -> &str {
+        OPENAPI_INTEGRATION_SCHEMA
+    }
+}
+
+async fn load_openapi_spec(spec_path: &PathBuf) -> Result<Arc<Value>, String> {
+    let mtime = tokio::fs::metadata(spec_path).await.and_then(|m| m.modified())
+        .map_err(|e| format!("cannot read OpenAPI spec {}: {}", spec_path.display(), e))?;
+    let cache = SPEC_CACHE.get_or_init(|| StdMutex::new(HashMap::new()));
+    if let Some((cached_mtime, spec)) = cache.lock().unwrap().get(spec_path) {
+        if *cached_mtime == mtime {
+            return Ok(spec.clone());
+        }
+    }
+    let spec_text = tokio::fs::read_to_string(spec_path).await
+        .map_err(|e| format!("cannot read OpenAPI spec {}: {}", spec_path.display(), e))?;
+    let spec = Arc::new(parse_openapi_spec(&spec_text)?);
+    cache.lock().unwrap().insert(spec_path.clone(), (mtime, spec.clone()));
+    Ok(spec)
+}
+
+pub fn parse_openapi_spec(spec_text: &str) -> Result<Value, String> {
+    // yaml is a superset of json, one parser for both
+    let spec_yaml: serde_yaml::Value = serde_yaml::from_str(spec_text)
+        .map_err(|e| format!("cannot parse OpenAPI spec: {}", e))?;
+    let spec: Value = serde_json::to_value(spec_yaml)
+        .map_err(|e| format!("cannot parse OpenAPI spec: {}", e))?;
+    match spec.get("openapi").and_then(|v| v.as_str()) {
+        Some(version) if version.starts_with("3.") => Ok(spec),
+        Some(version) => Err(format!("OpenAPI version {} is not supported, only 3.x", version)),
+        None => Err("not an OpenAPI 3 spec, `openapi` field is missing".to_string()),
+    }
+}
+
+fn spec_servers_url(spec: &Value) -> String {
+    spec.pointer("/servers/0/url").and_then(|v| v.as_str()).unwrap_or_default().to_string()
+}
+
+/// Follows local `$ref`s like `#/components/schemas/Pet`, remote refs are left as is
+fn resolve_ref<'a>(spec: &'a Value, value: &'a Value) -> &'a Value {
+    let mut current = value;
+    for _ in 0..MAX_REF_DEPTH {
+        match current.get("$ref").and_then(|r| r.as_str()).and_then(|r| r.strip_prefix('#')) {
+            Some(pointer) => match spec.pointer(pointer) {
+                Some(resolved) => current = resolved,
+                None => break,
+            },
+            None => break,
+        }
+    }
+    current
+}
+
+pub fn openapi_operations(spec: &Value) -> Vec<OpenAPIOperation> {
+    let mut operations = vec![];
+    let paths = match spec.get("paths").and_then(|p| p.as_object()) {
+        Some(paths) => paths,
+        None => return operations,
+    };
+    for (path, path_item) in paths {
+        let path_item = resolve_ref(spec, path_item);
+        let path_level_params = path_item.get("parameters").and_then(|p| p.as_array()).cloned().unwrap_or_default();
+        for method in HTTP_METHODS {
+            let op = match path_item.get(*method) {
+                Some(op) => op,
+                None => continue,
+            };
+            let operation_id = op.get("operationId").and_then(|v| v.as_str())
+                .map(|id| id.to_string())
+                .unwrap_or_else(|| format!("{}_{}", method, path));
+
+            let summary = op.get("summary").and_then(|v| v.as_str()).unwrap_or_default();
+            let details = op.get("description").and_then(|v| v.as_str()).unwrap_or_default();
+            let mut description = format!("{} {}", method.to_uppercase(), path);
+            for text in [summary, details] {
+                if !text.trim().is_empty() {
+                    description.push('\n');
+                    description.push_str(text.trim());
+                }
+            }
+
+            let mut params: Vec<OpenAPIParam> = vec![];
+            let op_params = op.get("parameters").and_then(|p| p.as_array()).cloned().unwrap_or_default();
+            // operation-level parameters override path-level ones with the same name and location
+            for param in op_params.iter().chain(path_level_params.iter()) {
+                let param = resolve_ref(spec, param);
+                let original_name = match param.get("name").and_then(|v| v.as_str()) {
+                    Some(name) => name.to_string(),
+                    None => continue,
+                };
+                let location = match param.get("in").and_then(|v| v.as_str()) {
+                    Some("path") => OpenAPIParamLocation::Path,
+                    Some("query") => OpenAPIParamLocation::Query,
+                    Some("header") => OpenAPIParamLocation::Header,
+                    _ => continue,
+                };
+                if params.iter().any(|p| p.original_name == original_name && p.location == location) {
+                    continue;
+                }
+                let schema = param.get("schema").map(|s| resolve_ref(spec, s)).cloned().unwrap_or(Value::Null);
+                let param_description = param.get("description").and_then(|v| v.as_str()).unwrap_or_default();
+                let required = location == OpenAPIParamLocation::Path || param.get("required").and_then(|v| v.as_bool()).unwrap_or(false);
+                params.push(make_param(&original_name, location, required, &schema, param_description, &params));
+            }
+
+            let mut raw_body = false;
+            if let Some(body_schema) = op.get("requestBody")
+                .map(|b| resolve_ref(spec, b))
+                .and_then(|b| b.pointer("/content/application~1json/schema"))
+                .map(|s| resolve_ref(spec, s))
+            {
+                let body_required = resolve_ref(spec, op.get("requestBody").unwrap()).get("required").and_then(|v| v.as_bool()).unwrap_or(false);
+                match body_schema.get("properties").and_then(|p| p.as_object()) {
+                    Some(properties) => {
+                        let required_props = body_schema.get("required").and_then(|r| r.as_array()).cloned().unwrap_or_default();
+                        for (prop_name, prop_schema) in properties {
+                            let prop_schema = resolve_ref(spec, prop_schema);
+                            let prop_description = prop_schema.get("description").and_then(|v| v.as_str()).unwrap_or_default();
+                            let required = body_required && required_props.iter().any(|r| r.as_str() == Some(prop_name));
+                            params.push(make_param(prop_name, OpenAPIParamLocation::Body, required, prop_schema, prop_description, &params));
+                        }
+                    }
+                    None => {
+                        raw_body = true;
+                        params.push(OpenAPIParam {
+                            tool_param: ToolParam {
+                                name: unique_param_name("body", &params),
+                                param_type: "string".to_string(),
+                                description: "Request body, JSON-encoded.".to_string(),
+                            },
+                            original_name: "body".to_string(),
+                            location: OpenAPIParamLocation::Body,
+                            required: body_required,
+                            json_encoded: true,
+                        });
+                    }
+                }
+            }
+
+            operations.push(OpenAPIOperation {
+                operation_id,
+                method: method.to_string(),
+                path: path.clone(),
+                description: description.chars().take(MAX_DESCRIPTION_LEN).collect(),
+                params,
+                raw_body,
+            });
+        }
+    }
+    operations
+}
+
+fn make_param(
+    original_name: &str,
+    location: OpenAPIParamLocation,
+    required: bool,
+    schema: &Value,
+    description: &str,
+    existing: &[OpenAPIParam],
+) -> OpenAPIParam {
+    let schema_type = schema.get("type").and_then(|v| v.as_str()).unwrap_or("string");
+    // not every model accepts array/object parameters, those are passed as json strings
+    let (param_type, json_encoded) = match schema_type {
+        "integer" | "number" | "boolean" | "string" => (schema_type.to_string(), false),
+        _ => ("string".to_string(), true),
+    };
+    let mut description = description.trim().to_string();
+    if let Some(values) = schema.get("enum").and_then(|v| v.as_array()) {
+        let values = values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ");
+        description = format!("{} One of: {}.", description, values).trim().to_string();
+    }
+    if json_encoded {
+        description = format!("{} JSON-encoded {}.", description, schema_type).trim().to_string();
+    }
+    OpenAPIParam {
+        tool_param: ToolParam {
+            name: unique_param_name(&to_snake_case(original_name), existing),
+            param_type,
+            description,
+        },
+        original_name: original_name.to_string(),
+        location,
+        required,
+        json_encoded,
+    }
+}
+
+fn unique_param_name(name: &str, existing: &[OpenAPIParam]) -> String {
+    let mut candidate = name.to_string();
+    let mut n = 2;
+    while existing.iter().any(|p| p.tool_param.name == candidate) {
+        candidate = format!("{}{}", name, n);
+        n += 1;
+    }
+    candidate
+}
+
+/// Function calling takes only `[a-zA-Z0-9_-]` names. Long names are cut and get a hash of the full name,
+/// so two operations sharing a long prefix stay two tools
+pub fn openapi_tool_name(integr_name: &str, operation_id: &str) -> String {
+    let name = format!("{}_{}", to_snake_case(integr_name), to_snake_case(operation_id));
+    if name.chars().count() <= MAX_TOOL_NAME_LEN {
+        return name;
+    }
+    let digest = Sha256::digest(name.as_bytes());
+    let suffix = digest[..4].iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    let prefix = name.chars().take(MAX_TOOL_NAME_LEN - suffix.len() - 1).collect::<String>();
+    format!("{}_{}", prefix.trim_end_matches('_'), suffix)
+}
+
+/// `getPetById`, `X-Request-Id`, `get_/pets/{id}` -> `get_pet_by_id`, `x_request_id`, `get_pets_id`
+pub fn to_snake_case(name: &str) -> String {
+    let mut out = String::new();
+    let mut prev_lower_or_digit = false;
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            if prev_lower_or_digit {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+            prev_lower_or_digit = false;
+        } else if c.is_ascii_alphanumeric() {
+            out.push(c);
+            prev_lower_or_digit = true;
+        } else {
+            out.push('_');
+            prev_lower_or_digit = false;
+        }
+    }
+    let mut result = out.split('_').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("_");
+    if !result.chars().next().map_or(false, |c| c.is_ascii_lowercase()) {
+        result.insert_str(0, "p_");
+    }
+    result
+}
+
+pub struct ToolOpenAPIOperation {
+    pub common: IntegrationCommon,
+    pub integr_name: String,
+    pub settings_openapi: SettingsOpenAPI,
+    pub base_url: String,
+    pub operation: OpenAPIOperation,
+    pub config_path: String,
+}
+
+impl ToolOpenAPIOperation {
+    fn build_request(&self, http_client: &reqwest::Client, args: &HashMap<String, Value>) -> Result<reqwest::RequestBuilder, String> {
+        if self.base_url.is_empty() {
+            return Err("base_url is not set and the spec has no servers".to_string());
+        }
+        let mut path = self.operation.path.clone();
+        let mut query: Vec<(String, String)> = vec![];
+        let mut headers: Vec<(String, String)> = vec![];
+        let mut body = serde_json::Map::new();
+        let mut raw_body: Option<Value> = None;
+
+        for param in &self.operation.params {
+            let value = match args.get(&param.tool_param.name) {
+                Some(Value::Null) | None => {
+                    if param.required {
+                        return Err(format!("Missing required argument `{}`", param.tool_param.name));
+                    }
+                    continue;
+                }
+                Some(v) => v.clone(),
+            };
+            let value = match (&value, param.json_encoded) {
+                (Value::String(s), true) => serde_json::from_str(s)
+                    .map_err(|e| format!("argument `{}` should be valid JSON: {}", param.tool_param.name, e))?,
+                _ => value,
+            };
+            let value_str = match &value {
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            };
+            match param.location {
+                OpenAPIParamLocation::Path => {
+                    let encoded = percent_encoding::utf8_percent_encode(&value_str, percent_encoding::NON_ALPHANUMERIC).to_string();
+                    path = path.replace(&format!("{{{}}}", param.original_name), &encoded);
+                }
+                OpenAPIParamLocation::Query => query.push((param.original_name.clone(), value_str)),
+                OpenAPIParamLocation::Header => headers.push((param.original_name.clone(), value_str)),
+                OpenAPIParamLocation::Body if self.operation.raw_body => raw_body = Some(value),
+                OpenAPIParamLocation::Body => { body.insert(param.original_name.clone(), value); }
+            }
+        }
+
+        let url = format!("{}/{}", self.base_url.trim_end_matches('/'), path.trim_start_matches('/'));
+        let method = reqwest::Method::from_bytes(self.operation.method.to_uppercase().as_bytes())
+            .map_err(|e| format!("invalid method {}: {}", self.operation.method, e))?;
+        let mut request = http_client.request(method, &url)
+            .timeout(std::time::Duration::from_secs(self.settings_openapi.request_timeout))
+            .header(reqwest::header::ACCEPT, "application/json");
+
+        let s = &self.settings_openapi;
+        match s.auth_type.as_str() {
+            "" | "none" => {}
+            "bearer" => request = request.bearer_auth(&s.auth_token),
+            "basic" => request = request.basic_auth(&s.auth_username, Some(&s.auth_token)),
+            "api_key_header" => request = request.header(s.auth_key_name.as_str(), s.auth_token.as_str()),
+            "api_key_query" => query.push((s.auth_key_name.clone(), s.auth_token.clone())),
+            other => return Err(format!("unknown auth_type {:?}, expected none, bearer, basic, api_key_header or api_key_query", other)),
+        }
+        for (k, v) in s.headers.iter().chain(headers.iter().map(|(k, v)| (k, v))) {
+            request = request.header(k.as_str(), v.as_str());
+        }
+        if !query.is_empty() {
+            request = request.query(&query);
+        }
+        if let Some(raw_body) = raw_body {
+            request = request.json(&raw_body);
+        } else if !body.is_empty() {
+            request = request.json(&Value::Object(body));
+        }
+        Ok(request)
+    }
+}
+
+pub fn format_openapi_response(status: reqwest::StatusCode, body: &str, output_limit: usize) -> String {
+    let pretty = match serde_json::from_str::<Value>(body) {
+        Ok(json) => serde_json::to_string_pretty(&json).unwrap_or(body.to_string()),
+        Err(_) => body.to_string(),
+    };
+    let total_chars = pretty.chars().count();
+    let mut out = format!("HTTP {}\n", status);
+    if total_chars > output_limit {
+        out.push_str(&pretty.chars().take(output_limit).collect::<String>());
+        out.push_str(&format!("\n...\n(response truncated, showing {} of {} characters)", output_limit, total_chars));
+    } else {
+        out.push_str(&pretty);
+    }
+    out
+}
+
+#[async_trait]
+impl Tool for ToolOpenAPIOperation {
+    fn as_any(&self) -> &dyn std::any::Any { self }
+
+    async fn tool_execute(
+        &mut self,
+        ccx: Arc<AMutex<AtCommandsContext>>,
+        tool_call_id: &String,
+        args: &HashMap<String, Value>,
+    ) -> Result<(bool, Vec<ContextEnum>), String> {
+        let gcx = ccx.lock().await.global_context.clone();
+        let http_client = gcx.read().await.http_client.clone();
+        let request = self.build_request(&http_client, args)?;
+        tracing::info!("OpenAPI {} {} {}", self.integr_name, self.operation.method.to_uppercase(), self.operation.path);
+
+        let response = request.send().await.map_err(|e| format!("request failed: {}", e))?;
+        let status = response.status();
+        let body = response.text().await.map_err(|e| format!("failed to read response: {}", e))?;
+        let content = format_openapi_response(status, &body, self.settings_openapi.output_limit);
+        if !status.is_success() {
+            return Err(content);
+        }
+
+        Ok((false, vec![ContextEnum::ChatMessage(ChatMessage {
+            role: "tool".to_string(),
+            content: ChatContent::SimpleText(content),
+            tool_calls: None,
+            tool_call_id: tool_call_id.clone(),
+            ..Default::default()
+        })]))
+    }
+
+    fn tool_description(&self) -> ToolDesc {
+        ToolDesc {
+            name: openapi_tool_name(&self.integr_name, &self.operation.operation_id),
+            display_name: self.operation.operation_id.clone(),
+            source: ToolSource {
+                source_type: ToolSourceType::Integration,
+                config_path: self.config_path.clone(),
+            },
+            agentic: true,
+            experimental: false,
+            description: self.operation.description.clone(),
+            parameters: self.operation.params.iter().map(|p| p.tool_param.clone()).collect(),
+            parameters_required: self.operation.params.iter().filter(|p| p.required).map(|p| p.tool_param.name.clone()).collect(),
+        }
+    }
+
+    async fn command_to_match_against_confirm_deny(
+        &self,
+        _ccx: Arc<AMutex<AtCommandsContext>>,
+        _args: &HashMap<String, Value>,
+    ) -> Result<String, String> {
+        Ok(format!("{} {}", self.operation.method.to_uppercase(), self.operation.path))
+    }
+
+    fn confirm_deny_rules(&self) -> Option<IntegrationConfirmation> {
+        Some(self.common.confirmation.clone())
+    }
+
+    fn has_config_path(&self) -> Option<String> {
+        Some(self.config_path.clone())
+    }
+}
+
+pub const OPENAPI_INTEGRATION_SCHEMA: &str = r#"
+fields:
+  spec_path:
+    f_type: string_long
+    f_desc: "Path to a local OpenAPI 3 spec file (yaml or json), relative to the workspace folder. Each operation becomes a tool."
+    f_placeholder: "openapi.yaml"
+  base_url:
+    f_type: string_long
+    f_desc: "Base URL of the API. If empty, the first entry of `servers` from the spec is used."
+    f_placeholder: "https://api.example.com/v1"
+  auth_type:
+    f_type: string_short
+    f_desc: "One of: none, bearer, basic, api_key_header, api_key_query."
+    f_default: "none"
+  auth_token:
+    f_type: string_long
+    f_desc: "Bearer token, password for basic auth, or the API key. Use $VAR to take it from secrets.yaml or variables.yaml."
+  auth_username:
+    f_type: string_short
+    f_desc: "Username for basic auth."
+    f_extra: true
+  auth_key_name:
+    f_type: string_short
+    f_desc: "Header or query parameter name for the API key."
+    f_placeholder: "X-API-Key"
+    f_extra: true
+  headers:
+    f_type: string_to_string_map
+    f_desc: "Extra HTTP headers to send with every request."
+    f_extra: true
+  operations:
+    f_type: string_array
+    f_desc: "Only make tools for these operationIds. If empty, all operations become tools."
+    f_extra: true
+  request_timeout:
+    f_type: string_short
+    f_desc: "Timeout in seconds for each request."
+    f_default: "30"
+    f_extra: true
+  output_limit:
+    f_type: string_short
+    f_desc: "Responses are pretty-printed and truncated to this number of characters."
+    f_default: "20000"
+    f_extra: true
+description: |
+  Turns a REST API described by an OpenAPI 3 spec into tools, one tool per operation. Reading operations (GET)
+  run without asking, operations that change something (POST, PUT, PATCH, DELETE) ask for confirmation.
+available:
+  on_your_laptop_possible: true
+  when_isolated_possible: true
+confirmation:
+  ask_user_default: ["POST *", "PUT *", "PATCH *", "DELETE *"]
+  deny_default: []
+smartlinks:
+  - sl_label: "Test"
+    sl_chat:
+      - role: "user"
+        content: |
+          🔧 Tools generated from %CURRENT_CONFIG% should be visible now. Call one reading (GET) operation that doesn't need parameters you don't know,
+          and express happiness if it works. Change nothing. If the tools aren't available or don't work, go through the usual plan in the system prompt.
+    sl_enable_only_with_tool: true
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PETSTORE: &str = r#"
+openapi: 3.0.0
+servers:
+  - url: https://petstore.example.com/v1
+paths:
+  /pets/{petId}:
+    parameters:
+      - name: petId
+        in: path
+        required: true
+        schema:
+          type: integer
+    get:
+      operationId: getPetById
+      summary: Info for a specific pet
+      parameters:
+        - $ref: '#/components/parameters/Verbose'
+    delete:
+      operationId: deletePet
+  /pets:
+    post:
+      operationId: createPet
+      requestBody:
+        required: true
+        content:
+          application/json:
+            schema:
+              $ref: '#/components/schemas/NewPet'
+components:
+  parameters:
+    Verbose:
+      name: X-Verbose
+      in: header
+      schema:
+        type: boolean
+  schemas:
+    NewPet:
+      type: object
+      required: [name]
+      properties:
+        name:
+          type: string
+        tags:
+          type: array
+          items:
+            type: string
+        kind:
+          type: string
+          enum: [cat, dog]
+"#;
+
+    #[test]
+    fn test_openapi_operations_from_spec() {
+        let spec = parse_openapi_spec(PETSTORE).unwrap();
+        assert_eq!(spec_servers_url(&spec), "https://petstore.example.com/v1");
+        let ops = openapi_operations(&spec);
+        assert_eq!(ops.iter().map(|op| op.operation_id.as_str()).collect::<Vec<_>>(), vec!["getPetById", "deletePet", "createPet"]);
+
+        let get_pet = &ops[0];
+        assert_eq!(get_pet.method, "get");
+        let names = get_pet.params.iter().map(|p| p.tool_param.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["x_verbose", "pet_id"]);
+        assert_eq!(get_pet.params[0].location, OpenAPIParamLocation::Header);
+        assert_eq!(get_pet.params[0].tool_param.param_type, "boolean");
+        assert!(get_pet.params[1].required);
+        assert_eq!(get_pet.params[1].tool_param.param_type, "integer");
+
+        let create_pet = &ops[2];
+        assert!(!create_pet.raw_body);
+        let tags = create_pet.params.iter().find(|p| p.original_name == "tags").unwrap();
+        assert!(tags.json_encoded);
+        assert_eq!(tags.tool_param.param_type, "string");
+        assert!(!tags.required);
+        assert!(create_pet.params.iter().find(|p| p.original_name == "name").unwrap().required);
+        assert!(create_pet.params.iter().find(|p| p.original_name == "kind").unwrap().tool_param.description.contains("\"cat\""));
+    }
+
+    #[tokio::test]
+    async fn test_load_openapi_spec_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec_path = dir.path().join("petstore.yaml");
+        assert!(load_openapi_spec(&spec_path).await.unwrap_err().contains("cannot read OpenAPI spec"));
+        std::fs::write(&spec_path, PETSTORE).unwrap();
+        let first = load_openapi_spec(&spec_path).await.unwrap();
+        let second = load_openapi_spec(&spec_path).await.unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn test_openapi_rejects_swagger_2() {
+        assert!(parse_openapi_spec("swagger: '2.0'\npaths: {}").is_err());
+    }
+
+    #[test]
+    fn test_to_snake_case() {
+        assert_eq!(to_snake_case("getPetById"), "get_pet_by_id");
+        assert_eq!(to_snake_case("X-Request-Id"), "x_request_id");
+        assert_eq!(to_snake_case("get_/pets/{id}"), "get_pets_id");
+        assert_eq!(to_snake_case("2fa"), "p_2fa");
+    }
+
+    #[test]
+    fn test_openapi_tool_name_truncated_with_hash() {
+        assert_eq!(openapi_tool_name("petstore", "getPetById"), "petstore_get_pet_by_id");
+        let long_a = openapi_tool_name("petstore", &format!("{}ListA", "getEveryPetInTheStoreIncludingArchived".repeat(2)));
+        let long_b = openapi_tool_name("petstore", &format!("{}ListB", "getEveryPetInTheStoreIncludingArchived".repeat(2)));
+        assert!(long_a.len() <= MAX_TOOL_NAME_LEN);
+        assert!(long_b.len() <= MAX_TOOL_NAME_LEN);
+        assert_ne!(long_a, long_b);
+        assert_eq!(long_a[..40], long_b[..40]);
+
+        for integr_name in ["météo_prévisions_régionales_".repeat(4), "погода".repeat(20)] {
+            let name = openapi_tool_name(&integr_name, "getForecast");
+            assert!(name.len() <= MAX_TOOL_NAME_LEN, "{}", name);
+            assert!(name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'), "{}", name);
+        }
+        assert_eq!(openapi_tool_name("my-api", "getPetById"), "my_api_get_pet_by_id");
+    }
+
+    #[test]
+    fn test_format_openapi_response_truncates() {
+        let out = format_openapi_response(reqwest::StatusCode::OK, r#"{"a":1}"#, 100);
+        assert_eq!(out, "HTTP 200 OK\n{\n  \"a\": 1\n}");
+        let out = format_openapi_response(reqwest::StatusCode::OK, &"x".repeat(50), 10);
+        assert!(out.contains("showing 10 of 50 characters"));
+    }
+}
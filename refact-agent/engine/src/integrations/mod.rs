@@ -11,6 +11,7 @@
 pub mod integr_abstract;
 pub mod integr_github;
 pub mod integr_gitlab;
+pub mod integr_jira;
 pub mod integr_pdb;
 pub mod integr_chrome;
 pub mod integr_postgres;
@@ -18,6 +19,7 @@ pub mod integr_mysql;
 pub mod integr_cmdline;
 pub mod integr_cmdline_service;
 pub mod integr_shell;
+pub mod integr_openapi;
 pub mod mcp;
 
 pub mod process_io_utils;
@@ -44,6 +46,7 @@ pub fn integration_from_name(n: &str) -> Result<Box<dyn IntegrationTrait + Send
         "mysql" => Ok(Box::new(integr_mysql::ToolMysql { ..Default::default() }) as Box<dyn IntegrationTrait + Send + Sync>),
         "docker" => Ok(Box::new(docker::integr_docker::ToolDocker {..Default::default() }) as Box<dyn IntegrationTrait + Send + Sync>),
         "shell" => Ok(Box::new(integr_shell::ToolShell {..Default::default() }) as Box<dyn IntegrationTrait + Send + Sync>),
+        "jira" => Ok(Box::new(integr_jira::IntegrationJira {..Default::default() }) as Box<dyn IntegrationTrait + Send + Sync>),
         cmdline if cmdline.starts_with("cmdline_") => {
             // let tool_name = cmdline.strip_prefix("cmdline_").unwrap();
             Ok(Box::new(integr_cmdline::ToolCmdline {..Default::default()}) as Box<dyn IntegrationTrait + Send + Sync>)
@@ -54,6 +57,9 @@ pub fn integration_from_name(n: &str) -> Result<Box<dyn IntegrationTrait + Send
         mcp_sse if mcp_sse.starts_with("mcp_sse_") => {
             Ok(Box::new(mcp::integr_mcp_sse::IntegrationMCPSse {..Default::default()}) as Box<dyn IntegrationTrait + Send + Sync>)
         },
+        openapi if openapi.starts_with("openapi_") => {
+            Ok(Box::new(integr_openapi::IntegrationOpenAPI {..Default::default()}) as Box<dyn IntegrationTrait + Send + Sync>)
+        },
         // We support also mcp_* as mcp_stdio_* for backwards compatibility, some users already have it configured.
         mcp_stdio if mcp_stdio.starts_with("mcp_stdio_") || mcp_stdio.starts_with("mcp_") => {
             Ok(Box::new(mcp::integr_mcp_stdio::IntegrationMCPStdio {..Default::default()}) as Box<dyn IntegrationTrait + Send + Sync>)
@@ -67,6 +73,7 @@ pub fn integrations_list(allow_experimental: bool) -> Vec<&'static str> {
     let mut integrations = vec![
         "github",
         "gitlab",
+        "jira",
         "pdb",
         "chrome",
         "postgres",
@@ -75,6 +82,7 @@ pub fn integrations_list(allow_experimental: bool) -> Vec<&'static str> {
         "service_TEMPLATE",
         "mcp_stdio_TEMPLATE",
         "mcp_sse_TEMPLATE",
+        "openapi_TEMPLATE",
         "docker",
         "shell",
     ];
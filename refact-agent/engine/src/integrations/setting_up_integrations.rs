@@ -50,13 +50,14 @@ fn get_array_of_str_or_empty(val: &serde_json::Value, path: &str) -> Vec<String>
 }
 
 fn parse_and_validate_yaml(path: &str, content: &String) -> Result<serde_json::Value, YamlError> {
-    let value_yaml = serde_yaml::from_str::<serde_yaml::Value>(&content)
+    let mut value_yaml = serde_yaml::from_str::<serde_yaml::Value>(&content)
         .map_err(|e| YamlError::from((path, &e)))?;
+    crate::secrets::untag_secrets_in_yaml(&mut value_yaml);
     let json_value = serde_json::to_value(value_yaml.clone()).unwrap();
     Ok(json_value)
 }
 
-pub fn read_integrations_d(
+pub async fn read_integrations_d(
     config_dirs: &Vec<PathBuf>,
     global_config_dir: &PathBuf,
     integrations_yaml_path: &String,
@@ -86,7 +87,8 @@ pub fn read_integrations_d(
         integrations_yaml_value = match fs::read_to_string(integrations_yaml_path) {
             Ok(content) => {
                 match serde_yaml::from_str::<serde_yaml::Value>(&content) {
-                    Ok(value_yaml) => {
+                    Ok(mut value_yaml) => {
+                        crate::secrets::untag_secrets_in_yaml(&mut value_yaml);
                         globally_allowed_integration_list =  Some(value_yaml.get("globally_allowed_integrations")
                             .and_then(|v| v.as_sequence())
                             .map(|seq| seq.iter().filter_map(|v| v.as_str())
@@ -132,7 +134,7 @@ pub fn read_integrations_d(
             };
             files_to_read.push((path_str, integr_name.to_string(), project_path));
         }
-        // Find special files that start with cmdline_* and service_*
+        // Find special files that start with cmdline_*, service_*, mcp_* and openapi_*
         if let Ok(entries) = fs::read_dir(config_dir.join("integrations.d")) {
             let mut entries: Vec<_> = entries.filter_map(Result::ok).collect();
             entries.sort_by_key(|entry| entry.file_name());
@@ -150,7 +152,7 @@ pub fn read_integrations_d(
                         continue;
                     }
                 };
-                if file_name_str.starts_with("cmdline_") || file_name_str.starts_with("service_") || file_name_str.starts_with("mcp_") {
+                if file_name_str.starts_with("cmdline_") || file_name_str.starts_with("service_") || file_name_str.starts_with("mcp_") || file_name_str.starts_with("openapi_") {
                     files_to_read.push((entry.path().to_string_lossy().to_string(), file_name_str_no_yaml, project_path));
                 }
             }
@@ -217,7 +219,7 @@ pub fn read_integrations_d(
             Some(mapping) => {
                 for (key, value) in mapping {
                     if let Some(key_str) = key.as_str() {
-                        if key_str.starts_with("cmdline_") || key_str.starts_with("service_") {
+                        if key_str.starts_with("cmdline_") || key_str.starts_with("service_") || key_str.starts_with("openapi_") {
                             let mut rec: IntegrationRecord = Default::default();
                             rec.integr_config_path = integrations_yaml_path.clone();
                             rec.integr_name = key_str.to_string();
@@ -252,9 +254,15 @@ pub fn read_integrations_d(
         if let serde_json::Value::Object(map) = &mut rec.config_unparsed {
             for (_key, value) in map.iter_mut() {
                 if let Some(str_value) = value.as_str() {
-                    let replaced_value = vars_for_replacements.iter().fold(str_value.to_string(), |acc, (var, replacement)| {
+                    let mut replaced_value = vars_for_replacements.iter().fold(str_value.to_string(), |acc, (var, replacement)| {
                         acc.replace(&format!("${}", var), replacement)
                     });
+                    if crate::secrets::is_tagged_secret(&replaced_value) {
+                        match crate::secrets::resolve_secret(&replaced_value).await {
+                            Ok(secret) => replaced_value = secret,
+                            Err(e) => tracing::warn!("{}: cannot resolve secret: {}", rec.integr_name, e),
+                        }
+                    }
                     *value = serde_json::Value::String(replaced_value);
                 }
             }
@@ -332,7 +340,10 @@ pub async fn get_vars_for_replacements(
         }
 
         match fs::read_to_string(path) {
-            Ok(content) => match serde_yaml::from_str::<HashMap<String, String>>(&content) {
+            Ok(content) => match serde_yaml::from_str::<serde_yaml::Value>(&content).and_then(|mut value| {
+                crate::secrets::untag_secrets_in_yaml(&mut value);
+                serde_yaml::from_value::<HashMap<String, String>>(value)
+            }) {
                 Ok(parsed_yaml) => Ok(parsed_yaml),
                 Err(e) => {
                     tracing::warn!("Failed to parse {}: {}", path.display(), e);
@@ -358,6 +369,7 @@ pub async fn get_vars_for_replacements(
 
     // Read and parse secrets.yaml
     if let Ok(secrets_yaml) = read_and_parse_yaml(&secrets_yaml_path, error_log).await {
+        secrets_yaml.values().for_each(|v| crate::secrets::register_secret(v));
         variables.extend(secrets_yaml);
     }
 
@@ -366,6 +378,23 @@ pub async fn get_vars_for_replacements(
         variables.extend(variables_yaml);
     }
 
+    // Values like `!cmd pass show postgres` are resolved here, so $VAR in integrations gets the secret
+    for (key, value) in variables.iter_mut() {
+        if crate::secrets::is_tagged_secret(value) {
+            match crate::secrets::resolve_secret(value).await {
+                Ok(secret) => *value = secret,
+                Err(e) => {
+                    tracing::warn!("cannot resolve secret {}: {}", key, e);
+                    error_log.push(YamlError {
+                        path: secrets_yaml_path.to_string_lossy().to_string(),
+                        error_line: 0,
+                        error_msg: format!("cannot resolve {}: {}", key, e),
+                    });
+                }
+            }
+        }
+    }
+
     variables
 }
 
@@ -449,7 +478,7 @@ pub async fn integrations_all(
     let lst: Vec<&str> = crate::integrations::integrations_list(allow_experimental);
     let mut error_log: Vec<YamlError> = Vec::new();
     let vars_for_replacements = get_vars_for_replacements(gcx.clone(), &mut error_log).await;
-    let integrations = read_integrations_d(&config_dirs, &global_config_dir, &integrations_yaml_path, &vars_for_replacements, &lst, &mut error_log, &["**/*".to_string()], include_non_existent_records);
+    let integrations = read_integrations_d(&config_dirs, &global_config_dir, &integrations_yaml_path, &vars_for_replacements, &lst, &mut error_log, &["**/*".to_string()], include_non_existent_records).await;
     IntegrationResult { integrations, error_log }
 }
 
@@ -0,0 +1,323 @@
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+use axum::extract::{Path, RawQuery};
+use axum::routing::any;
+use axum::{Extension, Router};
+use futures::StreamExt;
+use hyper::{Body, HeaderMap, Method, Response, StatusCode};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock as ARwLock;
+use tracing::{error, info};
+
+use crate::global_context::GlobalContext;
+
+// --llm-record <dir> sends requests to models through a local proxy that saves every request/response
+// pair into <dir>/<hash>.json, SSE streams as a list of events. --llm-replay <dir> answers from there
+// without network, a request that was never recorded fails with 404. The hash is over the endpoint and
+// the json body with sorted keys, `meta` (chat id and such) doesn't count. Headers are never saved.
+// A stream that broke before its last event isn't saved, replaying it would hide the error.
+
+const LLM_REPLAY_NOT_FOUND: &str = "llm replay: no recording";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LlmTrafficMode {
+    Record,
+    Replay,
+}
+
+struct LlmTrafficProxy {
+    mode: LlmTrafficMode,
+    dir: PathBuf,
+    http_client: reqwest::Client,
+}
+
+static LLM_TRAFFIC_PROXY_URL: OnceLock<String> = OnceLock::new();
+
+
+/// Where to send a request to a model, the endpoint itself unless recording or replaying
+pub fn llm_endpoint(endpoint: &str) -> String {
+    match LLM_TRAFFIC_PROXY_URL.get() {
+        Some(proxy_url) => proxy_endpoint(proxy_url, endpoint),
+        None => endpoint.to_string(),
+    }
+}
+
+fn proxy_endpoint(proxy_url: &str, endpoint: &str) -> String {
+    format!("{}/{}", proxy_url, utf8_percent_encode(endpoint, NON_ALPHANUMERIC))
+}
+
+pub async fn start_llm_traffic_proxy(gcx: Arc<ARwLock<GlobalContext>>) -> Result<(), String> {
+    let (cmdline, http_client) = {
+        let gcx_locked = gcx.read().await;
+        (gcx_locked.cmdline.clone(), gcx_locked.http_client.clone())
+    };
+    let (mode, dir) = match (cmdline.llm_record.is_empty(), cmdline.llm_replay.is_empty()) {
+        (true, true) => return Ok(()),
+        (false, true) => (LlmTrafficMode::Record, cmdline.llm_record),
+        (true, false) => (LlmTrafficMode::Replay, cmdline.llm_replay),
+        (false, false) => return Err("--llm-record and --llm-replay can't be used together".to_string()),
+    };
+    let dir = crate::files_correction::canonical_path(dir);
+    let proxy_url = spawn_llm_traffic_proxy(mode, dir.clone(), http_client).await?;
+    info!("llm traffic {:?} in {}, proxy at {}", mode, dir.display(), proxy_url);
+    LLM_TRAFFIC_PROXY_URL.set(proxy_url).map_err(|_| "llm traffic proxy is already running".to_string())
+}
+
+async fn spawn_llm_traffic_proxy(mode: LlmTrafficMode, dir: PathBuf, http_client: reqwest::Client) -> Result<String, String> {
+    if mode == LlmTrafficMode::Record {
+        tokio::fs::create_dir_all(&dir).await.map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+    } else if !dir.is_dir() {
+        return Err(format!("nothing to replay, {} is not a directory", dir.display()));
+    }
+    let proxy = Arc::new(LlmTrafficProxy { mode, dir, http_client });
+    let app = Router::new()
+        .route("/:endpoint", any(handle_llm_traffic))
+        .layer(Extension(proxy));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").map_err(|e| format!("cannot bind llm traffic proxy: {}", e))?;
+    let addr = listener.local_addr().map_err(|e| e.to_string())?;
+    let server = axum::Server::from_tcp(listener).map_err(|e| e.to_string())?;
+    tokio::spawn(server.serve(app.into_make_service()));
+    Ok(format!("http://{}", addr))
+}
+
+fn sorted_json(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut keys = map.keys().collect::<Vec<_>>();
+            keys.sort();
+            Value::Object(keys.into_iter().map(|k| (k.clone(), sorted_json(&map[k]))).collect())
+        },
+        Value::Array(items) => Value::Array(items.iter().map(sorted_json).collect()),
+        _ => value.clone(),
+    }
+}
+
+/// The body without things that change from run to run, as json if it is json
+fn normalized_request_body(body: &[u8]) -> Value {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(mut value) => {
+            if let Some(map) = value.as_object_mut() {
+                map.remove("meta");
+            }
+            sorted_json(&value)
+        },
+        Err(_) => Value::String(String::from_utf8_lossy(body).to_string()),
+    }
+}
+
+fn request_hash(endpoint: &str, query: &str, body: &Value) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}?{}\n{}", endpoint, query, body).as_bytes());
+    hasher.finalize().iter().take(12).map(|b| format!("{:02x}", b)).collect()
+}
+
+async fn handle_llm_traffic(
+    Extension(proxy): Extension<Arc<LlmTrafficProxy>>,
+    Path(endpoint): Path<String>,
+    RawQuery(query): RawQuery,
+    method: Method,
+    headers: HeaderMap,
+    body: hyper::body::Bytes,
+) -> Response<Body> {
+    let query = query.unwrap_or_default();
+    let request_body = normalized_request_body(&body);
+    let hash = request_hash(&endpoint, &query, &request_body);
+    let path = proxy.dir.join(format!("{}.json", hash));
+    let request = json!({"method": method.as_str(), "endpoint": endpoint, "query": query, "body": request_body});
+    let result = match proxy.mode {
+        LlmTrafficMode::Replay => replay(&path, &endpoint).await,
+        LlmTrafficMode::Record => record(&proxy.http_client, path, request, method, headers, body).await,
+    };
+    result.unwrap_or_else(|(status, e)| {
+        error!("{}", e);
+        Response::builder().status(status).body(Body::from(e)).unwrap()
+    })
+}
+
+async fn replay(path: &PathBuf, endpoint: &str) -> Result<Response<Body>, (StatusCode, String)> {
+    let content = tokio::fs::read_to_string(path).await.map_err(|_| (
+        StatusCode::NOT_FOUND,
+        format!("{} for a request to {}, expected {}", LLM_REPLAY_NOT_FOUND, endpoint, path.display()),
+    ))?;
+    let recorded: Value = serde_json::from_str(&content)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("llm replay: {}: {}", path.display(), e)))?;
+    let response = &recorded["response"];
+    let body = match response.get("sse_events").and_then(|x| x.as_array()) {
+        Some(events) => events.iter().filter_map(|x| x.as_str()).map(|x| format!("{}\n\n", x)).collect::<String>(),
+        None => match &response["body"] {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        },
+    };
+    Ok(Response::builder()
+        .status(response["status"].as_u64().unwrap_or(200) as u16)
+        .header("Content-Type", response["content_type"].as_str().unwrap_or("application/json"))
+        .body(Body::from(body))
+        .unwrap())
+}
+
+async fn record(
+    http_client: &reqwest::Client,
+    path: PathBuf,
+    request: Value,
+    method: Method,
+    headers: HeaderMap,
+    body: hyper::body::Bytes,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    let endpoint = request["endpoint"].as_str().unwrap_or_default().to_string();
+    let query = request["query"].as_str().unwrap_or_default().to_string();
+    let url = if query.is_empty() { endpoint.to_string() } else { format!("{}?{}", endpoint, query) };
+    let mut upstream_headers = reqwest::header::HeaderMap::new();
+    for (name, value) in headers.iter().filter(|(name, _)| !matches!(name.as_str(), "host" | "content-length")) {
+        if let (Ok(name), Ok(value)) = (reqwest::header::HeaderName::from_bytes(name.as_ref()), reqwest::header::HeaderValue::from_bytes(value.as_bytes())) {
+            upstream_headers.insert(name, value);
+        }
+    }
+    let method = reqwest::Method::from_bytes(method.as_str().as_bytes()).unwrap_or(reqwest::Method::POST);
+    let upstream = http_client.request(method, &url)
+        .headers(upstream_headers)
+        .body(body.to_vec())
+        .send().await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("llm record: {}: {}", endpoint, e)))?;
+    let status = upstream.status().as_u16();
+    let content_type = upstream.headers().get(reqwest::header::CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok()).unwrap_or("application/json").to_string();
+
+    let response = Response::builder().status(status).header("Content-Type", content_type.clone());
+    if !content_type.starts_with("text/event-stream") {
+        let text = upstream.text().await.map_err(|e| (StatusCode::BAD_GATEWAY, format!("llm record: {}: {}", endpoint, e)))?;
+        let body = serde_json::from_str::<Value>(&text).unwrap_or(Value::String(text.clone()));
+        save_recording(&path, request, json!({"status": status, "content_type": content_type, "body": body})).await;
+        return Ok(response.body(Body::from(text)).unwrap());
+    }
+
+    // the stream goes to the client as it comes, and is saved when it ends
+    let (mut sender, client_body) = Body::channel();
+    let mut upstream_stream = upstream.bytes_stream();
+    tokio::spawn(async move {
+        let mut text = String::new();
+        while let Some(chunk) = upstream_stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => { error!("llm record: stream interrupted: {}", e); break; }
+            };
+            text.push_str(&String::from_utf8_lossy(&chunk));
+            if sender.send_data(chunk).await.is_err() {
+                break;  // client went away
+            }
+        }
+        let events = text.replace("\r\n", "\n").split("\n\n")
+            .map(|x| x.trim()).filter(|x| !x.is_empty()).map(|x| x.to_string()).collect::<Vec<_>>();
+        if !sse_stream_finished(&events) {
+            error!("llm record: stream from {} didn't finish, not saving {}", endpoint, path.display());
+            return;
+        }
+        save_recording(&path, request, json!({"status": status, "content_type": content_type, "sse_events": events})).await;
+    });
+    Ok(response.body(client_body).unwrap())
+}
+
+/// `[DONE]` for openai, `message_stop` for anthropic, `response.completed` and such for openai responses
+fn sse_stream_finished(events: &[String]) -> bool {
+    events.last().map_or(false, |event| {
+        let data = event.lines()
+            .filter_map(|x| x.strip_prefix("data:")).map(|x| x.trim())
+            .collect::<Vec<_>>().join("\n");
+        if data == "[DONE]" {
+            return true;
+        }
+        let event_type = serde_json::from_str::<Value>(&data).ok()
+            .and_then(|x| x.get("type").and_then(|t| t.as_str()).map(|t| t.to_string()))
+            .unwrap_or_default();
+        matches!(event_type.as_str(), "message_stop" | "response.completed" | "response.incomplete" | "response.failed")
+    })
+}
+
+async fn save_recording(path: &PathBuf, request: Value, response: Value) {
+    let recording = json!({"request": request, "response": response});
+    if let Err(e) = tokio::fs::write(path, serde_json::to_string_pretty(&recording).unwrap()).await {
+        error!("llm record: cannot save {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_provider::start_test_server;
+    use axum::routing::post;
+    use axum::Json;
+
+    async fn start_upstream() -> String {
+        let app = Router::new()
+            .route("/v1/chat/completions", post(|Json(body): Json<Value>| async move {
+                if body["model"] == "broken" {
+                    Response::builder().header("Content-Type", "text/event-stream")
+                        .body(Body::from("data: {\"choices\":[{\"delta\":{\"content\":\"hel\"}}]}\n\n"))
+                        .unwrap()
+                } else if body["stream"].as_bool().unwrap_or(false) {
+                    Response::builder().header("Content-Type", "text/event-stream")
+                        .body(Body::from("data: {\"choices\":[{\"delta\":{\"content\":\"hel\"}}]}\n\ndata: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\ndata: [DONE]\n\n"))
+                        .unwrap()
+                } else {
+                    Response::builder().header("Content-Type", "application/json")
+                        .body(Body::from(json!({"choices": [{"message": {"content": body["messages"][0]["content"]}}]}).to_string()))
+                        .unwrap()
+                }
+            }));
+        format!("{}/v1/chat/completions", start_test_server(app))
+    }
+
+    #[test]
+    fn test_sse_stream_finished() {
+        let events = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        assert!(sse_stream_finished(&events(&["data: {\"choices\":[]}", "data: [DONE]"])));
+        assert!(sse_stream_finished(&events(&["event: message_stop\ndata: {\"type\":\"message_stop\"}"])));
+        assert!(sse_stream_finished(&events(&["event: response.completed\ndata: {\"type\":\"response.completed\"}"])));
+        assert!(!sse_stream_finished(&events(&["data: {\"choices\":[]}"])));
+        assert!(!sse_stream_finished(&events(&[])));
+    }
+
+    #[test]
+    fn test_request_hash_is_normalized() {
+        let a = normalized_request_body(br#"{"model": "m", "messages": [], "meta": {"chat_id": "1"}}"#);
+        let b = normalized_request_body(br#"{"messages": [], "model": "m", "meta": {"chat_id": "2"}}"#);
+        assert_eq!(request_hash("http://x", "", &a), request_hash("http://x", "", &b));
+        let c = normalized_request_body(br#"{"messages": [], "model": "other"}"#);
+        assert_ne!(request_hash("http://x", "", &a), request_hash("http://x", "", &c));
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let endpoint = start_upstream().await;
+        let client = reqwest::Client::new();
+        let chat = json!({"model": "m", "messages": [{"role": "user", "content": "hi"}], "stream": false});
+        let chat_stream = json!({"model": "m", "messages": [{"role": "user", "content": "hi"}], "stream": true});
+
+        let recorder = spawn_llm_traffic_proxy(LlmTrafficMode::Record, dir.path().to_path_buf(), client.clone()).await.unwrap();
+        let recorded = client.post(proxy_endpoint(&recorder, &endpoint)).json(&chat).send().await.unwrap().text().await.unwrap();
+        let recorded_stream = client.post(proxy_endpoint(&recorder, &endpoint)).json(&chat_stream).send().await.unwrap().text().await.unwrap();
+        assert!(recorded_stream.contains("[DONE]"));
+        let broken_stream = json!({"model": "broken", "messages": [{"role": "user", "content": "hi"}], "stream": true});
+        client.post(proxy_endpoint(&recorder, &endpoint)).json(&broken_stream).send().await.unwrap().text().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;  // stream is saved after it ends
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        let replayer = spawn_llm_traffic_proxy(LlmTrafficMode::Replay, dir.path().to_path_buf(), client.clone()).await.unwrap();
+        let unreachable = "http://127.0.0.1:1/v1/chat/completions";
+        assert_eq!(client.post(proxy_endpoint(&replayer, unreachable)).json(&chat).send().await.unwrap().status(), 404);
+        let replayed = client.post(proxy_endpoint(&replayer, &endpoint)).json(&chat).send().await.unwrap().text().await.unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&replayed).unwrap(), serde_json::from_str::<Value>(&recorded).unwrap());
+        let replayed_stream = client.post(proxy_endpoint(&replayer, &endpoint)).json(&chat_stream).send().await.unwrap();
+        assert_eq!(replayed_stream.headers()["content-type"], "text/event-stream");
+        assert_eq!(replayed_stream.text().await.unwrap(), recorded_stream);
+
+        let other = json!({"model": "m", "messages": [{"role": "user", "content": "bye"}], "stream": false});
+        let missing = client.post(proxy_endpoint(&replayer, &endpoint)).json(&other).send().await.unwrap();
+        assert_eq!(missing.status(), 404);
+        assert!(missing.text().await.unwrap().starts_with(LLM_REPLAY_NOT_FOUND));
+    }
+}
@@ -1,24 +1,32 @@
 use std::collections::HashMap;
 use std::fmt::Display;
+use std::future::Future;
 use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::Arc;
 use std::io::Write;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
 
 use serde::{Deserialize, Serialize};
 use tokio::net::TcpListener;
+use tokio::sync::Mutex as AMutex;
 use tokio::sync::RwLock as ARwLock;
 use tokio::task::JoinHandle;
 use tower_lsp::{ClientSocket, LanguageServer, LspService};
-use tower_lsp::jsonrpc::{Error, Result};
+use tower_lsp::jsonrpc::{Error, Request, Response, Result};
 use tower_lsp::lsp_types::*;
 use tracing::{error, info};
 
-use crate::call_validation::{CodeCompletionInputs, CodeCompletionPost, CursorPosition, SamplingParameters};
+use crate::ast::ast_structs::AstDB;
+use crate::caps::CodeAssistantCaps;
+use crate::call_validation::{ChatMessage, CodeCompletionInputs, CodeCompletionPost, CursorPosition, SamplingParameters};
 use crate::files_in_workspace;
-use crate::files_in_workspace::{on_did_change, on_did_delete};
-use crate::global_context::{CommandLine, GlobalContext};
+use crate::files_in_workspace::{on_did_change, on_did_change_incremental, on_did_delete, DocumentEdit};
+use crate::global_context::{try_load_caps_quickly_if_not_present, CommandLine, GlobalContext};
 use crate::http::routers::v1::code_completion::handle_v1_code_completion;
 use crate::telemetry::snippets_collection;
+use crate::yaml_configs::customization_loader::{fill_in_ide_variables, load_customization, CustomizationYaml};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -38,6 +46,8 @@ impl Display for APIError {
 pub struct LspBackend {
     pub gcx: Arc<ARwLock<GlobalContext>>,
     pub client: tower_lsp::Client,
+    // code lenses are asked for on every scroll, customization is loaded again only when caps or customization.yaml change
+    customization_cache: AMutex<Option<(Arc<CodeAssistantCaps>, Option<SystemTime>, Arc<CustomizationYaml>)>>,
 }
 
 
@@ -45,6 +55,8 @@ pub struct LspBackend {
 pub struct RequestParams {
     pub max_new_tokens: u32,
     pub temperature: f32,
+    #[serde(default)]
+    pub n: Option<usize>,  // several candidates, best first
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -61,11 +73,64 @@ pub struct CompletionParams1 {
     // pub model: String,
 }
 
+// textDocument/inlineCompletion is LSP 3.18, lsp-types we have doesn't know it yet
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionParams {
+    #[serde(flatten)]
+    pub text_document_position: TextDocumentPositionParams,
+    pub context: InlineCompletionContext,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionContext {
+    pub trigger_kind: u32,  // 1 invoked, 2 automatic
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionItem {
+    pub insert_text: String,
+    pub range: Range,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InlineCompletionList {
+    pub items: Vec<InlineCompletionItem>,
+}
+
+const ACCEPT_COMPLETION_COMMAND: &str = "refact.acceptCompletion";
+const OPEN_CHAT_COMMAND: &str = "refact.openChat";
+const WORKSPACE_SYMBOLS_LIMIT: usize = 50;
+const INLINE_COMPLETION_CANDIDATES: usize = 3;
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct SnippetAcceptedParams {
     snippet_telemetry_id: u64,
 }
 
+/// Arguments of `refact.openChat`, the server forwards them to the IDE as `refact/openChat`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OpenChatParams {
+    pub messages: Vec<ChatMessage>,
+    #[serde(default)]
+    pub auto_submit: bool,
+    #[serde(default)]
+    pub new_tab: bool,
+    #[serde(default)]
+    pub insert_at_cursor: bool,
+}
+
+pub enum OpenChatNotification {}
+
+impl notification::Notification for OpenChatNotification {
+    type Params = OpenChatParams;
+    const METHOD: &'static str = "refact/openChat";
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ChangeActiveFile {
     pub uri: Url,
@@ -97,6 +162,28 @@ fn internal_error<E: Display>(err: E) -> Error {
     }
 }
 
+fn text_in_range(text: &str, range: &Range) -> String {
+    let (line1, line2) = (range.start.line as usize, range.end.line as usize);
+    text.lines().enumerate()
+        .filter(|(i, _)| *i >= line1 && *i <= line2)
+        .map(|(i, line)| {
+            let chars: Vec<char> = line.chars().collect();
+            let start = if i == line1 { (range.start.character as usize).min(chars.len()) } else { 0 };
+            let end = if i == line2 { (range.end.character as usize).min(chars.len()) } else { chars.len() };
+            chars[start.min(end)..end].iter().collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn open_chat_command(title: &str, params: OpenChatParams) -> Command {
+    Command {
+        title: title.to_string(),
+        command: OPEN_CHAT_COMMAND.to_string(),
+        arguments: Some(vec![serde_json::json!(params)]),
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct Choice {
     pub index: u32,
@@ -119,41 +206,80 @@ pub struct SuccessRes {
 }
 
 impl LspBackend {
-    async fn flat_params_to_code_completion_post(&self, params: &CompletionParams1) -> Result<CodeCompletionPost> {
-        let path = crate::files_correction::canonical_path(&params.text_document_position.text_document.uri.to_file_path().unwrap_or_default().display().to_string());
-        let txt = match self.gcx.read().await.documents_state.memory_document_map.get(&path) {
-            Some(doc) => doc.read().await.clone().get_text_or_read_from_disk(self.gcx.clone()).await.unwrap_or_default(),
-            None => return Err(internal_error("document not found"))
+    async fn document_text(&self, uri: &Url) -> Result<String> {
+        let path = crate::files_correction::canonical_path(&uri.to_file_path().unwrap_or_default().display().to_string());
+        let doc = self.gcx.read().await.documents_state.memory_document_map.get(&path).cloned();
+        match doc {
+            Some(doc) => Ok(doc.read().await.clone().get_text_or_read_from_disk(self.gcx.clone()).await.unwrap_or_default()),
+            None => Err(internal_error("document not found")),
+        }
+    }
+
+    async fn ast_index(&self) -> Option<Arc<AstDB>> {
+        let ast_service = self.gcx.read().await.ast_service.clone()?;
+        let ast_index = ast_service.lock().await.ast_index.clone();
+        Some(ast_index)
+    }
+
+    async fn ast_definitions_at(&self, params: &TextDocumentPositionParams) -> Result<Vec<Arc<crate::ast::ast_structs::AstDefinition>>> {
+        let ast_index = match self.ast_index().await {
+            Some(ast_index) => ast_index,
+            None => return Ok(vec![]),
         };
+        let txt = self.document_text(&params.text_document.uri).await?;
+        let cpath = crate::files_correction::canonical_path(&params.text_document.uri.to_file_path().unwrap_or_default().display().to_string());
+        Ok(crate::ast::ast_lsp::definitions_at_position(
+            ast_index, &cpath.to_string_lossy().to_string(), &txt, params.position.line as usize, params.position.character as usize,
+        ).await)
+    }
+
+    async fn customization(&self) -> Arc<CustomizationYaml> {
+        let caps = try_load_caps_quickly_if_not_present(self.gcx.clone(), 0).await.ok();
+        let config_dir = self.gcx.read().await.config_dir.clone();
+        let mtime = tokio::fs::metadata(config_dir.join("customization.yaml")).await.and_then(|m| m.modified()).ok();
+        if let (Some(caps), Some((cached_caps, cached_mtime, customization))) = (&caps, self.customization_cache.lock().await.as_ref()) {
+            if Arc::ptr_eq(caps, cached_caps) && *cached_mtime == mtime {
+                return customization.clone();
+            }
+        }
+
+        let mut error_log = Vec::new();
+        let customization = Arc::new(load_customization(self.gcx.clone(), true, &mut error_log).await);
+        for e in error_log.iter() {
+            error!("{e}");
+        }
+        if let Some(caps) = caps.filter(|_| error_log.is_empty()) {
+            *self.customization_cache.lock().await = Some((caps, mtime, customization.clone()));
+        }
+        customization
+    }
+
+    async fn code_completion_post(&self, text_document_position: &TextDocumentPositionParams, multiline: bool, parameters: SamplingParameters) -> Result<CodeCompletionPost> {
+        let txt = self.document_text(&text_document_position.text_document.uri).await?;
         // url -> String method should be the same as in telemetry::snippets_collection::sources_changed
-        let path_string = params.text_document_position.text_document.uri.to_file_path().unwrap_or_default().to_string_lossy().to_string();
+        let path_string = text_document_position.text_document.uri.to_file_path().unwrap_or_default().to_string_lossy().to_string();
         Ok(CodeCompletionPost {
             inputs: CodeCompletionInputs {
                 sources: HashMap::from([(path_string.clone(), (&txt).to_string())]),
                 cursor: CursorPosition {
                     file: path_string.clone(),
-                    line: params.text_document_position.position.line as i32,
-                    character: params.text_document_position.position.character as i32,
+                    line: text_document_position.position.line as i32,
+                    character: text_document_position.position.character as i32,
                 },
-                multiline: params.multiline,
-            },
-            parameters: SamplingParameters {
-                max_new_tokens: params.parameters.max_new_tokens as usize,
-                temperature: Option::from(params.parameters.temperature),
-                ..Default::default()
+                multiline,
             },
+            parameters,
             model: "".to_string(),
             stream: false,
             no_cache: false,
             use_ast: false,
             use_vecdb: false,
             rag_tokens_n: 0,
+            next_edit: false,
         })
     }
 
-    pub async fn get_completions(&self, params: CompletionParams1) -> Result<CompletionRes> {
-        let mut post = self.flat_params_to_code_completion_post(&params).await?;
-
+    async fn run_code_completion(&self, mut post: CodeCompletionPost) -> Result<CompletionRes> {
         let res = handle_v1_code_completion(self.gcx.clone(), &mut post)
             .await.map_err(|e| internal_error(e))?;
 
@@ -165,6 +291,44 @@ impl LspBackend {
         Ok(value)
     }
 
+    pub async fn get_completions(&self, params: CompletionParams1) -> Result<CompletionRes> {
+        let post = self.code_completion_post(&params.text_document_position, params.multiline, SamplingParameters {
+            max_new_tokens: params.parameters.max_new_tokens as usize,
+            temperature: Option::from(params.parameters.temperature),
+            n: params.parameters.n,
+            ..Default::default()
+        }).await?;
+        self.run_code_completion(post).await
+    }
+
+    // $/cancelRequest is handled by tower-lsp: it drops this future, and with it the request to the model
+    pub async fn inline_completion(&self, params: InlineCompletionParams) -> Result<InlineCompletionList> {
+        let position = params.text_document_position.position;
+        let txt = self.document_text(&params.text_document_position.text_document.uri).await?;
+        let after_cursor = txt.lines().nth(position.line as usize).unwrap_or_default()
+            .chars().skip(position.character as usize).collect::<String>();
+        let multiline = after_cursor.trim().is_empty();
+        info!("LSP inline completion trigger_kind={} multiline={}", params.context.trigger_kind, multiline);
+
+        // explicitly invoked: the user is likely to cycle through the alternatives
+        let n = if params.context.trigger_kind == 1 { Some(INLINE_COMPLETION_CANDIDATES) } else { None };
+        let post = self.code_completion_post(&params.text_document_position, multiline, SamplingParameters { n, ..Default::default() }).await?;
+        let res = self.run_code_completion(post).await?;
+        let items = res.choices.into_iter()
+            .filter(|choice| !choice.code_completion.is_empty())
+            .map(|choice| InlineCompletionItem {
+                insert_text: choice.code_completion,
+                range: Range { start: position, end: position },
+                command: Some(Command {
+                    title: "Accept completion".to_string(),
+                    command: ACCEPT_COMPLETION_COMMAND.to_string(),
+                    arguments: Some(vec![serde_json::json!(res.snippet_telemetry_id)]),
+                }),
+            })
+            .collect();
+        Ok(InlineCompletionList { items })
+    }
+
     pub async fn accept_snippet(&self, params: SnippetAcceptedParams) -> Result<SuccessRes> {
         let success = snippets_collection::snippet_accepted(self.gcx.clone(), params.snippet_telemetry_id).await;
         Ok(SuccessRes { success })
@@ -248,6 +412,9 @@ impl LanguageServer for LspBackend {
         // wait for http server to be ready
         self.ping_http_server().await?;
 
+        // answered from AstDB, fallback for languages without a language server of their own
+        let ast_on = self.gcx.read().await.ast_service.is_some();
+
         Ok(InitializeResult {
             server_info: Some(ServerInfo {
                 name: "refact".to_owned(),
@@ -255,9 +422,19 @@ impl LanguageServer for LspBackend {
             }),
             capabilities: ServerCapabilities {
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                    TextDocumentSyncKind::INCREMENTAL,
                 )),
                 completion_provider: Some(completion_options),
+                definition_provider: ast_on.then_some(OneOf::Left(true)),
+                references_provider: ast_on.then_some(OneOf::Left(true)),
+                workspace_symbol_provider: ast_on.then_some(OneOf::Left(true)),
+                document_symbol_provider: ast_on.then_some(OneOf::Left(true)),
+                code_lens_provider: ast_on.then_some(CodeLensOptions { resolve_provider: Some(false) }),
+                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![ACCEPT_COMPLETION_COMMAND.to_string(), OPEN_CHAT_COMMAND.to_string()],
+                    work_done_progress_options: WorkDoneProgressOptions { work_done_progress: Some(false) },
+                }),
                 workspace: Some(WorkspaceServerCapabilities {
                     workspace_folders: Some(WorkspaceFoldersServerCapabilities {
                         supported: Some(true),
@@ -310,11 +487,20 @@ impl LanguageServer for LspBackend {
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         let path = crate::files_correction::canonical_path(&params.text_document.uri.to_file_path().unwrap_or_default().display().to_string());
-        on_did_change(
-            self.gcx.clone(),
-            &path,
-            &params.content_changes[0].text  // TODO: This text could be just a part of the whole file (if range is not none)
-        ).await
+        let edits: Vec<DocumentEdit> = params.content_changes.into_iter().map(|change| DocumentEdit {
+            range: change.range.map(|r| (
+                (r.start.line as usize, r.start.character as usize),
+                (r.end.line as usize, r.end.character as usize),
+            )),
+            text: change.text,
+        }).collect();
+        if let Err(e) = on_did_change_incremental(self.gcx.clone(), &path, &edits).await {
+            // not open, but the whole text is there anyway
+            match edits.last().filter(|edit| edit.range.is_none()) {
+                Some(full_text) => on_did_change(self.gcx.clone(), &path, &full_text.text).await,
+                None => error!("did_change: {}", e),
+            }
+        }
     }
 
     async fn did_save(&self, params: DidSaveTextDocumentParams) {
@@ -336,6 +522,151 @@ impl LanguageServer for LspBackend {
         Ok(Some(CompletionResponse::Array(vec![])))
     }
 
+    async fn goto_definition(&self, params: GotoDefinitionParams) -> Result<Option<GotoDefinitionResponse>> {
+        let defs = self.ast_definitions_at(&params.text_document_position_params).await?;
+        let locations: Vec<Location> = defs.iter().filter_map(|d| crate::ast::ast_lsp::definition_location(d)).collect();
+        if locations.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(GotoDefinitionResponse::Array(locations)))
+    }
+
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        let defs = self.ast_definitions_at(&params.text_document_position).await?;
+        if defs.is_empty() {
+            return Ok(None);
+        }
+        let ast_index = self.ast_index().await.ok_or(internal_error("AST is turned off"))?;
+        Ok(Some(crate::ast::ast_lsp::references_of(ast_index, &defs, params.context.include_declaration)))
+    }
+
+    async fn symbol(&self, params: WorkspaceSymbolParams) -> Result<Option<Vec<SymbolInformation>>> {
+        let ast_index = match self.ast_index().await {
+            Some(ast_index) if !params.query.trim().is_empty() => ast_index,
+            _ => return Ok(Some(vec![])),
+        };
+        let paths = crate::ast::ast_db::definition_paths_fuzzy(ast_index.clone(), params.query.trim(), WORKSPACE_SYMBOLS_LIMIT, 5000).await
+            .map_err(|e| internal_error(e))?;
+        let mut symbols = vec![];
+        for path in paths {
+            for def in crate::ast::ast_db::definitions(ast_index.clone(), &path).unwrap_or_default() {
+                symbols.extend(crate::ast::ast_lsp::workspace_symbol(&def));
+            }
+        }
+        Ok(Some(symbols))
+    }
+
+    async fn document_symbol(&self, params: DocumentSymbolParams) -> Result<Option<DocumentSymbolResponse>> {
+        let ast_index = match self.ast_index().await {
+            Some(ast_index) => ast_index,
+            None => return Ok(None),
+        };
+        let cpath = crate::files_correction::canonical_path(&params.text_document.uri.to_file_path().unwrap_or_default().display().to_string());
+        let defs = crate::ast::ast_db::doc_defs(ast_index, &cpath.to_string_lossy().to_string());
+        Ok(Some(DocumentSymbolResponse::Nested(crate::ast::ast_lsp::document_symbols(&defs))))
+    }
+
+    // lenses over definitions from AstDB, one per `code_lens` entry in customization
+    async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
+        let ast_index = match self.ast_index().await {
+            Some(ast_index) => ast_index,
+            None => return Ok(None),
+        };
+        let customization = self.customization().await;
+        if customization.code_lens.is_empty() {
+            return Ok(Some(vec![]));
+        }
+        let txt = self.document_text(&params.text_document.uri).await?;
+        let lines: Vec<&str> = txt.lines().collect();
+        let cpath = crate::files_correction::canonical_path(&params.text_document.uri.to_file_path().unwrap_or_default().display().to_string());
+        let cpath_str = cpath.to_string_lossy().to_string();
+
+        // whatever is indexed right now, the editor asks again after the next edit
+        let mut lenses = vec![];
+        for def in crate::ast::ast_db::doc_defs(ast_index, &cpath_str) {
+            if def.official_path.last().map_or(false, |last| last == "root") {
+                continue;
+            }
+            let (line1, line2) = (def.full_line1(), def.full_line2().min(lines.len()));
+            if line2 <= line1.saturating_sub(1) {
+                continue;
+            }
+            let code_selection = lines[line1.saturating_sub(1)..line2].join("\n");
+            for lens in customization.code_lens.values() {
+                lenses.push(CodeLens {
+                    range: crate::ast::ast_lsp::definition_range(&def),
+                    command: Some(open_chat_command(&lens.label, OpenChatParams {
+                        messages: fill_in_ide_variables(&lens.messages, &cpath_str, line1, &code_selection),
+                        auto_submit: lens.auto_submit,
+                        new_tab: lens.new_tab,
+                        insert_at_cursor: false,
+                    })),
+                    data: None,
+                });
+            }
+        }
+        Ok(Some(lenses))
+    }
+
+    // toolbox commands, filtered by how many lines are selected
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let kind = CodeActionKind::REFACTOR;
+        if let Some(only) = &params.context.only {
+            if !only.iter().any(|k| kind.as_str().starts_with(k.as_str())) {
+                return Ok(None);
+            }
+        }
+        let txt = self.document_text(&params.text_document.uri).await?;
+        let code_selection = text_in_range(&txt, &params.range);
+        let selected_lines = if code_selection.is_empty() { 0 } else { code_selection.lines().count() };
+        let cpath = crate::files_correction::canonical_path(&params.text_document.uri.to_file_path().unwrap_or_default().display().to_string());
+        let cpath_str = cpath.to_string_lossy().to_string();
+
+        let mut actions = vec![];
+        for command in self.customization().await.toolbox_commands.values() {
+            if command.messages.is_empty() || (command.selection_unwanted && selected_lines > 0) {
+                continue;
+            }
+            if let [min_lines, max_lines] = command.selection_needed[..] {
+                if selected_lines < min_lines || selected_lines > max_lines {
+                    continue;
+                }
+            }
+            actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                title: command.description.clone(),
+                kind: Some(kind.clone()),
+                command: Some(open_chat_command(&command.description, OpenChatParams {
+                    messages: fill_in_ide_variables(&command.messages, &cpath_str, params.range.start.line as usize + 1, &code_selection),
+                    auto_submit: true,
+                    new_tab: true,
+                    insert_at_cursor: command.insert_at_cursor,
+                })),
+                ..Default::default()
+            }));
+        }
+        Ok(Some(actions))
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<serde_json::Value>> {
+        match params.command.as_str() {
+            ACCEPT_COMPLETION_COMMAND => {
+                let snippet_telemetry_id = params.arguments.get(0).and_then(|v| v.as_u64())
+                    .ok_or(Error::invalid_params("expected snippet_telemetry_id as the first argument"))?;
+                let res = self.accept_snippet(SnippetAcceptedParams { snippet_telemetry_id }).await?;
+                Ok(Some(serde_json::json!(res)))
+            }
+            // the chat lives in the IDE, so the server just hands the pre-filled messages over
+            OPEN_CHAT_COMMAND => {
+                let open_chat = params.arguments.get(0)
+                    .and_then(|v| serde_json::from_value::<OpenChatParams>(v.clone()).ok())
+                    .ok_or(Error::invalid_params("expected chat messages as the first argument"))?;
+                self.client.send_notification::<OpenChatNotification>(open_chat.clone()).await;
+                Ok(Some(serde_json::json!(open_chat)))
+            }
+            _ => Err(Error::invalid_params(format!("unknown command {}", params.command))),
+        }
+    }
+
     async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
         for folder in params.event.added {
             info!("did_change_workspace_folders/add {}", folder.name);
@@ -365,18 +696,58 @@ impl LanguageServer for LspBackend {
     }
 }
 
+/// Adds `inlineCompletionProvider` to the `initialize` response, `ServerCapabilities` from lsp-types 0.94 has no such field
+pub struct WithInlineCompletionProvider<S>(S);
+
+impl<S> tower::Service<Request> for WithInlineCompletionProvider<S>
+where
+    S: tower::Service<Request, Response = Option<Response>>,
+    S::Future: Send + 'static,
+{
+    type Response = Option<Response>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = std::result::Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let is_initialize = req.method() == "initialize";
+        let fut = self.0.call(req);
+        Box::pin(async move {
+            let response = fut.await?;
+            if !is_initialize {
+                return Ok(response);
+            }
+            Ok(response.map(|response| {
+                let (id, result) = response.into_parts();
+                let result = result.map(|mut value| {
+                    if let Some(capabilities) = value.get_mut("capabilities").and_then(|c| c.as_object_mut()) {
+                        capabilities.insert("inlineCompletionProvider".to_string(), serde_json::json!({}));
+                    }
+                    value
+                });
+                Response::from_parts(id, result)
+            }))
+        })
+    }
+}
+
 async fn build_lsp_service(
     gcx: Arc<ARwLock<GlobalContext>>,
-) -> (LspService::<LspBackend>, ClientSocket) {
+) -> (WithInlineCompletionProvider<LspService::<LspBackend>>, ClientSocket) {
     let (lsp_service, socket) = LspService::build(|client| LspBackend {
         gcx,
         client,
+        customization_cache: AMutex::new(None),
     })
         .custom_method("refact/getCompletions", LspBackend::get_completions)
+        .custom_method("textDocument/inlineCompletion", LspBackend::inline_completion)
         .custom_method("refact/acceptCompletion", LspBackend::accept_snippet)
         .custom_method("refact/setActiveDocument", LspBackend::set_active_document)
         .finish();
-    (lsp_service, socket)
+    (WithInlineCompletionProvider(lsp_service), socket)
 }
 
 pub async fn spawn_lsp_task(
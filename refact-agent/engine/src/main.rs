@@ -35,6 +35,7 @@ mod json_utils;
 mod file_filter;
 mod files_in_workspace;
 mod files_in_jsonl;
+mod files_edit_history;
 mod files_blocklist;
 mod fuzzy_search;
 mod files_correction;
@@ -50,9 +51,15 @@ mod scratchpad_abstract;
 mod scratchpads;
 
 mod fetch_embedding;
+mod forward_to_anthropic_endpoint;
+mod forward_to_openai_responses_endpoint;
+mod model_failover;
 mod forward_to_hf_endpoint;
 mod forward_to_openai_endpoint;
 mod restream;
+mod llm_traffic;
+mod mock_provider;
+mod usage_ledger;
 
 mod call_validation;
 mod dashboard;
@@ -61,6 +68,7 @@ mod http;
 
 mod integrations;
 mod privacy;
+mod secrets;
 mod git;
 mod cloud;
 mod agentic;
@@ -89,6 +97,7 @@ async fn main() {
     tokio::fs::create_dir_all(&cache_dir).await.expect("failed to create cache dir");
     tokio::fs::create_dir_all(&config_dir).await.expect("failed to create cache dir");
     let (gcx, ask_shutdown_receiver, cmdline) = global_context::create_global_context(cache_dir.clone(), config_dir.clone()).await;
+    secrets::register_secret(&cmdline.api_key);
     let mut writer_is_stderr = false;
     let (logs_writer, _guard) = if cmdline.logs_stderr {
         writer_is_stderr = true;
@@ -166,6 +175,23 @@ async fn main() {
         gcx_locked.ast_service = tmp;
     }
 
+    if !cmdline.tokenizers_export.is_empty() || !cmdline.tokenizers_import.is_empty() {
+        let res = if !cmdline.tokenizers_export.is_empty() {
+            crate::tokens::export_tokenizers_bundle(gcx.clone(), &canonical_path(&cmdline.tokenizers_export)).await
+        } else {
+            crate::tokens::import_tokenizers_bundle(gcx.clone(), &canonical_path(&cmdline.tokenizers_import)).await
+        };
+        match res {
+            Ok(n) => { println!("{} tokenizers", n); std::process::exit(0); },
+            Err(e) => { eprintln!("{}", e); std::process::exit(1); },
+        }
+    }
+
+    if let Err(e) = crate::llm_traffic::start_llm_traffic_proxy(gcx.clone()).await {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    }
+
     // Privacy before we do anything else, the default is to block everything
     let _ = crate::privacy::load_privacy_if_needed(gcx.clone()).await;
 
@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex as StdMutex, OnceLock};
+use std::time::Duration;
+
+use axum::routing::post;
+use axum::{Extension, Json, Router};
+use hyper::{Body, Response, StatusCode};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+
+use crate::caps::providers::CapsProvider;
+
+// The `mock` provider answers chat, completion and embedding requests from inside the process, so the
+// agent and tests run without network. Endpoints `mock://...` point to a local server started on first use.
+// Answers come from a script, REFACT_MOCK_SCRIPT or <config_dir>/mock_script.yaml (or .jsonl), each
+// entry is used when `match` (a regex) finds the last user message or the prompt:
+//
+// - match: "(?i)hello"
+//   text: "Hi! Let me look."
+//   tool_calls: [{name: cat, arguments: {paths: "README.md"}}]
+//   chunk_size: 5            # characters per streamed chunk
+//   chunk_delay_ms: 20
+//   times: 1                 # how many times it can be used, unlimited if not set
+// - match: "overload"
+//   error: {status: 503, message: "overloaded", after_chunks: 2}
+//
+// Without a matching entry the answer is "Mock response to: <message>". Embeddings are vectors made
+// from the sha256 of the text, so equal texts get equal vectors, of the size set in embedding_model.
+
+const MOCK_SCHEME: &str = "mock://";
+const MOCK_SCRIPT_ENV: &str = "REFACT_MOCK_SCRIPT";
+const MOCK_DEFAULT_EMBEDDING_SIZE: usize = 384;
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct MockScriptEntry {
+    #[serde(default, rename = "match")]
+    pub pattern: String,
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub tool_calls: Vec<MockToolCall>,
+    #[serde(default)]
+    pub error: Option<MockError>,
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize,
+    #[serde(default)]
+    pub chunk_delay_ms: u64,
+    #[serde(default)]
+    pub times: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct MockToolCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct MockError {
+    #[serde(default = "default_error_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub message: String,
+    /// In a stream, the error comes after this many chunks instead of the response
+    #[serde(default)]
+    pub after_chunks: Option<usize>,
+}
+
+fn default_chunk_size() -> usize { 8 }
+
+fn default_error_status() -> u16 { 500 }
+
+struct MockServer {
+    url: String,
+    script_path: PathBuf,
+    used: StdMutex<HashMap<usize, usize>>,
+    embedding_sizes: StdMutex<HashMap<String, usize>>,
+}
+
+static MOCK_SERVER: OnceLock<Arc<MockServer>> = OnceLock::new();
+
+
+/// `mock://` endpoints of the provider go to the local mock server, which starts here if not yet running
+pub fn resolve_mock_endpoints(provider: &mut CapsProvider, config_dir: &PathBuf) -> Result<(), String> {
+    let endpoints = [&provider.chat_endpoint, &provider.completion_endpoint, &provider.embedding_endpoint];
+    if !endpoints.iter().any(|x| x.starts_with(MOCK_SCHEME)) {
+        return Ok(());
+    }
+    let server = match MOCK_SERVER.get() {
+        Some(server) => server.clone(),
+        None => {
+            let script_path = std::env::var(MOCK_SCRIPT_ENV).map(PathBuf::from)
+                .unwrap_or_else(|_| config_dir.join("mock_script.yaml"));
+            let server = spawn_mock_server(script_path)?;
+            MOCK_SERVER.get_or_init(|| server).clone()
+        }
+    };
+    if !provider.embedding_model.base.name.is_empty() && provider.embedding_model.embedding_size > 0 {
+        server.embedding_sizes.lock().unwrap()
+            .insert(provider.embedding_model.base.name.clone(), provider.embedding_model.embedding_size as usize);
+    }
+    for endpoint in [&mut provider.chat_endpoint, &mut provider.completion_endpoint, &mut provider.embedding_endpoint] {
+        if let Some(path) = endpoint.strip_prefix(MOCK_SCHEME) {
+            *endpoint = format!("{}/{}", server.url, path);
+        }
+    }
+    Ok(())
+}
+
+fn spawn_mock_server(script_path: PathBuf) -> Result<Arc<MockServer>, String> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").map_err(|e| format!("cannot bind mock server: {}", e))?;
+    let addr = listener.local_addr().map_err(|e| e.to_string())?;
+    let server = Arc::new(MockServer {
+        url: format!("http://{}", addr),
+        script_path,
+        used: StdMutex::new(HashMap::new()),
+        embedding_sizes: StdMutex::new(HashMap::new()),
+    });
+    let app = Router::new()
+        .route("/v1/chat/completions", post(handle_mock_chat))
+        .route("/v1/completions", post(handle_mock_completion))
+        .route("/v1/embeddings", post(handle_mock_embeddings))
+        .layer(Extension(server.clone()));
+    let http_server = axum::Server::from_tcp(listener).map_err(|e| e.to_string())?;
+    tokio::spawn(http_server.serve(app.into_make_service()));
+    info!("mock provider at {}, script {}", server.url, server.script_path.display());
+    Ok(server)
+}
+
+/// For tests that need a server of their own: serves `app` on a free local port, returns "http://127.0.0.1:<port>"
+#[cfg(test)]
+pub fn start_test_server(app: Router) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+    format!("http://{}", addr)
+}
+
+/// The script is read on every request, so it can be edited while the agent runs
+fn read_mock_script(path: &PathBuf) -> Vec<MockScriptEntry> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(_) => return vec![],
+    };
+    let parsed = if path.extension().map_or(false, |x| x == "jsonl") {
+        content.lines().filter(|x| !x.trim().is_empty())
+            .map(|line| serde_json::from_str::<MockScriptEntry>(line).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()
+    } else {
+        serde_yaml::from_str::<Option<Vec<MockScriptEntry>>>(&content).map(|x| x.unwrap_or_default()).map_err(|e| e.to_string())
+    };
+    parsed.unwrap_or_else(|e| {
+        error!("cannot parse mock script {}: {}", path.display(), e);
+        vec![]
+    })
+}
+
+impl MockServer {
+    fn pick_entry(&self, query: &str) -> MockScriptEntry {
+        let script = read_mock_script(&self.script_path);
+        let mut used = self.used.lock().unwrap();
+        for (i, entry) in script.into_iter().enumerate() {
+            let matches = entry.pattern.is_empty() || Regex::new(&entry.pattern).map_or_else(
+                |e| { error!("mock script: bad regex {:?}: {}", entry.pattern, e); false },
+                |re| re.is_match(query),
+            );
+            let times_used = used.get(&i).cloned().unwrap_or(0);
+            if matches && entry.times.map_or(true, |times| times_used < times) {
+                used.insert(i, times_used + 1);
+                return entry;
+            }
+        }
+        let preview = query.chars().take(200).collect::<String>();
+        MockScriptEntry { text: format!("Mock response to: {}", preview), chunk_size: default_chunk_size(), ..Default::default() }
+    }
+}
+
+fn last_user_message(body: &Value) -> String {
+    let last = body["messages"].as_array()
+        .and_then(|messages| messages.iter().rev().find(|m| m["role"] == "user"))
+        .map(|m| m["content"].clone())
+        .unwrap_or_default();
+    match last {
+        Value::String(s) => s,
+        Value::Array(parts) => parts.iter().filter_map(|p| p["text"].as_str().or(p["m_content"].as_str())).collect::<Vec<_>>().join("\n"),
+        _ => String::new(),
+    }
+}
+
+fn rough_token_count(text: &str) -> usize {
+    text.split_whitespace().count()
+}
+
+fn error_response(error: &MockError) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::from_u16(error.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR))
+        .header("Content-Type", "application/json")
+        .body(Body::from(error_json(error).to_string()))
+        .unwrap()
+}
+
+fn error_json(error: &MockError) -> Value {
+    json!({"error": {"message": error.message, "type": "mock_error", "code": error.status}})
+}
+
+fn text_chunks(text: &str, chunk_size: usize) -> Vec<String> {
+    let chars = text.chars().collect::<Vec<_>>();
+    chars.chunks(chunk_size.max(1)).map(|x| x.iter().collect()).collect()
+}
+
+fn tool_calls_json(tool_calls: &[MockToolCall]) -> Vec<Value> {
+    tool_calls.iter().enumerate().map(|(i, call)| json!({
+        "index": i,
+        "id": format!("call_mock_{}", i),
+        "type": "function",
+        "function": {
+            "name": call.name,
+            "arguments": match &call.arguments {
+                Value::String(s) => s.clone(),
+                Value::Null => "{}".to_string(),
+                other => other.to_string(),
+            },
+        },
+    })).collect()
+}
+
+/// Sends `data:` events with the entry's timing, the error (if any) replaces the rest of the stream
+fn sse_response(chunks: Vec<Value>, entry: &MockScriptEntry) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+    let delay = Duration::from_millis(entry.chunk_delay_ms);
+    let error = entry.error.clone();
+    tokio::spawn(async move {
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            if let Some(error) = error.as_ref().filter(|e| e.after_chunks == Some(i)) {
+                let _ = sender.send_data(format!("data: {}\n\n", error_json(error)).into()).await;
+                return;
+            }
+            if i > 0 && !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            if sender.send_data(format!("data: {}\n\n", chunk).into()).await.is_err() {
+                return;
+            }
+        }
+        let _ = sender.send_data("data: [DONE]\n\n".into()).await;
+    });
+    Response::builder().status(StatusCode::OK).header("Content-Type", "text/event-stream").body(body).unwrap()
+}
+
+async fn handle_mock_chat(Extension(server): Extension<Arc<MockServer>>, Json(body): Json<Value>) -> Response<Body> {
+    let entry = server.pick_entry(&last_user_message(&body));
+    if let Some(error) = entry.error.as_ref().filter(|e| e.after_chunks.is_none()) {
+        return error_response(error);
+    }
+    let model = body["model"].as_str().unwrap_or("mock").to_string();
+    let finish_reason = if entry.tool_calls.is_empty() { "stop" } else { "tool_calls" };
+    let tool_calls = tool_calls_json(&entry.tool_calls);
+    let usage = json!({
+        "prompt_tokens": rough_token_count(&body["messages"].to_string()),
+        "completion_tokens": rough_token_count(&entry.text),
+        "total_tokens": rough_token_count(&body["messages"].to_string()) + rough_token_count(&entry.text),
+    });
+
+    if !body["stream"].as_bool().unwrap_or(false) {
+        let mut message = json!({"role": "assistant", "content": entry.text});
+        if !tool_calls.is_empty() {
+            message["tool_calls"] = json!(tool_calls);
+        }
+        return Response::builder().header("Content-Type", "application/json").body(Body::from(json!({
+            "id": "mock-chat", "object": "chat.completion", "model": model,
+            "choices": [{"index": 0, "message": message, "finish_reason": finish_reason}],
+            "usage": usage,
+        }).to_string())).unwrap();
+    }
+
+    let chunk = |delta: Value, finish_reason: Value| json!({
+        "id": "mock-chat", "object": "chat.completion.chunk", "model": model,
+        "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+    });
+    let mut chunks = text_chunks(&entry.text, entry.chunk_size).into_iter()
+        .map(|text| chunk(json!({"role": "assistant", "content": text}), Value::Null))
+        .collect::<Vec<_>>();
+    if !tool_calls.is_empty() {
+        chunks.push(chunk(json!({"role": "assistant", "tool_calls": tool_calls}), Value::Null));
+    }
+    chunks.push(chunk(json!({}), json!(finish_reason)));
+    chunks.push(json!({"id": "mock-chat", "object": "chat.completion.chunk", "model": model, "choices": [], "usage": usage}));
+    sse_response(chunks, &entry)
+}
+
+async fn handle_mock_completion(Extension(server): Extension<Arc<MockServer>>, Json(body): Json<Value>) -> Response<Body> {
+    let prompt = body["prompt"].as_str().unwrap_or_default().to_string();
+    let entry = server.pick_entry(&prompt);
+    if let Some(error) = entry.error.as_ref().filter(|e| e.after_chunks.is_none()) {
+        return error_response(error);
+    }
+    let model = body["model"].as_str().unwrap_or("mock").to_string();
+    if !body["stream"].as_bool().unwrap_or(false) {
+        return Response::builder().header("Content-Type", "application/json").body(Body::from(json!({
+            "id": "mock-completion", "object": "text_completion", "model": model,
+            "choices": [{"index": 0, "text": entry.text, "finish_reason": "stop"}],
+        }).to_string())).unwrap();
+    }
+    let mut chunks = text_chunks(&entry.text, entry.chunk_size).into_iter()
+        .map(|text| json!({"id": "mock-completion", "model": model, "choices": [{"index": 0, "text": text, "finish_reason": null}]}))
+        .collect::<Vec<_>>();
+    chunks.push(json!({"id": "mock-completion", "model": model, "choices": [{"index": 0, "text": "", "finish_reason": "stop"}]}));
+    sse_response(chunks, &entry)
+}
+
+/// Same text, same vector; unit length so cosine distance works as usual
+pub fn mock_embedding(text: &str, size: usize) -> Vec<f32> {
+    let digest = Sha256::digest(text.as_bytes());
+    let mut state = u64::from_le_bytes(digest[..8].try_into().unwrap()) | 1;
+    let vector = (0..size).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state as f64 / u64::MAX as f64 * 2.0 - 1.0) as f32
+    }).collect::<Vec<_>>();
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt().max(f32::EPSILON);
+    vector.into_iter().map(|x| x / norm).collect()
+}
+
+async fn handle_mock_embeddings(Extension(server): Extension<Arc<MockServer>>, Json(body): Json<Value>) -> Response<Body> {
+    let model = body["model"].as_str().unwrap_or_default().to_string();
+    let size = server.embedding_sizes.lock().unwrap().get(&model).cloned().unwrap_or(MOCK_DEFAULT_EMBEDDING_SIZE);
+    let texts = match &body["input"] {
+        Value::String(s) => vec![s.clone()],
+        Value::Array(items) => items.iter().map(|x| x.as_str().unwrap_or_default().to_string()).collect(),
+        _ => vec![],
+    };
+    let data = texts.iter().enumerate()
+        .map(|(i, text)| json!({"object": "embedding", "index": i, "embedding": mock_embedding(text, size)}))
+        .collect::<Vec<_>>();
+    Response::builder().header("Content-Type", "application/json").body(Body::from(json!({
+        "object": "list", "model": model, "data": data,
+        "usage": {"prompt_tokens": texts.iter().map(|x| rough_token_count(x)).sum::<usize>()},
+    }).to_string())).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caps::providers::get_provider_templates;
+
+    #[tokio::test]
+    async fn test_mock_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("mock_script.yaml"), r#"
+- match: "(?i)read the readme"
+  text: "Reading it."
+  tool_calls: [{name: cat, arguments: {paths: "README.md"}}]
+  chunk_size: 3
+- match: "busy"
+  times: 1
+  error: {status: 503, message: "overloaded"}
+"#).unwrap();
+        let server = spawn_mock_server(dir.path().join("mock_script.yaml")).unwrap();
+        let client = reqwest::Client::new();
+        let chat = |content: &str, stream: bool| json!({"model": "mock-chat", "stream": stream, "messages": [{"role": "user", "content": content}]});
+        let url = format!("{}/v1/chat/completions", server.url);
+
+        let resp: Value = client.post(&url).json(&chat("Please READ the readme", false)).send().await.unwrap().json().await.unwrap();
+        assert_eq!(resp["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(resp["choices"][0]["message"]["tool_calls"][0]["function"]["arguments"], "{\"paths\":\"README.md\"}");
+
+        let stream = client.post(&url).json(&chat("read the readme", true)).send().await.unwrap().text().await.unwrap();
+        assert!(stream.contains("\"content\":\"Rea\"") && stream.ends_with("data: [DONE]\n\n"));
+
+        assert_eq!(client.post(&url).json(&chat("busy?", false)).send().await.unwrap().status(), 503);
+        let resp: Value = client.post(&url).json(&chat("busy?", false)).send().await.unwrap().json().await.unwrap();
+        assert_eq!(resp["choices"][0]["message"]["content"], "Mock response to: busy?");
+
+        let mut provider = get_provider_templates()["mock"].clone();
+        provider.embedding_model.embedding_size = 16;
+        resolve_mock_endpoints(&mut provider, &dir.path().to_path_buf()).unwrap();
+        assert!(provider.embedding_endpoint.starts_with("http://127.0.0.1:"));
+        let resp: Value = client.post(&provider.embedding_endpoint).json(&json!({"model": "mock-embedding", "input": ["a", "b", "a"]}))
+            .send().await.unwrap().json().await.unwrap();
+        let vectors = resp["data"].as_array().unwrap().iter().map(|x| x["embedding"].clone()).collect::<Vec<_>>();
+        assert_eq!(vectors[0].as_array().unwrap().len(), 16);
+        assert_eq!(vectors[0], vectors[2]);
+        assert_ne!(vectors[0], vectors[1]);
+    }
+}
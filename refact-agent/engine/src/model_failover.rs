@@ -0,0 +1,276 @@
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use regex::Regex;
+use reqwest::header::HeaderValue;
+use serde_json::{json, Value};
+use tokio::sync::RwLock as ARwLock;
+use tracing::warn;
+
+use crate::call_validation::SamplingParameters;
+use crate::caps::{resolve_chat_model, BaseModelRecord};
+use crate::global_context::GlobalContext;
+
+// A request that fails with 429 or 5xx is retried with exponential backoff (or after Retry-After, if the
+// provider says how long to wait), then the next model of the fallback chain is tried. Context length
+// errors switch right away. Fallbacks of chat models come from DefaultModels, see chat_fallback_models().
+
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+const CONTEXT_LENGTH_MARKERS: &[&str] = &[
+    "context_length_exceeded",
+    "maximum context length",
+    "context window",
+    "prompt is too long",
+    "too many tokens",
+];
+
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub attempts_per_model: usize,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts_per_model: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Code completions are useless when late, the editor has moved on: no retries, a failure goes to the client
+    pub fn for_scope(scope: &str) -> Self {
+        if scope.starts_with("completion") {
+            RetryPolicy { attempts_per_model: 1, ..Default::default() }
+        } else {
+            RetryPolicy::default()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FailureKind {
+    RateLimited,
+    ServerError,
+    ContextLength,
+    Fatal,
+}
+
+/// `status` is None when the request didn't get any response
+pub fn classify_failure(status: Option<u16>, text: &str) -> FailureKind {
+    let text_lower = text.to_lowercase();
+    match status {
+        Some(429) => FailureKind::RateLimited,
+        Some(408) | Some(500..=599) => FailureKind::ServerError,
+        Some(400) | Some(413) | Some(422) if CONTEXT_LENGTH_MARKERS.iter().any(|m| text_lower.contains(m)) => FailureKind::ContextLength,
+        None if text_lower.contains("error sending request") || text_lower.contains("timed out") => FailureKind::ServerError,
+        _ => FailureKind::Fatal,
+    }
+}
+
+/// Retry-After is either seconds or an HTTP date
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return (seconds >= 0.0).then(|| Duration::from_secs_f64(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let seconds = (date.timestamp_millis() - chrono::Utc::now().timestamp_millis()).max(0);
+    Some(Duration::from_millis(seconds as u64))
+}
+
+/// Error text for a bad status, Retry-After goes along so the caller can honour it
+pub fn endpoint_status_error(endpoint: &str, status_code: u16, retry_after: Option<&HeaderValue>, text: &str) -> String {
+    match retry_after.and_then(|x| x.to_str().ok()).and_then(parse_retry_after) {
+        Some(delay) => format!("{} status={} retry-after={}ms text {}", endpoint, status_code, delay.as_millis(), text),
+        None => format!("{} status={} text {}", endpoint, status_code, text),
+    }
+}
+
+/// Status and Retry-After back from an error made by endpoint_status_error()
+pub fn failure_from_error_text(text: &str) -> (Option<u16>, Option<Duration>) {
+    static STATUS_RE: OnceLock<Regex> = OnceLock::new();
+    let re = STATUS_RE.get_or_init(|| Regex::new(r" status=(\d{3})(?: retry-after=(\d+)ms)? text ").unwrap());
+    match re.captures(text) {
+        Some(caps) => (
+            caps.get(1).and_then(|x| x.as_str().parse().ok()),
+            caps.get(2).and_then(|x| x.as_str().parse().ok()).map(Duration::from_millis),
+        ),
+        None => (None, None),
+    }
+}
+
+/// Errors that come as json with status 200 or 400, also as a stream event. The status is in the error code
+/// if it's a number, otherwise the error type tells if it's worth to try again.
+pub fn failure_from_response_json(response: &Value) -> Option<(Option<u16>, String)> {
+    let error = response.get("error")?;
+    let status = error.get("code").and_then(|x| x.as_u64()).map(|x| x as u16).unwrap_or_else(|| {
+        match error.get("type").and_then(|x| x.as_str()) {
+            Some("rate_limit_error") => 429,
+            Some("overloaded_error") => 529,
+            Some("api_error") | Some("server_error") => 500,
+            _ => 400,
+        }
+    });
+    Some((Some(status), error.to_string()))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FailoverStep {
+    Retry(Duration),
+    Switch { from: String, to: String, reason: String },
+    GiveUp,
+}
+
+pub struct ModelFailover {
+    chain: Vec<BaseModelRecord>,
+    current: usize,
+    attempt: usize,
+    policy: RetryPolicy,
+}
+
+impl ModelFailover {
+    pub fn new(chain: Vec<BaseModelRecord>, policy: RetryPolicy) -> Self {
+        assert!(!chain.is_empty());
+        ModelFailover { chain, current: 0, attempt: 0, policy }
+    }
+
+    pub fn model(&self) -> &BaseModelRecord {
+        &self.chain[self.current]
+    }
+
+    pub fn is_fallback(&self) -> bool {
+        self.current > 0
+    }
+
+    pub fn on_failure(&mut self, status: Option<u16>, text: &str, retry_after: Option<Duration>) -> FailoverStep {
+        let kind = classify_failure(status, text);
+        if kind == FailureKind::Fatal {
+            return FailoverStep::GiveUp;
+        }
+        // no point to wait for a model that is busy for longer, the next one is better
+        let waits_too_long = retry_after.map_or(false, |x| x > MAX_RETRY_AFTER);
+        if kind != FailureKind::ContextLength && !waits_too_long && self.attempt + 1 < self.policy.attempts_per_model {
+            self.attempt += 1;
+            let backoff = self.policy.base_delay.saturating_mul(1 << (self.attempt - 1).min(16)).min(self.policy.max_delay);
+            return FailoverStep::Retry(retry_after.unwrap_or(backoff));
+        }
+        if self.current + 1 >= self.chain.len() {
+            return FailoverStep::GiveUp;
+        }
+        let from = self.model().id.clone();
+        self.current += 1;
+        self.attempt = 0;
+        let reason = match kind {
+            FailureKind::RateLimited => "rate limited".to_string(),
+            FailureKind::ContextLength => "context length exceeded".to_string(),
+            _ => format!("status {}", status.map(|x| x.to_string()).unwrap_or("no response".to_string())),
+        };
+        FailoverStep::Switch { from, to: self.model().id.clone(), reason }
+    }
+}
+
+/// What the client sees in the stream when the request goes to another model
+pub fn model_switch_message(from: &str, to: &str, reason: &str) -> Value {
+    json!({"model_switch": {"from": from, "to": to, "reason": reason}})
+}
+
+/// Reasoning parameters are set up for the first model, a fallback runs without them
+pub fn sampling_parameters_for_fallback(parameters: &SamplingParameters) -> SamplingParameters {
+    let mut parameters = parameters.clone();
+    parameters.reasoning_effort = None;
+    parameters.thinking = None;
+    parameters.enable_thinking = None;
+    parameters
+}
+
+/// The model itself, then its fallbacks. The prompt is already made for the first model, so fallbacks
+/// with another scratchpad can't take it and are skipped. Chat messages in PASSTHROUGH prompts are converted
+/// again for another endpoint_style, but hf endpoints take a text prompt and go only with hf.
+pub async fn failover_chain(gcx: Arc<ARwLock<GlobalContext>>, model_rec: &BaseModelRecord) -> Vec<BaseModelRecord> {
+    let mut chain = vec![model_rec.clone()];
+    let caps = match gcx.read().await.caps.clone() {
+        Some(caps) => caps,
+        None => return chain,
+    };
+    let primary = match caps.chat_models.get(&model_rec.id) {
+        Some(primary) => primary.clone(),
+        None => return chain,  // completion models only retry
+    };
+    for fallback_id in caps.defaults.chat_fallback_models(&model_rec.id) {
+        match resolve_chat_model(caps.clone(), fallback_id) {
+            Ok(fallback) if fallback.scratchpad != primary.scratchpad || (fallback.base.endpoint_style == "hf") != (primary.base.endpoint_style == "hf") => {
+                warn!("fallback model {} skipped for {}: different scratchpad, or hf endpoint_style on one side only", fallback_id, model_rec.id);
+            },
+            Ok(fallback) => {
+                if !chain.iter().any(|x| x.id == fallback.base.id) {
+                    chain.push(fallback.base.clone());
+                }
+            },
+            Err(e) => warn!("fallback model {}: {}", fallback_id, e),
+        }
+    }
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(id: &str) -> BaseModelRecord {
+        BaseModelRecord { id: id.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_classify_failure() {
+        assert_eq!(classify_failure(Some(429), ""), FailureKind::RateLimited);
+        assert_eq!(classify_failure(Some(503), ""), FailureKind::ServerError);
+        assert_eq!(classify_failure(Some(400), "{\"code\": \"context_length_exceeded\"}"), FailureKind::ContextLength);
+        assert_eq!(classify_failure(Some(400), "no such model"), FailureKind::Fatal);
+        assert_eq!(classify_failure(None, "error sending request for url (http://localhost:1)"), FailureKind::ServerError);
+        assert_eq!(classify_failure(None, "No endpoint configured"), FailureKind::Fatal);
+    }
+
+    #[test]
+    fn test_status_error_roundtrip() {
+        let err = endpoint_status_error("http://x", 429, Some(&HeaderValue::from_static("2")), "slow down");
+        assert_eq!(failure_from_error_text(&err), (Some(429), Some(Duration::from_secs(2))));
+        let err = endpoint_status_error("http://x", 502, None, "bad gateway");
+        assert_eq!(failure_from_error_text(&err), (Some(502), None));
+        assert_eq!(failure_from_error_text("error sending request"), (None, None));
+    }
+
+    #[test]
+    fn test_failure_from_response_json() {
+        assert!(failure_from_response_json(&json!({"choices": []})).is_none());
+        let overloaded = json!({"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}});
+        assert_eq!(failure_from_response_json(&overloaded).unwrap().0, Some(529));
+        assert_eq!(failure_from_response_json(&json!({"error": {"code": 503, "message": "busy"}})).unwrap().0, Some(503));
+        assert_eq!(failure_from_response_json(&json!({"error": {"message": "no such model"}})).unwrap().0, Some(400));
+    }
+
+    #[test]
+    fn test_model_failover() {
+        let policy = RetryPolicy { attempts_per_model: 3, base_delay: Duration::from_secs(1), max_delay: Duration::from_secs(3) };
+        let mut failover = ModelFailover::new(vec![model("a/1"), model("b/2"), model("c/3")], policy);
+        assert_eq!(failover.on_failure(Some(503), "", None), FailoverStep::Retry(Duration::from_secs(1)));
+        assert_eq!(failover.on_failure(Some(429), "", Some(Duration::from_secs(10))), FailoverStep::Retry(Duration::from_secs(10)));
+        assert_eq!(failover.on_failure(Some(503), "", None), FailoverStep::Switch {
+            from: "a/1".to_string(), to: "b/2".to_string(), reason: "status 503".to_string(),
+        });
+        assert_eq!(failover.on_failure(Some(400), "maximum context length is 8192 tokens", None), FailoverStep::Switch {
+            from: "b/2".to_string(), to: "c/3".to_string(), reason: "context length exceeded".to_string(),
+        });
+        assert_eq!(failover.model().id, "c/3");
+        assert_eq!(failover.on_failure(Some(401), "bad key", None), FailoverStep::GiveUp);
+        assert_eq!(failover.on_failure(Some(400), "context window", None), FailoverStep::GiveUp);
+
+        let mut completion = ModelFailover::new(vec![model("a/1")], RetryPolicy::for_scope("completion"));
+        assert_eq!(completion.on_failure(Some(429), "", Some(Duration::from_secs(10))), FailoverStep::GiveUp);
+        assert_eq!(RetryPolicy::for_scope("chat-stream").attempts_per_model, 3);
+    }
+}
@@ -55,6 +55,7 @@ where
             message: String::new(),
         };
         event.record(&mut visitor);
+        visitor.message = crate::secrets::redact_secrets(&visitor.message);
 
         let ev_level = event.metadata().level();
         let ev_file = event.metadata().file();
@@ -4,6 +4,7 @@ use tokio::sync::mpsc;
 use async_stream::stream;
 use futures::StreamExt;
 use hyper::{Body, Response, StatusCode};
+use reqwest::header::RETRY_AFTER;
 use reqwest_eventsource::Event;
 use reqwest_eventsource::Error as REError;
 use serde_json::{json, Value};
@@ -13,8 +14,11 @@ use uuid;
 use crate::call_validation::{ChatMeta, SamplingParameters};
 use crate::caps::BaseModelRecord;
 use crate::custom_error::ScratchError;
+use crate::model_failover::{failover_chain, failure_from_error_text, failure_from_response_json, model_switch_message, parse_retry_after,
+    sampling_parameters_for_fallback, FailoverStep, ModelFailover, RetryPolicy};
 use crate::nicer_logs;
 use crate::scratchpad_abstract::{FinishReason, ScratchpadAbstract};
+use crate::scratchpads::passthrough_convert_messages::passthrough_prompt_for_model;
 use crate::telemetry::telemetry_structs;
 use crate::at_commands::at_commands::AtCommandsContext;
 
@@ -42,25 +46,19 @@ pub async fn scratchpad_interaction_not_stream_json(
 
     let mut save_url: String = String::new();
     let _ = slowdown_arc.acquire().await;
+    let mut used_model_rec = model_rec.clone();
+    let mut model_switch = None;
     let mut model_says = if only_deterministic_messages {
         save_url = "only-det-messages".to_string();
         Ok(Value::Object(serde_json::Map::new()))
-    } else if model_rec.endpoint_style == "hf" {
-        crate::forward_to_hf_endpoint::forward_to_hf_style_endpoint(
-            &model_rec,
-            prompt,
-            &client,
-            &parameters,
-            meta
-        ).await
     } else {
-        crate::forward_to_openai_endpoint::forward_to_openai_style_endpoint(
-            &model_rec,
-            prompt,
-            &client,
-            &parameters,
-            meta
-        ).await
+        let mut failover = ModelFailover::new(failover_chain(gcx.clone(), model_rec).await, RetryPolicy::for_scope(&scope));
+        let result = forward_to_endpoint_with_failover(&mut failover, prompt, &client, parameters, meta).await;
+        used_model_rec = failover.model().clone();
+        result.map(|(model_says, switch)| {
+            model_switch = switch;
+            model_says
+        })
     }.map_err(|e| {
         tele_storage.write().unwrap().tele_net.push(telemetry_structs::TelemetryNetwork::new(
                 save_url.clone(),
@@ -71,7 +69,11 @@ pub async fn scratchpad_interaction_not_stream_json(
         ScratchError::new_but_skip_telemetry(StatusCode::INTERNAL_SERVER_ERROR, format!("forward_to_endpoint: {}", e))
     })?;
     generate_id_and_index_for_tool_calls_if_missing(&mut model_says);
-    
+    if let Some(usage) = crate::usage_ledger::add_cost_to_response(&used_model_rec, &mut model_says) {
+        let chat_id = ccx.lock().await.chat_id.clone();
+        crate::usage_ledger::record_usage(gcx.clone(), &used_model_rec, &chat_id, &scope, usage).await;
+    }
+
     tele_storage.write().unwrap().tele_net.push(telemetry_structs::TelemetryNetwork::new(
         save_url.clone(),
         scope.clone(),
@@ -104,6 +106,7 @@ pub async fn scratchpad_interaction_not_stream_json(
 
     } else if let Some(oai_choices) = model_says.clone().get("choices") {
         let choice0 = oai_choices.as_array().unwrap().get(0).unwrap();
+        scratchpad.set_choices_logprobs(oai_choices.as_array().unwrap().iter().map(_choice_mean_logprob).collect());
         let finish_reasons = oai_choices.clone().as_array().unwrap().iter().map(
             |x| FinishReason::from_json_val(x.get("finish_reason").unwrap_or(&json!(""))).unwrap_or_else(|err| {
                 tracing::error!("Couldn't parse finish_reason: {err}. Fallback to finish_reason=null");
@@ -180,7 +183,113 @@ pub async fn scratchpad_interaction_not_stream_json(
             format!("scratchpad: {}", problem))
         );
     }
-    return Ok(scratchpad_result.unwrap());
+    let mut scratchpad_result = scratchpad_result.unwrap();
+    // another model has answered, the caller should know, the same as in the stream
+    if let (Some(switch), Some(result_map)) = (model_switch, scratchpad_result.as_object_mut()) {
+        result_map.extend(switch.as_object().cloned().unwrap_or_default());
+    }
+    return Ok(scratchpad_result);
+}
+
+async fn forward_to_endpoint(
+    model_rec: &BaseModelRecord,
+    prompt: &str,
+    client: &reqwest::Client,
+    parameters: &SamplingParameters,
+    meta: Option<ChatMeta>,
+) -> Result<Value, String> {
+    if model_rec.endpoint_style == "hf" {
+        crate::forward_to_hf_endpoint::forward_to_hf_style_endpoint(
+            &model_rec,
+            prompt,
+            &client,
+            &parameters,
+            meta
+        ).await
+    } else if model_rec.endpoint_style == "anthropic" {
+        crate::forward_to_anthropic_endpoint::forward_to_anthropic_endpoint(
+            &model_rec,
+            prompt,
+            &client,
+            &parameters,
+        ).await
+    } else if model_rec.endpoint_style == "openai_responses" {
+        crate::forward_to_openai_responses_endpoint::forward_to_openai_responses_endpoint(
+            &model_rec,
+            prompt,
+            &client,
+            &parameters,
+        ).await
+    } else {
+        crate::forward_to_openai_endpoint::forward_to_openai_style_endpoint(
+            &model_rec,
+            prompt,
+            &client,
+            &parameters,
+            meta
+        ).await
+    }
+}
+
+/// The prompt and the parameters are made for the first model of the chain, a fallback needs them adapted
+fn prompt_and_parameters_for_model(failover: &ModelFailover, prompt: &str, parameters: &SamplingParameters) -> Result<(String, SamplingParameters), String> {
+    if !failover.is_fallback() {
+        return Ok((prompt.to_string(), parameters.clone()));
+    }
+    Ok((passthrough_prompt_for_model(prompt, failover.model())?, sampling_parameters_for_fallback(parameters)))
+}
+
+/// Returns the answer, and `model_switch_message()` if it came from another model than the one requested
+async fn forward_to_endpoint_with_failover(
+    failover: &mut ModelFailover,
+    prompt: &str,
+    client: &reqwest::Client,
+    parameters: &SamplingParameters,
+    meta: Option<ChatMeta>,
+) -> Result<(Value, Option<Value>), String> {
+    let requested_model_id = failover.model().id.clone();
+    let mut model_switch = None;
+    loop {
+        let result = match prompt_and_parameters_for_model(failover, prompt, parameters) {
+            Ok((model_prompt, model_parameters)) => forward_to_endpoint(failover.model(), &model_prompt, client, &model_parameters, meta.clone()).await,
+            Err(e) => Err(e),
+        };
+        let (status, problem_str, retry_after) = match &result {
+            Ok(json) => match failure_from_response_json(json) {
+                Some((status, problem_str)) => (status, problem_str, None),
+                None => return result.map(|json| (json, model_switch)),
+            },
+            Err(e) => {
+                let (status, retry_after) = failure_from_error_text(e);
+                (status, e.clone(), retry_after)
+            }
+        };
+        match failover.on_failure(status, &problem_str, retry_after) {
+            FailoverStep::Retry(delay) => {
+                tracing::warn!("{} failed, retry in {:?}: {}", failover.model().id, delay, problem_str);
+                tokio::time::sleep(delay).await;
+            },
+            FailoverStep::Switch { from, to, reason } => {
+                tracing::warn!("switching from {} to {}, {}: {}", from, to, reason, problem_str);
+                model_switch = Some(model_switch_message(&requested_model_id, &to, &reason));
+            },
+            FailoverStep::GiveUp => return result.map(|json| (json, model_switch)),
+        }
+    }
+}
+
+// completions return logprobs.token_logprobs, chat completions return logprobs.content[].logprob
+fn _choice_mean_logprob(choice: &serde_json::Value) -> Option<f64> {
+    let logprobs = choice.get("logprobs")?;
+    let token_logprobs: Vec<f64> = if let Some(arr) = logprobs.get("token_logprobs").and_then(|x| x.as_array()) {
+        arr.iter().filter_map(|x| x.as_f64()).collect()
+    } else {
+        logprobs.get("content")?.as_array()?.iter().filter_map(|x| x.get("logprob").and_then(|x| x.as_f64())).collect()
+    };
+    if token_logprobs.is_empty() {
+        return None;
+    }
+    Some(token_logprobs.iter().sum::<f64>() / token_logprobs.len() as f64)
 }
 
 pub async fn scratchpad_interaction_not_stream(
@@ -321,113 +430,209 @@ pub async fn scratchpad_interaction_stream(
                 break;
             }
             // info!("prompt: {:?}", prompt);
-            let event_source_maybe = if model_rec.endpoint_style == "hf" {
-                crate::forward_to_hf_endpoint::forward_to_hf_style_endpoint_streaming(
-                    &model_rec,
-                    &prompt,
-                    &client,
-                    &my_parameters,
-                    meta
-                ).await
-            } else {
-                crate::forward_to_openai_endpoint::forward_to_openai_style_endpoint_streaming(
-                    &model_rec,
-                    &prompt,
-                    &client,
-                    &my_parameters,
-                    meta
-                ).await
-            };
-            let mut event_source = match event_source_maybe {
-                Ok(event_source) => event_source,
-                Err(e) => {
-                    let e_str = format!("forward_to_endpoint: {:?}", e);
-                    tele_storage.write().unwrap().tele_net.push(telemetry_structs::TelemetryNetwork::new(
-                        model_rec.endpoint.clone(),
-                        scope.clone(),
-                        false,
-                        e_str.to_string(),
-                    ));
-                    tracing::error!(e_str);
-                    let value_str = format!("data: {}\n\n", serde_json::to_string(&json!({"detail": e_str})).unwrap());
-                    yield Result::<_, String>::Ok(value_str);
-                    return;
-                }
-            };
+            let requested_model_id = model_rec.id.clone();
+            let mut failover = ModelFailover::new(failover_chain(gcx.clone(), &model_rec).await, RetryPolicy::for_scope(&scope));
             let mut was_correct_output_even_if_error = false;
             let mut last_finish_reason = FinishReason::None;
-            // let mut test_countdown = 250;
-            while let Some(event) = event_source.next().await {
-                match event {
-                    Ok(Event::Open) => {},
-                    Ok(Event::Message(message)) => {
-                        // info!("Message: {:#?}", message);
-                        if message.data.starts_with("[DONE]") {
-                            break;
-                        }
-                        let mut json = serde_json::from_str::<serde_json::Value>(&message.data).unwrap();
-                        generate_id_and_index_for_tool_calls_if_missing(&mut json);
-                        crate::global_context::look_for_piggyback_fields(gcx.clone(), &json).await;
-                        match _push_streaming_json_into_scratchpad(
-                            my_scratchpad,
-                            &json,
-                            &mut model_rec.name,
-                            &mut was_correct_output_even_if_error,
-                        ) {
-                            Ok((mut value, finish_reason)) => {
-                                if finish_reason != FinishReason::None { // last event has service info(usage and other), there is no finish_reason
-                                    last_finish_reason = finish_reason;
-                                }
-                                value["created"] = json!(t1.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs_f64());
-                                let value_str = format!("data: {}\n\n", serde_json::to_string(&value).unwrap());
-                                // let last_60_chars: String = crate::nicer_logs::first_n_chars(&value_str, 60);
-                                // info!("yield: {:?}", last_60_chars);
-                                yield Result::<_, String>::Ok(value_str);
-                            },
-                            Err(err_str) => {
-                                tracing::error!("unexpected error: {}", err_str);
-                                let value_str = format!("data: {}\n\n", serde_json::to_string(&json!({"detail": err_str})).unwrap());
-                                yield Result::<_, String>::Ok(value_str);
-                                // TODO: send telemetry
+            let mut last_usage = None;
+            'failover: loop {
+                let event_source_maybe = match prompt_and_parameters_for_model(&failover, &prompt, &my_parameters) {
+                    Err(e) => Err(e),
+                    Ok((model_prompt, model_parameters)) => if model_rec.endpoint_style == "hf" {
+                        crate::forward_to_hf_endpoint::forward_to_hf_style_endpoint_streaming(
+                            &model_rec,
+                            &model_prompt,
+                            &client,
+                            &model_parameters,
+                            meta.clone()
+                        ).await
+                    } else if model_rec.endpoint_style == "anthropic" {
+                        crate::forward_to_anthropic_endpoint::forward_to_anthropic_endpoint_streaming(
+                            &model_rec,
+                            &model_prompt,
+                            &client,
+                            &model_parameters,
+                        ).await
+                    } else if model_rec.endpoint_style == "openai_responses" {
+                        crate::forward_to_openai_responses_endpoint::forward_to_openai_responses_endpoint_streaming(
+                            &model_rec,
+                            &model_prompt,
+                            &client,
+                            &model_parameters,
+                        ).await
+                    } else {
+                        crate::forward_to_openai_endpoint::forward_to_openai_style_endpoint_streaming(
+                            &model_rec,
+                            &model_prompt,
+                            &client,
+                            &model_parameters,
+                            meta.clone()
+                        ).await
+                    }
+                };
+                let mut event_source = match event_source_maybe {
+                    Ok(event_source) => event_source,
+                    Err(e) => {
+                        let e_str = format!("forward_to_endpoint: {:?}", e);
+                        tele_storage.write().unwrap().tele_net.push(telemetry_structs::TelemetryNetwork::new(
+                            model_rec.endpoint.clone(),
+                            scope.clone(),
+                            false,
+                            e_str.to_string(),
+                        ));
+                        tracing::error!(e_str);
+                        let value_str = format!("data: {}\n\n", serde_json::to_string(&json!({"detail": e_str})).unwrap());
+                        yield Result::<_, String>::Ok(value_str);
+                        return;
+                    }
+                };
+                let mut got_output = false;
+                let mut anthropic_stream = crate::forward_to_anthropic_endpoint::AnthropicStreamState::default();
+                let mut responses_stream = crate::forward_to_openai_responses_endpoint::ResponsesStreamState::default();
+                // let mut test_countdown = 250;
+                while let Some(event) = event_source.next().await {
+                    match event {
+                        Ok(Event::Open) => {},
+                        Ok(Event::Message(message)) => {
+                            // info!("Message: {:#?}", message);
+                            if message.data.starts_with("[DONE]") {
                                 break;
                             }
-                        }
+                            let mut json = serde_json::from_str::<serde_json::Value>(&message.data).unwrap();
+                            // an error as the first event of a 200 stream, nothing is sent to the client yet
+                            if let Some((status, problem_str)) = failure_from_response_json(&json).filter(|_| !got_output) {
+                                tracing::error!("restream error event: {}", problem_str);
+                                match failover.on_failure(status, &problem_str, None) {
+                                    FailoverStep::Retry(delay) => {
+                                        tracing::warn!("{} failed, retry in {:?}", model_rec.id, delay);
+                                        event_source.close();
+                                        tokio::time::sleep(delay).await;
+                                        continue 'failover;
+                                    },
+                                    FailoverStep::Switch { from, to, reason } => {
+                                        tracing::warn!("switching from {} to {}, {}", from, to, reason);
+                                        yield Result::<_, String>::Ok(format!("data: {}\n\n", serde_json::to_string(&model_switch_message(&requested_model_id, &to, &reason)).unwrap()));
+                                        event_source.close();
+                                        model_rec = failover.model().clone();
+                                        continue 'failover;
+                                    },
+                                    FailoverStep::GiveUp => {},
+                                }
+                            }
+                            got_output = true;
+                            if model_rec.endpoint_style == "anthropic" {
+                                if json.get("type").and_then(|t| t.as_str()) == Some("message_stop") {
+                                    break;
+                                }
+                                json = match anthropic_stream.event_to_openai_chunk(&json) {
+                                    Some(chunk) => chunk,
+                                    None => continue,
+                                };
+                            } else if model_rec.endpoint_style == "openai_responses" {
+                                json = match responses_stream.event_to_openai_chunk(&json) {
+                                    Some(chunk) => chunk,
+                                    None => continue,
+                                };
+                                // the server closes the stream after the last event, there's no [DONE]
+                                was_correct_output_even_if_error |= responses_stream.finished;
+                            }
+                            generate_id_and_index_for_tool_calls_if_missing(&mut json);
+                            // some servers repeat the running usage in every chunk, the last one is recorded
+                            if let Some(usage) = crate::usage_ledger::add_cost_to_response(&model_rec, &mut json) {
+                                last_usage = Some(usage);
+                            }
+                            crate::global_context::look_for_piggyback_fields(gcx.clone(), &json).await;
+                            match _push_streaming_json_into_scratchpad(
+                                my_scratchpad,
+                                &json,
+                                &mut model_rec.name,
+                                &mut was_correct_output_even_if_error,
+                            ) {
+                                Ok((mut value, finish_reason)) => {
+                                    if finish_reason != FinishReason::None { // last event has service info(usage and other), there is no finish_reason
+                                        last_finish_reason = finish_reason;
+                                    }
+                                    value["created"] = json!(t1.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs_f64());
+                                    let value_str = format!("data: {}\n\n", serde_json::to_string(&value).unwrap());
+                                    // let last_60_chars: String = crate::nicer_logs::first_n_chars(&value_str, 60);
+                                    // info!("yield: {:?}", last_60_chars);
+                                    yield Result::<_, String>::Ok(value_str);
+                                },
+                                Err(err_str) => {
+                                    tracing::error!("unexpected error: {}", err_str);
+                                    let value_str = format!("data: {}\n\n", serde_json::to_string(&json!({"detail": err_str})).unwrap());
+                                    yield Result::<_, String>::Ok(value_str);
+                                    // TODO: send telemetry
+                                    break;
+                                }
+                            }
 
-                    },
-                    Err(err) => {
-                        if was_correct_output_even_if_error {
-                            // "restream error: Stream ended"
-                            break;
-                        }
-                        let problem_str = match err {
-                            REError::InvalidStatusCode(err, resp) => {
-                                let text = resp.text().await.unwrap();
-                                let mut res = format!("{} with details = {:?}", err, text);
-                                if let Ok(value) = serde_json::from_str::<Value>(&text) {
-                                    if let Some(detail) = value.get("detail") {
-                                        res = format!("{}: {}", err, detail);
+                        },
+                        Err(err) => {
+                            if was_correct_output_even_if_error {
+                                // "restream error: Stream ended"
+                                break;
+                            }
+                            let (problem_str, status, retry_after) = match err {
+                                REError::InvalidStatusCode(err, resp) => {
+                                    let retry_after = resp.headers().get(RETRY_AFTER).and_then(|x| x.to_str().ok()).and_then(parse_retry_after);
+                                    let text = resp.text().await.unwrap();
+                                    let mut res = format!("{} with details = {:?}", err, text);
+                                    if let Ok(value) = serde_json::from_str::<Value>(&text) {
+                                        if let Some(detail) = value.get("detail") {
+                                            res = format!("{}: {}", err, detail);
+                                        }
                                     }
+                                    (res, Some(err.as_u16()), retry_after)
+                                }
+                                _ => {
+                                    (format!("{}", err), None, None)
                                 }
-                                res
+                            };
+                            tracing::error!("restream error: {}\n", problem_str);
+                            {
+                                tele_storage.write().unwrap().tele_net.push(telemetry_structs::TelemetryNetwork::new(
+                                    model_rec.endpoint.clone(),
+                                    scope.clone(),
+                                    false,
+                                    problem_str.clone(),
+                                ));
                             }
-                            _ => {
-                                format!("{}", err)
+                            // nothing is sent to the client yet, the request can go again
+                            if !got_output {
+                                match failover.on_failure(status, &problem_str, retry_after) {
+                                    FailoverStep::Retry(delay) => {
+                                        tracing::warn!("{} failed, retry in {:?}", model_rec.id, delay);
+                                        event_source.close();
+                                        tokio::time::sleep(delay).await;
+                                        continue 'failover;
+                                    },
+                                    FailoverStep::Switch { from, to, reason } => {
+                                        tracing::warn!("switching from {} to {}, {}", from, to, reason);
+                                        yield Result::<_, String>::Ok(format!("data: {}\n\n", serde_json::to_string(&model_switch_message(&requested_model_id, &to, &reason)).unwrap()));
+                                        event_source.close();
+                                        model_rec = failover.model().clone();
+                                        continue 'failover;
+                                    },
+                                    FailoverStep::GiveUp => {},
+                                }
                             }
-                        };
-                        tracing::error!("restream error: {}\n", problem_str);
-                        {
-                            tele_storage.write().unwrap().tele_net.push(telemetry_structs::TelemetryNetwork::new(
-                                model_rec.endpoint.clone(),
-                                scope.clone(),
-                                false,
-                                problem_str.clone(),
-                            ));
-                        }
-                        yield Result::<_, String>::Ok(format!("data: {}\n\n", serde_json::to_string(&json!({"detail": problem_str})).unwrap()));
-                        event_source.close();
-                        return;
-                    },
+                            yield Result::<_, String>::Ok(format!("data: {}\n\n", serde_json::to_string(&json!({"detail": problem_str})).unwrap()));
+                            event_source.close();
+                            // the provider bills what it has streamed so far
+                            if let Some(usage) = last_usage {
+                                let chat_id = my_ccx.lock().await.chat_id.clone();
+                                crate::usage_ledger::record_usage(gcx.clone(), &model_rec, &chat_id, &scope, usage).await;
+                            }
+                            return;
+                        },
+                    }
                 }
+                break;
+            }
+            if let Some(usage) = last_usage {
+                let chat_id = my_ccx.lock().await.chat_id.clone();
+                crate::usage_ledger::record_usage(gcx.clone(), &model_rec, &chat_id, &scope, usage).await;
             }
 
             let mut value = my_scratchpad.streaming_finished(last_finish_reason)?;
@@ -616,3 +821,245 @@ pub async fn cached_stream(
        .unwrap();
     return Ok(response);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_provider::start_test_server;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use axum::{Extension, Router, routing::post};
+    use axum::http::StatusCode as AxumStatusCode;
+    use axum::response::IntoResponse;
+
+    async fn mock_down(Extension(hits): Extension<Arc<AtomicUsize>>) -> impl IntoResponse {
+        hits.fetch_add(1, Ordering::SeqCst);
+        (AxumStatusCode::SERVICE_UNAVAILABLE, "overloaded")
+    }
+
+    async fn mock_busy_once(Extension(hits): Extension<Arc<AtomicUsize>>) -> axum::response::Response {
+        if hits.fetch_add(1, Ordering::SeqCst) == 0 {
+            return (AxumStatusCode::TOO_MANY_REQUESTS, [("retry-after", "0")], "slow down").into_response();
+        }
+        axum::Json(json!({"choices": [{"index": 0, "text": "busy", "finish_reason": "stop"}]})).into_response()
+    }
+
+    async fn mock_up() -> axum::Json<Value> {
+        axum::Json(json!({"choices": [{"index": 0, "text": "up", "finish_reason": "stop"}]}))
+    }
+
+    async fn mock_anthropic_overloaded(Extension(hits): Extension<Arc<AtomicUsize>>, axum::Json(body): axum::Json<Value>) -> axum::Json<Value> {
+        hits.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(body["system"], json!([{"type": "text", "text": "be brief", "cache_control": {"type": "ephemeral"}}]));
+        axum::Json(json!({"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}}))
+    }
+
+    async fn mock_openai_chat(axum::Json(body): axum::Json<Value>) -> axum::Json<Value> {
+        if body["messages"] != json!([{"role": "system", "content": "be brief"}, {"role": "user", "content": "hi"}]) {
+            return axum::Json(json!({"error": {"message": format!("unexpected messages {}", body["messages"])}}));
+        }
+        axum::Json(json!({"choices": [{"index": 0, "message": {"role": "assistant", "content": "hello"}, "finish_reason": "stop"}]}))
+    }
+
+    async fn mock_overloaded(Extension(hits): Extension<Arc<AtomicUsize>>) -> impl IntoResponse {
+        hits.fetch_add(1, Ordering::SeqCst);
+        (AxumStatusCode::SERVICE_UNAVAILABLE, [("retry-after", "0")], "overloaded")
+    }
+
+    async fn mock_stream() -> impl IntoResponse {
+        let chunk = json!({"choices": [{"index": 0, "delta": {"role": "assistant", "content": "hello"}, "finish_reason": "stop"}]});
+        ([("content-type", "text/event-stream")], format!("data: {}\n\ndata: [DONE]\n\n", chunk))
+    }
+
+    async fn mock_stream_broken() -> impl IntoResponse {
+        let chunk = json!({"choices": [{"index": 0, "delta": {"role": "assistant", "content": "hel"}, "finish_reason": null}],
+            "usage": {"prompt_tokens": 100, "completion_tokens": 1, "total_tokens": 101}});
+        let events: Vec<Result<String, std::io::Error>> = vec![
+            Ok(format!("data: {}\n\n", chunk)),
+            Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "gone")),
+        ];
+        ([("content-type", "text/event-stream")], axum::body::StreamBody::new(futures::stream::iter(events)))
+    }
+
+    async fn start_mock_provider(hits: Arc<AtomicUsize>) -> String {
+        let app = Router::new()
+            .route("/stream_broken", post(mock_stream_broken))
+            .route("/down", post(mock_down))
+            .route("/overloaded", post(mock_overloaded))
+            .route("/stream", post(mock_stream))
+            .route("/busy", post(mock_busy_once))
+            .route("/up", post(mock_up))
+            .route("/anthropic/v1/messages", post(mock_anthropic_overloaded))
+            .route("/openai/v1/chat/completions", post(mock_openai_chat))
+            .layer(Extension(hits));
+        start_test_server(app)
+    }
+
+    fn model(id: &str, endpoint: String) -> BaseModelRecord {
+        BaseModelRecord { id: id.to_string(), endpoint, endpoint_style: "openai".to_string(), ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn test_forward_to_endpoint_with_failover() {
+        let policy = RetryPolicy { attempts_per_model: 2, base_delay: Duration::from_millis(10), max_delay: Duration::from_millis(10) };
+        let client = reqwest::Client::new();
+        let params = SamplingParameters::default();
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let url = start_mock_provider(hits.clone()).await;
+        let mut failover = ModelFailover::new(vec![model("p/busy", format!("{url}/busy"))], policy.clone());
+        let (resp, model_switch) = forward_to_endpoint_with_failover(&mut failover, "def f():", &client, &params, None).await.unwrap();
+        assert_eq!(resp["choices"][0]["text"], "busy");
+        assert!(model_switch.is_none());
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let url = start_mock_provider(hits.clone()).await;
+        let chain = vec![model("p/down", format!("{url}/down")), model("q/up", format!("{url}/up"))];
+        let mut failover = ModelFailover::new(chain, policy.clone());
+        let (resp, model_switch) = forward_to_endpoint_with_failover(&mut failover, "def f():", &client, &params, None).await.unwrap();
+        assert_eq!(resp["choices"][0]["text"], "up");
+        assert_eq!(model_switch, Some(model_switch_message("p/down", "q/up", "status 503")));
+        assert_eq!(failover.model().id, "q/up");
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        let mut failover = ModelFailover::new(vec![model("p/down", format!("{url}/down"))], policy);
+        let err = forward_to_endpoint_with_failover(&mut failover, "def f():", &client, &params, None).await.unwrap_err();
+        assert!(err.contains("status=503"));
+    }
+
+    #[tokio::test]
+    async fn test_failover_from_anthropic_to_openai_style() {
+        let policy = RetryPolicy { attempts_per_model: 2, base_delay: Duration::from_millis(10), max_delay: Duration::from_millis(10) };
+        let client = reqwest::Client::new();
+        let params = SamplingParameters { max_new_tokens: 100, ..Default::default() };
+        let hits = Arc::new(AtomicUsize::new(0));
+        let url = start_mock_provider(hits.clone()).await;
+
+        let messages = vec![
+            crate::call_validation::ChatMessage::new("system".to_string(), "be brief".to_string()),
+            crate::call_validation::ChatMessage::new("user".to_string(), "hi".to_string()),
+        ];
+        let mut big_json = json!({});
+        crate::scratchpads::passthrough_convert_messages::set_passthrough_messages(&mut big_json, messages, "anthropic", &None, "anthropic/claude-3-7-sonnet");
+        let prompt = format!("PASSTHROUGH {}", big_json);
+
+        let mut claude = model("anthropic/claude-3-7-sonnet", format!("{url}/anthropic/v1/messages"));
+        claude.endpoint_style = "anthropic".to_string();
+        let gpt = model("openai/gpt-4o", format!("{url}/openai/v1/chat/completions"));
+        let mut failover = ModelFailover::new(vec![claude, gpt], policy);
+        let (resp, model_switch) = forward_to_endpoint_with_failover(&mut failover, &prompt, &client, &params, None).await.unwrap();
+        assert_eq!(resp["choices"][0]["message"]["content"], "hello");
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        assert_eq!(model_switch.unwrap()["model_switch"]["to"], "openai/gpt-4o");
+    }
+
+    struct PromptOnlyScratchpad {
+        prompt: String,
+    }
+
+    #[async_trait::async_trait]
+    impl ScratchpadAbstract for PromptOnlyScratchpad {
+        async fn apply_model_adaptation_patch(&mut self, _patch: &Value) -> Result<(), String> {
+            Ok(())
+        }
+
+        async fn prompt(&mut self, _ccx: Arc<AMutex<AtCommandsContext>>, _sampling_parameters_to_patch: &mut SamplingParameters) -> Result<String, String> {
+            Ok(self.prompt.clone())
+        }
+
+        fn response_n_choices(&mut self, _choices: Vec<String>, _finish_reasons: Vec<FinishReason>) -> Result<Value, String> {
+            Err("not implemented".to_string())
+        }
+
+        fn response_streaming(&mut self, _delta: String, _finish_reason: FinishReason) -> Result<(Value, FinishReason), String> {
+            Err("not implemented".to_string())
+        }
+
+        fn response_message_streaming(&mut self, _delta: &Value, _finish_reason: FinishReason) -> Result<(Value, FinishReason), String> {
+            Err("not implemented".to_string())
+        }
+
+        fn response_spontaneous(&mut self) -> Result<Vec<Value>, String> {
+            Ok(vec![])
+        }
+
+        fn streaming_finished(&mut self, finish_reason: FinishReason) -> Result<Value, String> {
+            Ok(json!({"choices": [{"index": 0, "delta": {}, "finish_reason": finish_reason.to_json_val()}]}))
+        }
+    }
+
+    async fn test_gcx(dir: &tempfile::TempDir) -> Arc<tokio::sync::RwLock<crate::global_context::GlobalContext>> {
+        use structopt::StructOpt;
+        use crate::global_context::{create_global_context_with_cmdline, CommandLine};
+        let cmdline = CommandLine::from_iter(["refact-lsp"]);
+        let (gcx, _ask_shutdown_receiver, _) = create_global_context_with_cmdline(dir.path().join("cache"), dir.path().join("config"), cmdline).await;
+        gcx
+    }
+
+    async fn collect_body(response: Response<Body>) -> String {
+        String::from_utf8(hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stream_switch_names_requested_model() {
+        use crate::caps::{ChatModelRecord, CodeAssistantCaps};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let url = start_mock_provider(hits.clone()).await;
+        let dir = tempfile::tempdir().unwrap();
+        let gcx = test_gcx(&dir).await;
+
+        // two hops: a -> b -> c
+        let mut caps = CodeAssistantCaps::default();
+        for (id, path) in [("p/a", "overloaded"), ("p/b", "overloaded"), ("p/c", "stream")] {
+            let base = BaseModelRecord { name: id.to_string(), ..model(id, format!("{url}/{path}")) };
+            caps.chat_models.insert(id.to_string(), Arc::new(ChatModelRecord { base, scratchpad: "PASSTHROUGH".to_string(), ..Default::default() }));
+        }
+        caps.defaults.chat_default_fallback_models = vec!["p/b".to_string(), "p/c".to_string()];
+        gcx.write().await.caps = Some(Arc::new(caps));
+
+        let messages = vec![crate::call_validation::ChatMessage::new("user".to_string(), "hi".to_string())];
+        let mut big_json = json!({});
+        crate::scratchpads::passthrough_convert_messages::set_passthrough_messages(&mut big_json, messages.clone(), "openai", &None, "p/a");
+        let scratchpad = Box::new(PromptOnlyScratchpad { prompt: format!("PASSTHROUGH {}", big_json) });
+        let ccx = Arc::new(AMutex::new(AtCommandsContext::new(gcx.clone(), 4096, 1, false, messages, "chat1".to_string(), false, "p/a".to_string()).await));
+        let model_a = gcx.read().await.caps.as_ref().unwrap().chat_models["p/a"].base.clone();
+
+        let response = scratchpad_interaction_stream(ccx, scratchpad, "chat-stream".to_string(), model_a, SamplingParameters::default(), false, None).await.unwrap();
+        let body = collect_body(response).await;
+        let switches = body.split("\n\n")
+            .filter_map(|x| x.strip_prefix("data: "))
+            .filter_map(|x| serde_json::from_str::<Value>(x).ok())
+            .filter(|x| x.get("model_switch").is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(switches, vec![
+            model_switch_message("p/a", "p/b", "status 503"),
+            model_switch_message("p/a", "p/c", "status 503"),
+        ]);
+        assert!(body.contains("hello"), "{}", body);
+        assert_eq!(hits.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn test_broken_stream_usage_is_recorded() {
+        let url = start_mock_provider(Arc::new(AtomicUsize::new(0))).await;
+        let dir = tempfile::tempdir().unwrap();
+        let gcx = test_gcx(&dir).await;
+        std::fs::create_dir_all(dir.path().join("cache")).unwrap();
+
+        let messages = vec![crate::call_validation::ChatMessage::new("user".to_string(), "hi".to_string())];
+        let mut big_json = json!({});
+        crate::scratchpads::passthrough_convert_messages::set_passthrough_messages(&mut big_json, messages.clone(), "openai", &None, "p/broken");
+        let scratchpad = Box::new(PromptOnlyScratchpad { prompt: format!("PASSTHROUGH {}", big_json) });
+        let ccx = Arc::new(AMutex::new(AtCommandsContext::new(gcx.clone(), 4096, 1, false, messages, "chat1".to_string(), false, "p/broken".to_string()).await));
+        let model_rec = model("p/broken", format!("{url}/stream_broken"));
+
+        let response = scratchpad_interaction_stream(ccx, scratchpad, "chat-stream".to_string(), model_rec, SamplingParameters::default(), false, None).await.unwrap();
+        let body = collect_body(response).await;
+        assert!(body.contains("\"detail\""), "{}", body);
+        let ledger = std::fs::read_to_string(dir.path().join("cache").join("usage_ledger.jsonl")).unwrap();
+        assert_eq!(ledger.lines().count(), 1);
+        assert!(ledger.contains("\"prompt_tokens\":100"), "{}", ledger);
+    }
+}
@@ -82,6 +82,9 @@ pub trait ScratchpadAbstract: Send {
         sampling_parameters_to_patch: &mut SamplingParameters,
     ) -> Result<String, String>;
 
+    // Mean token log-prob of each choice if the backend returned them, comes right before response_n_choices
+    fn set_choices_logprobs(&mut self, _logprobs: Vec<Option<f64>>) {}
+
     // Not streaming, convert what model says (choices) to final result
     fn response_n_choices(
         &mut self,
@@ -123,11 +126,12 @@ pub struct HasTokenizerAndEot {
     pub eos: String,
     pub context_format: String,
     pub rag_ratio: f64,
+    pub vecdb_ratio: f64,  // part of the RAG budget for VecDB results, when AST has something to say as well
 }
 
 impl HasTokenizerAndEot {
     pub fn new(tokenizer: Option<Arc<Tokenizer>>) -> Self {
-        HasTokenizerAndEot { tokenizer, eot: String::new(), eos: String::new(), context_format: String::new(), rag_ratio: 0.5}
+        HasTokenizerAndEot { tokenizer, eot: String::new(), eos: String::new(), context_format: String::new(), rag_ratio: 0.5, vecdb_ratio: 0.5}
     }
 
     pub fn count_tokens(
@@ -12,11 +12,12 @@ use crate::caps::resolve_chat_model;
 use crate::http::http_get_json;
 use crate::http::routers::v1::at_tools::ToolGroupResponse;
 use crate::integrations::docker::docker_container_manager::docker_container_get_host_lsp_port_to_connect;
+use crate::integrations::docker::docker_compose::compose_tools_for_chat;
 use crate::scratchpad_abstract::{FinishReason, HasTokenizerAndEot, ScratchpadAbstract};
 use crate::scratchpads::chat_utils_limit_history::fix_and_limit_messages_history;
 use crate::scratchpads::scratchpad_utils::HasRagResults;
 use crate::scratchpads::chat_utils_prompts::prepend_the_right_system_prompt_and_maybe_more_initial_messages;
-use crate::scratchpads::passthrough_convert_messages::convert_messages_to_openai_format;
+use crate::scratchpads::passthrough_convert_messages::set_passthrough_messages;
 use crate::tools::tools_description::ToolDesc;
 use crate::tools::tools_list::get_available_tools;
 use crate::tools::tools_execute::{run_tools_locally, run_tools_remotely};
@@ -149,9 +150,11 @@ impl ScratchpadAbstract for ChatPassthrough {
                 let port = docker_container_get_host_lsp_port_to_connect(gcx.clone(), &self.post.meta.chat_id).await?;
                 tracing::info!("Calling tools on port: {}", port);
                 let tool_desclist: Vec<ToolGroupResponse> = http_get_json(&format!("http://localhost:{port}/v1/tools")).await?;
+                let host_tools = compose_tools_for_chat(gcx.clone(), &self.post.meta.chat_id).await;
                 tool_desclist.into_iter()
                     .flat_map(|tool_group| tool_group.tools)
                     .map(|tool| tool.spec)
+                    .chain(host_tools.iter().map(|tool| tool.tool_description()))
                     .collect()
             } else {
                 self.tools.iter()
@@ -206,6 +209,7 @@ impl ScratchpadAbstract for ChatPassthrough {
             assert_eq!(limited_msgs.first().unwrap().role, "system");
         }
 
+        let endpoint_style = model_record_mb.as_ref().map(|m| m.base.endpoint_style.clone()).unwrap_or_default();
         // Handle models that support reasoning
         let limited_adapted_msgs = if let Some(supports_reasoning) = supports_reasoning {
             let model_record = model_record_mb.clone().unwrap();
@@ -215,6 +219,7 @@ impl ScratchpadAbstract for ChatPassthrough {
                 supports_reasoning,
                 model_record.default_temperature.clone(),
                 model_record.supports_boost_reasoning.clone(),
+                &endpoint_style,
             )
         } else {
             // drop all reasoning parameters in case of non-reasoning model
@@ -225,8 +230,7 @@ impl ScratchpadAbstract for ChatPassthrough {
         };
 
         let model_id = model_record_mb.map(|m| m.base.id.clone()).unwrap_or_default();
-        let converted_messages = convert_messages_to_openai_format(limited_adapted_msgs, &style, &model_id);
-        big_json["messages"] = json!(converted_messages);
+        set_passthrough_messages(&mut big_json, limited_adapted_msgs, &endpoint_style, &style, &model_id);
         big_json["compression_strength"] = json!(compression_strength);
 
         let prompt = "PASSTHROUGH ".to_string() + &serde_json::to_string(&big_json).unwrap();
@@ -276,6 +280,7 @@ fn _adapt_for_reasoning_models(
     supports_reasoning: String,
     default_temperature: Option<f32>,
     supports_boost_reasoning: bool,
+    endpoint_style: &str,
 ) -> Vec<ChatMessage> {
     match supports_reasoning.as_ref() {
         "openai" => {
@@ -284,6 +289,13 @@ fn _adapt_for_reasoning_models(
             }
             sampling_parameters.temperature = default_temperature;
 
+            // NOTE: Responses API takes the system prompt as developer message, and needs reasoning_effort
+            // to return the reasoning items
+            if endpoint_style == "openai_responses" {
+                sampling_parameters.reasoning_effort.get_or_insert(ReasoningEffort::Medium);
+                return messages;
+            }
+
             // NOTE: OpenAI prefer user message over system
             messages.into_iter().map(|mut msg| {
                 if msg.role == "system" {
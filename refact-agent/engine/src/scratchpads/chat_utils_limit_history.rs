@@ -1269,6 +1269,7 @@ mod tests {
                 eos: "".to_string(),
                 context_format: "".to_string(),
                 rag_ratio: 0.5,
+                vecdb_ratio: 0.5,
             })
         }
     }
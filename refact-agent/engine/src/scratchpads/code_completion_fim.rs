@@ -15,7 +15,8 @@ use crate::call_validation::{CodeCompletionPost, SamplingParameters};
 use crate::global_context::GlobalContext;
 use crate::completion_cache;
 use crate::scratchpad_abstract::{FinishReason, HasTokenizerAndEot, ScratchpadAbstract};
-use crate::scratchpads::completon_rag::retrieve_ast_based_extra_context;
+use crate::scratchpads::completon_rag::{retrieve_extra_context, vecdb_query_near_cursor};
+use crate::scratchpads::completion_ranking::postprocess_post_choices;
 use crate::telemetry::snippets_collection;
 use crate::telemetry::telemetry_structs;
 
@@ -31,6 +32,7 @@ pub struct FillInTheMiddleScratchpad {
     pub fim_middle: String,
     pub extra_stop_tokens: Vec<String>,
     pub context_used: Value,
+    pub logprobs: Vec<Option<f64>>,
     pub data4cache: completion_cache::CompletionSaveToCache,
     pub data4snippet: snippets_collection::SaveSnippet,
     pub ast_service: Option<Arc<AMutex<AstIndexService>>>,
@@ -58,6 +60,7 @@ impl FillInTheMiddleScratchpad {
             fim_middle: String::new(),
             extra_stop_tokens: vec![],
             context_used: json!({}),
+            logprobs: vec![],
             data4cache,
             data4snippet,
             ast_service,
@@ -89,6 +92,7 @@ impl ScratchpadAbstract for FillInTheMiddleScratchpad {
         self.t.eos = patch.get("eos").and_then(|x| x.as_str()).unwrap_or("").to_string();
         self.t.context_format = patch.get("context_format").and_then(|x| x.as_str()).unwrap_or_default().to_string();
         self.t.rag_ratio = patch.get("rag_ratio").and_then(|x| x.as_f64()).unwrap_or(0.5);
+        self.t.vecdb_ratio = patch.get("vecdb_ratio").and_then(|x| x.as_f64()).unwrap_or(0.5);
         if self.t.tokenizer.is_some() {
             self.t.assert_one_token(&self.fim_prefix.as_str())?;
             self.t.assert_one_token(&self.fim_suffix.as_str())?;
@@ -108,7 +112,10 @@ impl ScratchpadAbstract for FillInTheMiddleScratchpad {
     ) -> Result<String, String> {
         let n_ctx = ccx.lock().await.n_ctx;
         let fim_t0 = Instant::now();
-        let use_rag = !self.t.context_format.is_empty() && self.t.rag_ratio > 0.0 && self.post.use_ast && self.ast_service.is_some();
+        let use_ast = self.post.use_ast && self.ast_service.is_some();
+        let vec_db = self.global_context.read().await.vec_db.clone();
+        let use_vecdb = self.post.use_vecdb && vec_db.lock().await.is_some();
+        let use_rag = !self.t.context_format.is_empty() && self.t.rag_ratio > 0.0 && (use_ast || use_vecdb);
         let mut rag_tokens_n = if self.post.rag_tokens_n > 0 {
             self.post.rag_tokens_n.min(4096).max(50)
         } else {
@@ -117,8 +124,8 @@ impl ScratchpadAbstract for FillInTheMiddleScratchpad {
         if !use_rag {
             rag_tokens_n = 0;
         }
-        if !use_rag && self.post.use_ast {
-            tracing::warn!("will not use ast because {}{}{}{}", self.t.context_format.is_empty() as i32, self.post.use_ast as i32, (rag_tokens_n > 0) as i32, self.ast_service.is_some() as i32);
+        if !use_rag && (self.post.use_ast || self.post.use_vecdb) {
+            tracing::warn!("will not use rag because {}{}{}{}{}", self.t.context_format.is_empty() as i32, self.post.use_ast as i32, (rag_tokens_n > 0) as i32, self.ast_service.is_some() as i32, use_vecdb as i32);
         }
 
         let limit: i32 = (n_ctx as i32) - (self.post.parameters.max_new_tokens as i32) - (rag_tokens_n as i32);
@@ -249,20 +256,22 @@ impl ScratchpadAbstract for FillInTheMiddleScratchpad {
 
             // NOTE: why do we need this loop?
             // postprocess_context_files doesn't care about additional tokens after lines skip
-            // in real world retrieve_ast_based_extra_context can produce context that doesn't fit in the budget
+            // in real world retrieve_extra_context can produce context that doesn't fit in the budget
             // if so we need to reduce budget and retrieve context again
             let mut extra_content_collect_counter = 0;
             let mut content_tokens_budget = rag_tokens_n as i32;
+            let vecdb_query = use_vecdb.then(|| vecdb_query_near_cursor(&source, pos.line));
             loop {
-                let extra_context = retrieve_ast_based_extra_context(
+                let extra_context = retrieve_extra_context(
                     self.global_context.clone(),
-                    self.ast_service.clone(),
+                    if use_ast { self.ast_service.clone() } else { None },
                     &self.t,
                     &cpath,
                     &pos,
                     (fim_line1, fim_line2),
                     pp_settings.clone(),
                     content_tokens_budget as usize,
+                    vecdb_query.clone(),
                     &mut self.context_used
                 ).await;
                 let content_tokens_n = self.t.count_tokens(&extra_context.as_str())?;
@@ -285,6 +294,10 @@ impl ScratchpadAbstract for FillInTheMiddleScratchpad {
         Ok(prompt)
     }
 
+    fn set_choices_logprobs(&mut self, logprobs: Vec<Option<f64>>) {
+        self.logprobs = logprobs;
+    }
+
     fn response_n_choices(
         &mut self,
         choices: Vec<String>,
@@ -302,6 +315,7 @@ impl ScratchpadAbstract for FillInTheMiddleScratchpad {
                 "finish_reason": finish_reasons[i].to_json_val(),
             })
         }).collect::<Vec<_>>();
+        let json_choices = postprocess_post_choices(json_choices, &self.post, &self.logprobs, &mut self.data4cache);
         if DEBUG {
             info!("response_n_choices\n{:?}", json_choices);
         }
@@ -15,8 +15,9 @@ use ropey::Rope;
 use serde_json::{json, Value};
 use std::collections::VecDeque;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::sync::RwLock as StdRwLock;
+use std::sync::Mutex as StdMutex;
 use std::time::Instant;
 use std::vec;
 use tokenizers::Tokenizer;
@@ -25,7 +26,10 @@ use tokio::sync::RwLock as ARwLock;
 use tracing::{info, warn};
 use crate::ast::ast_db::doc_defs;
 use crate::ast::ast_structs::AstDefinition;
-use crate::scratchpads::completon_rag::retrieve_ast_based_extra_context;
+use crate::scratchpads::completon_rag::{retrieve_extra_context, vecdb_query_near_cursor};
+use crate::scratchpads::completion_ranking::postprocess_post_choices;
+use crate::files_edit_history::{edit_to_diff, EditHistory};
+use regex::Regex;
 
 const DEBUG: bool = false;
 const SYSTEM_PROMPT: &str = r#"You are given a code file, <BLOCK_OF_CODE> from that file and an extra context from other files.
@@ -40,10 +44,15 @@ Keep identation symbols unchanged. Do not output multiple <REWRITTEN_BLOCK_OF_CO
 Strictly follow the user's intention.
 User's intention:
 <comment>"#;
+const SYSTEM_PROMPT_NEXT_EDIT: &str = r#"You are given a code file with numbered lines, the user's recent edits in that file and an extra context from other files.
+Predict the next edit the user is going to make somewhere else in this file, typically the same kind of change applied at another place.
+Answer with the range of lines to replace as <LINES>first-last</LINES>, followed by a single code block with the new text for these lines.
+Keep identation symbols unchanged. If there is nothing left to change, answer <LINES>none</LINES>"#;
 const MAX_ROWS_UP_OR_DOWNS: usize = 10;
 const MIN_ROWS_TO_SKIP_CARET: usize = 2;
 const SUBBLOCK_REQUIRED_TOKENS: usize = 128;
 const CURSORFILE_MIN_TOKENS: usize = 128;
+const EDIT_HISTORY_MAX_TOKENS: usize = 512;
 const MAX_NEW_TOKENS: usize = 1024;  // it's quite high since we want to avoid having a stripped message
 const TEMPERATURE_INITIAL: f32 = 0.0;
 const TEMPERATURE_NOCACHE: f32 = 0.5;
@@ -100,12 +109,17 @@ fn prepare_cursor_file(
     file_name: &PathBuf,
     file_text: &Rope,
     cursor_pos: &CursorPosition,
+    numbered_lines: bool,
 ) -> Result<(String, usize, (usize, usize)), String> {
+    let render_line = |idx: usize| {
+        let line = file_text.line(idx).to_string();
+        if numbered_lines { format!("{}|{}", idx + 1, line) } else { line }
+    };
     let mut output_lines: VecDeque<String> = VecDeque::new();
     let mut tokens_used: usize = 0;
     let mut line_idx_offset: i32 = 1;
 
-    let line = file_text.line(cursor_pos.line as usize).to_string();
+    let line = render_line(cursor_pos.line as usize);
     output_lines.push_front(line.to_string());
     tokens_used += tokenizer.count_tokens(&line).unwrap_or(0) as usize;
     if tokens_used > max_tokens {
@@ -115,7 +129,7 @@ fn prepare_cursor_file(
     let mut line2: usize = usize::MIN;
     loop {
         if cursor_pos.line - line_idx_offset >= 0 {
-            let line = file_text.line((cursor_pos.line - line_idx_offset) as usize).to_string();
+            let line = render_line((cursor_pos.line - line_idx_offset) as usize);
             tokens_used += tokenizer.count_tokens(&line).unwrap_or(0) as usize;
             if tokens_used > max_tokens {
                 break;
@@ -124,7 +138,7 @@ fn prepare_cursor_file(
             line1 = (cursor_pos.line - line_idx_offset) as usize;
         }
         if cursor_pos.line + line_idx_offset < file_text.len_lines() as i32 {
-            let line = file_text.line((cursor_pos.line + line_idx_offset) as usize).to_string();
+            let line = render_line((cursor_pos.line + line_idx_offset) as usize);
             tokens_used += tokenizer.count_tokens(&line).unwrap_or(0) as usize;
             if tokens_used > max_tokens {
                 break;
@@ -385,6 +399,94 @@ fn unfence_the_last_code_block(text: &String) -> Option<String> {
     blocks.iter().last().cloned()
 }
 
+/// Recent edits in the file as diff hunks, the most recent ones that fit into `max_tokens`, oldest first
+fn prepare_edit_history(
+    edit_history: &Arc<StdMutex<EditHistory>>,
+    tokenizer: &HasTokenizerAndEot,
+    max_tokens: usize,
+    cpath: &PathBuf,
+) -> Result<String, String> {
+    let edits = edit_history.lock().unwrap().file_edits(cpath);
+    let mut hunks = vec![];
+    let mut tokens_used = 0;
+    for edit in edits.iter().rev() {
+        let hunk = edit_to_diff(edit);
+        tokens_used += tokenizer.count_tokens(&hunk)? as usize;
+        if tokens_used > max_tokens {
+            break;
+        }
+        hunks.push(hunk);
+    }
+    if hunks.is_empty() {
+        return Ok("".to_string());
+    }
+    hunks.reverse();
+    Ok(format!("Recent edits in this file, oldest first:\n```diff\n{}```", hunks.join("")))
+}
+
+/// `file_text` is the whole file the model has seen numbered, the lines are compared without line endings
+fn process_next_edit_choices(
+    file_text: &str,
+    choices: &Vec<String>,
+    finish_reasons: &Vec<FinishReason>,
+) -> Vec<Value> {
+    static LINES_RE: OnceLock<Regex> = OnceLock::new();
+    let lines_re = LINES_RE.get_or_init(|| Regex::new(r"<LINES>\s*(\d+)\s*(?:-\s*(\d+)\s*)?</LINES>").unwrap());
+    let file_lines = file_text.lines().collect::<Vec<_>>();
+    choices.iter().enumerate().map(|(i, x)| {
+        let no_edit = json!({
+            "index": i,
+            "code_completion": "",
+            "finish_reason": finish_reasons[i].to_json_val()
+        });
+        let (line1, line2, after_tag) = match lines_re.captures(x) {
+            Some(caps) => {
+                let line1 = caps[1].parse::<usize>().unwrap_or(0);
+                let line2 = caps.get(2).map_or(line1, |m| m.as_str().parse::<usize>().unwrap_or(0));
+                (line1, line2, x[caps.get(0).unwrap().end()..].to_string())
+            }
+            None => {
+                info!("next edit: no line range in the model response, probably nothing to change");
+                return no_edit;
+            }
+        };
+        if line1 == 0 || line1 > line2 || line2 > file_lines.len() {
+            warn!("next edit: lines {line1}-{line2} are outside of the file with {} lines", file_lines.len());
+            return no_edit;
+        }
+        let replacement = match unfence_the_last_code_block(&after_tag) {
+            Some(replacement) => strip_echoed_line_numbers(&replacement.replace("\r", "")),
+            None => {
+                warn!("next edit: no code block found in the model response");
+                return no_edit;
+            }
+        };
+        let current = file_lines[line1 - 1..line2].join("\n");
+        if current.trim_end() == replacement.trim_end() {
+            return no_edit;
+        }
+        json!({
+            "index": i,
+            "code_completion": replacement,
+            "finish_reason": finish_reasons[i].to_json_val(),
+            "edit_range": {"line1": line1, "line2": line2},
+        })
+    }).collect()
+}
+
+/// The prompt shows the file as `N|line`, sometimes the model copies these prefixes into its answer
+fn strip_echoed_line_numbers(code: &str) -> String {
+    static LINE_NUMBER_RE: OnceLock<Regex> = OnceLock::new();
+    let line_number_re = LINE_NUMBER_RE.get_or_init(|| Regex::new(r"^\d+\|").unwrap());
+    let lines = code.split_inclusive('\n').collect::<Vec<_>>();
+    let all_numbered = lines.iter().any(|line| !line.trim().is_empty())
+        && lines.iter().all(|line| line.trim().is_empty() || line_number_re.is_match(line));
+    if !all_numbered {
+        return code.to_string();
+    }
+    lines.iter().map(|line| line_number_re.replace(line, "")).collect()
+}
+
 fn process_n_choices(
     subblock: &mut Option<SubBlock>,
     choices: &Vec<String>,
@@ -550,7 +652,9 @@ pub struct CodeCompletionReplaceScratchpad {
 
     pub new_line_symbol: Option<String>,
     pub cursor_subblock: Option<SubBlock>,
+    pub next_edit_text: Option<String>,
     pub context_used: Value,
+    pub logprobs: Vec<Option<f64>>,
     pub data4cache: completion_cache::CompletionSaveToCache,
     pub data4snippet: snippets_collection::SaveSnippet,
     pub ast_service: Option<Arc<AMutex<AstIndexService>>>,
@@ -578,7 +682,9 @@ impl CodeCompletionReplaceScratchpad {
             keyword_asst: "".to_string(),
             new_line_symbol: None,
             cursor_subblock: None,
+            next_edit_text: None,
             context_used: json!({}),
+            logprobs: vec![],
             data4cache,
             data4snippet,
             ast_service,
@@ -647,6 +753,10 @@ impl ScratchpadAbstract for CodeCompletionReplaceScratchpad {
             .get("rag_ratio")
             .and_then(|x| x.as_f64())
             .unwrap_or(0.5);
+        self.t.vecdb_ratio = patch
+            .get("vecdb_ratio")
+            .and_then(|x| x.as_f64())
+            .unwrap_or(0.5);
         if self.t.tokenizer.is_some() {
             if !self.token_bos.is_empty() {
                 self.t.assert_one_token(&self.token_bos.as_str())?;
@@ -674,9 +784,14 @@ impl ScratchpadAbstract for CodeCompletionReplaceScratchpad {
             (ccx_locked.n_ctx, ccx_locked.global_context.clone())
         };
         let completion_t0 = Instant::now();
-        let use_rag = self.t.rag_ratio > 0.0 && self.post.use_ast && self.ast_service.is_some();
+        let use_ast = self.post.use_ast && self.ast_service.is_some();
+        let vec_db = self.global_context.read().await.vec_db.clone();
+        let use_vecdb = self.post.use_vecdb && vec_db.lock().await.is_some();
+        let use_rag = self.t.rag_ratio > 0.0 && (use_ast || use_vecdb);
         sampling_parameters_to_patch.max_new_tokens = MAX_NEW_TOKENS;
-        sampling_parameters_to_patch.temperature = if !self.post.no_cache { Some(TEMPERATURE_INITIAL) } else { Some(TEMPERATURE_NOCACHE) };
+        // several candidates at zero temperature would be all the same
+        let several_candidates = sampling_parameters_to_patch.n.unwrap_or(1) > 1;
+        sampling_parameters_to_patch.temperature = if !self.post.no_cache && !several_candidates { Some(TEMPERATURE_INITIAL) } else { Some(TEMPERATURE_NOCACHE) };
         sampling_parameters_to_patch.stop = vec![self.t.eot.clone()];
         let cpath = crate::files_correction::canonical_path(&self.post.inputs.cursor.file);
         let source = self
@@ -688,7 +803,9 @@ impl ScratchpadAbstract for CodeCompletionReplaceScratchpad {
             .clone();
         let mut prompt = self.token_bos.clone();
         prompt.push_str(self.keyword_syst.as_str());
-        if let Some(comment) = retrieve_a_comment(&source, &cpath, &self.post.inputs.cursor) {
+        if self.post.next_edit {
+            prompt.push_str(SYSTEM_PROMPT_NEXT_EDIT);
+        } else if let Some(comment) = retrieve_a_comment(&source, &cpath, &self.post.inputs.cursor) {
             prompt.push_str(&SYSTEM_PROMPT_USERS_INTENTION.replace("<comment>", &comment));
         } else {
             prompt.push_str(SYSTEM_PROMPT);
@@ -709,7 +826,17 @@ impl ScratchpadAbstract for CodeCompletionReplaceScratchpad {
         };
         available_tokens = available_tokens.saturating_sub(2 + 2 * self.t.count_tokens(self.keyword_user.as_str())? as usize);
         available_tokens = available_tokens.saturating_sub(1 + self.t.count_tokens(self.keyword_asst.as_str())? as usize);
-        let subblock_required_tokens = SUBBLOCK_REQUIRED_TOKENS;
+        let edit_history = prepare_edit_history(
+            &self.global_context.read().await.documents_state.edit_history.clone(),
+            &self.t,
+            EDIT_HISTORY_MAX_TOKENS.min(available_tokens / 4),
+            &cpath,
+        )?;
+        if self.post.next_edit && edit_history.is_empty() {
+            return Err("no recent edits in this file to predict the next edit from".to_string());
+        }
+        available_tokens = available_tokens.saturating_sub(self.t.count_tokens(edit_history.as_str())? as usize + 2);
+        let subblock_required_tokens = if self.post.next_edit { 0 } else { SUBBLOCK_REQUIRED_TOKENS };
         let cursor_file_available_tokens = available_tokens.saturating_sub(subblock_required_tokens);
         if cursor_file_available_tokens <= CURSORFILE_MIN_TOKENS {
             return Err(format!("not enough tokens for the cursor file: {cursor_file_available_tokens} <= {CURSORFILE_MIN_TOKENS}"));
@@ -722,46 +849,59 @@ impl ScratchpadAbstract for CodeCompletionReplaceScratchpad {
             &cpath,
             &text,
             &self.post.inputs.cursor,
+            self.post.next_edit,
         )?;
-        let (subblock, _) = prepare_subblock(
-            self.ast_service.clone(),
-            &self.t,
-            subblock_required_tokens,
-            &cpath,
-            &text,
-            &self.post.inputs.cursor,
-            MAX_ROWS_UP_OR_DOWNS,
-            MIN_ROWS_TO_SKIP_CARET
-        ).await?;
         if use_rag {
             let pp_settings = {
                 let ccx_locked = ccx.lock().await;
                 ccx_locked.postprocess_parameters.clone()
             };
-            let extra_context = retrieve_ast_based_extra_context(
+            let extra_context = retrieve_extra_context(
                 self.global_context.clone(),
-                self.ast_service.clone(),
+                if use_ast { self.ast_service.clone() } else { None },
                 &self.t,
                 &cpath,
                 &self.post.inputs.cursor,
                 (line1 as i32, line2 as i32),
                 pp_settings,
                 rag_tokens_n,
+                use_vecdb.then(|| vecdb_query_near_cursor(&source, self.post.inputs.cursor.line)),
                 &mut self.context_used
             ).await;
             prompt.push_str(self.keyword_user.as_str());
             prompt.push_str(extra_context.as_str());
             prompt.push_str(self.token_esc.as_str());
         }
-        self.cursor_subblock = Some(subblock);
-        self.new_line_symbol = if self.cursor_subblock.as_ref().unwrap().cursor_line.ends_with("\r\n") {
-            Some("\r\n".to_string())
-        } else {
-            Some("\n".to_string())
-        };
-        // Editing file and the subblock within it to rewrite by the model
+        if !edit_history.is_empty() {
+            prompt.push_str(self.keyword_user.as_str());
+            prompt.push_str(edit_history.as_str());
+            prompt.push_str(self.token_esc.as_str());
+        }
         prompt.push_str(self.keyword_user.as_str());
-        prompt.push_str(format!("{file_content}\n{}", self.cursor_subblock.as_ref().unwrap().prompt()?).as_str());
+        if self.post.next_edit {
+            // Whole numbered file, the model picks the lines to rewrite
+            self.next_edit_text = Some(text.to_string());
+            prompt.push_str(format!("{file_content}\nThe cursor is at line {}.", self.post.inputs.cursor.line + 1).as_str());
+        } else {
+            let (subblock, _) = prepare_subblock(
+                self.ast_service.clone(),
+                &self.t,
+                subblock_required_tokens,
+                &cpath,
+                &text,
+                &self.post.inputs.cursor,
+                MAX_ROWS_UP_OR_DOWNS,
+                MIN_ROWS_TO_SKIP_CARET
+            ).await?;
+            self.cursor_subblock = Some(subblock);
+            self.new_line_symbol = if self.cursor_subblock.as_ref().unwrap().cursor_line.ends_with("\r\n") {
+                Some("\r\n".to_string())
+            } else {
+                Some("\n".to_string())
+            };
+            // Editing file and the subblock within it to rewrite by the model
+            prompt.push_str(format!("{file_content}\n{}", self.cursor_subblock.as_ref().unwrap().prompt()?).as_str());
+        }
         prompt.push_str(self.token_esc.as_str());
 
         let completion_ms = completion_t0.elapsed().as_millis() as i32;
@@ -780,11 +920,22 @@ impl ScratchpadAbstract for CodeCompletionReplaceScratchpad {
         Ok(prompt)
     }
 
+    fn set_choices_logprobs(&mut self, logprobs: Vec<Option<f64>>) {
+        self.logprobs = logprobs;
+    }
+
     fn response_n_choices(
         &mut self,
         choices: Vec<String>,
         finish_reasons: Vec<FinishReason>,
     ) -> Result<Value, String> {
+        if let Some(file_text) = &self.next_edit_text {
+            return Ok(json!({
+                "choices": process_next_edit_choices(file_text, &choices, &finish_reasons),
+                "model": self.post.model.clone(),
+                "context": self.context_used,
+            }));
+        }
         let json_choices = process_n_choices(
             &mut self.cursor_subblock,
             &choices,
@@ -792,6 +943,7 @@ impl ScratchpadAbstract for CodeCompletionReplaceScratchpad {
             self.post.inputs.multiline,
             &mut self.data4cache,
         );
+        let json_choices = postprocess_post_choices(json_choices, &self.post, &self.logprobs, &mut self.data4cache);
         snippets_collection::snippet_register_from_data4cache(
             &self.data4snippet,
             &mut self.data4cache,
@@ -837,7 +989,9 @@ pub struct CodeCompletionReplacePassthroughScratchpad {
     pub post: CodeCompletionPost,
     pub new_line_symbol: Option<String>,
     pub cursor_subblock: Option<SubBlock>,
+    pub next_edit_text: Option<String>,
     pub context_used: Value,
+    pub logprobs: Vec<Option<f64>>,
     pub data4cache: completion_cache::CompletionSaveToCache,
     pub data4snippet: snippets_collection::SaveSnippet,
     pub ast_service: Option<Arc<AMutex<AstIndexService>>>,
@@ -860,7 +1014,9 @@ impl CodeCompletionReplacePassthroughScratchpad {
             post: post.clone(),
             new_line_symbol: None,
             cursor_subblock: None,
+            next_edit_text: None,
             context_used: json!({}),
+            logprobs: vec![],
             data4cache,
             data4snippet,
             ast_service,
@@ -884,6 +1040,10 @@ impl ScratchpadAbstract for CodeCompletionReplacePassthroughScratchpad {
             .get("rag_ratio")
             .and_then(|x| x.as_f64())
             .unwrap_or(0.5);
+        self.t.vecdb_ratio = patch
+            .get("vecdb_ratio")
+            .and_then(|x| x.as_f64())
+            .unwrap_or(0.5);
         Ok(())
     }
 
@@ -898,9 +1058,14 @@ impl ScratchpadAbstract for CodeCompletionReplacePassthroughScratchpad {
         };
         let caps = gcx.read().await.caps.clone().ok_or_else(|| "No caps".to_string())?;
         let completion_t0 = Instant::now();
-        let use_rag = self.t.rag_ratio > 0.0 && self.post.use_ast && self.ast_service.is_some();
+        let use_ast = self.post.use_ast && self.ast_service.is_some();
+        let vec_db = self.global_context.read().await.vec_db.clone();
+        let use_vecdb = self.post.use_vecdb && vec_db.lock().await.is_some();
+        let use_rag = self.t.rag_ratio > 0.0 && (use_ast || use_vecdb);
         sampling_parameters_to_patch.max_new_tokens = MAX_NEW_TOKENS;
-        sampling_parameters_to_patch.temperature = if !self.post.no_cache { Some(TEMPERATURE_INITIAL) } else { Some(TEMPERATURE_NOCACHE) };
+        // several candidates at zero temperature would be all the same
+        let several_candidates = sampling_parameters_to_patch.n.unwrap_or(1) > 1;
+        sampling_parameters_to_patch.temperature = if !self.post.no_cache && !several_candidates { Some(TEMPERATURE_INITIAL) } else { Some(TEMPERATURE_NOCACHE) };
         sampling_parameters_to_patch.stop = vec![]; // avoid model cutting completion too early 
         let cpath = crate::files_correction::canonical_path(&self.post.inputs.cursor.file);
         let source = self
@@ -912,7 +1077,13 @@ impl ScratchpadAbstract for CodeCompletionReplacePassthroughScratchpad {
             .clone();
 
         let mut messages = vec![];
-        if let Some(comment) = retrieve_a_comment(&source, &cpath, &self.post.inputs.cursor) {
+        if self.post.next_edit {
+            messages.push(ChatMessage {
+                role: "system".to_string(),
+                content: ChatContent::SimpleText(SYSTEM_PROMPT_NEXT_EDIT.to_string()),
+                ..Default::default()
+            });
+        } else if let Some(comment) = retrieve_a_comment(&source, &cpath, &self.post.inputs.cursor) {
             messages.push(ChatMessage {
                 role: "system".to_string(),
                 content: ChatContent::SimpleText(
@@ -943,7 +1114,17 @@ impl ScratchpadAbstract for CodeCompletionReplacePassthroughScratchpad {
         } else {
             0
         };
-        let subblock_required_tokens = SUBBLOCK_REQUIRED_TOKENS;
+        let edit_history = prepare_edit_history(
+            &self.global_context.read().await.documents_state.edit_history.clone(),
+            &self.t,
+            EDIT_HISTORY_MAX_TOKENS.min(available_tokens / 4),
+            &cpath,
+        )?;
+        if self.post.next_edit && edit_history.is_empty() {
+            return Err("no recent edits in this file to predict the next edit from".to_string());
+        }
+        available_tokens = available_tokens.saturating_sub(self.t.count_tokens(edit_history.as_str())? as usize + 3);
+        let subblock_required_tokens = if self.post.next_edit { 0 } else { SUBBLOCK_REQUIRED_TOKENS };
         let cursor_file_available_tokens = available_tokens.saturating_sub(subblock_required_tokens);
         if cursor_file_available_tokens <= CURSORFILE_MIN_TOKENS {
             return Err(format!("not enough tokens for the cursor file: {cursor_file_available_tokens} <= {CURSORFILE_MIN_TOKENS}"));
@@ -956,31 +1137,23 @@ impl ScratchpadAbstract for CodeCompletionReplacePassthroughScratchpad {
             &cpath,
             &text,
             &self.post.inputs.cursor,
+            self.post.next_edit,
         )?;
-        let (subblock, _subblock_tokens_count) = prepare_subblock(
-            self.ast_service.clone(),
-            &self.t,
-            subblock_required_tokens,
-            &cpath,
-            &text,
-            &self.post.inputs.cursor,
-            MAX_ROWS_UP_OR_DOWNS,
-            MIN_ROWS_TO_SKIP_CARET
-        ).await?;
         if use_rag {
             let pp_settings = {
                 let ccx_locked = ccx.lock().await;
                 ccx_locked.postprocess_parameters.clone()
             };
-            let extra_context = retrieve_ast_based_extra_context(
+            let extra_context = retrieve_extra_context(
                 self.global_context.clone(),
-                self.ast_service.clone(),
+                if use_ast { self.ast_service.clone() } else { None },
                 &self.t,
                 &cpath,
                 &self.post.inputs.cursor,
                 (line1 as i32, line2 as i32),
                 pp_settings,
                 rag_tokens_n,
+                use_vecdb.then(|| vecdb_query_near_cursor(&source, self.post.inputs.cursor.line)),
                 &mut self.context_used
             ).await;
             if !extra_context.is_empty() {
@@ -991,19 +1164,40 @@ impl ScratchpadAbstract for CodeCompletionReplacePassthroughScratchpad {
                 });
             }
         }
-        self.cursor_subblock = Some(subblock);
-        self.new_line_symbol = if self.cursor_subblock.as_ref().unwrap().cursor_line.ends_with("\r\n") {
-            Some("\r\n".to_string())
+        if !edit_history.is_empty() {
+            messages.push(ChatMessage {
+                role: "user".to_string(),
+                content: ChatContent::SimpleText(edit_history),
+                ..Default::default()
+            });
+        }
+        let user_content = if self.post.next_edit {
+            // Whole numbered file, the model picks the lines to rewrite
+            self.next_edit_text = Some(text.to_string());
+            format!("{file_content}\nThe cursor is at line {}.", self.post.inputs.cursor.line + 1)
         } else {
-            Some("\n".to_string())
+            let (subblock, _subblock_tokens_count) = prepare_subblock(
+                self.ast_service.clone(),
+                &self.t,
+                subblock_required_tokens,
+                &cpath,
+                &text,
+                &self.post.inputs.cursor,
+                MAX_ROWS_UP_OR_DOWNS,
+                MIN_ROWS_TO_SKIP_CARET
+            ).await?;
+            self.cursor_subblock = Some(subblock);
+            self.new_line_symbol = if self.cursor_subblock.as_ref().unwrap().cursor_line.ends_with("\r\n") {
+                Some("\r\n".to_string())
+            } else {
+                Some("\n".to_string())
+            };
+            // Editing file and the subblock within it to rewrite by the model
+            format!("{file_content}\n{}", self.cursor_subblock.as_ref().unwrap().prompt()?)
         };
-        // Editing file and the subblock within it to rewrite by the model
         messages.push(ChatMessage {
             role: "user".to_string(),
-            content: ChatContent::SimpleText(format!(
-                "{file_content}\n{}",
-                self.cursor_subblock.as_ref().unwrap().prompt()?
-            )),
+            content: ChatContent::SimpleText(user_content),
             ..Default::default()
         });
 
@@ -1029,6 +1223,10 @@ impl ScratchpadAbstract for CodeCompletionReplacePassthroughScratchpad {
         Ok(prompt)
     }
 
+    fn set_choices_logprobs(&mut self, logprobs: Vec<Option<f64>>) {
+        self.logprobs = logprobs;
+    }
+
     fn response_n_choices(
         &mut self,
         _choices: Vec<String>,
@@ -1050,6 +1248,13 @@ impl ScratchpadAbstract for CodeCompletionReplacePassthroughScratchpad {
         choices: Vec<String>,
         finish_reasons: Vec<FinishReason>,
     ) -> Result<Value, String> {
+        if let Some(file_text) = &self.next_edit_text {
+            return Ok(json!({
+                "choices": process_next_edit_choices(file_text, &choices, &finish_reasons),
+                "model": self.post.model.clone(),
+                "context": self.context_used,
+            }));
+        }
         let json_choices = process_n_choices(
             &mut self.cursor_subblock,
             &choices,
@@ -1057,6 +1262,7 @@ impl ScratchpadAbstract for CodeCompletionReplacePassthroughScratchpad {
             self.post.inputs.multiline,
             &mut self.data4cache,
         );
+        let json_choices = postprocess_post_choices(json_choices, &self.post, &self.logprobs, &mut self.data4cache);
         snippets_collection::snippet_register_from_data4cache(
             &self.data4snippet,
             &mut self.data4cache,
@@ -1086,3 +1292,45 @@ impl ScratchpadAbstract for CodeCompletionReplacePassthroughScratchpad {
         Err("not implemented".to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_next_edit_choices() {
+        let file_text = "def f(a, b):\r\n    pass\r\n\r\nf(1, 2)\r\nf(3)\r\n";
+        let choices = vec![
+            "<LINES>5-5</LINES>\n```python\nf(3, 2)\n```".to_string(),
+            "<LINES>none</LINES>".to_string(),
+            "<LINES>4</LINES>\n```\nf(1, 2)\n```".to_string(),
+            "<LINES>5-9</LINES>\n```\nf(3, 2)\n```".to_string(),
+            "<LINES>1-2</LINES>\n```\ndef f(a, b):\n    pass\n```".to_string(),
+        ];
+        let finish_reasons = vec![FinishReason::Stop; 5];
+        let result = process_next_edit_choices(file_text, &choices, &finish_reasons);
+        assert_eq!(result[0]["code_completion"], "f(3, 2)\n");
+        assert_eq!(result[0]["edit_range"], json!({"line1": 5, "line2": 5}));
+        // nothing to change, the same text, out of the file, the same multi-line range
+        for i in 1..5 {
+            assert_eq!(result[i]["code_completion"], "");
+            assert!(result[i].get("edit_range").is_none());
+        }
+    }
+
+    #[test]
+    fn test_process_next_edit_choices_strips_echoed_line_numbers() {
+        let file_text = "def f(a, b):\n    pass\n\nf(1, 2)\nf(3)\n";
+        let choices = vec![
+            "<LINES>4-5</LINES>\n```python\n4|f(1, 2)\n5|f(3, 4)\n```".to_string(),
+            "<LINES>2-4</LINES>\n```python\n2|    return a | b\n\n4|f(1, 2)\n```".to_string(),
+            "<LINES>5</LINES>\n```python\nx = 1|2\n5|f(3)\n```".to_string(),
+        ];
+        let finish_reasons = vec![FinishReason::Stop; 3];
+        let result = process_next_edit_choices(file_text, &choices, &finish_reasons);
+        assert_eq!(result[0]["code_completion"], "f(1, 2)\nf(3, 4)\n");
+        assert_eq!(result[1]["code_completion"], "    return a | b\n\nf(1, 2)\n");
+        // not every line has a number, so the numbers are the code
+        assert_eq!(result[2]["code_completion"], "x = 1|2\n5|f(3)\n");
+    }
+}
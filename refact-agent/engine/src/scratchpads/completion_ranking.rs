@@ -0,0 +1,149 @@
+use std::path::PathBuf;
+use serde_json::Value;
+use tracing::info;
+
+use crate::ast::treesitter::parsers::get_language_id_by_filename;
+use crate::call_validation::CodeCompletionPost;
+use crate::completion_cache::CompletionSaveToCache;
+use crate::scratchpad_abstract::FinishReason;
+use crate::scratchpads::completion_syntax::{split_at_cursor, syntax_trim_completion};
+
+const FOLLOWING_LINES_TO_COMPARE: usize = 3;
+
+
+#[derive(Debug, Default)]
+struct CandidateScore {
+    syntax_trimmed: bool,
+    duplicates_following_lines: bool,
+    logprob: Option<f64>,
+}
+
+// The model continues past the end of the completion and writes what's already there below the cursor
+fn duplicates_following_lines(completion: &str, text_after_cursor_line: &str) -> bool {
+    let following: Vec<&str> = text_after_cursor_line.lines()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .take(FOLLOWING_LINES_TO_COMPARE)
+        .collect();
+    let completion_tail: Vec<&str> = completion.lines()
+        .skip(1)
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .collect();
+    // the completion ends with the first lines that follow
+    (1..=following.len().min(completion_tail.len()))
+        .any(|n| completion_tail[completion_tail.len() - n..] == following[..n])
+}
+
+/// Best first: complete as the model wrote it, no repeating the code below the cursor, then higher log-prob.
+/// Equal and empty completions are dropped, indexes are renumbered in the new order.
+fn rank_completion_choices(
+    json_choices: Vec<Value>,
+    text_after_cursor_line: &str,
+    syntax_trimmed: &Vec<bool>,
+    logprobs: &Vec<Option<f64>>,
+) -> Vec<Value> {
+    if json_choices.len() < 2 {
+        return json_choices;
+    }
+    let mut seen = vec![];
+    let mut scored: Vec<(CandidateScore, Value)> = vec![];
+    for (i, choice) in json_choices.iter().enumerate() {
+        let completion = choice.get("code_completion").and_then(|x| x.as_str()).unwrap_or_default().to_string();
+        if completion.trim().is_empty() || seen.contains(&completion) {
+            continue;
+        }
+        seen.push(completion.clone());
+        let score = CandidateScore {
+            syntax_trimmed: syntax_trimmed.get(i).cloned().unwrap_or(false),
+            duplicates_following_lines: duplicates_following_lines(&completion, text_after_cursor_line),
+            logprob: logprobs.get(i).cloned().flatten(),
+        };
+        scored.push((score, choice.clone()));
+    }
+    if scored.is_empty() {
+        return vec![json_choices[0].clone()];
+    }
+    // sort is stable, the model's order is kept for equal scores
+    scored.sort_by(|(a, _), (b, _)| {
+        a.syntax_trimmed.cmp(&b.syntax_trimmed)
+            .then(a.duplicates_following_lines.cmp(&b.duplicates_following_lines))
+            .then(b.logprob.unwrap_or(f64::MIN).total_cmp(&a.logprob.unwrap_or(f64::MIN)))
+    });
+    info!("ranked {} completion candidates out of {}: {:?}", scored.len(), json_choices.len(), scored.iter().map(|(s, _)| s).collect::<Vec<_>>());
+    scored.into_iter().enumerate().map(|(rank, (score, mut choice))| {
+        choice["index"] = Value::from(rank);
+        if let Some(logprob) = score.logprob {
+            choice["logprob"] = Value::from(logprob);
+        }
+        choice
+    }).collect()
+}
+
+/// Syntax check and ranking of the choices for the cursor in `post`, the best one is what goes to the cache
+pub fn postprocess_post_choices(
+    mut json_choices: Vec<Value>,
+    post: &CodeCompletionPost,
+    logprobs: &Vec<Option<f64>>,
+    data4cache: &mut CompletionSaveToCache,
+) -> Vec<Value> {
+    let source = post.inputs.sources.get(&post.inputs.cursor.file).cloned().unwrap_or_default();
+    let (before, after) = split_at_cursor(&source, &post.inputs.cursor);
+    let language_id = get_language_id_by_filename(&PathBuf::from(&post.inputs.cursor.file));
+    let mut syntax_trimmed = vec![];
+    for choice in json_choices.iter_mut() {
+        let completion = choice.get("code_completion").and_then(|x| x.as_str()).unwrap_or_default().to_string();
+        // cut by max tokens, it's not supposed to be complete
+        let cut_by_length = choice.get("finish_reason").and_then(|x| x.as_str()) == Some("length");
+        if completion.is_empty() || cut_by_length {
+            syntax_trimmed.push(false);
+            continue;
+        }
+        let trimmed = syntax_trim_completion(language_id, &before, &after, &completion).unwrap_or_else(|| {
+            info!("completion breaks the syntax of the file, dropped:\n{completion}");
+            "".to_string()
+        });
+        syntax_trimmed.push(!trimmed.is_empty() && trimmed != completion);
+        choice["code_completion"] = Value::from(trimmed);
+    }
+
+    let after_cursor_line = after.split_once('\n').map(|(_, x)| x).unwrap_or_default();
+    let ranked = rank_completion_choices(json_choices, after_cursor_line, &syntax_trimmed, logprobs);
+    if let Some(best) = ranked.get(0) {
+        data4cache.completion0_text = best["code_completion"].as_str().unwrap_or_default().to_string();
+        data4cache.completion0_finish_reason = FinishReason::from_json_val(&best["finish_reason"]).unwrap_or(FinishReason::None).to_string();
+    }
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_rank_completion_choices() {
+        let after_cursor_line = "    return y\n";
+        let choices = vec![
+            json!({"index": 0, "code_completion": "y = g(x", "finish_reason": "stop"}),
+            json!({"index": 1, "code_completion": "y = x + 1\n    return y", "finish_reason": "stop"}),
+            json!({"index": 2, "code_completion": "y = x * 2", "finish_reason": "stop"}),
+            json!({"index": 3, "code_completion": "y = x + 1", "finish_reason": "stop"}),
+            json!({"index": 4, "code_completion": "y = x * 2", "finish_reason": "stop"}),
+            json!({"index": 5, "code_completion": "", "finish_reason": "stop"}),
+        ];
+        let syntax_trimmed = vec![true, false, false, false, false, false];
+        let logprobs = vec![Some(-0.1), Some(-0.2), Some(-0.5), Some(-0.3), None, None];
+        let ranked = rank_completion_choices(choices, after_cursor_line, &syntax_trimmed, &logprobs);
+        let texts = ranked.iter().map(|x| x["code_completion"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(texts, vec!["y = x + 1", "y = x * 2", "y = x + 1\n    return y", "y = g(x"]);
+        assert_eq!(ranked[1]["index"], 1);
+    }
+
+    #[test]
+    fn test_duplicates_following_lines() {
+        assert!(duplicates_following_lines("foo()\n    return y\n", "    return y\n"));
+        assert!(!duplicates_following_lines("foo()", "    return y\n"));
+        assert!(!duplicates_following_lines("foo()\n    bar()\n", "    return y\n"));
+    }
+}
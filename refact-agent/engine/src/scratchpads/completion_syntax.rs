@@ -0,0 +1,160 @@
+use ropey::Rope;
+use tree_sitter::{InputEdit, Node, Parser, Point, Tree};
+
+use crate::ast::treesitter::language_id::LanguageId;
+use crate::ast::treesitter::parsers::{count_syntax_errors_in_tree, get_tree_sitter_language};
+use crate::call_validation::CursorPosition;
+
+const MAX_TRIM_ATTEMPTS: usize = 12;
+
+
+/// Text before and after the cursor, the cursor character is clamped to its line
+pub fn split_at_cursor(source: &str, cursor: &CursorPosition) -> (String, String) {
+    let rope = Rope::from_str(source);
+    let line = (cursor.line.max(0) as usize).min(rope.len_lines().saturating_sub(1));
+    let line_start = rope.line_to_char(line);
+    let line_len = rope.line(line).to_string().trim_end_matches(&['\r', '\n'][..]).chars().count();
+    let cursor_char = line_start + (cursor.character.max(0) as usize).min(line_len);
+    (rope.slice(..cursor_char).to_string(), rope.slice(cursor_char..).to_string())
+}
+
+fn end_point(start: Point, text: &str) -> Point {
+    match text.rfind('\n') {
+        Some(last_nl) => Point { row: start.row + text.matches('\n').count(), column: text.len() - last_nl - 1 },
+        None => Point { row: start.row, column: start.column + text.len() },
+    }
+}
+
+// Parsing the file with a completion inserted reuses the tree of the file without it
+struct InsertionParser {
+    parser: Parser,
+    base_tree: Tree,
+    before: String,
+    after: String,
+    insert_point: Point,
+}
+
+impl InsertionParser {
+    fn new(language_id: LanguageId, before: &str, after: &str) -> Option<Self> {
+        let mut parser = Parser::new();
+        parser.set_language(&get_tree_sitter_language(language_id)?).ok()?;
+        let base_tree = parser.parse(format!("{before}{after}"), None)?;
+        Some(InsertionParser {
+            parser,
+            base_tree,
+            before: before.to_string(),
+            after: after.to_string(),
+            insert_point: end_point(Point { row: 0, column: 0 }, before),
+        })
+    }
+
+    fn parse_with(&mut self, inserted: &str) -> Option<Tree> {
+        let mut tree = self.base_tree.clone();
+        tree.edit(&InputEdit {
+            start_byte: self.before.len(),
+            old_end_byte: self.before.len(),
+            new_end_byte: self.before.len() + inserted.len(),
+            start_position: self.insert_point,
+            old_end_position: self.insert_point,
+            new_end_position: end_point(self.insert_point, inserted),
+        });
+        self.parser.parse(format!("{}{inserted}{}", self.before, self.after), Some(&tree))
+    }
+}
+
+// Ends of the nodes without errors within the inserted text, relative to its start
+fn complete_node_ends(node: Node, start: usize, end: usize, result: &mut Vec<usize>) {
+    if node.end_byte() <= start || node.start_byte() >= end {
+        return;
+    }
+    if !node.has_error() && node.end_byte() < end {
+        result.push(node.end_byte() - start);
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        complete_node_ends(child, start, end, result);
+    }
+}
+
+// The count of errors alone is not enough: the cursor is typically in an incomplete statement, so the file
+// without the completion already has an error right there, and a bad completion just moves it
+fn has_error_within(node: Node, start: usize, end: usize) -> bool {
+    if !node.has_error() || node.end_byte() < start || node.start_byte() > end {
+        return false;
+    }
+    if node.is_missing() || (node.is_error() && node.start_byte() < end && node.end_byte() > start) {
+        return true;
+    }
+    let mut cursor = node.walk();
+    let found = node.children(&mut cursor).any(|child| has_error_within(child, start, end));
+    found
+}
+
+fn adds_syntax_errors(tree: &Tree, base_errors: usize, start: usize, end: usize) -> bool {
+    count_syntax_errors_in_tree(tree) > base_errors || has_error_within(tree.root_node(), start, end)
+}
+
+/// The completion as is if it doesn't add syntax errors to the file, otherwise cut back to the end of the
+/// last complete syntax node that fixes it. None if nothing helps, files in other languages pass through.
+pub fn syntax_trim_completion(language_id: Option<LanguageId>, before: &str, after: &str, completion: &str) -> Option<String> {
+    let Some(mut iparser) = language_id.and_then(|lang| InsertionParser::new(lang, before, after)) else {
+        return Some(completion.to_string());
+    };
+    let base_errors = count_syntax_errors_in_tree(&iparser.base_tree);
+    let Some(tree) = iparser.parse_with(completion) else {
+        return Some(completion.to_string());
+    };
+    let start = before.len();
+    if !adds_syntax_errors(&tree, base_errors, start, start + completion.len()) {
+        return Some(completion.to_string());
+    }
+
+    let mut cuts = vec![];
+    complete_node_ends(tree.root_node(), start, start + completion.len(), &mut cuts);
+    cuts.sort_unstable_by(|a, b| b.cmp(a));
+    cuts.dedup();
+    for cut in cuts.into_iter().take(MAX_TRIM_ATTEMPTS) {
+        let trimmed = &completion[..cut];
+        if trimmed.trim().is_empty() {
+            break;
+        }
+        if iparser.parse_with(trimmed).map_or(false, |t| !adds_syntax_errors(&t, base_errors, start, start + cut)) {
+            return Some(trimmed.to_string());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syntax_trim_completion() {
+        let py = Some(LanguageId::Python);
+        let before = "def f(x):\n    y = ";
+        let after = "\n    return y\n";
+        assert_eq!(syntax_trim_completion(py, before, after, "x + 1"), Some("x + 1".to_string()));
+        // the model closed the bracket it didn't open
+        assert_eq!(syntax_trim_completion(py, before, after, "g(x) + 1)"), Some("g(x) + 1".to_string()));
+        assert_eq!(syntax_trim_completion(py, before, after, "(((("), None);
+        // no grammar, no checks
+        assert_eq!(syntax_trim_completion(None, before, after, "(((("), Some("((((".to_string()));
+
+        let rs = Some(LanguageId::Rust);
+        let before = "fn main() {\n    let v = vec![1, 2];\n    ";
+        let after = "\n}\n";
+        assert_eq!(syntax_trim_completion(rs, before, after, "for x in v {\n        println!(\"{x}\");\n    }"),
+            Some("for x in v {\n        println!(\"{x}\");\n    }".to_string()));
+        assert_eq!(syntax_trim_completion(rs, before, after, "let n = v.len();\n    if n > 1 {"),
+            Some("let n = v.len();".to_string()));
+    }
+
+    #[test]
+    fn test_split_at_cursor() {
+        let cursor = CursorPosition { file: "a.py".to_string(), line: 1, character: 2 };
+        assert_eq!(split_at_cursor("ab\ncdef\n", &cursor), ("ab\ncd".to_string(), "ef\n".to_string()));
+        let cursor = CursorPosition { file: "a.py".to_string(), line: 0, character: 100 };
+        assert_eq!(split_at_cursor("ab\ncd", &cursor), ("ab".to_string(), "\ncd".to_string()));
+    }
+}
@@ -5,8 +5,9 @@ use crate::custom_error::trace_and_default;
 use crate::global_context::GlobalContext;
 use crate::postprocessing::pp_context_files::postprocess_context_files;
 use crate::scratchpad_abstract::HasTokenizerAndEot;
+use crate::vecdb::vdb_structs::VecdbSearch;
 use serde_json::{json, Value};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Instant;
@@ -18,6 +19,42 @@ use tracing::info;
 const DEBUG: bool = false;
 
 const TAKE_USAGES_AROUND_CURSOR: usize = 20;
+const VECDB_QUERY_LINES: usize = 15;
+const VECDB_TOP_N: usize = 10;
+const VECDB_CACHE_TTL: f64 = 300.0;
+const VECDB_CACHE_MAX_ENTRIES: usize = 100;
+
+#[derive(Default)]
+pub struct CompletionVecdbCache {
+    store: HashMap<String, (Vec<ContextFile>, f64)>,
+}
+
+impl CompletionVecdbCache {
+    fn get(&self, query: &String, now: f64) -> Option<Vec<ContextFile>> {
+        self.store.get(query).filter(|(_, ts)| now - *ts <= VECDB_CACHE_TTL).map(|(files, _)| files.clone())
+    }
+
+    fn insert(&mut self, query: String, files: Vec<ContextFile>, now: f64) {
+        self.store.retain(|_, (_, ts)| now - *ts <= VECDB_CACHE_TTL);
+        if self.store.len() >= VECDB_CACHE_MAX_ENTRIES {
+            if let Some(oldest) = self.store.iter().min_by(|a, b| a.1.1.total_cmp(&b.1.1)).map(|(k, _)| k.clone()) {
+                self.store.remove(&oldest);
+            }
+        }
+        self.store.insert(query, (files, now));
+    }
+}
+
+/// Complete lines above the cursor: typing on the cursor line leaves the query the same, so it hits the cache
+pub fn vecdb_query_near_cursor(source: &str, cursor_line: i32) -> String {
+    let cursor_line = cursor_line.max(0) as usize;
+    let lines: Vec<&str> = source.lines().take(cursor_line).collect();
+    lines[lines.len().saturating_sub(VECDB_QUERY_LINES)..].iter()
+        .map(|line| line.trim_end())
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
 async fn _render_context_files(
     gcx: Arc<ARwLock<GlobalContext>>,
@@ -154,7 +191,59 @@ async fn _cursor_position_to_context_file(
     output
 }
 
-pub async fn retrieve_ast_based_extra_context(
+async fn _vecdb_query_to_context_files(
+    gcx: Arc<ARwLock<GlobalContext>>,
+    query: &String,
+    context_used: &mut Value,
+) -> Vec<ContextFile> {
+    if query.trim().is_empty() {
+        return vec![];
+    }
+    let cache = gcx.read().await.completion_vecdb_cache.clone();
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs_f64();
+    let output = match cache.lock().await.get(query, now) {
+        Some(files) => {
+            context_used["vecdb_cached"] = Value::from(true);
+            files
+        }
+        None => {
+            let vec_db = gcx.read().await.vec_db.clone();
+            let search_result = match *vec_db.lock().await {
+                Some(ref db) => db.vecdb_search(query.clone(), VECDB_TOP_N, None).await,
+                None => return vec![],
+            };
+            let files: Vec<ContextFile> = match search_result {
+                Ok(search_result) => search_result.results.iter().map(|r| ContextFile {
+                    file_name: r.file_path.to_string_lossy().to_string(),
+                    file_content: "".to_string(),
+                    line1: r.start_line as usize + 1,
+                    line2: r.end_line as usize + 1,
+                    symbols: vec![],
+                    gradient_type: 4,
+                    usefulness: r.usefulness,
+                }).collect(),
+                Err(e) => {
+                    // not cached, the vectorizer might be just starting up
+                    tracing::warn!("vecdb search for completion failed: {}", e);
+                    return vec![];
+                }
+            };
+            cache.lock().await.insert(query.clone(), files.clone(), now);
+            files
+        }
+    };
+    context_used["bucket_vecdb"] = Value::Array(output.iter().map(|x| json!({
+        "file_path": x.file_name,
+        "line1": x.line1,
+        "line2": x.line2,
+        "usefulness": x.usefulness,
+    })).collect());
+    output
+}
+
+/// Context from AST usages around the cursor, and from VecDB if `vecdb_query` is given, `rag_tokens_n` is split
+/// between the two using `t.vecdb_ratio`
+pub async fn retrieve_extra_context(
     gcx: Arc<ARwLock<GlobalContext>>,
     ast_service: Option<Arc<AMutex<AstIndexService>>>,
     t: &HasTokenizerAndEot,
@@ -163,9 +252,10 @@ pub async fn retrieve_ast_based_extra_context(
     subblock_to_ignore_range: (i32, i32),
     pp_settings: PostprocessSettings,
     rag_tokens_n: usize,
+    vecdb_query: Option<String>,
     context_used: &mut Value,
 ) -> String {
-    info!(" -- ast-based rag search starts --");
+    info!(" -- rag search starts --");
     let mut pp_settings = pp_settings;
     if pp_settings.max_files_n == 0 {
         pp_settings.max_files_n = 5;
@@ -185,6 +275,13 @@ pub async fn retrieve_ast_based_extra_context(
         vec![]
     };
 
+    let mut vecdb_context_file_vec: Vec<ContextFile> = match &vecdb_query {
+        Some(query) => _vecdb_query_to_context_files(gcx.clone(), query, context_used).await,
+        None => vec![],
+    };
+
+    let ast_has_files = !ast_context_file_vec.is_empty();
+
     let to_buckets_ms = rag_t0.elapsed().as_millis() as i32;
     if subblock_to_ignore_range.0 != i32::MAX && subblock_to_ignore_range.1 != i32::MIN {
         // disable (usefulness==-1) the FIM region around the cursor from getting into the results
@@ -197,20 +294,44 @@ pub async fn retrieve_ast_based_extra_context(
             gradient_type: 4,
             usefulness: -1.0,
         };
+        if !vecdb_context_file_vec.is_empty() {
+            vecdb_context_file_vec.push(fim_ban.clone());
+        }
         ast_context_file_vec.push(fim_ban);
     }
 
+    let vecdb_tokens_n = match (ast_has_files, vecdb_context_file_vec.is_empty()) {
+        (_, true) => 0,
+        (false, false) => rag_tokens_n,
+        (true, false) => ((rag_tokens_n as f64 * t.vecdb_ratio) as usize).min(rag_tokens_n),
+    };
+
     info!(" -- post processing starts --");
     let post_t0 = Instant::now();
-    let postprocessed_messages = postprocess_context_files(
-        gcx.clone(),
-        &mut ast_context_file_vec,
-        t.tokenizer.clone(),
-        rag_tokens_n,
-        false,
-        &pp_settings,
-    )
-    .await;
+    let mut postprocessed_messages = if ast_has_files {
+        postprocess_context_files(
+            gcx.clone(),
+            &mut ast_context_file_vec,
+            t.tokenizer.clone(),
+            rag_tokens_n - vecdb_tokens_n,
+            false,
+            &pp_settings,
+        )
+        .await
+    } else {
+        vec![]
+    };
+    if vecdb_tokens_n > 0 {
+        postprocessed_messages.extend(postprocess_context_files(
+            gcx.clone(),
+            &mut vecdb_context_file_vec,
+            t.tokenizer.clone(),
+            vecdb_tokens_n,
+            false,
+            &pp_settings,
+        )
+        .await);
+    }
     let rag_ms = rag_t0.elapsed().as_millis() as i32;
     let post_ms = post_t0.elapsed().as_millis() as i32;
     info!(
@@ -248,3 +369,34 @@ pub async fn retrieve_ast_based_extra_context(
 //     // context["bucket_usage_of_same_stuff"] = Value::Array(search_traces.bucket_usage_of_same_stuff.iter()
 //     // context["bucket_high_overlap"] = Value::Array(search_traces.bucket_high_overlap.iter()
 //     // context["bucket_imports"] = Value::Array(search_traces.bucket_imports.iter()
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vecdb_query_near_cursor() {
+        let source = (0..30).map(|i| format!("line{i}  ")).collect::<Vec<_>>().join("\n");
+        let query = vecdb_query_near_cursor(&source, 20);
+        assert_eq!(query.lines().count(), VECDB_QUERY_LINES);
+        assert!(query.starts_with("line5\n") && query.ends_with("line19"));
+        // the cursor line itself is not in the query, so typing there hits the cache
+        let mut typed = source.lines().map(|x| x.to_string()).collect::<Vec<_>>();
+        typed[20].push_str("goat");
+        assert_eq!(vecdb_query_near_cursor(&typed.join("\n"), 20), query);
+        assert_eq!(vecdb_query_near_cursor(&source, 0), "");
+    }
+
+    #[test]
+    fn test_completion_vecdb_cache() {
+        let mut cache = CompletionVecdbCache::default();
+        cache.insert("q".to_string(), vec![], 1000.0);
+        assert!(cache.get(&"q".to_string(), 1000.0 + VECDB_CACHE_TTL).is_some());
+        assert!(cache.get(&"q".to_string(), 1001.0 + VECDB_CACHE_TTL).is_none());
+        for i in 0..VECDB_CACHE_MAX_ENTRIES + 5 {
+            cache.insert(format!("q{i}"), vec![], 2000.0 + i as f64);
+        }
+        assert_eq!(cache.store.len(), VECDB_CACHE_MAX_ENTRIES);
+        assert!(cache.get(&"q0".to_string(), 2200.0).is_none());
+    }
+}
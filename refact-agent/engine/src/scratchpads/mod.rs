@@ -13,8 +13,10 @@ pub mod scratchpad_utils;
 pub mod code_completion_replace;
 pub mod multimodality;
 mod comments_parser;
+mod completion_ranking;
+mod completion_syntax;
 pub mod passthrough_convert_messages;
-mod completon_rag;
+pub mod completon_rag;
 
 use crate::ast::ast_indexer_thread::AstIndexService;
 use crate::call_validation::{ChatMessage, CodeCompletionPost};
@@ -42,6 +44,9 @@ pub async fn create_code_completion_scratchpad(
 ) -> Result<Box<dyn ScratchpadAbstract>, String> {
     let mut result: Box<dyn ScratchpadAbstract>;
     let tokenizer_arc = crate::tokens::cached_tokenizer(global_context.clone(), &model_rec.base).await?;
+    if post.next_edit && !model_rec.scratchpad.starts_with("REPLACE") {
+        return Err(format!("next edit prediction needs a REPLACE scratchpad, model has \"{}\"", model_rec.scratchpad));
+    }
     if model_rec.scratchpad == "FIM-PSM" {
         result = Box::new(code_completion_fim::FillInTheMiddleScratchpad::new(
             tokenizer_arc, &post, "PSM".to_string(), cache_arc, tele_storage, ast_module, global_context.clone()
@@ -1,7 +1,8 @@
 use itertools::Itertools;
-use serde_json::Value;
+use serde_json::{json, Value};
 use tracing::{error, warn};
 use crate::call_validation::{ChatContent, ChatMessage, ContextFile, DiffChunk};
+use crate::caps::BaseModelRecord;
 
 
 pub fn convert_messages_to_openai_format(messages: Vec<ChatMessage>, style: &Option<String>, model_id: &str) -> Vec<Value> {
@@ -105,14 +106,254 @@ pub fn convert_messages_to_openai_format(messages: Vec<ChatMessage>, style: &Opt
     results
 }
 
+fn anthropic_content_blocks(content: &ChatContent) -> Vec<Value> {
+    match content {
+        ChatContent::SimpleText(text) if text.is_empty() => vec![],
+        ChatContent::SimpleText(text) => vec![json!({"type": "text", "text": text})],
+        ChatContent::Multimodal(elements) => elements.iter().map(|el| if el.is_image() {
+            json!({"type": "image", "source": {"type": "base64", "media_type": el.m_type, "data": el.m_content}})
+        } else {
+            json!({"type": "text", "text": el.m_content})
+        }).collect(),
+    }
+}
+
+fn anthropic_assistant_blocks(msg: &ChatMessage) -> Vec<Value> {
+    let mut blocks = vec![];
+    // thinking goes back unchanged, the signature is what lets the model continue its reasoning after tool calls,
+    // blocks are rebuilt from the fields the API accepts, the saved ones carry the stream index
+    for block in msg.thinking_blocks.iter().flatten() {
+        match block.get("type").and_then(|x| x.as_str()).unwrap_or("thinking") {
+            "redacted_thinking" => if let Some(data) = block.get("data").filter(|x| x.as_str().map_or(false, |x| !x.is_empty())) {
+                blocks.push(json!({"type": "redacted_thinking", "data": data}));
+            },
+            "thinking" => if let Some(signature) = block.get("signature").filter(|x| x.as_str().map_or(false, |x| !x.is_empty())) {
+                blocks.push(json!({"type": "thinking", "thinking": block.get("thinking").cloned().unwrap_or(json!("")), "signature": signature}));
+            },
+            _ => {},
+        }
+    }
+    blocks.extend(anthropic_content_blocks(&msg.content));
+    for tool_call in msg.tool_calls.clone().unwrap_or_default() {
+        let input = serde_json::from_str::<Value>(&tool_call.function.arguments).ok()
+            .filter(|x| x.is_object())
+            .unwrap_or(json!({}));
+        blocks.push(json!({"type": "tool_use", "id": tool_call.id, "name": tool_call.function.name, "input": input}));
+    }
+    if blocks.is_empty() {
+        blocks.push(json!({"type": "text", "text": "_"}));
+    }
+    blocks
+}
+
+/// Messages API format: system prompt blocks separately, user and assistant turns alternate, tool results
+/// (with their images) are blocks inside of user turns. Other roles are converted like for OpenAI first.
+pub fn convert_messages_to_anthropic_format(messages: Vec<ChatMessage>, model_id: &str) -> (Vec<Value>, Vec<Value>) {
+    let mut system = vec![];
+    let mut turns: Vec<(String, Vec<Value>)> = vec![];
+    let push_blocks = |turns: &mut Vec<(String, Vec<Value>)>, role: &str, blocks: Vec<Value>| {
+        match turns.last_mut() {
+            Some((last_role, last_blocks)) if last_role == role => last_blocks.extend(blocks),
+            _ => turns.push((role.to_string(), blocks)),
+        }
+    };
+
+    for msg in messages {
+        match msg.role.as_str() {
+            "system" => system.extend(anthropic_content_blocks(&msg.content)),
+            "user" => push_blocks(&mut turns, "user", anthropic_content_blocks(&msg.content)),
+            "assistant" => push_blocks(&mut turns, "assistant", anthropic_assistant_blocks(&msg)),
+            "tool" => {
+                let mut tool_result = json!({"type": "tool_result", "tool_use_id": msg.tool_call_id});
+                let content = anthropic_content_blocks(&msg.content);
+                if !content.is_empty() {
+                    tool_result["content"] = json!(content);
+                }
+                if msg.tool_failed == Some(true) {
+                    tool_result["is_error"] = json!(true);
+                }
+                push_blocks(&mut turns, "user", vec![tool_result]);
+            },
+            _ => {
+                for converted in convert_messages_to_openai_format(vec![msg], &None, model_id) {
+                    let text = converted.get("content").and_then(|x| x.as_str()).unwrap_or_default().to_string();
+                    if converted["role"] == "tool" {
+                        let tool_use_id = converted.get("tool_call_id").cloned().unwrap_or(json!(""));
+                        push_blocks(&mut turns, "user", vec![json!({"type": "tool_result", "tool_use_id": tool_use_id, "content": text})]);
+                    } else {
+                        push_blocks(&mut turns, "user", vec![json!({"type": "text", "text": text})]);
+                    }
+                }
+            }
+        }
+    }
+
+    let messages = turns.into_iter()
+        .filter(|(_, blocks)| !blocks.is_empty())
+        .map(|(role, blocks)| json!({"role": role, "content": blocks}))
+        .collect();
+    (system, messages)
+}
+
+fn responses_content_parts(content: &ChatContent, text_type: &str) -> Vec<Value> {
+    match content {
+        ChatContent::SimpleText(text) if text.is_empty() => vec![],
+        ChatContent::SimpleText(text) => vec![json!({"type": text_type, "text": text})],
+        ChatContent::Multimodal(elements) => elements.iter().map(|el| if el.is_image() {
+            json!({"type": "input_image", "image_url": format!("data:{};base64,{}", el.m_type, el.m_content)})
+        } else {
+            json!({"type": text_type, "text": el.m_content})
+        }).collect(),
+    }
+}
+
+fn responses_message(role: &str, content: Vec<Value>) -> Value {
+    json!({"type": "message", "role": role, "content": content})
+}
+
+/// Only the fields the Responses API accepts in a reasoning input item, the saved block may carry more
+/// (the stream index, keys added by the client while merging deltas).
+fn responses_reasoning_item(block: &Value) -> Option<Value> {
+    if block.get("type").and_then(|x| x.as_str()) != Some("reasoning") {
+        return None;
+    }
+    let encrypted_content = block.get("encrypted_content").filter(|x| x.is_string())?;
+    let mut item = json!({
+        "type": "reasoning",
+        "summary": block.get("summary").filter(|x| x.is_array()).cloned().unwrap_or(json!([])),
+        "encrypted_content": encrypted_content,
+    });
+    if let Some(id) = block.get("id").filter(|x| x.is_string()) {
+        item["id"] = id.clone();
+    }
+    Some(item)
+}
+
+/// Responses API input items: reasoning items saved in thinking_blocks go back before the assistant's message
+/// and function calls, tool results are function_call_output items, their images follow as a user message.
+pub fn convert_messages_to_openai_responses_format(messages: Vec<ChatMessage>, model_id: &str) -> Vec<Value> {
+    let mut results = vec![];
+    let mut delay_images: Vec<Value> = vec![];
+
+    for msg in messages {
+        if msg.role != "tool" && msg.role != "diff" {
+            results.extend(delay_images.drain(..).map(|image| responses_message("user", vec![image])));
+        }
+        match msg.role.as_str() {
+            "system" => results.push(responses_message("developer", responses_content_parts(&msg.content, "input_text"))),
+            "user" => results.push(responses_message("user", responses_content_parts(&msg.content, "input_text"))),
+            "assistant" => {
+                results.extend(msg.thinking_blocks.iter().flatten().filter_map(responses_reasoning_item));
+                let content = responses_content_parts(&msg.content, "output_text");
+                if !content.is_empty() {
+                    results.push(responses_message("assistant", content));
+                }
+                for tool_call in msg.tool_calls.clone().unwrap_or_default() {
+                    results.push(json!({
+                        "type": "function_call",
+                        "call_id": tool_call.id,
+                        "name": tool_call.function.name,
+                        "arguments": tool_call.function.arguments,
+                    }));
+                }
+            },
+            "tool" => {
+                let mut texts = vec![];
+                for part in responses_content_parts(&msg.content, "input_text") {
+                    if part["type"] == "input_image" {
+                        delay_images.push(part);
+                    } else {
+                        texts.push(part["text"].as_str().unwrap_or_default().to_string());
+                    }
+                }
+                results.push(json!({"type": "function_call_output", "call_id": msg.tool_call_id, "output": texts.join("\n")}));
+            },
+            _ => {
+                for converted in convert_messages_to_openai_format(vec![msg], &None, model_id) {
+                    let text = converted.get("content").and_then(|x| x.as_str()).unwrap_or_default().to_string();
+                    if converted["role"] == "tool" {
+                        let call_id = converted.get("tool_call_id").cloned().unwrap_or(json!(""));
+                        results.push(json!({"type": "function_call_output", "call_id": call_id, "output": text}));
+                    } else {
+                        results.push(responses_message("user", vec![json!({"type": "input_text", "text": text})]));
+                    }
+                }
+            }
+        }
+    }
+    results.extend(delay_images.drain(..).map(|image| responses_message("user", vec![image])));
+    results
+}
+
+/// Puts the messages into the json of a PASSTHROUGH prompt in the format `endpoint_style` wants. The messages also stay
+/// as they are, so a fallback model with another endpoint_style can take the same prompt, see passthrough_prompt_for_model().
+pub fn set_passthrough_messages(big_json: &mut Value, messages: Vec<ChatMessage>, endpoint_style: &str, style: &Option<String>, model_id: &str) {
+    big_json["chat_messages"] = json!(messages);
+    big_json["endpoint_style"] = json!(endpoint_style);
+    big_json["style"] = json!(style);
+    if let Some(big_json_map) = big_json.as_object_mut() {
+        big_json_map.remove("system");
+    }
+    if endpoint_style == "anthropic" {
+        let (system, converted_messages) = convert_messages_to_anthropic_format(messages, model_id);
+        big_json["system"] = json!(system);
+        big_json["messages"] = json!(converted_messages);
+    } else if endpoint_style == "openai_responses" {
+        big_json["messages"] = json!(convert_messages_to_openai_responses_format(messages, model_id));
+    } else {
+        big_json["messages"] = json!(convert_messages_to_openai_format(messages, style, model_id));
+    }
+}
+
+/// The prompt is made for the first model, the messages are converted again if `model_rec` has another endpoint_style
+pub fn passthrough_prompt_for_model(prompt: &str, model_rec: &BaseModelRecord) -> Result<String, String> {
+    let mut big_json: Value = match prompt.strip_prefix("PASSTHROUGH ") {
+        Some(big_json_str) => serde_json::from_str(big_json_str).map_err(|e| format!("can't parse PASSTHROUGH prompt: {}", e))?,
+        None => return Ok(prompt.to_string()),
+    };
+    let made_for = big_json.get("endpoint_style").and_then(|x| x.as_str()).unwrap_or_default();
+    if made_for == model_rec.endpoint_style || big_json.get("chat_messages").is_none() {
+        return Ok(prompt.to_string());
+    }
+    let messages = serde_json::from_value::<Vec<ChatMessage>>(big_json["chat_messages"].clone())
+        .map_err(|e| format!("can't convert messages for {}: {}", model_rec.id, e))?;
+    let style = big_json.get("style").and_then(|x| x.as_str()).map(|x| x.to_string());
+    set_passthrough_messages(&mut big_json, messages, &model_rec.endpoint_style, &style, &model_rec.id);
+    Ok(format!("PASSTHROUGH {}", big_json))
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::call_validation::{ChatContent, ChatMessage};
+    use crate::call_validation::{ChatContent, ChatMessage, ChatToolCall, ChatToolFunction};
     use serde_json::json;
     use crate::scratchpads::multimodality::MultimodalElement;
 
+    /// Collects thinking_blocks of streamed chunks the way the chat UI does: deltas with the same index
+    /// are one block, thinking and signature are concatenated, other fields are taken from the latest delta
+    fn merge_streamed_thinking_blocks(chunks: &[Value]) -> Vec<Value> {
+        let mut merged: Vec<Value> = vec![];
+        for chunk in chunks {
+            for add in chunk["choices"][0]["delta"]["thinking_blocks"].as_array().cloned().unwrap_or_default() {
+                let Some(block) = merged.iter_mut().find(|x| x["index"] == add["index"]) else {
+                    merged.push(add);
+                    continue;
+                };
+                let mut combined = block.as_object().unwrap().clone();
+                combined.extend(add.as_object().unwrap().clone());
+                for key in ["thinking", "signature"] {
+                    if block.get(key).is_some() || add.get(key).is_some() {
+                        let text = format!("{}{}", block[key].as_str().unwrap_or_default(), add[key].as_str().unwrap_or_default());
+                        combined.insert(key.to_string(), json!(text));
+                    }
+                }
+                *block = Value::Object(combined);
+            }
+        }
+        merged
+    }
+
     // cargo test -- --nocapture test_convert_messages_to_openai_format
     #[test]
     fn test_convert_messages_to_openai_format() {
@@ -201,4 +442,183 @@ mod tests {
 
         assert_eq!(roles_out, roles_out_expected);
     }
+
+    #[test]
+    fn test_passthrough_prompt_for_model() {
+        let messages = vec![
+            ChatMessage::new("system".to_string(), "be brief".to_string()),
+            ChatMessage::new("user".to_string(), "hi".to_string()),
+        ];
+        let mut big_json = json!({"tools": null});
+        set_passthrough_messages(&mut big_json, messages, "anthropic", &None, "anthropic/claude-3-7-sonnet");
+        assert_eq!(big_json["system"], json!([{"type": "text", "text": "be brief"}]));
+        let prompt = format!("PASSTHROUGH {}", big_json);
+
+        let claude = BaseModelRecord { id: "anthropic/claude-3-5-haiku".to_string(), endpoint_style: "anthropic".to_string(), ..Default::default() };
+        assert_eq!(passthrough_prompt_for_model(&prompt, &claude).unwrap(), prompt);
+
+        let gpt = BaseModelRecord { id: "openai/gpt-4o".to_string(), endpoint_style: "openai".to_string(), ..Default::default() };
+        let converted: Value = serde_json::from_str(&passthrough_prompt_for_model(&prompt, &gpt).unwrap()[12..]).unwrap();
+        assert!(converted.get("system").is_none());
+        assert_eq!(converted["endpoint_style"], "openai");
+        assert_eq!(converted["messages"], json!([{"role": "system", "content": "be brief"}, {"role": "user", "content": "hi"}]));
+
+        assert_eq!(passthrough_prompt_for_model("def f():", &gpt).unwrap(), "def f():");
+    }
+
+    #[test]
+    fn test_convert_messages_to_anthropic_format() {
+        let tool_call = ChatToolCall {
+            id: "toolu_1".to_string(),
+            function: ChatToolFunction { name: "cat".to_string(), arguments: "{\"paths\": \"a.py\"}".to_string() },
+            tool_type: "function".to_string(),
+        };
+        let messages = vec![
+            ChatMessage::new("system".to_string(), "system".to_string()),
+            ChatMessage::new("user".to_string(), "user".to_string()),
+            ChatMessage {
+                role: "assistant".to_string(),
+                tool_calls: Some(vec![tool_call]),
+                thinking_blocks: Some(vec![json!({"type": "thinking", "thinking": "hmm", "signature": "sig"})]),
+                ..Default::default()
+            },
+            ChatMessage {
+                role: "tool".to_string(),
+                tool_call_id: "toolu_1".to_string(),
+                content: ChatContent::Multimodal(vec![
+                    MultimodalElement::new("text".to_string(), "text".to_string()).unwrap(),
+                    MultimodalElement::new("image/png".to_string(), "image/png".to_string()).unwrap(),
+                ]),
+                ..Default::default()
+            },
+            ChatMessage::new("plain_text".to_string(), "plain_text".to_string()),
+        ];
+
+        let (system, output) = convert_messages_to_anthropic_format(messages, "claude-3-7-sonnet");
+        assert_eq!(system, vec![json!({"type": "text", "text": "system"})]);
+        let roles = output.iter().map(|x| x["role"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(roles, vec!["user", "assistant", "user"]);
+        let assistant_types = output[1]["content"].as_array().unwrap().iter().map(|x| x["type"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(assistant_types, vec!["thinking", "tool_use"]);
+        assert_eq!(output[1]["content"][1]["input"]["paths"], "a.py");
+        // the tool result keeps its image, the plain text follows in the same user turn
+        assert_eq!(output[2]["content"][0]["type"], "tool_result");
+        assert_eq!(output[2]["content"][0]["content"][1]["type"], "image");
+        assert_eq!(output[2]["content"][1]["text"], "plain_text");
+    }
+
+    #[test]
+    fn test_convert_messages_to_openai_responses_format() {
+        let tool_call = ChatToolCall {
+            id: "call_1".to_string(),
+            function: ChatToolFunction { name: "cat".to_string(), arguments: "{}".to_string() },
+            tool_type: "function".to_string(),
+        };
+        let messages = vec![
+            ChatMessage::new("system".to_string(), "system".to_string()),
+            ChatMessage::new("user".to_string(), "user".to_string()),
+            ChatMessage {
+                role: "assistant".to_string(),
+                tool_calls: Some(vec![tool_call]),
+                thinking_blocks: Some(vec![
+                    json!({"type": "reasoning", "id": "rs_1", "encrypted_content": "xxx", "summary": []}),
+                    json!({"type": "thinking", "thinking": "from another model", "signature": "sig"}),
+                ]),
+                ..Default::default()
+            },
+            ChatMessage {
+                role: "tool".to_string(),
+                tool_call_id: "call_1".to_string(),
+                content: ChatContent::Multimodal(vec![
+                    MultimodalElement::new("text".to_string(), "text".to_string()).unwrap(),
+                    MultimodalElement::new("image/png".to_string(), "image/png".to_string()).unwrap(),
+                ]),
+                ..Default::default()
+            },
+            ChatMessage::new("assistant".to_string(), "assistant".to_string()),
+        ];
+
+        let output = convert_messages_to_openai_responses_format(messages, "gpt-5");
+        let types = output.iter().map(|x| x.get("role").unwrap_or(&x["type"]).as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(types, vec!["developer", "user", "reasoning", "function_call", "function_call_output", "user", "assistant"]);
+        assert_eq!(output[3]["call_id"], "call_1");
+        assert_eq!(output[4]["output"], "text");
+        assert_eq!(output[5]["content"][0]["type"], "input_image");
+        assert_eq!(output[6]["content"][0]["type"], "output_text");
+    }
+
+    #[test]
+    fn test_anthropic_thinking_streamed_and_sent_back() {
+        use crate::forward_to_anthropic_endpoint::AnthropicStreamState;
+        let events = vec![
+            json!({"type": "message_start", "message": {"model": "claude", "usage": {"input_tokens": 10, "output_tokens": 1}}}),
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "thinking", "thinking": ""}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "thinking_delta", "thinking": "first "}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "thinking_delta", "thinking": "thought"}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "signature_delta", "signature": "sig1"}}),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "content_block_start", "index": 1, "content_block": {"type": "redacted_thinking", "data": "opaque"}}),
+            json!({"type": "content_block_stop", "index": 1}),
+            json!({"type": "content_block_start", "index": 2, "content_block": {"type": "thinking", "thinking": ""}}),
+            json!({"type": "content_block_delta", "index": 2, "delta": {"type": "thinking_delta", "thinking": "second"}}),
+            json!({"type": "content_block_delta", "index": 2, "delta": {"type": "signature_delta", "signature": "sig2"}}),
+            json!({"type": "content_block_stop", "index": 2}),
+            json!({"type": "content_block_start", "index": 3, "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_delta", "index": 3, "delta": {"type": "text_delta", "text": "done"}}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "end_turn"}, "usage": {"output_tokens": 20}}),
+        ];
+        let mut state = AnthropicStreamState::default();
+        let chunks: Vec<Value> = events.iter().filter_map(|x| state.event_to_openai_chunk(x)).collect();
+        let thinking_blocks = merge_streamed_thinking_blocks(&chunks);
+        assert_eq!(thinking_blocks.len(), 3);
+
+        let messages = vec![
+            ChatMessage::new("user".to_string(), "user".to_string()),
+            ChatMessage {
+                role: "assistant".to_string(),
+                content: ChatContent::SimpleText("done".to_string()),
+                thinking_blocks: Some(thinking_blocks),
+                ..Default::default()
+            },
+        ];
+        let (_system, output) = convert_messages_to_anthropic_format(messages, "claude");
+        assert_eq!(output[1]["content"], json!([
+            {"type": "thinking", "thinking": "first thought", "signature": "sig1"},
+            {"type": "redacted_thinking", "data": "opaque"},
+            {"type": "thinking", "thinking": "second", "signature": "sig2"},
+            {"type": "text", "text": "done"},
+        ]));
+    }
+
+    #[test]
+    fn test_responses_reasoning_streamed_and_sent_back() {
+        use crate::forward_to_openai_responses_endpoint::ResponsesStreamState;
+        let events = vec![
+            json!({"type": "response.output_item.added", "output_index": 0, "item": {"type": "reasoning", "id": "rs_1"}}),
+            json!({"type": "response.output_item.done", "output_index": 0, "item": {"type": "reasoning", "id": "rs_1", "summary": [{"type": "summary_text", "text": "one"}], "encrypted_content": "aaa"}}),
+            json!({"type": "response.output_item.added", "output_index": 1, "item": {"type": "reasoning", "id": "rs_2"}}),
+            json!({"type": "response.output_item.done", "output_index": 1, "item": {"type": "reasoning", "id": "rs_2", "summary": [], "encrypted_content": "bbb"}}),
+        ];
+        let mut state = ResponsesStreamState::default();
+        let chunks: Vec<Value> = events.iter().filter_map(|x| state.event_to_openai_chunk(x)).collect();
+        let mut thinking_blocks = merge_streamed_thinking_blocks(&chunks);
+        assert_eq!(thinking_blocks.len(), 2);
+        // keys a client may add while merging don't go back
+        thinking_blocks[0]["thinking"] = json!("");
+        thinking_blocks[0]["signature"] = json!("");
+
+        let messages = vec![
+            ChatMessage::new("user".to_string(), "user".to_string()),
+            ChatMessage {
+                role: "assistant".to_string(),
+                content: ChatContent::SimpleText("done".to_string()),
+                thinking_blocks: Some(thinking_blocks),
+                ..Default::default()
+            },
+        ];
+        let output = convert_messages_to_openai_responses_format(messages, "gpt-5");
+        assert_eq!(output[1], json!({"type": "reasoning", "id": "rs_1", "summary": [{"type": "summary_text", "text": "one"}], "encrypted_content": "aaa"}));
+        assert_eq!(output[2], json!({"type": "reasoning", "id": "rs_2", "summary": [], "encrypted_content": "bbb"}));
+        assert_eq!(output[3]["role"], "assistant");
+    }
 }
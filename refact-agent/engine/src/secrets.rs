@@ -0,0 +1,215 @@
+use std::collections::{HashMap, HashSet};
+use std::process::Stdio;
+use std::sync::{Mutex as StdMutex, OnceLock, RwLock as StdRwLock};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+// Where a secret (api key, password) can come from:
+//   literal            the value itself
+//   $VAR               environment variable
+//   !cmd pass show x   stdout of a shell command
+//   !file ~/.x/key     content of a file
+//   !keyring svc [acc] Secret Service on Linux (secret-tool), Keychain on macOS
+// Resolved values are cached for SECRETS_CACHE_TTL and redacted from logs, see redact_secrets().
+
+const SECRETS_CACHE_TTL: Duration = Duration::from_secs(300);
+const SECRET_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+const SECRET_MIN_LEN_TO_REDACT: usize = 6;
+const SECRET_TAGS: &[&str] = &["cmd", "file", "keyring"];
+
+static SECRETS_CACHE: OnceLock<StdMutex<HashMap<String, (Instant, String)>>> = OnceLock::new();
+static KNOWN_SECRETS: OnceLock<StdRwLock<HashSet<String>>> = OnceLock::new();
+
+
+/// `!cmd`, `!file` or `!keyring`, things that `$VAR` can't express
+pub fn is_tagged_secret(value: &str) -> bool {
+    SECRET_TAGS.iter().any(|tag| value.starts_with(&format!("!{} ", tag)))
+}
+
+pub fn is_secret_reference(value: &str) -> bool {
+    value.starts_with('$') || is_tagged_secret(value)
+}
+
+/// Literal values are returned as they are, references are resolved (cached) and remembered for redaction
+pub async fn resolve_secret(value: &str) -> Result<String, String> {
+    if !is_secret_reference(value) {
+        return Ok(value.to_string());
+    }
+    let cache = SECRETS_CACHE.get_or_init(|| StdMutex::new(HashMap::new()));
+    if let Some((ts, secret)) = cache.lock().unwrap().get(value) {
+        if ts.elapsed() < SECRETS_CACHE_TTL {
+            return Ok(secret.clone());
+        }
+    }
+    let secret = resolve_secret_uncached(value).await?;
+    register_secret(&secret);
+    cache.lock().unwrap().insert(value.to_string(), (Instant::now(), secret.clone()));
+    Ok(secret)
+}
+
+async fn resolve_secret_uncached(value: &str) -> Result<String, String> {
+    if let Some(var) = value.strip_prefix('$') {
+        return std::env::var(var).map_err(|e| format!("env var {}: {}", value, e));
+    }
+    let (tag, arg) = value[1..].split_once(' ').unwrap_or((&value[1..], ""));
+    let arg = arg.trim();
+    if arg.is_empty() {
+        return Err(format!("!{} needs an argument", tag));
+    }
+    let secret = match tag {
+        "cmd" => run_secret_command(shell_command(arg)).await?,
+        "file" => {
+            let path = crate::files_correction::canonical_path(expand_home(arg));
+            tokio::fs::read_to_string(&path).await.map_err(|e| format!("cannot read {}: {}", path.display(), e))?
+        },
+        "keyring" => run_secret_command(keyring_command(arg)?).await?,
+        _ => return Err(format!("unknown secret source !{}", tag)),
+    };
+    let secret = secret.trim().to_string();
+    if secret.is_empty() {
+        return Err(format!("{} is empty", value));
+    }
+    Ok(secret)
+}
+
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), home::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest).to_string_lossy().to_string(),
+        _ => path.to_string(),
+    }
+}
+
+fn shell_command(cmd: &str) -> Command {
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    command.arg(cmd);
+    command
+}
+
+/// `!keyring <service> [<account>]`
+fn keyring_command(arg: &str) -> Result<Command, String> {
+    let words = shell_words::split(arg).map_err(|e| format!("cannot parse !keyring {}: {}", arg, e))?;
+    let (service, account) = match words.as_slice() {
+        [service] => (service, None),
+        [service, account] => (service, Some(account)),
+        _ => return Err(format!("!keyring takes a service and an optional account, got {:?}", arg)),
+    };
+    if cfg!(target_os = "macos") {
+        let mut command = Command::new("security");
+        command.args(["find-generic-password", "-w", "-s", service]);
+        if let Some(account) = account {
+            command.args(["-a", account]);
+        }
+        Ok(command)
+    } else if cfg!(target_os = "linux") {
+        let mut command = Command::new("secret-tool");
+        command.args(["lookup", "service", service]);
+        if let Some(account) = account {
+            command.args(["account", account]);
+        }
+        Ok(command)
+    } else {
+        Err("!keyring is supported on Linux and macOS only".to_string())
+    }
+}
+
+async fn run_secret_command(mut command: Command) -> Result<String, String> {
+    let program = command.as_std().get_program().to_string_lossy().to_string();
+    let child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("cannot run {}: {}", program, e))?;
+    // on timeout the future is dropped together with the child, that kills it
+    let output = tokio::time::timeout(SECRET_COMMAND_TIMEOUT, child.wait_with_output()).await
+        .map_err(|_| format!("{} timed out after {:?}", program, SECRET_COMMAND_TIMEOUT))?
+        .map_err(|e| format!("{}: {}", program, e))?;
+    if !output.status.success() {
+        // stdout is not in the error, it might be the secret
+        return Err(format!("{} failed with {}: {}", program, output.status, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    String::from_utf8(output.stdout).map_err(|e| format!("{} printed not utf-8: {}", program, e))
+}
+
+pub fn register_secret(secret: &str) {
+    if secret.len() < SECRET_MIN_LEN_TO_REDACT {
+        return;
+    }
+    let known = KNOWN_SECRETS.get_or_init(|| StdRwLock::new(HashSet::new()));
+    known.write().unwrap().insert(secret.to_string());
+}
+
+/// Every resolved secret that appears in `text` becomes "***"
+pub fn redact_secrets(text: &str) -> String {
+    let known = match KNOWN_SECRETS.get() {
+        Some(known) => known.read().unwrap(),
+        None => return text.to_string(),
+    };
+    known.iter().fold(text.to_string(), |acc, secret| {
+        if acc.contains(secret.as_str()) { acc.replace(secret.as_str(), "***") } else { acc }
+    })
+}
+
+/// `api_key: !cmd pass show openai` without quotes is a yaml tag, it becomes the string "!cmd pass show openai"
+pub fn untag_secrets_in_yaml(value: &mut serde_yaml::Value) {
+    match value {
+        serde_yaml::Value::Tagged(tagged) => {
+            let tag = tagged.tag.to_string();
+            match (&tagged.value, SECRET_TAGS.contains(&tag.trim_start_matches('!'))) {
+                (serde_yaml::Value::String(arg), true) => *value = serde_yaml::Value::String(format!("{} {}", tag, arg)),
+                _ => untag_secrets_in_yaml(&mut tagged.value),
+            }
+        },
+        serde_yaml::Value::Mapping(map) => map.iter_mut().for_each(|(_, v)| untag_secrets_in_yaml(v)),
+        serde_yaml::Value::Sequence(seq) => seq.iter_mut().for_each(untag_secrets_in_yaml),
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resolve_secret() {
+        assert_eq!(resolve_secret("sk-literal").await.unwrap(), "sk-literal");
+        std::env::set_var("TEST_REFACT_SECRET_ENV", "from-env-123");
+        assert_eq!(resolve_secret("$TEST_REFACT_SECRET_ENV").await.unwrap(), "from-env-123");
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), "from-file-456\n").unwrap();
+        assert_eq!(resolve_secret(&format!("!file {}", file.path().display())).await.unwrap(), "from-file-456");
+
+        if cfg!(unix) {
+            assert_eq!(resolve_secret("!cmd echo from-cmd-789").await.unwrap(), "from-cmd-789");
+            let err = resolve_secret("!cmd echo leaked-secret; exit 3").await.unwrap_err();
+            assert!(!err.contains("leaked-secret"));
+        }
+        assert!(resolve_secret("!file /nonexistent/refact/key").await.is_err());
+        assert!(resolve_secret("!keyring a b c").await.is_err());
+
+        // cached, the file can go away
+        let path = file.path().display().to_string();
+        drop(file);
+        assert_eq!(resolve_secret(&format!("!file {}", path)).await.unwrap(), "from-file-456");
+
+        assert_eq!(redact_secrets("key=from-env-123, sk-literal"), "key=***, sk-literal");
+    }
+
+    #[test]
+    fn test_untag_secrets_in_yaml() {
+        let mut value: serde_yaml::Value = serde_yaml::from_str("api_key: !cmd pass show openai\nother: !custom x\n").unwrap();
+        untag_secrets_in_yaml(&mut value);
+        assert_eq!(value["api_key"].as_str(), Some("!cmd pass show openai"));
+        assert!(matches!(value["other"], serde_yaml::Value::Tagged(_)));
+    }
+}
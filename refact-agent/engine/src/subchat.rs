@@ -116,7 +116,7 @@ async fn chat_interaction_non_stream(
     let j = crate::restream::scratchpad_interaction_not_stream_json(
         ccx.clone(),
         &mut spad,
-        "chat".to_string(),
+        "subchat".to_string(),
         prompt,
         &model_rec.base,
         &chat_post.parameters,   // careful: includes n
@@ -127,6 +127,9 @@ async fn chat_interaction_non_stream(
         format!("network error communicating with the model (2): {:?}", e)
     })?;
     info!("non stream generation took {:?}ms", t1.elapsed().as_millis() as i32);
+    if let Some(model_switch) = j.get("model_switch") {
+        warn!("subchat answered by another model: {}", model_switch);
+    }
 
     let usage_mb = j.get("usage")
         .and_then(|value| match value {
@@ -194,6 +197,12 @@ async fn chat_interaction_non_stream(
         let content = chat_content_raw_from_value(content_value).and_then(|c|c.to_internal_format())
             .map_err(|e| format!("error parsing model's output: {}", e))?;
 
+        // thinking must go back to the model with the tool results, otherwise the next turn is rejected
+        let thinking_blocks = message.get("thinking_blocks")
+            .and_then(|v| v.as_array())
+            .filter(|arr| !arr.is_empty())
+            .cloned();
+
         let mut ch_results = vec![];
         let msg = ChatMessage {
             role,
@@ -201,6 +210,7 @@ async fn chat_interaction_non_stream(
             tool_calls,
             tool_call_id,
             usage: usage_mb.clone(),
+            thinking_blocks,
             ..Default::default()
         };
         ch_results.extend(det_messages.clone());
@@ -243,6 +253,13 @@ fn update_usage_from_messages(usage: &mut ChatUsage, messages: &Vec<Vec<ChatMess
                 usage.total_tokens += u.total_tokens;
                 usage.completion_tokens += u.completion_tokens;
                 usage.prompt_tokens += u.prompt_tokens;
+                if u.cache_creation_input_tokens.is_some() || u.cache_read_input_tokens.is_some() {
+                    usage.cache_creation_input_tokens = Some(usage.cache_creation_input_tokens.unwrap_or(0) + u.cache_creation_input_tokens.unwrap_or(0));
+                    usage.cache_read_input_tokens = Some(usage.cache_read_input_tokens.unwrap_or(0) + u.cache_read_input_tokens.unwrap_or(0));
+                }
+                if let Some(cost) = u.cost {
+                    usage.cost = Some(usage.cost.unwrap_or(0.0) + cost);
+                }
             }
         }
     }
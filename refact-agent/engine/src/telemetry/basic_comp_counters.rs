@@ -39,6 +39,22 @@ pub fn create_data_accumulator_for_accepted_snippet(
     ))
 }
 
+// the same time windows as in on_file_text_changed, outside of them the text is not looked at
+pub fn waits_for_text(
+    snippet_data_accumulator: &Vec<TeleCompletionAccum>,
+    uri: &String,
+) -> bool {
+    let now = chrono::Local::now().timestamp();
+    snippet_data_accumulator.iter().any(|comp| {
+        comp.uri.eq(uri) && comp.finished_ts == 0 && (
+            (comp.created_ts + 30 < now && comp.created_ts + 90 > now && comp.after_30s_remaining == -1.) ||
+            (comp.created_ts + 90 < now && comp.created_ts + 180 > now && comp.after_90s_remaining == -1.) ||
+            (comp.created_ts + 180 < now && comp.created_ts + 360 > now && comp.after_180s_remaining == -1.) ||
+            (comp.created_ts + 360 < now && comp.after_360s_remaining == -1.)
+        )
+    })
+}
+
 pub fn on_file_text_changed(
     snippet_data_accumulator: &mut Vec<TeleCompletionAccum>,
     uri: &String,
@@ -10,6 +10,7 @@ use crate::global_context;
 use crate::telemetry::utils;
 use crate::telemetry::telemetry_structs::{SnippetTracker, Storage, TeleRobotHumanAccum};
 use crate::telemetry::utils::compress_tele_records_to_file;
+use crate::telemetry::local_stats::{LocalRobotHumanRecord, local_stats_save_robot_human};
 
 
 // if human characters / diff_time > 20 => ignore (don't count copy-paste and branch changes)
@@ -151,15 +152,25 @@ pub async fn tele_robot_human_compress_to_file(
     cx: Arc<ARwLock<global_context::GlobalContext>>,
 ) {
     let mut records = vec![];
+    let mut local_records = vec![];
     for rec in compress_robot_human(&cx.read().await.telemetry.read().unwrap()) {
         if rec.model.is_empty() && rec.robot_characters == 0 && rec.human_characters == 0 {
             continue;
         }
+        local_records.push(LocalRobotHumanRecord {
+            file_extension: rec.file_extension.clone(),
+            model: rec.model.clone(),
+            robot_characters: rec.robot_characters,
+            human_characters: rec.human_characters,
+            completions_cnt: rec.completions_cnt,
+        });
         let json_dict = serde_json::to_value(rec).unwrap();
         records.push(json_dict);
     }
     match compress_tele_records_to_file(cx.clone(), records, "robot_human".to_string(), "rh".to_string()).await {
         Ok(_) => {
+            // the counters are cleared only after saving, the same goes for the local copy to not count them twice
+            local_stats_save_robot_human(cx.clone(), local_records).await;
             cx.write().await.telemetry.write().unwrap().tele_robot_human.clear();
         },
         Err(_) => {}
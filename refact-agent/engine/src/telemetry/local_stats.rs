@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use serde::Serialize;
+use tokio::sync::RwLock as ARwLock;
+use tokio_rusqlite::Connection;
+use tracing::{error, info};
+
+use crate::global_context::GlobalContext;
+
+// Completion and robot/human counters also go to a local database, they stay on this machine and
+// are independent of --basic-telemetry. Each row is one completion or one compression period.
+
+const LATENCY_PERCENTILES: [usize; 3] = [50, 90, 99];
+
+
+pub struct LocalStatsDb {
+    conn: Connection,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LocalCompletionRecord {
+    pub created_ts: i64,
+    pub file_extension: String,
+    pub model: String,
+    pub multiline: bool,
+    pub accepted: bool,
+    pub latency_ms: i64,
+    pub remaining_percentage: f64,   // -1 if not accepted or not followed
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LocalRobotHumanRecord {
+    pub file_extension: String,
+    pub model: String,
+    pub robot_characters: i64,
+    pub human_characters: i64,
+    pub completions_cnt: i64,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct AcceptanceStats {
+    pub file_extension: String,
+    pub model: String,
+    pub completions: i64,
+    pub accepted: i64,
+    pub acceptance_rate: f64,
+    pub avg_remaining_percentage: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct RobotHumanStats {
+    pub file_extension: String,
+    pub model: String,
+    pub robot_characters: i64,
+    pub human_characters: i64,
+    pub robot_share: f64,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct LatencyStats {
+    pub model: String,
+    pub completions: usize,
+    pub p50_ms: i64,
+    pub p90_ms: i64,
+    pub p99_ms: i64,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct LocalStats {
+    pub since_ts: i64,
+    pub acceptance: Vec<AcceptanceStats>,
+    pub robot_human: Vec<RobotHumanStats>,
+    pub latency: Vec<LatencyStats>,
+}
+
+/// Nearest-rank percentile of sorted values
+fn percentile(sorted: &Vec<i64>, p: usize) -> i64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (p * sorted.len() + 99) / 100;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl LocalStatsDb {
+    pub async fn init(conn: Connection) -> Result<LocalStatsDb, String> {
+        conn.call(|conn| {
+            let _: String = conn.query_row("PRAGMA journal_mode=WAL", [], |row| row.get(0))?;
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS completions (
+                    created_ts INTEGER NOT NULL,
+                    file_extension TEXT NOT NULL,
+                    model TEXT NOT NULL,
+                    multiline INTEGER NOT NULL,
+                    accepted INTEGER NOT NULL,
+                    latency_ms INTEGER NOT NULL,
+                    remaining_percentage REAL NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS idx_completions_ts ON completions (created_ts);
+                CREATE TABLE IF NOT EXISTS robot_human (
+                    created_ts INTEGER NOT NULL,
+                    file_extension TEXT NOT NULL,
+                    model TEXT NOT NULL,
+                    robot_characters INTEGER NOT NULL,
+                    human_characters INTEGER NOT NULL,
+                    completions_cnt INTEGER NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS idx_robot_human_ts ON robot_human (created_ts);"
+            )?;
+            Ok(())
+        }).await.map_err(|e| e.to_string())?;
+        Ok(LocalStatsDb { conn })
+    }
+
+    pub async fn add_completions(&self, records: Vec<LocalCompletionRecord>) -> Result<(), String> {
+        self.conn.call(move |conn| {
+            let transaction = conn.transaction()?;
+            {
+                let mut stmt = transaction.prepare(
+                    "INSERT INTO completions (created_ts, file_extension, model, multiline, accepted, latency_ms, remaining_percentage) \
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+                )?;
+                for r in records {
+                    stmt.execute(rusqlite::params![r.created_ts, r.file_extension, r.model, r.multiline, r.accepted, r.latency_ms, r.remaining_percentage])?;
+                }
+            }
+            transaction.commit()?;
+            Ok(())
+        }).await.map_err(|e| e.to_string())
+    }
+
+    pub async fn add_robot_human(&self, created_ts: i64, records: Vec<LocalRobotHumanRecord>) -> Result<(), String> {
+        self.conn.call(move |conn| {
+            let transaction = conn.transaction()?;
+            {
+                let mut stmt = transaction.prepare(
+                    "INSERT INTO robot_human (created_ts, file_extension, model, robot_characters, human_characters, completions_cnt) \
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+                )?;
+                for r in records {
+                    stmt.execute(rusqlite::params![created_ts, r.file_extension, r.model, r.robot_characters, r.human_characters, r.completions_cnt])?;
+                }
+            }
+            transaction.commit()?;
+            Ok(())
+        }).await.map_err(|e| e.to_string())
+    }
+
+    pub async fn remove_older_than(&self, ts: i64) -> Result<usize, String> {
+        self.conn.call(move |conn| {
+            let mut removed = conn.execute("DELETE FROM completions WHERE created_ts < ?1", [ts])?;
+            removed += conn.execute("DELETE FROM robot_human WHERE created_ts < ?1", [ts])?;
+            Ok(removed)
+        }).await.map_err(|e| e.to_string())
+    }
+
+    pub async fn stats(&self, since_ts: i64) -> Result<LocalStats, String> {
+        self.conn.call(move |conn| {
+            let mut stats = LocalStats { since_ts, ..Default::default() };
+
+            let mut stmt = conn.prepare(
+                "SELECT file_extension, model, COUNT(*), SUM(accepted), \
+                AVG(CASE WHEN accepted = 1 AND remaining_percentage >= 0 THEN remaining_percentage END) \
+                FROM completions WHERE created_ts >= ?1 GROUP BY file_extension, model ORDER BY COUNT(*) DESC"
+            )?;
+            let rows = stmt.query_map([since_ts], |row| {
+                let completions: i64 = row.get(2)?;
+                let accepted: i64 = row.get(3)?;
+                Ok(AcceptanceStats {
+                    file_extension: row.get(0)?,
+                    model: row.get(1)?,
+                    completions,
+                    accepted,
+                    acceptance_rate: if completions > 0 { accepted as f64 / completions as f64 } else { 0. },
+                    avg_remaining_percentage: row.get(4)?,
+                })
+            })?;
+            for row in rows {
+                stats.acceptance.push(row?);
+            }
+
+            let mut stmt = conn.prepare(
+                "SELECT file_extension, model, SUM(robot_characters), SUM(human_characters) \
+                FROM robot_human WHERE created_ts >= ?1 GROUP BY file_extension, model \
+                ORDER BY SUM(robot_characters) + SUM(human_characters) DESC"
+            )?;
+            let rows = stmt.query_map([since_ts], |row| {
+                let robot_characters: i64 = row.get(2)?;
+                let human_characters: i64 = row.get(3)?;
+                let total = robot_characters + human_characters;
+                Ok(RobotHumanStats {
+                    file_extension: row.get(0)?,
+                    model: row.get(1)?,
+                    robot_characters,
+                    human_characters,
+                    robot_share: if total > 0 { robot_characters as f64 / total as f64 } else { 0. },
+                })
+            })?;
+            for row in rows {
+                stats.robot_human.push(row?);
+            }
+
+            let mut stmt = conn.prepare(
+                "SELECT model, latency_ms FROM completions WHERE created_ts >= ?1 AND latency_ms >= 0 ORDER BY model, latency_ms"
+            )?;
+            let rows = stmt.query_map([since_ts], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
+            let mut latencies: HashMap<String, Vec<i64>> = HashMap::new();
+            for row in rows {
+                let (model, latency_ms) = row?;
+                latencies.entry(model).or_default().push(latency_ms);
+            }
+            for (model, sorted) in latencies {
+                let [p50_ms, p90_ms, p99_ms] = LATENCY_PERCENTILES.map(|p| percentile(&sorted, p));
+                stats.latency.push(LatencyStats { model, completions: sorted.len(), p50_ms, p90_ms, p99_ms });
+            }
+            stats.latency.sort_by(|a, b| b.completions.cmp(&a.completions).then(a.model.cmp(&b.model)));
+            Ok(stats)
+        }).await.map_err(|e| e.to_string())
+    }
+}
+
+async fn local_stats_db_with_retention(gcx: Arc<ARwLock<GlobalContext>>) -> Option<(Arc<tokio::sync::Mutex<Option<LocalStatsDb>>>, u32)> {
+    let (local_stats, cache_dir, keep_days) = {
+        let gcx_locked = gcx.read().await;
+        (gcx_locked.local_stats.clone(), gcx_locked.cache_dir.clone(), gcx_locked.cmdline.local_stats_keep_days)
+    };
+    if keep_days == 0 {
+        return None;
+    }
+    let mut db_locked = local_stats.lock().await;
+    if db_locked.is_none() {
+        let db_path = cache_dir.join("telemetry").join("local_stats.sqlite");
+        let _ = tokio::fs::create_dir_all(cache_dir.join("telemetry")).await;
+        let opened = match Connection::open(&db_path).await {
+            Ok(conn) => LocalStatsDb::init(conn).await,
+            Err(e) => Err(e.to_string()),
+        };
+        match opened {
+            Ok(db) => {
+                info!("local stats database {:?}", db_path);
+                *db_locked = Some(db);
+            },
+            Err(e) => {
+                error!("cannot open local stats database {:?}: {}", db_path, e);
+                return None;
+            }
+        }
+    }
+    drop(db_locked);
+    Some((local_stats, keep_days))
+}
+
+async fn local_stats_cleanup(db: &LocalStatsDb, keep_days: u32) {
+    let keep_since = chrono::Local::now().timestamp() - keep_days as i64 * 86400;
+    match db.remove_older_than(keep_since).await {
+        Ok(removed) if removed > 0 => info!("local stats: removed {} records older than {} days", removed, keep_days),
+        Ok(_) => {},
+        Err(e) => error!("local stats cleanup failed: {}", e),
+    }
+}
+
+pub async fn local_stats_save_completions(gcx: Arc<ARwLock<GlobalContext>>, records: Vec<LocalCompletionRecord>) {
+    if records.is_empty() {
+        return;
+    }
+    let Some((local_stats, keep_days)) = local_stats_db_with_retention(gcx).await else { return };
+    let db_locked = local_stats.lock().await;
+    if let Some(db) = db_locked.as_ref() {
+        if let Err(e) = db.add_completions(records).await {
+            error!("local stats: cannot save completions: {}", e);
+        }
+        local_stats_cleanup(db, keep_days).await;
+    }
+}
+
+pub async fn local_stats_save_robot_human(gcx: Arc<ARwLock<GlobalContext>>, records: Vec<LocalRobotHumanRecord>) {
+    if records.is_empty() {
+        return;
+    }
+    let Some((local_stats, keep_days)) = local_stats_db_with_retention(gcx).await else { return };
+    let db_locked = local_stats.lock().await;
+    if let Some(db) = db_locked.as_ref() {
+        if let Err(e) = db.add_robot_human(chrono::Local::now().timestamp(), records).await {
+            error!("local stats: cannot save robot/human counters: {}", e);
+        }
+        local_stats_cleanup(db, keep_days).await;
+    }
+}
+
+/// Aggregated stats for the last `days`, limited by how long the records are kept
+pub async fn local_stats_get(gcx: Arc<ARwLock<GlobalContext>>, days: Option<u32>) -> Result<LocalStats, String> {
+    let (local_stats, keep_days) = local_stats_db_with_retention(gcx).await
+        .ok_or("local stats are turned off or the database cannot be opened, see --local-stats-keep-days".to_string())?;
+    let days = days.unwrap_or(keep_days).min(keep_days);
+    let since_ts = chrono::Local::now().timestamp() - days as i64 * 86400;
+    let db_locked = local_stats.lock().await;
+    db_locked.as_ref().ok_or("local stats database is not open".to_string())?.stats(since_ts).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completion(model: &str, ext: &str, accepted: bool, latency_ms: i64) -> LocalCompletionRecord {
+        LocalCompletionRecord {
+            created_ts: 1000,
+            file_extension: ext.to_string(),
+            model: model.to_string(),
+            accepted,
+            latency_ms,
+            remaining_percentage: if accepted { 0.5 } else { -1. },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_percentile() {
+        let sorted: Vec<i64> = (1..=100).collect();
+        assert_eq!(percentile(&sorted, 50), 50);
+        assert_eq!(percentile(&sorted, 99), 99);
+        assert_eq!(percentile(&vec![7], 90), 7);
+        assert_eq!(percentile(&vec![], 90), 0);
+    }
+
+    #[tokio::test]
+    async fn test_local_stats_db() {
+        let db = LocalStatsDb::init(Connection::open_in_memory().await.unwrap()).await.unwrap();
+        let mut records = vec![
+            completion("m1", ".py", true, 100),
+            completion("m1", ".py", false, 300),
+            completion("m1", ".rs", true, 200),
+            completion("m2", ".py", false, 50),
+        ];
+        records.push(LocalCompletionRecord { created_ts: 10, ..completion("m2", ".py", true, 50) });
+        db.add_completions(records).await.unwrap();
+        db.add_robot_human(1000, vec![LocalRobotHumanRecord {
+            file_extension: ".py".to_string(), model: "m1".to_string(), robot_characters: 30, human_characters: 90, completions_cnt: 2,
+        }]).await.unwrap();
+
+        let stats = db.stats(500).await.unwrap();
+        assert_eq!(stats.acceptance.len(), 3);
+        let m1_py = stats.acceptance.iter().find(|x| x.model == "m1" && x.file_extension == ".py").unwrap();
+        assert_eq!((m1_py.completions, m1_py.accepted, m1_py.acceptance_rate), (2, 1, 0.5));
+        assert_eq!(m1_py.avg_remaining_percentage, Some(0.5));
+        assert_eq!(stats.robot_human[0].robot_share, 0.25);
+        assert_eq!((stats.latency[0].model.as_str(), stats.latency[0].p50_ms, stats.latency[0].p99_ms), ("m1", 200, 300));
+
+        assert_eq!(db.remove_older_than(500).await.unwrap(), 1);
+        assert_eq!(db.stats(0).await.unwrap().acceptance.len(), 3);
+    }
+}
@@ -3,6 +3,7 @@ pub mod utils;
 pub mod basic_transmit;
 pub mod snippets_collection;
 pub mod snippets_transmit;
+pub mod local_stats;
 mod basic_robot_human;
 mod basic_comp_counters;
 mod basic_network;
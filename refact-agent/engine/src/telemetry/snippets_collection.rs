@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use std::sync::RwLock as StdRwLock;
+use std::time::Instant;
 
 use tokio::sync::RwLock as ARwLock;
 use tracing::debug;
@@ -8,6 +9,7 @@ use tracing::debug;
 use crate::call_validation::CodeCompletionPost;
 use crate::completion_cache;
 use crate::files_correction::canonical_path;
+use crate::files_in_workspace::DocumentEdit;
 use crate::global_context;
 use crate::telemetry::basic_comp_counters;
 use crate::telemetry::basic_robot_human;
@@ -27,6 +29,7 @@ pub struct SaveSnippet {
     // Purpose is to aggregate this struct to a scratchpad
     pub storage_arc: Arc<StdRwLock<telemetry_structs::Storage>>,
     pub post: CodeCompletionPost,
+    pub created: Instant,
 }
 
 impl SaveSnippet {
@@ -37,6 +40,7 @@ impl SaveSnippet {
         SaveSnippet {
             storage_arc,
             post: post.clone(),
+            created: Instant::now(),
         }
     }
 }
@@ -62,6 +66,11 @@ fn snippet_register(
         created_ts: chrono::Local::now().timestamp(),
         accepted_ts: 0,
         finished_ts: 0,
+        latency_ms: ss.created.elapsed().as_millis() as i64,
+        lines_now: {
+            let line = ss.post.inputs.cursor.line.max(0) as usize;
+            (line, line + grey_text.matches('\n').count())
+        },
     };
     storage_locked.tele_snippet_next_id += 1;
     storage_locked.tele_snippets.push(snip);
@@ -161,3 +170,106 @@ pub async fn sources_changed(
     basic_robot_human::on_file_text_changed(&mut storage_locked.tele_robot_human, uri, text);
     basic_comp_counters::on_file_text_changed(&mut storage_locked.snippet_data_accumulators, uri, text);
 }
+
+/// Follows accepted snippets of the file through range edits: edits above a snippet move it, edits inside
+/// change its size. True if some edit touches a snippet or replaces the whole text, or a snippet isn't counted yet.
+fn edits_touch_accepted_snippets(
+    storage: &mut telemetry_structs::Storage,
+    uri: &String,
+    edits: &Vec<DocumentEdit>,
+) -> bool {
+    let uri_path = canonical_path(uri).to_string_lossy().to_string();
+    let counted_snip_ids = storage.tele_robot_human.iter().find(|stat| stat.uri.eq(uri))
+        .map(|stat| stat.used_snip_ids.clone())
+        .unwrap_or_default();
+    let mut touched = false;
+    for snip in storage.tele_snippets.iter_mut() {
+        if snip.accepted_ts == 0 || snip.finished_ts != 0 ||
+            !uri_path.ends_with(&canonical_path(&snip.inputs.cursor.file).to_string_lossy().to_string()) {
+            continue;
+        }
+        touched |= !counted_snip_ids.contains(&snip.snippet_telemetry_id);
+        for edit in edits.iter() {
+            let ((line1, _), (line2, _)) = match edit.range {
+                Some(range) => range,
+                None => {
+                    touched = true;
+                    continue;
+                }
+            };
+            let lines_delta = edit.text.matches('\n').count() as i64 - (line2 - line1) as i64;
+            let (first, last) = snip.lines_now;
+            if line2 < first {
+                snip.lines_now = ((first as i64 + lines_delta).max(0) as usize, (last as i64 + lines_delta).max(0) as usize);
+            } else if line1 <= last {
+                snip.lines_now = (first, (last as i64 + lines_delta).max(first as i64) as usize);
+                touched = true;
+            }
+        }
+    }
+    touched
+}
+
+/// False when nothing tracked for this file would look at the new text: the robot/human record already exists,
+/// the edits don't touch accepted snippets being followed and no completion is waiting for its remaining-percentage checkpoints
+pub async fn sources_changed_needs_text(
+    gcx: Arc<ARwLock<global_context::GlobalContext>>,
+    uri: &String,
+    edits: &Vec<DocumentEdit>,
+) -> bool {
+    let tele_storage_arc = gcx.read().await.telemetry.clone();
+    let mut storage_locked = tele_storage_arc.write().unwrap();
+    let touched = edits_touch_accepted_snippets(&mut storage_locked, uri, edits);
+    touched ||
+        !storage_locked.tele_robot_human.iter().any(|stat| stat.uri.eq(uri)) ||
+        basic_comp_counters::waits_for_text(&storage_locked.snippet_data_accumulators, uri)
+}
+
+pub async fn sources_touched(
+    gcx: Arc<ARwLock<global_context::GlobalContext>>,
+    uri: &String,
+) {
+    let tele_storage_arc = gcx.read().await.telemetry.clone();
+    let mut storage_locked = tele_storage_arc.write().unwrap();
+    basic_robot_human::on_file_text_changed(&mut storage_locked.tele_robot_human, uri, &"".to_string());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::call_validation::CursorPosition;
+    use crate::telemetry::telemetry_structs::TeleRobotHumanAccum;
+
+    fn edit(line1: usize, line2: usize, text: &str) -> Vec<DocumentEdit> {
+        vec![DocumentEdit { range: Some(((line1, 0), (line2, 0))), text: text.to_string() }]
+    }
+
+    #[test]
+    fn test_edits_touch_accepted_snippets() {
+        let uri = "/tmp/a.py".to_string();
+        let mut storage = telemetry_structs::Storage::new();
+        storage.tele_robot_human.push(TeleRobotHumanAccum { uri: uri.clone(), used_snip_ids: vec![101], ..Default::default() });
+        let mut snip = SnippetTracker {
+            snippet_telemetry_id: 101,
+            grey_text: "a\nb\n".to_string(),
+            accepted_ts: 1,
+            lines_now: (10, 12),
+            ..Default::default()
+        };
+        snip.inputs.cursor = CursorPosition { file: uri.clone(), line: 10, character: 0 };
+        storage.tele_snippets.push(snip);
+
+        // two lines added above, typing below
+        assert!(!edits_touch_accepted_snippets(&mut storage, &uri, &edit(1, 1, "x\ny\n")));
+        assert_eq!(storage.tele_snippets[0].lines_now, (12, 14));
+        assert!(!edits_touch_accepted_snippets(&mut storage, &uri, &edit(20, 20, "z")));
+        // inside of the snippet, the whole text
+        assert!(edits_touch_accepted_snippets(&mut storage, &uri, &edit(13, 14, "")));
+        assert_eq!(storage.tele_snippets[0].lines_now, (12, 13));
+        assert!(edits_touch_accepted_snippets(&mut storage, &uri, &vec![DocumentEdit { range: None, text: "".to_string() }]));
+        // accepted but not counted yet
+        storage.tele_robot_human[0].used_snip_ids.clear();
+        assert!(edits_touch_accepted_snippets(&mut storage, &uri, &edit(20, 20, "z")));
+    }
+}
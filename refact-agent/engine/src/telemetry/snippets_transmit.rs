@@ -2,6 +2,8 @@ use std::sync::Arc;
 use tokio::sync::RwLock as ARwLock;
 
 use crate::global_context;
+use crate::telemetry::local_stats::{LocalCompletionRecord, local_stats_save_completions};
+use crate::telemetry::utils;
 
 
 const SNIP_NOT_ACCEPTED_TIMEOUT_AFTER : i64 = 30;
@@ -16,6 +18,7 @@ pub async fn send_finished_snippets(gcx: Arc<ARwLock<global_context::GlobalConte
         tele_storage = cx.telemetry.clone();
     }
 
+    let mut local_records = vec![];
     {
         let mut to_remove: Vec<usize> = vec![];
         let mut storage_locked = tele_storage.write().unwrap();
@@ -37,9 +40,19 @@ pub async fn send_finished_snippets(gcx: Arc<ARwLock<global_context::GlobalConte
         to_remove.sort_by(|a, b| b.cmp(a));
         to_remove.dedup();
         for idx in to_remove {
-            storage_locked.tele_snippets.remove(idx);
+            let snip = storage_locked.tele_snippets.remove(idx);
+            local_records.push(LocalCompletionRecord {
+                created_ts: snip.created_ts,
+                file_extension: utils::extract_extension_or_filename(&snip.inputs.cursor.file),
+                model: snip.model,
+                multiline: snip.grey_text.contains("\n"),
+                accepted: snip.accepted_ts != 0,
+                latency_ms: snip.latency_ms,
+                remaining_percentage: if snip.accepted_ts != 0 { snip.remaining_percentage } else { -1. },
+            });
         }
     }
+    local_stats_save_completions(gcx.clone(), local_records).await;
 
     // Snippet sending code was here, but it was removed because we at Refact didn't find a good way to
     // use it (in cloud or self-hosting), so we don't have an option to collect it anymore.
@@ -63,6 +63,10 @@ pub struct SnippetTracker {
     pub created_ts: i64,
     pub accepted_ts: i64,
     pub finished_ts: i64,
+    pub latency_ms: i64,
+    /// First and last line of the snippet in the current text, moved by the range edits above and inside it
+    #[serde(skip)]
+    pub lines_now: (usize, usize),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
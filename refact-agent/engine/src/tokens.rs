@@ -1,9 +1,11 @@
 use tokio::io::AsyncWriteExt;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::RwLock as ARwLock;
 use tokio::sync::Mutex as AMutex;
+use itertools::Itertools;
 use tokenizers::Tokenizer;
 use reqwest::header::AUTHORIZATION;
 use reqwest::Response;
@@ -12,7 +14,9 @@ use uuid::Uuid;
 use crate::custom_error::MapErrToString;
 use crate::files_correction::canonical_path;
 use crate::global_context::GlobalContext;
-use crate::caps::{default_hf_tokenizer_template, strip_model_from_finetune, BaseModelRecord};
+use crate::caps::{default_hf_tokenizer_template, strip_model_from_finetune, BaseModelRecord, CompletionModelFamily};
+
+const TOKENIZER_RETRY_AFTER: Duration = Duration::from_secs(300);
 
 
 async fn try_open_tokenizer(
@@ -139,20 +143,82 @@ pub async fn cached_tokenizer(
     let tokenizer_download_lock: Arc<AMutex<bool>> = global_context.read().await.tokenizer_download_lock.clone();
     let _tokenizer_download_locked = tokenizer_download_lock.lock().await;
 
-    let (client2, cache_dir, tokenizer_in_gcx, hf_tokenizer_template) = {
+    let (client2, cache_dir, tokenizer_in_gcx, fallback_in_gcx, hf_tokenizer_template, model_family) = {
         let cx_locked = global_context.read().await;
         let template = cx_locked.caps.clone().map(|caps| caps.hf_tokenizer_template.clone())
             .unwrap_or_else(default_hf_tokenizer_template);
-        (cx_locked.http_client.clone(), cx_locked.cache_dir.clone(), cx_locked.tokenizer_map.clone().get(&model_id).cloned(), template)
+        let model_family = cx_locked.caps.as_ref()
+            .and_then(|caps| caps.completion_models.get(&model_rec.id))
+            .and_then(|model| model.model_family);
+        let fallback = cx_locked.tokenizer_fallbacks.get(&model_id)
+            .filter(|(_, retry_at)| std::time::Instant::now() < *retry_at)
+            .map(|(tokenizer, _)| tokenizer.clone());
+        (cx_locked.http_client.clone(), cx_locked.cache_dir.clone(), cx_locked.tokenizer_map.get(&model_id).cloned(), fallback, template, model_family)
     };
 
     if let Some(tokenizer) = tokenizer_in_gcx {
         return Ok(tokenizer)
     }
+    if let Some(tokenizer) = fallback_in_gcx {
+        return Ok(Some(tokenizer))
+    }
+    if model_rec.tokenizer.starts_with("fake") {
+        return Ok(None);
+    }
+    if model_rec.tokenizer.is_empty() {
+        return Err(format!("failed to load tokenizer: empty tokenizer for {model_id}"));
+    }
+
+    // the approximate splitter is kept for a while only, the network might be back later
+    let tokenizer_cache_dir = cache_dir.join("tokenizers");
+    let (mut tokenizer, is_fallback) = match load_tokenizer(&client2, &tokenizer_cache_dir, &hf_tokenizer_template, &model_id, model_rec).await {
+        Ok(tokenizer) => (tokenizer, false),
+        Err(e) => {
+            let family = approximate_tokenizer_family(model_rec, model_family);
+            tracing::warn!("failed to load tokenizer of {}: {}; for {}s tokens are counted with the \"{}\" approximate splitter, it's not a real tokenizer: counts are not exact, tokens can't be decoded",
+                model_id, e, TOKENIZER_RETRY_AFTER.as_secs(), family);
+            (approximate_tokenizer(family)?, true)
+        }
+    };
+    let _ = tokenizer.with_truncation(None);
+    tokenizer.with_padding(None);
+    let arc = Arc::new(tokenizer);
 
+    let mut gcx_locked = global_context.write().await;
+    if is_fallback {
+        gcx_locked.tokenizer_fallbacks.insert(model_id, (arc.clone(), std::time::Instant::now() + TOKENIZER_RETRY_AFTER));
+    } else {
+        gcx_locked.tokenizer_fallbacks.remove(&model_id);
+        gcx_locked.tokenizer_map.insert(model_id, Some(arc.clone()));
+    }
+    Ok(Some(arc))
+}
+
+fn sanitize_for_path(s: &str) -> String {
+    s.chars().map(|c| if c.is_alphanumeric() { c } else { '_' }).collect()
+}
+
+fn tokenizer_cache_path(tokenizer_cache_dir: &Path, model_id: &str) -> PathBuf {
+    tokenizer_cache_dir.join(sanitize_for_path(model_id)).join("tokenizer.json")
+}
+
+/// Bundles are keyed by the tokenizer ("hf://Xenova/gpt-4o"), not by the model, so they work with any provider names
+fn tokenizer_bundle_path(tokenizer_cache_dir: &Path, tokenizer: &str) -> PathBuf {
+    tokenizer_cache_dir.join(TOKENIZERS_BUNDLE_DIR).join(sanitize_for_path(tokenizer)).join("tokenizer.json")
+}
+
+async fn load_tokenizer(
+    http_client: &reqwest::Client,
+    tokenizer_cache_dir: &Path,
+    hf_tokenizer_template: &str,
+    model_id: &str,
+    model_rec: &BaseModelRecord,
+) -> Result<Tokenizer, String> {
     let (mut tok_file_path, tok_url) = match &model_rec.tokenizer {
         empty_tok if empty_tok.is_empty() => return Err(format!("failed to load tokenizer: empty tokenizer for {model_id}")),
-        fake_tok if fake_tok.starts_with("fake") => return Ok(None),
+        approx_tok if approx_tok.starts_with(APPROX_TOKENIZER_PREFIX) => {
+            return approximate_tokenizer(approx_tok.strip_prefix(APPROX_TOKENIZER_PREFIX).unwrap());
+        }
         hf_tok if hf_tok.starts_with("hf://") => {
             let hf_model = hf_tok.strip_prefix("hf://").unwrap();
             let url = hf_tokenizer_template.replace("$HF_MODEL", hf_model);
@@ -174,25 +240,193 @@ pub async fn cached_tokenizer(
     };
 
     if tok_file_path.as_os_str().is_empty() {
-        let tokenizer_cache_dir = std::path::PathBuf::from(cache_dir).join("tokenizers");
-        let sanitized_model_id = model_id.chars()
-            .map(|c| if c.is_alphanumeric() { c } else { '_' })
-            .collect::<String>();
-        
-        tok_file_path = tokenizer_cache_dir.join(&sanitized_model_id).join("tokenizer.json");
-
-        try_download_tokenizer_file_and_open(&client2, &tok_url, &model_rec.tokenizer_api_key, &tok_file_path).await?;
+        tok_file_path = tokenizer_cache_path(tokenizer_cache_dir, model_id);
+        let bundle_path = tokenizer_bundle_path(tokenizer_cache_dir, &model_rec.tokenizer);
+        if !tok_file_path.exists() && check_json_file(&bundle_path) {
+            tok_file_path = bundle_path;
+        } else {
+            try_download_tokenizer_file_and_open(http_client, &tok_url, &model_rec.tokenizer_api_key, &tok_file_path).await?;
+        }
     }
-    
+
     tracing::info!("loading tokenizer \"{}\"", tok_file_path.display());
-    let mut tokenizer = Tokenizer::from_file(tok_file_path)
-        .map_err(|e| format!("failed to load tokenizer: {}", e))?;
-    let _ = tokenizer.with_truncation(None);
-    tokenizer.with_padding(None);
-    let arc = Some(Arc::new(tokenizer));
+    Tokenizer::from_file(tok_file_path)
+        .map_err(|e| format!("failed to load tokenizer: {}", e))
+}
+
+fn is_downloadable_tokenizer(tokenizer: &str) -> bool {
+    ["hf://", "http://", "https://"].iter().any(|prefix| tokenizer.starts_with(prefix))
+}
+
+/// `--tokenizers-export`: downloads tokenizers of all models in caps and copies them into `to`, returns how many
+pub async fn export_tokenizers_bundle(gcx: Arc<ARwLock<GlobalContext>>, to: &Path) -> Result<usize, String> {
+    let caps = crate::global_context::try_load_caps_quickly_if_not_present(gcx.clone(), 0).await
+        .map_err(|e| format!("cannot load caps: {}", e.message))?;
+    let tokenizer_cache_dir = gcx.read().await.cache_dir.join("tokenizers");
+    let model_recs = caps.chat_models.values().map(|m| m.base.clone())
+        .chain(caps.completion_models.values().map(|m| m.base.clone()))
+        .chain(std::iter::once(caps.embedding_model.base.clone()))
+        .filter(|base| is_downloadable_tokenizer(&base.tokenizer))
+        .unique_by(|base| base.tokenizer.clone())
+        .collect::<Vec<_>>();
 
-    global_context.write().await.tokenizer_map.insert(model_id, arc.clone());
-    Ok(arc)
+    let mut exported = 0;
+    for model_rec in model_recs {
+        let _ = cached_tokenizer(gcx.clone(), &model_rec).await;
+        let model_id = strip_model_from_finetune(&model_rec.id);
+        let from = [tokenizer_cache_path(&tokenizer_cache_dir, &model_id), tokenizer_bundle_path(&tokenizer_cache_dir, &model_rec.tokenizer)]
+            .into_iter().find(|path| check_json_file(path));
+        let Some(from) = from else {
+            tracing::warn!("tokenizer {} of {} is not available, not exported", model_rec.tokenizer, model_id);
+            continue;
+        };
+        let dest = to.join(sanitize_for_path(&model_rec.tokenizer)).join("tokenizer.json");
+        tokio::fs::create_dir_all(dest.parent().unwrap()).await
+            .map_err(|e| format!("cannot create {}: {}", to.display(), e))?;
+        tokio::fs::copy(&from, &dest).await
+            .map_err(|e| format!("cannot copy {} to {}: {}", from.display(), dest.display(), e))?;
+        tracing::info!("exported tokenizer {} to {}", model_rec.tokenizer, dest.display());
+        exported += 1;
+    }
+    Ok(exported)
+}
+
+/// `--tokenizers-import`: takes a directory made by `--tokenizers-export`, returns how many tokenizers were imported
+pub async fn import_tokenizers_bundle(gcx: Arc<ARwLock<GlobalContext>>, from: &Path) -> Result<usize, String> {
+    let bundle_dir = gcx.read().await.cache_dir.join("tokenizers").join(TOKENIZERS_BUNDLE_DIR);
+    let mut entries = tokio::fs::read_dir(from).await
+        .map_err(|e| format!("cannot read {}: {}", from.display(), e))?;
+    let mut imported = 0;
+    while let Some(entry) = entries.next_entry().await.map_err(|e| format!("cannot read {}: {}", from.display(), e))? {
+        let src = entry.path().join("tokenizer.json");
+        if !check_json_file(&src) {
+            tracing::warn!("{} is not a tokenizer, skipped", src.display());
+            continue;
+        }
+        let dest = bundle_dir.join(entry.file_name()).join("tokenizer.json");
+        tokio::fs::create_dir_all(dest.parent().unwrap()).await
+            .map_err(|e| format!("cannot create {}: {}", bundle_dir.display(), e))?;
+        tokio::fs::copy(&src, &dest).await
+            .map_err(|e| format!("cannot copy {} to {}: {}", src.display(), dest.display(), e))?;
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+// Approximate tokenizers are compiled in, for when the real one can't be loaded. They are not BPE tokenizers
+// but splitters, good for counting only: text is split with a regex in the style of the family's pre-tokenizer,
+// capping the length of each piece so the number of pieces is close to the number of BPE tokens, much closer
+// for code than estimate_tokens(). There's no vocabulary, every piece is <unk> and decode() can't give the
+// text back. Special tokens of the family are single tokens, as scratchpads check with assert_one_token().
+struct ApproxTokenizerFamily {
+    name: &'static str,
+    max_letters: usize,
+    max_digits: usize,
+    max_punct: usize,
+    special_tokens: &'static [&'static str],
+}
+
+const APPROX_TOKENIZER_FAMILIES: &[ApproxTokenizerFamily] = &[
+    // gpt-4o and newer openai models
+    ApproxTokenizerFamily { name: "o200k", max_letters: 9, max_digits: 3, max_punct: 3, special_tokens: &[
+        "<|endoftext|>", "<|endofprompt|>",
+    ] },
+    // gpt-4, gpt-3.5, the default
+    ApproxTokenizerFamily { name: "cl100k", max_letters: 8, max_digits: 3, max_punct: 3, special_tokens: &[
+        "<|endoftext|>", "<|fim_prefix|>", "<|fim_middle|>", "<|fim_suffix|>", "<|endofprompt|>",
+    ] },
+    // llama 3, tiktoken-based with a bigger vocab
+    ApproxTokenizerFamily { name: "llama3", max_letters: 8, max_digits: 3, max_punct: 3, special_tokens: &[
+        "<|begin_of_text|>", "<|end_of_text|>", "<|start_header_id|>", "<|end_header_id|>", "<|eot_id|>", "<|eom_id|>", "<|python_tag|>",
+    ] },
+    ApproxTokenizerFamily { name: "qwen", max_letters: 8, max_digits: 1, max_punct: 3, special_tokens: &[
+        "<|endoftext|>", "<|im_start|>", "<|im_end|>", "<|fim_prefix|>", "<|fim_middle|>", "<|fim_suffix|>", "<|fim_pad|>",
+        "<|repo_name|>", "<|file_sep|>",
+    ] },
+    ApproxTokenizerFamily { name: "starcoder", max_letters: 7, max_digits: 1, max_punct: 3, special_tokens: &[
+        "<|endoftext|>", "<fim_prefix>", "<fim_middle>", "<fim_suffix>", "<fim_pad>", "<filename>", "<file_sep>", "<repo_name>",
+    ] },
+    ApproxTokenizerFamily { name: "deepseek", max_letters: 7, max_digits: 1, max_punct: 3, special_tokens: &[
+        "<｜begin▁of▁sentence｜>", "<｜end▁of▁sentence｜>", "<｜fim▁begin｜>", "<｜fim▁hole｜>", "<｜fim▁end｜>", "<|EOT|>",
+    ] },
+    // llama 2, codellama, mistral: small sentencepiece vocab
+    ApproxTokenizerFamily { name: "llama", max_letters: 5, max_digits: 1, max_punct: 2, special_tokens: &[
+        "<s>", "</s>", "<PRE>", "<SUF>", "<MID>", "<EOT>",
+    ] },
+];
+
+// Substrings of the tokenizer or model id, the first match wins
+const APPROX_TOKENIZER_HINTS: &[(&str, &str)] = &[
+    ("gpt-4o", "o200k"), ("gpt-4.1", "o200k"), ("gpt-5", "o200k"), ("/o1", "o200k"), ("/o3", "o200k"), ("/o4", "o200k"),
+    ("gpt-4", "cl100k"), ("gpt-3.5", "cl100k"), ("text-embedding", "cl100k"),
+    ("llama-3", "llama3"), ("llama3", "llama3"),
+    ("qwen", "qwen"), ("qwq", "qwen"),
+    ("deepseek", "deepseek"), ("magicoder", "deepseek"),
+    ("starcoder", "starcoder"), ("refact", "starcoder"), ("stable-code", "starcoder"),
+    ("llama", "llama"), ("mistral", "llama"), ("wizardlm", "llama"), ("phind", "llama"),
+];
+
+const APPROX_TOKENIZER_PREFIX: &str = "approx://";
+const APPROX_TOKENIZER_UNK: &str = "<unk>";
+const TOKENIZERS_BUNDLE_DIR: &str = "bundle";
+
+/// Family of the approximate tokenizer for a model id or tokenizer name
+fn approximate_tokenizer_family_by_hint(hint: &str) -> &'static str {
+    let hint = hint.to_lowercase();
+    APPROX_TOKENIZER_HINTS.iter()
+        .find(|(substring, _)| hint.contains(substring))
+        .map(|(_, family)| *family)
+        .unwrap_or("cl100k")
+}
+
+/// An explicit `approx://` tokenizer first, then `model_family` of completion models, then guessing by names
+fn approximate_tokenizer_family(model_rec: &BaseModelRecord, model_family: Option<CompletionModelFamily>) -> &'static str {
+    if let Some(family) = model_rec.tokenizer.strip_prefix(APPROX_TOKENIZER_PREFIX) {
+        if let Some(family) = APPROX_TOKENIZER_FAMILIES.iter().find(|f| f.name == family) {
+            return family.name;
+        }
+    }
+    match model_family {
+        Some(CompletionModelFamily::Qwen2_5CoderBase) => return "qwen",
+        Some(CompletionModelFamily::Starcoder) => return "starcoder",
+        Some(CompletionModelFamily::DeepseekCoder) => return "deepseek",
+        None => {},
+    }
+    approximate_tokenizer_family_by_hint(&format!("{} {}", model_rec.tokenizer, model_rec.id))
+}
+
+pub fn approximate_tokenizer(family: &str) -> Result<Tokenizer, String> {
+    let family = APPROX_TOKENIZER_FAMILIES.iter()
+        .find(|f| f.name == family)
+        .ok_or_else(|| format!("unknown approximate tokenizer \"{}\", known are {}", family,
+            APPROX_TOKENIZER_FAMILIES.iter().map(|f| f.name).join(", ")))?;
+    let (letters, digits, punct) = (family.max_letters, family.max_digits, family.max_punct);
+    // words split on case changes: getUserName -> get User Name
+    let pattern = format!(
+        r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{{L}}\p{{N}}]?(?:\p{{Lu}}?\p{{Ll}}{{1,{letters}}}|\p{{Lu}}{{1,{letters}}}|\p{{L}}{{1,{letters}}})|\p{{N}}{{1,{digits}}}| ?[^\s\p{{L}}\p{{N}}]{{1,{punct}}}[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+"
+    );
+    let mut vocab = serde_json::Map::new();
+    vocab.insert(APPROX_TOKENIZER_UNK.to_string(), serde_json::json!(0));
+    let added_tokens = family.special_tokens.iter().enumerate().map(|(i, token)| {
+        vocab.insert(token.to_string(), serde_json::json!(i + 1));
+        serde_json::json!({
+            "id": i + 1, "content": token, "single_word": false, "lstrip": false, "rstrip": false,
+            "normalized": false, "special": true,
+        })
+    }).collect::<Vec<_>>();
+    let tokenizer_json = serde_json::json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": added_tokens,
+        "normalizer": null,
+        "pre_tokenizer": {"type": "Split", "pattern": {"Regex": pattern}, "behavior": "Isolated", "invert": false},
+        "post_processor": null,
+        "decoder": null,
+        "model": {"type": "WordLevel", "vocab": vocab, "unk_token": APPROX_TOKENIZER_UNK},
+    });
+    Tokenizer::from_str(&tokenizer_json.to_string())
+        .map_err(|e| format!("failed to build approximate tokenizer {}: {}", family.name, e))
 }
 
 /// Estimate as length / 3.5, since 3 is reasonable estimate for code, and 4 for natural language
@@ -223,4 +457,66 @@ pub fn count_text_tokens_with_fallback(
         tracing::error!("{e}");
         estimate_tokens(text)
     })
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+    use crate::global_context::{create_global_context_with_cmdline, CommandLine};
+
+    #[test]
+    fn test_approximate_tokenizers() {
+        let code = "def getUserName(self, user_id: int) -> str:\n    return self.users[user_id].name  # 12345\n";
+        for family in APPROX_TOKENIZER_FAMILIES {
+            let tokenizer = Arc::new(approximate_tokenizer(family.name).unwrap());
+            let n = count_text_tokens(Some(tokenizer.clone()), code).unwrap();
+            assert!((20..40).contains(&n), "{} counted {} tokens", family.name, n);
+            assert_eq!(count_text_tokens(Some(tokenizer.clone()), "getUserName").unwrap(), 3);
+            for token in family.special_tokens {
+                assert_eq!(count_text_tokens(Some(tokenizer.clone()), token).unwrap(), 1, "{} {}", family.name, token);
+            }
+        }
+        let qwen = Arc::new(approximate_tokenizer("qwen").unwrap());
+        assert_eq!(count_text_tokens(Some(qwen), "<|fim_prefix|>def<|fim_suffix|><|fim_middle|>").unwrap(), 4);
+        assert!(approximate_tokenizer("unknown").is_err());
+
+        assert_eq!(approximate_tokenizer_family_by_hint("hf://Xenova/gpt-4o"), "o200k");
+        assert_eq!(approximate_tokenizer_family_by_hint("hf://Qwen/Qwen2.5-Coder-1.5B"), "qwen");
+        assert_eq!(approximate_tokenizer_family_by_hint("hf://bigcode/starcoder2-3b"), "starcoder");
+        assert_eq!(approximate_tokenizer_family_by_hint("hf://Xenova/Meta-Llama-3.1-Tokenizer"), "llama3");
+        assert_eq!(approximate_tokenizer_family_by_hint("ollama/codellama:7b"), "llama");
+        assert_eq!(approximate_tokenizer_family_by_hint("anthropic/claude-3-7-sonnet"), "cl100k");
+
+        let model_rec = BaseModelRecord { id: "ollama/my-coder:7b".to_string(), ..Default::default() };
+        assert_eq!(approximate_tokenizer_family(&model_rec, None), "cl100k");
+        assert_eq!(approximate_tokenizer_family(&model_rec, Some(CompletionModelFamily::Starcoder)), "starcoder");
+        let model_rec = BaseModelRecord { tokenizer: "approx://llama".to_string(), ..model_rec };
+        assert_eq!(approximate_tokenizer_family(&model_rec, Some(CompletionModelFamily::Starcoder)), "llama");
+    }
+
+    #[tokio::test]
+    async fn test_fallback_tokenizer_is_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let cmdline = CommandLine::from_iter(["refact-lsp"]);
+        let (gcx, _ask_shutdown_receiver, _) = create_global_context_with_cmdline(dir.path().join("cache"), dir.path().join("config"), cmdline).await;
+        let tokenizer_path = dir.path().join("tokenizer.json");
+        let model_rec = BaseModelRecord {
+            id: "local/qwen-coder".to_string(),
+            tokenizer: tokenizer_path.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+
+        assert!(cached_tokenizer(gcx.clone(), &model_rec).await.unwrap().is_some());
+        assert!(gcx.read().await.tokenizer_map.get("local/qwen-coder").is_none());
+        assert!(gcx.read().await.tokenizer_fallbacks.contains_key("local/qwen-coder"));
+
+        approximate_tokenizer("qwen").unwrap().save(&tokenizer_path, false).unwrap();
+        gcx.write().await.tokenizer_fallbacks.get_mut("local/qwen-coder").unwrap().1 = std::time::Instant::now();
+        assert!(cached_tokenizer(gcx.clone(), &model_rec).await.unwrap().is_some());
+        assert!(gcx.read().await.tokenizer_map.get("local/qwen-coder").is_some());
+        assert!(gcx.read().await.tokenizer_fallbacks.is_empty());
+
+        let no_tokenizer = BaseModelRecord { id: "local/no-tokenizer".to_string(), ..Default::default() };
+        assert!(cached_tokenizer(gcx.clone(), &no_tokenizer).await.is_err());
+    }
+}
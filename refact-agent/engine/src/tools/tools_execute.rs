@@ -9,11 +9,12 @@ use tracing::{info, warn};
 
 use crate::at_commands::at_commands::AtCommandsContext;
 use crate::at_commands::execute_at::MIN_RAG_CONTEXT_LIMIT;
-use crate::call_validation::{ChatContent, ChatMessage, ChatModelType, ChatUsage, ContextEnum, ContextFile, SubchatParameters};
+use crate::call_validation::{ChatContent, ChatMessage, ChatModelType, ChatToolCall, ChatUsage, ContextEnum, ContextFile, SubchatParameters};
 use crate::custom_error::MapErrToString;
 use crate::global_context::try_load_caps_quickly_if_not_present;
 use crate::http::http_post_json;
 use crate::integrations::docker::docker_container_manager::docker_container_get_host_lsp_port_to_connect;
+use crate::integrations::docker::docker_compose::compose_tools_for_chat;
 use crate::postprocessing::pp_context_files::postprocess_context_files;
 use crate::postprocessing::pp_plain_text::postprocess_plain_text;
 use crate::scratchpads::scratchpad_utils::{HasRagResults, max_tokens_for_rag_chat_by_tools};
@@ -102,32 +103,80 @@ pub async fn run_tools_remotely(
         )
     };
 
-    let port = docker_container_get_host_lsp_port_to_connect(gcx.clone(), &chat_id).await?;
-    info!("run_tools_remotely: connecting to port {}", port);
-
-    let tools_execute_post = ToolsExecutePost {
-        messages: original_messages.to_vec(),
-        n_ctx,
-        maxgen,
-        subchat_tool_parameters,
-        postprocess_parameters,
-        model_name: model_id.to_string(),
-        chat_id,
-        style: style.clone(),
-    };
+    // Some tools (docker compose) live on the host, next to the container, they are not visible from inside
+    let mut host_tools: IndexMap<String, Box<dyn Tool + Send>> = compose_tools_for_chat(gcx.clone(), &chat_id).await.into_iter()
+        .map(|tool| (tool.tool_description().name, tool)).collect();
+    let (remote_messages, host_messages) = split_last_tool_calls(original_messages, |name| host_tools.contains_key(name));
+
+    let mut new_messages = vec![];
+    let mut tools_ran = false;
+    if let Some(host_messages) = host_messages {
+        let (host_new_messages, host_tools_ran) = run_tools(
+            ccx.clone(), &mut host_tools, None, maxgen, &host_messages, style
+        ).await?;
+        new_messages.extend(host_new_messages);
+        tools_ran |= host_tools_ran;
+    }
 
-    let url = format!("http://localhost:{port}/v1/tools-execute");
-    let response: ToolExecuteResponse = http_post_json(&url, &tools_execute_post).await?;
-    info!("run_tools_remotely: got response: {:?}", response);
+    if let Some(remote_messages) = remote_messages {
+        let port = docker_container_get_host_lsp_port_to_connect(gcx.clone(), &chat_id).await?;
+        info!("run_tools_remotely: connecting to port {}", port);
+
+        let tools_execute_post = ToolsExecutePost {
+            messages: remote_messages,
+            n_ctx,
+            maxgen,
+            subchat_tool_parameters,
+            postprocess_parameters,
+            model_name: model_id.to_string(),
+            chat_id,
+            style: style.clone(),
+        };
 
-    let mut all_messages = tools_execute_post.messages;
+        let url = format!("http://localhost:{port}/v1/tools-execute");
+        let response: ToolExecuteResponse = http_post_json(&url, &tools_execute_post).await?;
+        info!("run_tools_remotely: got response: {:?}", response);
+        new_messages.extend(response.messages);
+        tools_ran |= response.tools_ran;
+    }
 
-    for msg in response.messages {
+    // tool results must go first
+    new_messages.sort_by_key(|msg| msg.role != "tool");
+
+    let mut all_messages = original_messages.to_vec();
+    for msg in new_messages {
         stream_back_to_user.push_in_json(json!(&msg));
         all_messages.push(msg);
     }
 
-    Ok((all_messages, response.tools_ran))
+    Ok((all_messages, tools_ran))
+}
+
+/// Splits tool calls of the last assistant message into two copies of the history: the calls that should run
+/// remotely, and the calls that match `is_host_tool`. `None` means there is nothing to run on that side.
+fn split_last_tool_calls(
+    original_messages: &[ChatMessage],
+    is_host_tool: impl Fn(&str) -> bool,
+) -> (Option<Vec<ChatMessage>>, Option<Vec<ChatMessage>>) {
+    let last_msg_tool_calls = match original_messages.last().filter(|m| m.role == "assistant") {
+        Some(m) => m.tool_calls.clone().unwrap_or(vec![]),
+        None => vec![],
+    };
+    let (host_calls, remote_calls): (Vec<_>, Vec<_>) = last_msg_tool_calls.into_iter()
+        .partition(|t_call| is_host_tool(&t_call.function.name));
+    if host_calls.is_empty() {
+        return (Some(original_messages.to_vec()), None);
+    }
+
+    let with_tool_calls = |tool_calls: Vec<ChatToolCall>| {
+        let mut messages = original_messages.to_vec();
+        if let Some(last_msg) = messages.last_mut() {
+            last_msg.tool_calls = Some(tool_calls);
+        }
+        messages
+    };
+    let remote_messages = if remote_calls.is_empty() { None } else { Some(with_tool_calls(remote_calls)) };
+    (remote_messages, Some(with_tool_calls(host_calls)))
 }
 
 pub async fn run_tools_locally(
@@ -435,5 +484,52 @@ pub fn update_usage_from_message(usage: &mut ChatUsage, message: &ChatMessage) {
         usage.total_tokens += u.total_tokens;
         usage.completion_tokens += u.completion_tokens;
         usage.prompt_tokens += u.prompt_tokens;
+        if let Some(cost) = u.cost {
+            usage.cost = Some(usage.cost.unwrap_or(0.0) + cost);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::call_validation::ChatToolFunction;
+
+    fn tool_call(id: &str, name: &str) -> ChatToolCall {
+        ChatToolCall {
+            id: id.to_string(),
+            function: ChatToolFunction { name: name.to_string(), arguments: "{}".to_string() },
+            tool_type: "function".to_string(),
+        }
+    }
+
+    fn tool_call_names(messages: &Option<Vec<ChatMessage>>) -> Vec<String> {
+        messages.as_ref().unwrap().last().unwrap().tool_calls.as_ref().unwrap()
+            .iter().map(|t| t.function.name.clone()).collect()
+    }
+
+    #[test]
+    fn test_split_last_tool_calls() {
+        let messages = vec![
+            ChatMessage::new("user".to_string(), "up the stack".to_string()),
+            ChatMessage {
+                role: "assistant".to_string(),
+                tool_calls: Some(vec![tool_call("call_1", "cat"), tool_call("call_2", "compose_up"), tool_call("call_3", "tree")]),
+                ..Default::default()
+            },
+        ];
+        let (remote, host) = split_last_tool_calls(&messages, |name| name.starts_with("compose_"));
+        assert_eq!(tool_call_names(&remote), vec!["cat", "tree"]);
+        assert_eq!(tool_call_names(&host), vec!["compose_up"]);
+        assert_eq!(remote.as_ref().unwrap().len(), 2);
+        assert_eq!(host.as_ref().unwrap()[0].content.content_text_only(), "up the stack");
+
+        let (remote, host) = split_last_tool_calls(&messages, |_| true);
+        assert!(remote.is_none());
+        assert_eq!(tool_call_names(&host), vec!["cat", "compose_up", "tree"]);
+
+        let (remote, host) = split_last_tool_calls(&messages, |_| false);
+        assert_eq!(tool_call_names(&remote), vec!["cat", "compose_up", "tree"]);
+        assert!(host.is_none());
     }
 }
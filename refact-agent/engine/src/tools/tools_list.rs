@@ -164,6 +164,7 @@ async fn get_integration_tools(
     };
 
     let mut mcp_groups = HashMap::new();
+    let mut openapi_groups = HashMap::new();
 
     let (integrations_map, _yaml_errors) = load_integrations(gcx.clone(), &["**/*".to_string()]).await;
     for (name, integr) in integrations_map {
@@ -188,6 +189,15 @@ async fn get_integration_tools(
                 }
                 mcp_groups.entry(mcp_server_name.to_string())
                     .and_modify(|group| group.tools.push(tool));
+            } else if name.starts_with("openapi_") {
+                openapi_groups.entry(name.clone())
+                    .or_insert_with(|| ToolGroup {
+                        name: format!("OpenAPI {}", name.trim_start_matches("openapi_")),
+                        description: format!("Tools generated from the OpenAPI spec in {}", name),
+                        category: ToolGroupCategory::Integration,
+                        tools: vec![],
+                    })
+                    .tools.push(tool);
             } else {
                 integrations_group.tools.push(tool);
             }
@@ -196,6 +206,7 @@ async fn get_integration_tools(
 
     let mut tool_groups = vec![integrations_group];
     tool_groups.extend(mcp_groups.into_values());
+    tool_groups.extend(openapi_groups.into_values());
 
     for tool_group in tool_groups.iter_mut() {
         tool_group.retain_available_tools(gcx.clone()).await;
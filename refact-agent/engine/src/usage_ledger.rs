@@ -0,0 +1,276 @@
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicI64, Ordering};
+use chrono::TimeZone;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex as AMutex;
+use tokio::sync::RwLock as ARwLock;
+
+use crate::call_validation::ChatUsage;
+use crate::caps::{BaseModelRecord, ModelPricing};
+use crate::global_context::GlobalContext;
+
+// Every model call that reports usage (chat, subchat, tool, code completion) is appended to
+// cache_dir/usage_ledger.jsonl with the cost from `pricing` of the model, /v1/usage-costs sums it up.
+// Entries older than --usage-ledger-keep-days are dropped from the file, at most once an hour.
+
+const LEDGER_FILENAME: &str = "usage_ledger.jsonl";
+const LEDGER_CLEANUP_INTERVAL: i64 = 3600;  // seconds
+
+static LEDGER_LOCK: OnceLock<AMutex<()>> = OnceLock::new();
+static LEDGER_LAST_CLEANUP_TS: AtomicI64 = AtomicI64::new(0);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LedgerEntry {
+    pub ts: i64,
+    pub model: String,
+    #[serde(default)]
+    pub chat_id: String,
+    pub scope: String,
+    #[serde(flatten)]
+    pub usage: ChatUsage,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct UsageSummary {
+    pub requests: usize,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub cache_read_input_tokens: usize,
+    pub cache_creation_input_tokens: usize,
+    pub reasoning_tokens: usize,
+    pub cost: f64,
+    /// Requests of models without pricing, not in `cost`
+    pub unpriced_requests: usize,
+}
+
+impl UsageSummary {
+    fn add(&mut self, usage: &ChatUsage) {
+        self.requests += 1;
+        self.prompt_tokens += usage.prompt_tokens;
+        self.completion_tokens += usage.completion_tokens;
+        self.cache_read_input_tokens += usage.cache_read_input_tokens.unwrap_or(0);
+        self.cache_creation_input_tokens += usage.cache_creation_input_tokens.unwrap_or(0);
+        self.reasoning_tokens += usage.reasoning_tokens.unwrap_or(0);
+        match usage.cost {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced_requests += 1,
+        }
+    }
+}
+
+/// OpenAI style has cached and reasoning tokens in prompt_tokens_details and completion_tokens_details
+fn usage_from_value(usage: &Value) -> Option<ChatUsage> {
+    let mut parsed = serde_json::from_value::<ChatUsage>(usage.clone()).ok()?;
+    if parsed.cache_read_input_tokens.is_none() {
+        parsed.cache_read_input_tokens = usage["prompt_tokens_details"]["cached_tokens"].as_u64().map(|x| x as usize);
+    }
+    if parsed.reasoning_tokens.is_none() {
+        parsed.reasoning_tokens = usage["completion_tokens_details"]["reasoning_tokens"].as_u64().map(|x| x as usize);
+    }
+    Some(parsed)
+}
+
+pub fn usage_cost(pricing: &ModelPricing, usage: &ChatUsage) -> f64 {
+    let cached = usage.cache_read_input_tokens.unwrap_or(0).min(usage.prompt_tokens);
+    let written = usage.cache_creation_input_tokens.unwrap_or(0).min(usage.prompt_tokens - cached);
+    let reasoning = usage.reasoning_tokens.unwrap_or(0).min(usage.completion_tokens);
+    let per_million = (usage.prompt_tokens - cached - written) as f64 * pricing.input
+        + cached as f64 * pricing.cached_input.unwrap_or(pricing.input)
+        + written as f64 * pricing.cache_write_input.unwrap_or(pricing.input)
+        + (usage.completion_tokens - reasoning) as f64 * pricing.output
+        + reasoning as f64 * pricing.reasoning.unwrap_or(pricing.output);
+    per_million / 1_000_000.0
+}
+
+/// Puts the cost into `usage` of a model response (openai style), returns the usage to record after the call
+pub fn add_cost_to_response(model_rec: &BaseModelRecord, response: &mut Value) -> Option<ChatUsage> {
+    let usage_value = response.get_mut("usage").filter(|x| x.is_object())?;
+    let mut usage = usage_from_value(usage_value)?;
+    if let Some(pricing) = &model_rec.pricing {
+        let cost = usage_cost(pricing, &usage);
+        usage.cost = Some(cost);
+        usage_value["cost"] = json!(cost);
+    }
+    Some(usage)
+}
+
+pub async fn record_usage(
+    gcx: Arc<ARwLock<GlobalContext>>,
+    model_rec: &BaseModelRecord,
+    chat_id: &str,
+    scope: &str,
+    usage: ChatUsage,
+) {
+    let entry = LedgerEntry {
+        ts: chrono::Local::now().timestamp(),
+        model: model_rec.id.clone(),
+        chat_id: chat_id.to_string(),
+        scope: scope.to_string(),
+        usage,
+    };
+    let (path, keep_days) = {
+        let gcx_locked = gcx.read().await;
+        (gcx_locked.cache_dir.join(LEDGER_FILENAME), gcx_locked.cmdline.usage_ledger_keep_days)
+    };
+    if keep_days == 0 {
+        return;
+    }
+    let line = format!("{}\n", serde_json::to_string(&entry).unwrap());
+    let _ledger_locked = LEDGER_LOCK.get_or_init(|| AMutex::new(())).lock().await;
+    let res = async {
+        let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(&path).await?;
+        file.write_all(line.as_bytes()).await
+    }.await;
+    if let Err(e) = res {
+        tracing::error!("cannot write {}: {}", path.display(), e);
+    }
+    if entry.ts - LEDGER_LAST_CLEANUP_TS.load(Ordering::Relaxed) >= LEDGER_CLEANUP_INTERVAL {
+        LEDGER_LAST_CLEANUP_TS.store(entry.ts, Ordering::Relaxed);
+        if let Err(e) = ledger_cleanup(&path, entry.ts - keep_days as i64 * 86400).await {
+            tracing::error!("cannot clean up {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Entries are appended in order, so the old ones are at the start of the file
+fn lines_to_keep(ledger: &str, keep_since: i64) -> Option<&str> {
+    let first_to_keep = ledger.lines()
+        .position(|line| serde_json::from_str::<LedgerEntry>(line).map_or(false, |entry| entry.ts >= keep_since))
+        .unwrap_or(ledger.lines().count());
+    if first_to_keep == 0 {
+        return None;
+    }
+    let offset = ledger.split_inclusive('\n').take(first_to_keep).map(|x| x.len()).sum::<usize>();
+    Some(&ledger[offset..])
+}
+
+async fn ledger_cleanup(path: &std::path::Path, keep_since: i64) -> Result<(), String> {
+    let ledger = match tokio::fs::read_to_string(path).await {
+        Ok(ledger) => ledger,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.to_string()),
+    };
+    let Some(kept) = lines_to_keep(&ledger, keep_since) else { return Ok(()) };
+    let tmp_path = path.with_extension("jsonl.tmp");
+    tokio::fs::write(&tmp_path, kept).await.map_err(|e| e.to_string())?;
+    tokio::fs::rename(&tmp_path, path).await.map_err(|e| e.to_string())?;
+    tracing::info!("usage ledger: removed {} entries", ledger.lines().count() - kept.lines().count());
+    Ok(())
+}
+
+fn summarize_ledger(ledger: &str, since_ts: i64) -> Value {
+    let mut total = UsageSummary::default();
+    let mut by_day: IndexMap<String, UsageSummary> = IndexMap::new();
+    let mut by_model: IndexMap<String, UsageSummary> = IndexMap::new();
+    let mut by_chat: IndexMap<String, UsageSummary> = IndexMap::new();
+    for line in ledger.lines().filter(|x| !x.trim().is_empty()) {
+        let entry = match serde_json::from_str::<LedgerEntry>(line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                tracing::warn!("skipping a broken line in {}: {}", LEDGER_FILENAME, e);
+                continue;
+            }
+        };
+        if entry.ts < since_ts {
+            continue;
+        }
+        let day = chrono::Local.timestamp_opt(entry.ts, 0).single()
+            .map(|dt| dt.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        total.add(&entry.usage);
+        by_day.entry(day).or_default().add(&entry.usage);
+        by_model.entry(entry.model).or_default().add(&entry.usage);
+        by_chat.entry(entry.chat_id).or_default().add(&entry.usage);
+    }
+    by_day.sort_keys();
+    json!({
+        "total": total,
+        "by_day": by_day,
+        "by_model": by_model,
+        "by_chat": by_chat,
+    })
+}
+
+/// Spend since `since_ts` in total and by day (local time), model and chat, limited by how long the ledger is kept
+pub async fn summarize_usage(gcx: Arc<ARwLock<GlobalContext>>, since_ts: i64) -> Result<Value, String> {
+    let (path, keep_days) = {
+        let gcx_locked = gcx.read().await;
+        (gcx_locked.cache_dir.join(LEDGER_FILENAME), gcx_locked.cmdline.usage_ledger_keep_days)
+    };
+    let since_ts = since_ts.max(chrono::Local::now().timestamp() - keep_days as i64 * 86400);
+    let ledger = match tokio::fs::read_to_string(&path).await {
+        Ok(ledger) => ledger,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+    };
+    Ok(summarize_ledger(&ledger, since_ts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_cost_and_summary() {
+        let model_rec = BaseModelRecord {
+            id: "openai/o4-mini".to_string(),
+            pricing: Some(ModelPricing { input: 1.0, cached_input: Some(0.25), cache_write_input: None, output: 4.0, reasoning: None }),
+            ..Default::default()
+        };
+        let mut response = json!({"usage": {
+            "prompt_tokens": 1_000_000, "completion_tokens": 500_000, "total_tokens": 1_500_000,
+            "prompt_tokens_details": {"cached_tokens": 400_000},
+            "completion_tokens_details": {"reasoning_tokens": 100_000},
+        }});
+        let usage = add_cost_to_response(&model_rec, &mut response).unwrap();
+        // 0.6 + 0.1 + 1.6 + 0.4
+        assert!((usage.cost.unwrap() - 2.7).abs() < 1e-9);
+        assert_eq!(response["usage"]["cost"], usage.cost.unwrap());
+        assert_eq!(usage.reasoning_tokens, Some(100_000));
+
+        let unpriced = add_cost_to_response(&BaseModelRecord::default(), &mut json!({"usage": {
+            "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15,
+        }})).unwrap();
+        assert!(unpriced.cost.is_none());
+        assert!(add_cost_to_response(&model_rec, &mut json!({"choices": []})).is_none());
+
+        let ts = chrono::Local::now().timestamp();
+        let ledger = [
+            LedgerEntry { ts, model: "openai/o4-mini".to_string(), chat_id: "chat1".to_string(), scope: "chat-stream".to_string(), usage: usage.clone() },
+            LedgerEntry { ts, model: "openai/o4-mini".to_string(), chat_id: "chat1".to_string(), scope: "subchat".to_string(), usage },
+            LedgerEntry { ts, model: "ollama/qwen".to_string(), chat_id: "chat2".to_string(), scope: "chat-stream".to_string(), usage: unpriced },
+            LedgerEntry { ts: ts - 100 * 86400, model: "openai/o4-mini".to_string(), chat_id: "old".to_string(), scope: "chat".to_string(), usage: ChatUsage::default() },
+        ].iter().map(|x| serde_json::to_string(x).unwrap()).collect::<Vec<_>>().join("\n") + "\nbroken line\n";
+        let summary = summarize_ledger(&ledger, ts - 86400);
+        assert_eq!(summary["total"]["requests"], 3);
+        assert_eq!(summary["total"]["unpriced_requests"], 1);
+        assert!((summary["total"]["cost"].as_f64().unwrap() - 5.4).abs() < 1e-9);
+        assert_eq!(summary["by_model"]["openai/o4-mini"]["requests"], 2);
+        assert_eq!(summary["by_chat"]["chat2"]["prompt_tokens"], 10);
+        assert!(summary["by_chat"].get("old").is_none());
+        assert_eq!(summary["by_day"].as_object().unwrap().len(), 1);
+
+        let expired = LedgerEntry { ts: ts - 100 * 86400, model: "openai/o4-mini".to_string(), chat_id: "expired".to_string(), scope: "chat".to_string(), usage: ChatUsage::default() };
+        let with_expired = format!("{}\n{}", serde_json::to_string(&expired).unwrap(), ledger);
+        assert_eq!(lines_to_keep(&with_expired, ts - 86400), Some(ledger.as_str()));
+        assert_eq!(lines_to_keep(&ledger, ts - 86400), None);
+    }
+
+    #[test]
+    fn test_cache_write_cost() {
+        let pricing = ModelPricing { input: 3.0, cached_input: Some(0.3), cache_write_input: Some(3.75), output: 15.0, reasoning: None };
+        // prompt_tokens include what was read from and written to the cache
+        let usage = ChatUsage {
+            prompt_tokens: 1_100_000, completion_tokens: 0, total_tokens: 1_100_000,
+            cache_creation_input_tokens: Some(1_000_000), cache_read_input_tokens: Some(0),
+            ..Default::default()
+        };
+        // 0.3 + 3.75
+        assert!((usage_cost(&pricing, &usage) - 4.05).abs() < 1e-9);
+        let without_write_price = ModelPricing { cache_write_input: None, ..pricing };
+        assert!((usage_cost(&without_write_price, &usage) - 3.3).abs() < 1e-9);
+    }
+}
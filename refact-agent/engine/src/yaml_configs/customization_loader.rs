@@ -5,7 +5,7 @@ use std::sync::Arc;
 use indexmap::IndexMap;
 use tokio::sync::RwLock as ARwLock;
 
-use crate::call_validation::{ChatMessage, SubchatParameters};
+use crate::call_validation::{ChatContent, ChatMessage, SubchatParameters};
 use crate::global_context::{GlobalContext, try_load_caps_quickly_if_not_present};
 use crate::custom_error::YamlError;
 
@@ -238,6 +238,26 @@ pub async fn load_customization(
     )
 }
 
+/// Same expansion IDE plugins do before opening a chat from a toolbox command or a code lens
+pub fn fill_in_ide_variables(
+    messages: &Vec<ChatMessage>,
+    current_file: &str,
+    cursor_line: usize,
+    code_selection: &str,
+) -> Vec<ChatMessage> {
+    messages.iter().map(|msg| {
+        let mut msg = msg.clone();
+        if let ChatContent::SimpleText(text) = &msg.content {
+            // selection goes last, it's user text and might contain anything
+            msg.content = ChatContent::SimpleText(text
+                .replace("%CURRENT_FILE%", current_file)
+                .replace("%CURSOR_LINE%", &cursor_line.to_string())
+                .replace("%CODE_SELECTION%", code_selection));
+        }
+        msg
+    }).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -258,4 +278,15 @@ mod tests {
         assert_eq!(config.system_prompts.get("configurator").is_some(), true);
         assert_eq!(config.system_prompts.get("project_summary").is_some(), true);
     }
+
+    #[test]
+    fn test_fill_in_ide_variables() {
+        let mut error_log = Vec::new();
+        let config = load_and_mix_with_users_config("", "", true, true, &mut error_log);
+        let messages = fill_in_ide_variables(&config.code_lens["explain"].messages, "/home/user/goat.py", 42, "def jump(): %CURSOR_LINE%");
+        let text = messages[0].content.content_text_only();
+        assert!(text.contains("@file /home/user/goat.py:42\n"));
+        assert!(text.contains("def jump(): %CURSOR_LINE%"));
+        assert_eq!(messages[0].role, "user");
+    }
 }
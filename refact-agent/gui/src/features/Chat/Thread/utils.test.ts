@@ -6,9 +6,15 @@ import {
   PlainTextResponse,
   UserMessage,
   UserMessageResponse,
+  type ThinkingBlock,
   type ToolCall,
 } from "../../../services/refact";
-import { mergeToolCalls, formatChatResponse, consumeStream } from "./utils";
+import {
+  mergeToolCalls,
+  mergeThinkingBlocks,
+  formatChatResponse,
+  consumeStream,
+} from "./utils";
 
 describe("formatChatResponse", () => {
   test("it should replace the last user message", () => {
@@ -1673,6 +1679,62 @@ describe("mergeToolCalls", () => {
   });
 });
 
+describe("mergeThinkingBlocks", () => {
+  test("keeps reasoning items with different indexes apart", () => {
+    const first = {
+      type: "reasoning",
+      id: "rs_1",
+      summary: [],
+      encrypted_content: "aaa",
+      index: 0,
+    } as ThinkingBlock;
+    const second = {
+      type: "reasoning",
+      id: "rs_2",
+      summary: [],
+      encrypted_content: "bbb",
+      index: 1,
+    } as ThinkingBlock;
+
+    const result = mergeThinkingBlocks(mergeThinkingBlocks([], [first]), [
+      second,
+    ]);
+
+    expect(result).toEqual([first, second]);
+  });
+
+  test("merges thinking deltas by index and keeps redacted data", () => {
+    const deltas: ThinkingBlock[] = [
+      { type: "thinking", thinking: "first ", signature: null, index: 0 },
+      { type: "thinking", thinking: "thought", signature: null, index: 0 },
+      { type: "thinking", thinking: "", signature: "sig1", index: 0 },
+      {
+        type: "redacted_thinking",
+        data: "opaque",
+        index: 1,
+      } as ThinkingBlock,
+      { type: "thinking", thinking: "second", signature: null, index: 2 },
+      { type: "thinking", thinking: "", signature: "sig2", index: 2 },
+    ];
+
+    const result = deltas.reduce<ThinkingBlock[]>(
+      (acc, delta) => mergeThinkingBlocks(acc, [delta]),
+      [],
+    );
+
+    expect(result).toEqual([
+      {
+        type: "thinking",
+        thinking: "first thought",
+        signature: "sig1",
+        index: 0,
+      },
+      { type: "redacted_thinking", data: "opaque", index: 1 },
+      { type: "thinking", thinking: "second", signature: "sig2", index: 2 },
+    ]);
+  });
+});
+
 function stringToUint8Array(str: string): Uint8Array {
   const encoder = new TextEncoder();
   return encoder.encode(str);
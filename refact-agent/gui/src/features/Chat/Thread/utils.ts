@@ -114,22 +114,38 @@ export function mergeToolCalls(prev: ToolCall[], add: ToolCall[]): ToolCall[] {
   }, prev);
 }
 
+function concatThinkingBlock(
+  block: ThinkingBlock,
+  add: ThinkingBlock,
+): ThinkingBlock {
+  const merged = { ...block, ...add };
+  if ("thinking" in block || "thinking" in add) {
+    merged.thinking = (block.thinking ?? "") + (add.thinking ?? "");
+  }
+  if ("signature" in block || "signature" in add) {
+    merged.signature = (block.signature ?? "") + (add.signature ?? "");
+  }
+  return merged;
+}
+
 function mergeThinkingBlock(
   prev: ThinkingBlock[],
   add: ThinkingBlock,
 ): ThinkingBlock[] {
   if (prev.length === 0) {
     return [add];
-  } else {
-    return [
-      {
-        ...prev[0],
-        thinking: (prev[0].thinking ?? "") + (add.thinking ?? ""),
-        signature: (prev[0].signature ?? "") + (add.signature ?? ""),
-      },
-      ...prev.slice(1),
-    ];
   }
+  // blocks streamed with an index are merged into the block with the same index
+  if (typeof add.index === "number") {
+    const position = prev.findIndex((block) => block.index === add.index);
+    if (position === -1) {
+      return [...prev, add];
+    }
+    return prev.map((block, i) =>
+      i === position ? concatThinkingBlock(block, add) : block,
+    );
+  }
+  return [concatThinkingBlock(prev[0], add), ...prev.slice(1)];
 }
 
 export function mergeThinkingBlocks(
@@ -1,6 +1,7 @@
 import { createSlice, type PayloadAction } from "@reduxjs/toolkit";
 import { chatResponse } from "../Chat";
 import { smallCloudApi } from "../../services/smallcloud";
+import { isModelSwitchResponse } from "../../services/refact/types";
 
 export type InformationSliceState = {
   message: string | null;
@@ -41,6 +42,11 @@ export const informationSlice = createSlice({
 
   extraReducers: (builder) => {
     builder.addMatcher(chatResponse.match, (state, action) => {
+      if (isModelSwitchResponse(action.payload) && !state.message) {
+        const { from, to, reason } = action.payload.model_switch;
+        state.message = `${from} is unavailable (${reason}), switched to ${to}`;
+        return state;
+      }
       if (
         state.dismissed &&
         "metering_balance" in action.payload &&
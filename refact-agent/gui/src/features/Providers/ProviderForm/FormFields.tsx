@@ -21,8 +21,18 @@ export const FormFields: FC<FormFieldsProps> = ({
 }) => {
   return Object.entries(fields).map(([key, value], idx) => {
     if (key === "endpoint_style" && providerData.name === "custom") {
-      const availableOptions: Provider["endpoint_style"][] = ["openai", "hf"];
-      const displayValues = ["OpenAI", "HuggingFace"];
+      const availableOptions: Provider["endpoint_style"][] = [
+        "openai",
+        "hf",
+        "anthropic",
+        "openai_responses",
+      ];
+      const displayValues = [
+        "OpenAI",
+        "HuggingFace",
+        "Anthropic",
+        "OpenAI Responses",
+      ];
       return (
         <Flex key={`${key}_${idx}`} direction="column">
           {toPascalCase(key)}
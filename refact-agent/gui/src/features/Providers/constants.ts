@@ -2,6 +2,7 @@ export const BEAUTIFUL_PROVIDER_NAMES: Record<string, string> = {
   refact: "Refact Cloud",
   refact_self_hosted: "Refact Self-Hosted",
   openai: "OpenAI",
+  azure_openai: "Azure OpenAI",
   openrouter: "OpenRouter",
   groq: "Groq", // not sure about this one
   anthropic: "Anthropic",
@@ -11,4 +12,5 @@ export const BEAUTIFUL_PROVIDER_NAMES: Record<string, string> = {
   lmstudio: "LM Studio",
   xai: "xAI",
   custom: "Custom Provider",
+  mock: "Mock (offline)",
 };
@@ -14,6 +14,7 @@ export const iconsMap: Record<string, JSX.Element> = {
   refact: <RefactIcon />,
   refact_self_hosted: <RefactIcon />,
   openai: <OpenAIIcon />,
+  azure_openai: <OpenAIIcon />,
   anthropic: <AnthropicIcon />,
   google_gemini: <GeminiIcon />,
   openrouter: <OpenRouterIcon />,
@@ -23,4 +24,5 @@ export const iconsMap: Record<string, JSX.Element> = {
   lmstudio: <LMStudioIcon />,
   xai: <XaiIcon />,
   custom: <CustomIcon />,
+  mock: <CustomIcon />,
 };
@@ -137,6 +137,7 @@ export type Usage = {
   prompt_tokens_details?: PromptTokenDetails | null;
   cache_creation_input_tokens?: number;
   cache_read_input_tokens?: number;
+  cost?: number;
 };
 
 // TODO: add config url
@@ -203,7 +203,7 @@ export const providersApi = createApi({
 
 export type Provider = {
   name: string;
-  endpoint_style: "openai" | "hf";
+  endpoint_style: "openai" | "hf" | "anthropic" | "openai_responses";
   chat_endpoint: string;
   completion_endpoint: string;
   embedding_endpoint: string;
@@ -261,7 +261,12 @@ export function isProvider(data: unknown): data is Provider {
     return false;
 
   if (typeof data.name !== "string") return false;
-  if (data.endpoint_style !== "openai" && data.endpoint_style !== "hf")
+  if (
+    data.endpoint_style !== "openai" &&
+    data.endpoint_style !== "hf" &&
+    data.endpoint_style !== "anthropic" &&
+    data.endpoint_style !== "openai_responses"
+  )
     return false;
   if (typeof data.chat_endpoint !== "string") return false;
   if (typeof data.completion_endpoint !== "string") return false;
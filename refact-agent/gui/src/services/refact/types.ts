@@ -370,9 +370,17 @@ export function isToolCallDelta(delta: unknown): delta is ToolCallDelta {
 }
 
 export type ThinkingBlock = {
-  type?: "thinking";
+  type?: "thinking" | "redacted_thinking" | "reasoning";
   thinking: null | string;
   signature: null | string;
+  // position of the block in the streamed response, deltas with the same index belong to one block
+  index?: number;
+  // redacted_thinking
+  data?: string;
+  // reasoning items of the Responses API
+  id?: string;
+  summary?: unknown[];
+  encrypted_content?: string;
 };
 
 interface ThinkingBlocksDelta extends BaseDelta {
@@ -588,6 +596,20 @@ export function isSubchatResponse(json: unknown): json is SubchatResponse {
   return true;
 }
 
+export type ModelSwitchResponse = {
+  model_switch: { from: string; to: string; reason: string };
+};
+
+export function isModelSwitchResponse(
+  json: unknown,
+): json is ModelSwitchResponse {
+  if (!json) return false;
+  if (typeof json !== "object") return false;
+  if (!("model_switch" in json)) return false;
+  if (!json.model_switch || typeof json.model_switch !== "object") return false;
+  return "from" in json.model_switch && "to" in json.model_switch;
+}
+
 export function isSystemResponse(json: unknown): json is SystemMessage {
   if (!json) return false;
   if (typeof json !== "object") return false;